@@ -0,0 +1,391 @@
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use crate::handlers::story::parse_story_concurrency;
+use crate::services::browser_pool::parse_browser_pool_size;
+use crate::services::dedup::parse_index_path;
+use crate::services::downloader::{parse_cookies_file, parse_max_backoff_ms, parse_max_global_downloads, parse_temp_dir, parse_ytdlp_concurrent_fragments, JitterStrategy, MediaHeaders};
+use crate::services::extractor::{parse_max_carousel_slides, parse_nav_timeout_secs, parse_stealth_js_file, ExtractionRetryConfig};
+use crate::services::http_client::HttpClientConfig;
+use crate::services::queue::parse_max_queue;
+use crate::utils::url_guard::UrlAllowlist;
+
+// Default per-route request timeouts in seconds
+const DEFAULT_PREVIEW_TIMEOUT_SECS: u64 = 20;
+const DEFAULT_DOWNLOAD_TIMEOUT_SECS: u64 = 120;
+// Most URLs accepted in one /api/download/batch request
+const DEFAULT_MAX_BATCH_URLS: usize = 25;
+// Largest media file a preview with inline=true embeds as a data: URI
+const DEFAULT_INLINE_MEDIA_MAX_BYTES: usize = 1024 * 1024;
+// Log level used when neither RUST_LOG nor LOG_LEVEL is set
+const DEFAULT_LOG_LEVEL: &str = "info";
+// Where the HTTP server listens
+const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 9090;
+
+// Parse a timeout in seconds, falling back to the given default
+fn parse_timeout(value: Option<&str>, default_secs: u64) -> u64 {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(default_secs)
+}
+
+// Parse a positive count, falling back to the given default
+fn parse_count(value: Option<&str>, default: usize) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(default)
+}
+
+// Resolve the worker thread count from WORKER_THREADS, falling back to the CPU count
+fn parse_worker_threads(value: Option<&str>) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+}
+
+/// How log lines are written: the human-readable default or one JSON object per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl LogFormat {
+    /// Parse `LOG_FORMAT` (`pretty` or `json`), falling back to `pretty` on missing or unknown values
+    pub fn parse(value: Option<&str>) -> Self {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            Some("json") => Self::Json,
+            _ => Self::Pretty,
+        }
+    }
+}
+
+/// The tracing filter directives: `RUST_LOG` when set (e.g. `insta=debug,tower_http=info`),
+/// otherwise the plain level in `LOG_LEVEL`, otherwise `info`.
+pub fn log_filter(rust_log: Option<&str>, log_level: Option<&str>) -> String {
+    let non_empty = |v: Option<&str>| v.map(str::trim).filter(|v| !v.is_empty()).map(str::to_string);
+    non_empty(rust_log)
+        .or_else(|| non_empty(log_level))
+        .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string())
+}
+
+/// Resolve the listen address from `BIND_ADDR` and `PORT`, defaulting to `0.0.0.0:9090`.
+/// Errors name the offending variable when a value doesn't parse.
+pub fn parse_listen_addr(bind_addr: Option<&str>, port: Option<&str>) -> Result<SocketAddr, String> {
+    let ip = match bind_addr.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(v) => v
+            .parse::<IpAddr>()
+            .map_err(|_| format!("BIND_ADDR '{}' is not a valid IP address", v))?,
+        None => DEFAULT_BIND_ADDR,
+    };
+    let port = match port.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(v) => v
+            .parse::<u16>()
+            .map_err(|_| format!("PORT '{}' is not a valid port number", v))?,
+        None => DEFAULT_PORT,
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Validate `PUBLIC_BASE_URL`, the absolute `http(s)://host[:port][/prefix]` the server is
+/// reached at from outside, returned without a trailing slash.
+pub fn parse_public_base_url(value: Option<&str>) -> Result<Option<String>, String> {
+    let Some(value) = value.map(str::trim).filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    let url = url::Url::parse(value)
+        .map_err(|e| format!("PUBLIC_BASE_URL '{}' is not a valid URL: {}", value, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(format!("PUBLIC_BASE_URL '{}' must be an absolute http or https URL", value));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!("PUBLIC_BASE_URL '{}' must not have a query or fragment", value));
+    }
+    Ok(Some(url.as_str().trim_end_matches('/').to_string()))
+}
+
+/// A value that must never be shown: serializes (and debug-prints) as `"***"` when set
+/// and `null` when not.
+#[derive(Clone, Default)]
+pub struct Secret(Option<String>);
+
+impl Secret {
+    fn new(value: Option<String>) -> Self {
+        Self(value.filter(|v| !v.is_empty()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("\"***\""),
+            None => f.write_str("None"),
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Some(_) => serializer.serialize_str("***"),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// The configuration the server actually resolved from its environment, with the
+/// defaults filled in. Loaded once at startup and the only place settings are read from:
+/// handlers get it through the app state, other code through `Config::current()`.
+/// Served by `GET /api/config`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    pub bind_addr: IpAddr,
+    pub port: u16,
+    pub worker_threads: usize,
+    pub log_format: LogFormat,
+    pub log_filter: String,
+    pub preview_timeout_secs: u64,
+    pub download_timeout_secs: u64,
+    pub max_batch_urls: usize,
+    pub inline_media_max_bytes: usize,
+    pub public_base_url: Option<String>,
+    pub allowed_url_patterns: UrlAllowlist,
+    pub extraction_retries: usize,
+    pub extraction_retry_delay_secs: u64,
+    pub nav_timeout_secs: u64,
+    pub max_carousel_slides: usize,
+    pub story_concurrency: u32,
+    pub max_backoff_ms: u64,
+    pub backoff_jitter: JitterStrategy,
+    pub max_global_downloads: usize,
+    pub max_queue: usize,
+    pub browser_pool_size: usize,
+    pub ytdlp_concurrent_fragments: usize,
+    pub http_pool_max_idle_per_host: usize,
+    pub http_pool_idle_timeout_secs: u64,
+    pub http_connect_timeout_secs: u64,
+    pub accept_language: String,
+    pub media_referer: String,
+    /// Names only: values may carry tokens
+    pub media_headers: Vec<String>,
+    /// The headers themselves, sent with every media fetch
+    #[serde(skip)]
+    pub media: MediaHeaders,
+    pub debug_dump_dir: Option<String>,
+    pub cookies_file: Option<String>,
+    pub stealth_js_file: Option<String>,
+    pub temp_dir: Option<String>,
+    pub storage_backend: String,
+    pub storage_local_root: String,
+    pub dedup_index_path: String,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_endpoint: Option<String>,
+    pub aws_access_key_id: Secret,
+    pub aws_secret_access_key: Secret,
+}
+
+// The configuration installed at startup, for code that runs outside a request handler
+static CURRENT: OnceLock<Arc<Config>> = OnceLock::new();
+
+impl Config {
+    /// Resolve every setting from the environment. This is the only place the server reads
+    /// its settings from; fails only on settings the server can't start without.
+    pub fn from_env() -> Result<Self, String> {
+        Self::resolve(|name| std::env::var(name).ok())
+    }
+
+    /// Resolve every setting from the values `var` returns for the variable names
+    fn resolve(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let extraction = ExtractionRetryConfig::parse(
+            var("EXTRACTION_RETRIES").as_deref(),
+            var("EXTRACTION_RETRY_DELAY_SECS").as_deref(),
+        );
+        let http = HttpClientConfig::parse(&var);
+        let media = MediaHeaders::parse(var("MEDIA_REFERER").as_deref(), var("MEDIA_HEADERS").as_deref())?;
+        let non_empty = |name: &str| var(name).filter(|v| !v.is_empty());
+        let listen_addr = parse_listen_addr(var("BIND_ADDR").as_deref(), var("PORT").as_deref())?;
+        Ok(Self {
+            bind_addr: listen_addr.ip(),
+            port: listen_addr.port(),
+            worker_threads: parse_worker_threads(var("WORKER_THREADS").as_deref()),
+            log_format: LogFormat::parse(var("LOG_FORMAT").as_deref()),
+            log_filter: log_filter(var("RUST_LOG").as_deref(), var("LOG_LEVEL").as_deref()),
+            preview_timeout_secs: parse_timeout(var("PREVIEW_TIMEOUT_SECS").as_deref(), DEFAULT_PREVIEW_TIMEOUT_SECS),
+            download_timeout_secs: parse_timeout(var("DOWNLOAD_TIMEOUT_SECS").as_deref(), DEFAULT_DOWNLOAD_TIMEOUT_SECS),
+            max_batch_urls: parse_count(var("MAX_BATCH_URLS").as_deref(), DEFAULT_MAX_BATCH_URLS),
+            inline_media_max_bytes: parse_count(var("INLINE_MEDIA_MAX_BYTES").as_deref(), DEFAULT_INLINE_MEDIA_MAX_BYTES),
+            public_base_url: parse_public_base_url(var("PUBLIC_BASE_URL").as_deref())?,
+            allowed_url_patterns: UrlAllowlist::parse(var("ALLOWED_URL_PATTERNS").as_deref())?,
+            extraction_retries: extraction.retries,
+            extraction_retry_delay_secs: extraction.delay.as_secs(),
+            nav_timeout_secs: parse_nav_timeout_secs(var("NAV_TIMEOUT_SECS").as_deref()),
+            max_carousel_slides: parse_max_carousel_slides(var("MAX_CAROUSEL_SLIDES").as_deref()),
+            story_concurrency: parse_story_concurrency(var("STORY_CONCURRENCY").as_deref()),
+            max_backoff_ms: parse_max_backoff_ms(var("MAX_BACKOFF_MS").as_deref()),
+            backoff_jitter: JitterStrategy::parse(var("BACKOFF_JITTER").as_deref()),
+            max_global_downloads: parse_max_global_downloads(var("MAX_GLOBAL_DOWNLOADS").as_deref()),
+            max_queue: parse_max_queue(var("MAX_QUEUE").as_deref()),
+            browser_pool_size: parse_browser_pool_size(var("BROWSER_POOL_SIZE").as_deref()),
+            ytdlp_concurrent_fragments: parse_ytdlp_concurrent_fragments(var("YTDLP_CONCURRENT_FRAGMENTS").as_deref()),
+            http_pool_max_idle_per_host: http.pool_max_idle_per_host,
+            http_pool_idle_timeout_secs: http.pool_idle_timeout.as_secs(),
+            http_connect_timeout_secs: http.connect_timeout.as_secs(),
+            accept_language: http.accept_language,
+            media_referer: media.referer().to_string(),
+            media_headers: media.names(),
+            media,
+            debug_dump_dir: non_empty("DEBUG_DUMP_DIR"),
+            cookies_file: parse_cookies_file(var("COOKIES_FILE").as_deref())?.map(|path| path.display().to_string()),
+            stealth_js_file: parse_stealth_js_file(var("STEALTH_JS_FILE").as_deref())?.map(|path| path.display().to_string()),
+            temp_dir: parse_temp_dir(var("TEMP_DIR").as_deref()).map(|dir| dir.display().to_string()),
+            storage_backend: non_empty("STORAGE_BACKEND").unwrap_or_else(|| "local".to_string()),
+            storage_local_root: var("STORAGE_LOCAL_ROOT").unwrap_or_else(|| ".".to_string()),
+            dedup_index_path: parse_index_path(var("DEDUP_INDEX_PATH").as_deref()),
+            s3_bucket: non_empty("S3_BUCKET"),
+            s3_region: var("S3_REGION").unwrap_or_else(|| "us-east-1".to_string()),
+            s3_endpoint: non_empty("S3_ENDPOINT"),
+            aws_access_key_id: Secret::new(var("AWS_ACCESS_KEY_ID")),
+            aws_secret_access_key: Secret::new(var("AWS_SECRET_ACCESS_KEY")),
+        })
+    }
+
+    /// Make this the configuration `Config::current()` returns and hand it back for the app
+    /// state. Called once at startup, before anything reads a setting.
+    pub fn install(self) -> Arc<Self> {
+        let config = Arc::new(self);
+        if CURRENT.set(config.clone()).is_err() {
+            panic!("Config::install called twice");
+        }
+        config
+    }
+
+    /// The configuration installed at startup. Code that runs before `install` (unit tests)
+    /// gets one resolved from the environment instead.
+    pub fn current() -> &'static Config {
+        CURRENT.get_or_init(|| Arc::new(Self::from_env().expect("invalid configuration")))
+    }
+
+    /// Settings for the outgoing HTTP clients
+    pub fn http_client(&self) -> HttpClientConfig {
+        HttpClientConfig {
+            pool_max_idle_per_host: self.http_pool_max_idle_per_host,
+            pool_idle_timeout: Duration::from_secs(self.http_pool_idle_timeout_secs),
+            connect_timeout: Duration::from_secs(self.http_connect_timeout_secs),
+            accept_language: self.accept_language.clone(),
+        }
+    }
+
+    /// How often extraction is retried when it finds nothing
+    pub fn extraction_retry(&self) -> ExtractionRetryConfig {
+        ExtractionRetryConfig {
+            retries: self.extraction_retries,
+            delay: Duration::from_secs(self.extraction_retry_delay_secs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::resolve(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn settings_are_resolved_from_the_given_variables() {
+        let config = resolve(&[
+            ("MAX_QUEUE", "7"),
+            ("NAV_TIMEOUT_SECS", "not a number"),
+            ("BACKOFF_JITTER", "full"),
+            ("MEDIA_REFERER", "https://example.com/"),
+            ("DEBUG_DUMP_DIR", ""),
+            ("AWS_SECRET_ACCESS_KEY", "hunter2"),
+        ])
+        .unwrap();
+        assert_eq!(config.max_queue, 7);
+        assert_eq!(config.nav_timeout_secs, parse_nav_timeout_secs(None));
+        assert_eq!(config.backoff_jitter, JitterStrategy::Full);
+        assert_eq!(config.media_referer, "https://example.com/");
+        assert_eq!(config.debug_dump_dir, None);
+        assert_eq!(config.storage_backend, "local");
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["aws_secret_access_key"], "***");
+        assert_eq!(json["aws_access_key_id"], serde_json::Value::Null);
+        assert!(json.get("media").is_none());
+    }
+
+    #[test]
+    fn invalid_required_settings_fail() {
+        assert!(resolve(&[("PORT", "99999")]).is_err());
+        assert!(resolve(&[("MEDIA_HEADERS", "no colon")]).is_err());
+        assert!(resolve(&[("ALLOWED_URL_PATTERNS", "(")]).is_err());
+        assert!(resolve(&[("COOKIES_FILE", "/nonexistent/cookies.txt")]).is_err());
+    }
+
+    #[test]
+    fn public_base_url_is_validated_and_trimmed() {
+        assert_eq!(parse_public_base_url(None), Ok(None));
+        assert_eq!(parse_public_base_url(Some("  ")), Ok(None));
+        assert_eq!(parse_public_base_url(Some("https://dl.example.com/")), Ok(Some("https://dl.example.com".to_string())));
+        assert_eq!(parse_public_base_url(Some("https://example.com/insta/")), Ok(Some("https://example.com/insta".to_string())));
+        assert!(parse_public_base_url(Some("dl.example.com")).is_err());
+        assert!(parse_public_base_url(Some("ftp://dl.example.com")).is_err());
+        assert!(parse_public_base_url(Some("https://dl.example.com/?a=1")).is_err());
+    }
+
+    #[test]
+    fn secrets_never_show_their_value() {
+        let secret = Secret::new(Some("hunter2".to_string()));
+        assert_eq!(format!("{:?}", secret), "\"***\"");
+        assert_eq!(serde_json::to_value(&secret).unwrap(), "***");
+        let unset = Secret::new(Some(String::new()));
+        assert_eq!(serde_json::to_value(&unset).unwrap(), serde_json::Value::Null);
+        assert!(!format!("{:?}", Secret::new(None)).contains("***"));
+    }
+
+    #[test]
+    fn listen_address_defaults_and_errors() {
+        assert_eq!(parse_listen_addr(None, None), Ok("0.0.0.0:9090".parse().unwrap()));
+        assert_eq!(parse_listen_addr(Some(" ::1 "), Some("8080")), Ok("[::1]:8080".parse().unwrap()));
+        assert_eq!(parse_listen_addr(Some(""), Some(" ")), Ok("0.0.0.0:9090".parse().unwrap()));
+        assert!(parse_listen_addr(Some("localhost"), None).unwrap_err().contains("BIND_ADDR"));
+        assert!(parse_listen_addr(None, Some("70000")).unwrap_err().contains("PORT"));
+    }
+
+    #[test]
+    fn worker_threads_fall_back_to_the_cpu_count() {
+        let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        assert_eq!(parse_worker_threads(Some(" 3 ")), 3);
+        assert_eq!(parse_worker_threads(Some("0")), cpus);
+        assert_eq!(parse_worker_threads(Some("-2")), cpus);
+        assert_eq!(parse_worker_threads(Some("many")), cpus);
+        assert_eq!(parse_worker_threads(None), cpus);
+        assert_eq!(resolve(&[("WORKER_THREADS", "5")]).unwrap().worker_threads, 5);
+    }
+
+    #[test]
+    fn log_settings_fall_back_to_pretty_info() {
+        assert_eq!(LogFormat::parse(Some(" JSON ")), LogFormat::Json);
+        assert_eq!(LogFormat::parse(Some("yaml")), LogFormat::Pretty);
+        assert_eq!(LogFormat::parse(None), LogFormat::Pretty);
+
+        assert_eq!(log_filter(Some("insta=debug"), Some("warn")), "insta=debug");
+        assert_eq!(log_filter(Some("  "), Some(" warn ")), "warn");
+        assert_eq!(log_filter(None, None), "info");
+    }
+}
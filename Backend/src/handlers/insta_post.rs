@@ -1,15 +1,35 @@
 use axum::extract::Json;
 use serde::{Deserialize}; // Removed unused Serialize
+use crate::handlers::post;
+use crate::services::downloader::DownloadLimiter;
+use crate::services::outcome::DownloadResult;
 
 #[derive(Deserialize)]
 pub struct PostDownloadRequest {
     pub url: String,
-    #[allow(dead_code)]
-    pub browser: Option<String>, // Marked as allowed dead code
+    pub browser: Option<String>,
+    pub cookies: Option<String>,
+    pub only: Option<String>,
+    pub convert_to: Option<String>,
+    pub quality: Option<u64>,
+    pub filename: Option<String>,
+    pub max_retries: Option<usize>,
+    pub strip_metadata: Option<bool>,
+    pub disable_ytdlp: Option<bool>,
 }
 
-pub async fn download(Json(request): Json<PostDownloadRequest>) -> Json<String> {
-    // Implement post download logic here
-    // For now, just return a placeholder response
-    Json(format!("✅ Post download started for URL: {}", request.url))
+pub async fn download(limiter: DownloadLimiter, Json(request): Json<PostDownloadRequest>) -> DownloadResult {
+    // Delegate to the browser-based post handler
+    post::download(limiter, Json(post::PostDownloadRequest {
+        url: request.url,
+        browser: request.browser,
+        cookies: request.cookies,
+        only: request.only,
+        convert_to: request.convert_to,
+        quality: request.quality,
+        filename: request.filename,
+        max_retries: request.max_retries,
+        strip_metadata: request.strip_metadata,
+        disable_ytdlp: request.disable_ytdlp,
+    })).await
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
 pub mod reel;
 pub mod story;
 pub mod insta_post;
+pub mod post;
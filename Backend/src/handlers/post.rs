@@ -1,70 +1,140 @@
-use axum::{Json, extract::Json as ExtractJson};
-use chrono::Utc;
+use axum::extract::Json as ExtractJson;
 use serde::Deserialize;
-use std::{fs::create_dir_all, sync::Arc};
+use std::sync::Arc;
 use tokio::task;
 use tokio::time::sleep;
 use futures::future::join_all;
 use crate::services::{
-    extractor::{create_browser_client, extract_post_media},
-    downloader::{download_media_with_retry, download_with_ytdlp},
+    browser_pool::checkout_browser_client,
+    extractor::{apply_session_cookies, extract_post_media, filter_media_by_type, goto_with_timeout, parse_media_filter, shortcode_from_url},
+    downloader::{convert_or_keep, DISK_FULL, download_media_with_retry, download_with_ytdlp, media_client_builder, parse_max_retries, DownloadError, DownloadLimiter, DownloadOrder, ImageConversion, YtDlpOptions, extension_for},
+    metrics::{extraction_metrics, Method},
 };
-use reqwest::Client;
+use crate::services::manifest::write_manifest;
+use crate::services::outcome::{DownloadResult, ExtractionOutcome};
+use crate::utils::job::{create_job_folder, new_job_id, parse_output_name};
+use std::path::PathBuf;
 
 #[derive(Deserialize)]
 pub struct PostDownloadRequest {
     pub url: String,
     pub browser: Option<String>,
+    /// Session cookies supplied by the caller (Netscape file or `name=value; ...` header form)
+    pub cookies: Option<String>,
+    /// Only keep media of this type (`"video"` or `"image"`)
+    pub only: Option<String>,
+    /// Re-encode downloaded images as `"jpg"` or `"png"`
+    pub convert_to: Option<String>,
+    /// JPEG quality (1 to 100) for `convert_to: "jpg"`, 85 by default
+    pub quality: Option<u64>,
+    /// Base name of a single saved file, or prefix (`{filename}_1.jpg`, ...) for carousels
+    pub filename: Option<String>,
+    /// Download attempts per file (1 to 10) instead of the default 5
+    pub max_retries: Option<usize>,
+    /// Remove EXIF and other metadata from downloaded JPEG images
+    pub strip_metadata: Option<bool>,
+    /// `true` never runs yt-dlp, even when the browser finds nothing
+    pub disable_ytdlp: Option<bool>,
 }
 
-pub async fn download(Json(payload): ExtractJson<PostDownloadRequest>) -> Json<String> {
+pub async fn download(limiter: DownloadLimiter, ExtractJson(payload): ExtractJson<PostDownloadRequest>) -> DownloadResult {
     let url = payload.url;
+    // Only a browser the caller named explicitly lends its profile cookies to the session
+    let cookie_browser = payload.browser.clone();
     let browser = payload.browser.unwrap_or_else(|| "chrome".to_string());
-    let timestamp = Utc::now().timestamp();
-    let folder_name = format!("insta_post_{}", timestamp);
-
-    // Handle directory creation errors
-    if let Err(e) = create_dir_all(&folder_name) {
-        return Json(format!("Failed to create folder '{}': {}", folder_name, e));
-    }
+    let cookies = payload.cookies;
+    let only = match parse_media_filter(payload.only.as_deref()) {
+        Ok(only) => only,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let convert_to = match ImageConversion::parse(payload.convert_to.as_deref(), payload.quality) {
+        Ok(convert_to) => convert_to,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let output_name = match parse_output_name(payload.filename.as_deref()) {
+        Ok(output_name) => output_name,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let max_retries = match parse_max_retries(payload.max_retries) {
+        Ok(max_retries) => max_retries,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let strip_metadata = payload.strip_metadata.unwrap_or(false);
+    // Posts always try the browser first; only `disable_ytdlp` matters here
+    let order = DownloadOrder::from_flags(None, payload.disable_ytdlp);
+    let ytdlp_skipped = ytdlp_skip_reason(order, strip_metadata, only, convert_to);
+    let folder_name = match create_job_folder("post") {
+        Ok(folder_name) => folder_name,
+        Err(e) => return DownloadResult::Failed(format!("❌ Failed to create folder: {}", e)),
+    };
+    let ytdlp = YtDlpOptions {
+        folder: Some(&folder_name),
+        browser: Some(&browser),
+        cookies: cookies.as_deref(),
+        output_name: output_name.as_deref(),
+        ..Default::default()
+    };
 
     // Connect to browser and go to post URL
-    let mut client = match create_browser_client(&browser).await {
+    let mut client = match checkout_browser_client(&browser).await {
         Ok(client) => client,
         Err(e) => {
-            if let Err(e) = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await {
+            if let Err(e) = ytdlp_after_browser_failure(&url, &ytdlp, ytdlp_skipped).await {
                 // Use {:?} for debug formatting of the error
-                return Json(format!("yt-dlp fallback failed: {:?}", e));
+                return DownloadResult::Failed(format!("❌ yt-dlp fallback failed: {:?}", e));
             }
             // Use {:?} for debug formatting of the error
-            return Json(format!("Browser error. Fallback to yt-dlp. Info: {:?}", e));
+            return DownloadResult::done(format!("✅ Downloaded with yt-dlp after a browser error: {:?}", e));
         }
     };
 
-    if let Err(e) = client.goto(&url).await {
-        return Json(format!("Failed to navigate to Instagram post: {}", e));
+    if let Err(e) = apply_session_cookies(&client, cookies.as_deref(), cookie_browser.as_deref()).await {
+        println!("⚠️ {}", e);
+    }
+
+    if let Err(e) = goto_with_timeout(&client, &url).await {
+        client.release().await;
+        record_browser_only_failure();
+        return DownloadResult::Failed(format!("❌ Failed to navigate to Instagram post: {}", e));
     }
 
     sleep(std::time::Duration::from_secs(8)).await;
 
     // Build reqwest client
-    let reqwest_client = Client::builder()
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/96.0.4664.110 Safari/537.36")
+    let reqwest_client = media_client_builder()
         .timeout(std::time::Duration::from_secs(30))
         .build()
         .unwrap();
 
-    let media_items = match extract_post_media(&mut client).await { // Pass mutable reference
+    let extracted = extract_post_media(&mut client, &new_job_id()).await; // Pass mutable reference
+    client.release().await;
+    let media_items = match extracted {
         Ok(m) if !m.is_empty() => m,
         Ok(_) => {
-            let _ = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await;
-            return Json("No valid media found, fallback to yt-dlp executed.".to_string());
+            if let Some(only) = only {
+                record_browser_only_failure();
+                return DownloadResult::Failed(format!("❌ No media of requested type '{}' in this post", only));
+            }
+            return ytdlp_result(
+                ytdlp_after_browser_failure(&url, &ytdlp, ytdlp_skipped).await,
+                "No valid media found, fallback to yt-dlp executed.",
+            );
         },
         Err(e) => {
-            return Json(format!("Failed to extract media: {}", e));
+            record_browser_only_failure();
+            return DownloadResult::Failed(format!("❌ Failed to extract media: {}", e));
         }
     };
+    // Filtered after extraction; yt-dlp can't filter by type so there is no fallback here
+    let media_items = filter_media_by_type(media_items, only);
+    if let (Some(only), true) = (only, media_items.is_empty()) {
+        return DownloadResult::Failed(format!("❌ No media of requested type '{}' in this post", only));
+    }
 
+    // Name files after the post's shortcode so they can be matched back to it, unless the
+    // caller chose a name; a single requested-name file isn't numbered
+    let single_named = output_name.is_some() && media_items.len() == 1;
+    let file_stem = output_name.clone().or_else(|| shortcode_from_url(&url)).unwrap_or_else(|| "media".to_string());
     let semaphore = Arc::new(tokio::sync::Semaphore::new(10));
     let mut download_tasks = Vec::new();
 
@@ -72,14 +142,19 @@ pub async fn download(Json(payload): ExtractJson<PostDownloadRequest>) -> Json<S
     for (i, (url, media_type)) in media_items.clone().into_iter().enumerate() {
         let semaphore_clone = semaphore.clone(); // Clone semaphore
         let reqwest_client = reqwest_client.clone();
-        let filename = format!("{}/media_{}.{}", &folder_name, i + 1, if media_type == "video" { "mp4" } else { "jpg" });
+        let limiter = limiter.clone();
+        let extension = if media_type == "video" { "mp4" } else { extension_for(&url, None) };
+        let filename = media_filename(&folder_name, &file_stem, i, single_named, extension);
 
         let task = task::spawn(async move {
             let permit = semaphore_clone.acquire().await.unwrap(); // Acquire permit inside async block
             let _permit = permit; // Ensure permit is held for the duration of the task
-            match download_media_with_retry(&reqwest_client, &url, &filename).await {
-                Ok(_) => Ok((filename, "Download success".to_string())),
-                Err(e) => Err((filename, format!("Download failed: {:?}", e))),
+            match download_media_with_retry(&reqwest_client, &limiter, &url, &filename, max_retries, strip_metadata).await {
+                Ok(file) => match convert_to.filter(|_| media_type == "image") {
+                    Some(to) => Ok((convert_or_keep(file, to).await, url)),
+                    None => Ok((file, url)),
+                },
+                Err(e) => Err((filename, e)),
             }
         });
 
@@ -87,20 +162,144 @@ pub async fn download(Json(payload): ExtractJson<PostDownloadRequest>) -> Json<S
     }
 
     let results = join_all(download_tasks).await;
-    let success_count = results.iter().filter(|res| match res {
-        Ok(Ok((_, _))) => true, // Match Ok(Ok(...)) for successful task and successful download
-        _ => false,
-    }).count();
-
-    if success_count == 0 {
-        let _ = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await;
-        return Json("All downloads failed. yt-dlp fallback executed.".to_string());
+    let success_count = results.iter().filter(|res| matches!(res, Ok(Ok(_)))).count();
+    let deduped_count = results.iter().filter(|res| matches!(res, Ok(Ok((file, _))) if file.deduped)).count();
+
+    let outcome = ExtractionOutcome::classify(success_count, media_items.len(), false, None);
+    if !outcome.is_success() {
+        // yt-dlp would write to the same full disk
+        if results.iter().any(|res| matches!(res, Ok(Err((_, e))) if e.is_disk_full())) {
+            record_browser_only_failure();
+            return DownloadResult::DiskFull(DISK_FULL.to_string());
+        }
+        if only.is_some() {
+            record_browser_only_failure();
+            return DownloadResult::Failed("❌ All downloads failed.".to_string());
+        }
+        return ytdlp_result(
+            ytdlp_after_browser_failure(&url, &ytdlp, ytdlp_skipped).await,
+            "All downloads failed. yt-dlp fallback executed.",
+        );
     }
 
-    Json(format!(
-        "✅ Downloaded {}/{} media items successfully to '{}'",
-        success_count,
-        media_items.len(),
-        folder_name
-    ))
+    let known_sources: Vec<(String, String)> = results
+        .into_iter()
+        .filter_map(|res| res.ok()?.ok())
+        .map(|(file, source)| (file.path, source))
+        .collect();
+    write_manifest(&folder_name, &url, "post", &known_sources).await;
+    extraction_metrics().record(Method::Browser, true);
+
+    DownloadResult::Done {
+        message: download_summary(&outcome, success_count, media_items.len(), &folder_name, deduped_count),
+        deduped: deduped_count > 0,
+    }
+}
+
+// Status message for a browser download that saved at least one item
+fn download_summary(outcome: &ExtractionOutcome, saved: usize, total: usize, folder_name: &str, deduped: usize) -> String {
+    let dedup_note = if deduped > 0 { format!(" ({} deduplicated)", deduped) } else { String::new() };
+    format!(
+        "{} Downloaded {}/{} media items successfully to '{}'{}",
+        outcome.emoji(),
+        saved,
+        total,
+        folder_name,
+        dedup_note
+    )
+}
+
+// The browser path failed and yt-dlp can't help (navigation error, type filter, full disk)
+fn record_browser_only_failure() {
+    extraction_metrics().record(Method::Browser, false);
+    extraction_metrics().record_all_failed();
+}
+
+// Report a yt-dlp fallback by whether it saved anything
+fn ytdlp_result(result: Result<Vec<PathBuf>, DownloadError>, note: &str) -> DownloadResult {
+    match result {
+        Ok(_) => DownloadResult::done(format!("✅ {}", note)),
+        Err(e) => DownloadResult::Failed(format!("❌ {} ({})", note, e)),
+    }
+}
+
+// Why yt-dlp can't stand in for the browser on this request: the caller disabled it, or it
+// would save every item of the post as it is, so it can't strip metadata, keep only one media
+// type or convert images
+fn ytdlp_skip_reason(order: DownloadOrder, strip_metadata: bool, only: Option<&str>, convert_to: Option<ImageConversion>) -> Option<&'static str> {
+    if !order.ytdlp_allowed() {
+        Some("disabled by the request")
+    } else if strip_metadata {
+        Some("it can't strip image metadata")
+    } else if only.is_some() {
+        Some("it can't filter by media type")
+    } else if convert_to.is_some() {
+        Some("it can't convert images")
+    } else {
+        None
+    }
+}
+
+// Fall back to yt-dlp after the browser path failed, counting both in the extraction metrics.
+// Skipped, with `skipped` as the reason, when yt-dlp can't honour the request.
+async fn ytdlp_after_browser_failure(url: &str, ytdlp: &YtDlpOptions<'_>, skipped: Option<&str>) -> Result<Vec<PathBuf>, DownloadError> {
+    if let Some(reason) = skipped {
+        record_browser_only_failure();
+        return Err(DownloadError(format!("yt-dlp fallback skipped: {}", reason)));
+    }
+    let metrics = extraction_metrics();
+    metrics.record(Method::Browser, false);
+    let result = download_with_ytdlp(url, ytdlp).await;
+    metrics.record(Method::Ytdlp, result.is_ok());
+    if result.is_err() {
+        metrics.record_all_failed();
+    }
+    result
+}
+
+// Path of the `index`th (0-based) item in the job folder: `{stem}.{ext}` for a lone file the
+// caller named, otherwise `{stem}_{n}.{ext}`
+fn media_filename(folder: &str, stem: &str, index: usize, single_named: bool, extension: &str) -> String {
+    if single_named {
+        format!("{}/{}.{}", folder, stem, extension)
+    } else {
+        format!("{}/{}_{}.{}", folder, stem, index + 1, extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requested_names_are_used_whole_or_as_a_prefix() {
+        let name = parse_output_name(Some(" Holiday trip.JPG ")).unwrap().unwrap();
+        assert_eq!(name, "Holiday trip");
+        assert_eq!(media_filename("insta_post_1", &name, 0, true, "jpg"), "insta_post_1/Holiday trip.jpg");
+        assert_eq!(media_filename("insta_post_1", &name, 1, false, "mp4"), "insta_post_1/Holiday trip_2.mp4");
+        assert_eq!(parse_output_name(None), Ok(None));
+        for unsafe_name in ["../escape", "a\\b", "..", "..."] {
+            assert!(parse_output_name(Some(unsafe_name)).is_err(), "{} was accepted", unsafe_name);
+        }
+    }
+
+    #[test]
+    fn ytdlp_is_skipped_when_it_cant_honour_the_request() {
+        let allowed = DownloadOrder::from_flags(None, None);
+        assert_eq!(ytdlp_skip_reason(allowed, false, None, None), None);
+        assert_eq!(ytdlp_skip_reason(allowed, false, Some("image"), None), Some("it can't filter by media type"));
+        assert_eq!(ytdlp_skip_reason(allowed, true, None, None), Some("it can't strip image metadata"));
+        assert_eq!(ytdlp_skip_reason(allowed, false, None, Some(ImageConversion::Png)), Some("it can't convert images"));
+        let disabled = DownloadOrder::from_flags(None, Some(true));
+        assert_eq!(ytdlp_skip_reason(disabled, false, None, None), Some("disabled by the request"));
+    }
+
+    #[test]
+    fn success_message_has_a_real_checkmark() {
+        let outcome = ExtractionOutcome::classify(2, 2, false, None);
+        let message = download_summary(&outcome, 2, 2, "downloads/abc", 1);
+        assert_eq!(message, "✅ Downloaded 2/2 media items successfully to 'downloads/abc' (1 deduplicated)");
+        // UTF-8 bytes of ✅ read back as Windows-1252
+        assert!(!message.contains("âœ"));
+    }
 }
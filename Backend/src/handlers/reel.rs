@@ -1,31 +1,85 @@
 use axum::extract::Json;
 use serde::Deserialize;
 use chrono::Utc;
-use std::fs::create_dir_all;
-use reqwest::Client;
 use tokio::time::sleep;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use crate::services::downloader::{download_media_with_retry, download_with_ytdlp};
+use crate::utils::job::{create_job_folder, parse_output_name};
+use crate::services::downloader::{download_media_with_retry, download_with_ytdlp, file_media_kind, DownloadError, media_client_builder, parse_max_retries, DownloadLimiter, DownloadOrder, ImageConversion, MediaKind, YtDlpOptions, COVER_FILE, MIN_REEL_BYTES};
+use crate::services::browser_pool::checkout_browser_client;
+use crate::services::extractor::{absolutize_media_url, apply_session_cookies, extract_cover_url, first_mp4_resource, goto_with_timeout, parse_media_filter, shortcode_from_url};
+use crate::services::manifest::write_manifest;
+use crate::services::outcome::DownloadResult;
+use crate::services::metrics::{extraction_metrics, Method};
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize)]
 pub struct ReelDownloadRequest {
     pub url: String,
     pub browser: Option<String>,
-    #[allow(dead_code)]
-    pub use_ytdlp_first: Option<bool>, // Added option to use yt-dlp as primary method
+    /// Session cookies supplied by the caller (Netscape file or `name=value; ...` header form)
+    pub cookies: Option<String>,
+    /// `false` tries browser extraction before yt-dlp
+    pub use_ytdlp_first: Option<bool>,
+    /// `true` never runs yt-dlp
+    pub disable_ytdlp: Option<bool>,
+    /// `true` runs yt-dlp and browser extraction at the same time and keeps the first to succeed
+    pub race: Option<bool>,
+    /// Only keep media of this type (`"video"` or `"image"`)
+    pub only: Option<String>,
+    /// Re-encode downloaded images as `"jpg"` or `"png"`
+    pub convert_to: Option<String>,
+    /// Base name of the saved video instead of the shortcode
+    pub filename: Option<String>,
+    /// Also save the reel's poster image as `cover.jpg`
+    pub include_cover: Option<bool>,
+    /// Download attempts per file (1 to 10) instead of the default 5
+    pub max_retries: Option<usize>,
 }
 
-pub async fn download(Json(request): Json<ReelDownloadRequest>) -> Json<String> {
+pub async fn download(limiter: DownloadLimiter, Json(request): Json<ReelDownloadRequest>) -> DownloadResult {
     let url = request.url;
+    // Only a browser the caller named explicitly lends its profile cookies to the session
+    let cookie_browser = request.browser.clone();
     let browser = request.browser.unwrap_or_else(|| "chrome".to_string());
+    let cookies = request.cookies;
+    let order = DownloadOrder::from_flags(request.use_ytdlp_first, request.disable_ytdlp).racing(request.race);
+    let include_cover = request.include_cover.unwrap_or(false);
+    // A reel is a single video, so an image filter can never match
+    match parse_media_filter(request.only.as_deref()) {
+        Ok(Some("image")) => return DownloadResult::Failed("❌ No media of requested type 'image': reels only contain video".to_string()),
+        Ok(_) => {}
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    }
+    match ImageConversion::parse(request.convert_to.as_deref(), None) {
+        Ok(Some(_)) => return DownloadResult::Failed("❌ convert_to only applies to images; reels are videos".to_string()),
+        Ok(None) => {}
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    }
+    let output_name = match parse_output_name(request.filename.as_deref()) {
+        Ok(output_name) => output_name,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let max_retries = match parse_max_retries(request.max_retries) {
+        Ok(max_retries) => max_retries,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
     let timestamp = Utc::now().timestamp();
-    let folder_name = format!("insta_reel_{}", timestamp);
+    let folder_name = match create_job_folder("reel") {
+        Ok(folder_name) => folder_name,
+        Err(e) => return DownloadResult::Failed(format!("❌ Failed to create folder: {}", e)),
+    };
+    let ytdlp = YtDlpOptions {
+        folder: Some(&folder_name),
+        browser: Some(&browser),
+        cookies: cookies.as_deref(),
+        output_name: output_name.as_deref(),
+        write_cover: include_cover,
+        ..Default::default()
+    };
     let metadata_path = format!("{}/metadata.txt", folder_name);
     
-    if let Err(e) = create_dir_all(&folder_name) {
-        return Json(format!("❌ Failed to create folder: {}", e));
-    }
-    
     // Log metadata
     let metadata = format!(
         "Source URL: {}\nTimestamp: {}\nBrowser: {}\n",
@@ -35,51 +89,135 @@ pub async fn download(Json(request): Json<ReelDownloadRequest>) -> Json<String>
         println!("⚠️ Failed to write metadata: {}", e);
     }
 
-    // Always use yt-dlp with Chrome cookies as the first method
-    println!("🔄 Using yt-dlp as primary download method (with Chrome cookies)...");
-    if let Ok(_) = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), true).await {
-        return Json(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'", folder_name));
+    // yt-dlp with Chrome cookies is the primary method unless the caller opted out
+    if order.ytdlp_first() {
+        println!("🔄 Using yt-dlp as primary download method (with Chrome cookies)...");
+        let downloaded = download_with_ytdlp(&url, &YtDlpOptions { is_story: true, ..ytdlp }).await;
+        extraction_metrics().record(Method::Ytdlp, downloaded.is_ok());
+        if let Ok(paths) = downloaded {
+            write_manifest(&folder_name, &url, "reel", &[]).await;
+            return DownloadResult::done(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'{}", saved_location(&paths, &folder_name), saved_cover_note(&folder_name)));
+        }
+        println!("⚠️ yt-dlp download failed, falling back to browser extraction...");
     }
-    println!("⚠️ yt-dlp download failed, falling back to browser extraction...");
 
     // Try browser automation first, as in fullcode.rs
-    let mut caps = serde_json::Map::new();
-    caps.insert("browserName".to_string(), serde_json::Value::String("chrome".to_string()));
-    let mut chrome_opts = serde_json::Map::new();
-    chrome_opts.insert("args".to_string(), serde_json::Value::Array(vec![
-        "--disable-blink-features=AutomationControlled".into(),
-        "--no-sandbox".into(),
-        "--disable-dev-shm-usage".into(),
-        "--headless=new".into(),
-        "--disable-gpu".into(),
-        "--disable-extensions".into(),
-    ]));
-    caps.insert("goog:chromeOptions".to_string(), serde_json::Value::Object(chrome_opts));
-
-    let client_res = fantoccini::ClientBuilder::native()
-        .capabilities(caps)
-        .connect("http://localhost:9515")
-        .await;
-
-    let client = match client_res {
+    let client = match checkout_browser_client(&browser).await {
         Ok(c) => c,
         Err(e) => {
             println!("⚠️ Failed to connect to chromedriver: {}. Falling back to yt-dlp...", e);
-            if let Err(e) = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await {
-                return Json(format!("❌ yt-dlp failed: {}", e));
+            return ytdlp_fallback(&url, &ytdlp, order, None, "❌ yt-dlp failed").await;
+        }
+    };
+
+    // Set when yt-dlp already failed in a race, so browser-path failures don't run it again
+    let (video_src, ytdlp_error) = if order == DownloadOrder::Race {
+        match race_extraction(&client, &url, cookies.as_deref(), cookie_browser.as_deref(), &ytdlp, &folder_name).await {
+            RaceOutcome::Ytdlp(paths) => {
+                client.release().await;
+                write_manifest(&folder_name, &url, "reel", &[]).await;
+                return DownloadResult::done(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'{}", saved_location(&paths, &folder_name), saved_cover_note(&folder_name)));
+            }
+            RaceOutcome::Browser { video_src, ytdlp_error } => (video_src, ytdlp_error),
+            RaceOutcome::Failed(e) => {
+                client.release().await;
+                extraction_metrics().record_all_failed();
+                return DownloadResult::Failed(format!("❌ yt-dlp and browser extraction both failed: {}", e));
+            }
+        }
+    } else {
+        match extract_video_src(&client, &url, cookies.as_deref(), cookie_browser.as_deref()).await {
+            Some(video_src) => (video_src, None),
+            None => {
+                client.release().await;
+                println!("⚠️ Direct video URL not available. Using yt-dlp fallback...");
+                return ytdlp_fallback(&url, &ytdlp, order, None, "❌ yt-dlp failed").await;
             }
-            return Json(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'", folder_name));
         }
     };
 
-    println!("📲 Opening Instagram URL: {}", url);
-    if let Err(e) = client.goto(&url).await {
-        let _ = client.close().await;
-        println!("❌ Navigation error: {}. Falling back to yt-dlp...", e);
-        if let Err(e) = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await {
-            return Json(format!("❌ yt-dlp failed: {}", e));
+    // Read the poster while the page is still open
+    let cover_url = if include_cover { extract_cover_url(&client).await } else { None };
+    let file_stem = output_name.clone().or_else(|| shortcode_from_url(&url)).unwrap_or_else(|| "reel".to_string());
+    let output_path = format!("{}/{}.mp4", folder_name, file_stem);
+    println!("✅ Found video URL: {}\n⬇️ Downloading to {}", video_src, output_path);
+    let reqwest_client = media_client_builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+    match download_media_with_retry(&reqwest_client, &limiter, &video_src, &output_path, max_retries, false).await {
+        Ok(file) => {
+            // The URL may have been the poster image rather than the video, so check what was
+            // actually saved; the size floor only catches truncated placeholders
+            let kind = file_media_kind(&output_path);
+            let size = std::fs::metadata(&output_path).map(|m| m.len()).unwrap_or(0);
+            if kind != Some(MediaKind::Video) {
+                println!("⚠️ Downloaded file is not a video ({:?}), likely a thumbnail. Falling back to yt-dlp...", kind);
+                client.release().await;
+                return ytdlp_fallback(&url, &ytdlp, order, ytdlp_error.as_deref(), "❌ yt-dlp fallback also failed").await;
+            }
+            if size < MIN_REEL_BYTES {
+                println!("⚠️ Downloaded video is too small ({} bytes). Falling back to yt-dlp...", size);
+                client.release().await;
+                return ytdlp_fallback(&url, &ytdlp, order, ytdlp_error.as_deref(), "❌ yt-dlp fallback also failed").await;
+            }
+            
+            // Save a screenshot for debugging purposes
+            if client.screenshot().await.map(|png_data| {
+                std::fs::write(format!("{}/debug_screenshot.png", folder_name), png_data)
+            }).is_ok() {
+                println!("📷 Saved debug screenshot");
+            }
+            
+            client.release().await;
+            let mut known_sources = vec![(file.path.clone(), video_src.clone())];
+            let mut cover_note = String::new();
+            if let Some(cover_url) = cover_url {
+                let cover_path = format!("{}/{}", folder_name, COVER_FILE);
+                match download_media_with_retry(&reqwest_client, &limiter, &cover_url, &cover_path, max_retries, false).await {
+                    Ok(cover) => {
+                        cover_note = format!(" (cover: {})", cover.path);
+                        known_sources.push((cover.path, cover_url));
+                    }
+                    Err(e) => println!("⚠️ Failed to download the cover image: {}", e),
+                }
+            } else if include_cover {
+                println!("⚠️ No cover image found on the page");
+            }
+            write_manifest(&folder_name, &url, "reel", &known_sources).await;
+            extraction_metrics().record(Method::Browser, true);
+            let dedup_note = if file.deduped { " (deduplicated)" } else { "" };
+            DownloadResult::Done {
+                message: format!("🎉 Download complete: {}{}{}", file.path, dedup_note, cover_note),
+                deduped: file.deduped,
+            }
+        },
+        Err(e) if e.is_disk_full() => {
+            // yt-dlp would write to the same full disk
+            client.release().await;
+            extraction_metrics().record(Method::Browser, false);
+            extraction_metrics().record_all_failed();
+            DownloadResult::DiskFull(e.to_string())
+        }
+        Err(e) => {
+            client.release().await;
+            println!("❌ Download failed: {}. Trying yt-dlp fallback...", e);
+            ytdlp_fallback(&url, &ytdlp, order, ytdlp_error.as_deref(), "❌ yt-dlp fallback also failed").await
         }
-        return Json(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'", folder_name));
+    }
+}
+
+// Open the reel with the caller's cookies, or those of the browser profile they named, and
+// look for a direct (non-blob:) video URL, polling the page for up to 10s
+async fn extract_video_src(client: &fantoccini::Client, url: &str, cookies: Option<&str>, cookie_browser: Option<&str>) -> Option<String> {
+    if let Err(e) = apply_session_cookies(client, cookies, cookie_browser).await {
+        println!("⚠️ {}", e);
+    }
+
+    println!("📲 Opening Instagram URL: {}", url);
+    if let Err(e) = goto_with_timeout(client, url).await {
+        println!("❌ Navigation error: {}", e);
+        return None;
     }
     
     // Dynamic waiting and extraction with multiple methods
@@ -132,36 +270,23 @@ pub async fn download(Json(request): Json<ReelDownloadRequest>) -> Json<String>
         }
         
         // Method 3: JSON-LD metadata extraction
+        // Pages often carry several LD+JSON blocks and the video one isn't necessarily first
         let json_ld_result = client
             .execute(
                 r#"
-                try {
-                    const script = document.querySelector('script[type="application/ld+json"]');
-                    if (script) {
-                        const json = JSON.parse(script.innerText);
-                        if (json.contentUrl) {
-                            return json.contentUrl;
-                        } else if (json.video && json.video.contentUrl) {
-                            return json.video.contentUrl;
-                        }
-                    }
-                    return null;
-                } catch (e) {
-                    console.error("Error parsing JSON-LD:", e);
-                    return null;
-                }
+                return Array.from(document.querySelectorAll('script[type="application/ld+json"]'))
+                    .map(script => script.textContent);
                 "#,
                 vec![],
             )
             .await;
             
         if let Ok(val) = json_ld_result {
-            if let Some(src) = val.as_str() {
-                if !src.is_empty() && !src.starts_with("blob:") {
-                    video_src = src.to_string();
-                    println!("✅ Found video URL in JSON-LD: {}", video_src);
-                    break;
-                }
+            let blocks: Vec<String> = serde_json::from_value(val).unwrap_or_default();
+            if let Some(src) = json_ld_video_url(&blocks) {
+                video_src = src;
+                println!("✅ Found video URL in JSON-LD: {}", video_src);
+                break;
             }
         }
         
@@ -186,58 +311,265 @@ pub async fn download(Json(request): Json<ReelDownloadRequest>) -> Json<String>
             }
         }
         
+        // Method 5: Resource Timing. With a blob: src the player still fetched the real
+        // CDN file (or segments of it), and those requests are listed here
+        let resources_result = client
+            .execute(
+                r#"
+                return performance.getEntriesByType('resource')
+                    .map(entry => entry.name)
+                    .filter(name => name.includes('.mp4'));
+                "#,
+                vec![],
+            )
+            .await;
+
+        if let Ok(val) = resources_result {
+            let resources: Vec<String> = serde_json::from_value(val).unwrap_or_default();
+            if let Some(src) = first_mp4_resource(&resources) {
+                video_src = src;
+                println!("✅ Found video URL in resource timing: {}", video_src);
+                break;
+            }
+        }
+
         attempts += 1;
         sleep(Duration::from_millis(500)).await;
     }
 
+    // JSON-LD and og:video may hold protocol-relative or relative URLs
+    if let Some(absolute) = absolutize_media_url(&video_src) {
+        video_src = absolute;
+    }
     if video_src.is_empty() || video_src.starts_with("blob:") {
-        let _ = client.close().await;
-        println!("⚠️ Direct video URL not available. Using yt-dlp fallback...");
-        if let Err(e) = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await {
-            return Json(format!("❌ yt-dlp failed: {}", e));
-        }
-        return Json(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'", folder_name));
+        None
     } else {
-        let output_path = format!("{}/reel.mp4", folder_name);
-        println!("✅ Found video URL: {}\n⬇️ Downloading to {}", video_src, output_path);
-        let reqwest_client = Client::builder()
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/96.0.4664.110 Safari/537.36")
-            .timeout(Duration::from_secs(30))
-            .build()
-            .unwrap();
-        match download_media_with_retry(&reqwest_client, &video_src, &output_path).await {
-            Ok(_) => {
-                // Verify the file size to make sure it's not just a thumbnail
-                if let Ok(metadata) = std::fs::metadata(&output_path) {
-                    if metadata.len() < 200_000 { // Less than 200KB, likely a thumbnail
-                        println!("⚠️ Downloaded file is too small ({}KB), likely a thumbnail. Falling back to yt-dlp...", 
-                                metadata.len() / 1024);
-                        let _ = client.close().await;
-                        if let Err(e) = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await {
-                            return Json(format!("❌ yt-dlp fallback also failed: {}", e));
-                        }
-                        return Json(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'", folder_name));
-                    }
-                }
-                
-                // Save a screenshot for debugging purposes
-                if let Ok(_) = client.screenshot().await.map(|png_data| {
-                    std::fs::write(format!("{}/debug_screenshot.png", folder_name), png_data)
-                }) {
-                    println!("📷 Saved debug screenshot");
-                }
-                
-                let _ = client.close().await;
-                return Json(format!("🎉 Download complete: {}", output_path));
-            },
-            Err(e) => {
-                let _ = client.close().await;
-                println!("❌ Download failed: {}. Trying yt-dlp fallback...", e);
-                if let Err(e) = download_with_ytdlp(&url, Some(&folder_name), Some(&browser), false).await {
-                    return Json(format!("❌ yt-dlp fallback also failed: {}", e));
+        Some(video_src)
+    }
+}
+
+// How a race between yt-dlp and browser extraction ended
+enum RaceOutcome {
+    /// yt-dlp downloaded the reel first
+    Ytdlp(Vec<PathBuf>),
+    /// The browser found the video URL, first or after yt-dlp failed with `ytdlp_error`
+    Browser { video_src: String, ytdlp_error: Option<String> },
+    /// Neither worked; the message says why
+    Failed(String),
+}
+
+// The first video URL in the page's LD+JSON blocks. Pages often carry several and the video
+// one isn't necessarily first; a block may also hold an array of objects.
+fn json_ld_video_url(blocks: &[String]) -> Option<String> {
+    blocks
+        .iter()
+        .filter_map(|block| serde_json::from_str::<serde_json::Value>(block).ok())
+        .flat_map(|parsed| match parsed {
+            serde_json::Value::Array(items) => items,
+            item => vec![item],
+        })
+        .find_map(|item| {
+            let url = item.get("contentUrl").or_else(|| item.get("video")?.get("contentUrl"))?.as_str()?;
+            (!url.is_empty() && !url.starts_with("blob:")).then(|| url.to_string())
+        })
+}
+
+// Run yt-dlp and browser extraction side by side and take whichever succeeds first. When the
+// browser wins, yt-dlp is killed (its child is started with kill_on_drop) and any files it had
+// started writing are removed; when yt-dlp wins, the caller closes the browser session.
+async fn race_extraction(
+    client: &fantoccini::Client,
+    url: &str,
+    cookies: Option<&str>,
+    cookie_browser: Option<&str>,
+    ytdlp: &YtDlpOptions<'_>,
+    folder: &str,
+) -> RaceOutcome {
+    println!("🏁 Racing yt-dlp against browser extraction...");
+    let options = YtDlpOptions { is_story: true, ..*ytdlp };
+    race(download_with_ytdlp(url, &options), extract_video_src(client, url, cookies, cookie_browser), folder).await
+}
+
+// The race itself, over any yt-dlp run writing into `folder` and any browser lookup
+async fn race(
+    ytdlp_run: impl Future<Output = Result<Vec<PathBuf>, DownloadError>>,
+    browser_run: impl Future<Output = Option<String>>,
+    folder: &str,
+) -> RaceOutcome {
+    let metrics = extraction_metrics();
+    let files_before = folder_entries(folder);
+    let mut ytdlp_run = Box::pin(ytdlp_run);
+    let mut browser_run = Box::pin(browser_run);
+    let first = tokio::select! {
+        downloaded = &mut ytdlp_run => Ok(downloaded),
+        found = &mut browser_run => Err(found),
+    };
+    match first {
+        Ok(Ok(paths)) => {
+            metrics.record(Method::Ytdlp, true);
+            println!("🏁 yt-dlp finished first, dropping browser extraction");
+            RaceOutcome::Ytdlp(paths)
+        }
+        Ok(Err(e)) => {
+            metrics.record(Method::Ytdlp, false);
+            println!("⚠️ yt-dlp failed ({}), waiting for browser extraction...", e);
+            match browser_run.await {
+                Some(video_src) => RaceOutcome::Browser { video_src, ytdlp_error: Some(e.to_string()) },
+                None => {
+                    metrics.record(Method::Browser, false);
+                    RaceOutcome::Failed(format!("{}; no direct video URL found in the browser", e))
                 }
-                return Json(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'", folder_name));
             }
         }
+        Err(Some(video_src)) => {
+            drop(ytdlp_run);
+            remove_new_entries(folder, &files_before);
+            println!("🏁 Browser extraction finished first, stopped yt-dlp");
+            RaceOutcome::Browser { video_src, ytdlp_error: None }
+        }
+        Err(None) => {
+            metrics.record(Method::Browser, false);
+            println!("⚠️ No direct video URL found in the browser, waiting for yt-dlp...");
+            let downloaded = ytdlp_run.await;
+            metrics.record(Method::Ytdlp, downloaded.is_ok());
+            match downloaded {
+                Ok(paths) => RaceOutcome::Ytdlp(paths),
+                Err(e) => RaceOutcome::Failed(format!("{}; no direct video URL found in the browser", e)),
+            }
+        }
+    }
+}
+
+// Every file currently in `folder`
+fn folder_entries(folder: &str) -> HashSet<PathBuf> {
+    std::fs::read_dir(folder)
+        .map(|entries| entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect())
+        .unwrap_or_default()
+}
+
+// Remove what a cancelled yt-dlp run left in `folder` (partial downloads, fragments, its cover)
+fn remove_new_entries(folder: &str, before: &HashSet<PathBuf>) {
+    for path in folder_entries(folder).difference(before) {
+        if path.is_file() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Last resort once browser extraction failed: yt-dlp, unless the caller disabled it or it
+// already failed with `ytdlp_error` earlier in the request
+async fn ytdlp_fallback(url: &str, ytdlp: &YtDlpOptions<'_>, order: DownloadOrder, ytdlp_error: Option<&str>, failure: &str) -> DownloadResult {
+    let metrics = extraction_metrics();
+    metrics.record(Method::Browser, false);
+    if !order.ytdlp_allowed() {
+        metrics.record_all_failed();
+        return DownloadResult::Failed("❌ Browser extraction failed and yt-dlp is disabled for this request".to_string());
+    }
+    if let Some(e) = ytdlp_error {
+        metrics.record_all_failed();
+        return DownloadResult::Failed(format!("❌ Browser download failed and yt-dlp had already failed: {}", e));
+    }
+    let folder = ytdlp.folder.unwrap_or(".");
+    let downloaded = download_with_ytdlp(url, ytdlp).await;
+    metrics.record(Method::Ytdlp, downloaded.is_ok());
+    match downloaded {
+        Ok(paths) => {
+            write_manifest(folder, url, "reel", &[]).await;
+            DownloadResult::done(format!("✅ Reel downloaded with yt-dlp. Saved to '{}'{}", saved_location(&paths, folder), saved_cover_note(folder)))
+        }
+        Err(e) => {
+            metrics.record_all_failed();
+            DownloadResult::Failed(format!("{}: {}", failure, e))
+        }
+    }
+}
+
+// " (cover: <path>)" when yt-dlp saved the reel's thumbnail as cover.jpg
+fn saved_cover_note(folder: &str) -> String {
+    let path = Path::new(folder).join(COVER_FILE);
+    if path.is_file() {
+        format!(" (cover: {})", path.display())
+    } else {
+        String::new()
+    }
+}
+
+// The file yt-dlp reported saving, or the job folder when it didn't report one
+fn saved_location(paths: &[PathBuf], folder: &str) -> String {
+    paths.first().map(|path| path.display().to_string()).unwrap_or_else(|| folder.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // Flags when the future holding it is dropped, the way kill_on_drop stops yt-dlp
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn browser_win_aborts_ytdlp_and_removes_its_files() {
+        let folder = create_job_folder("reel_race_test").unwrap();
+        std::fs::write(format!("{}/metadata.txt", folder), "kept").unwrap();
+        let aborted = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(aborted.clone());
+        let partial = format!("{}/reel.mp4.part", folder);
+        let ytdlp_run = async move {
+            let _flag = flag;
+            std::fs::write(&partial, b"partial").unwrap();
+            sleep(Duration::from_secs(30)).await;
+            Ok(vec![])
+        };
+        let browser_run = async {
+            sleep(Duration::from_millis(50)).await;
+            Some("https://scontent.cdninstagram.com/reel.mp4".to_string())
+        };
+
+        let outcome = race(ytdlp_run, browser_run, &folder).await;
+        assert!(matches!(
+            outcome,
+            RaceOutcome::Browser { ref video_src, ytdlp_error: None } if video_src.ends_with("reel.mp4")
+        ));
+        assert!(aborted.load(Ordering::SeqCst));
+        assert!(!Path::new(&folder).join("reel.mp4.part").exists());
+        assert!(Path::new(&folder).join("metadata.txt").exists());
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn browser_win_after_ytdlp_failed_carries_the_error() {
+        let folder = create_job_folder("reel_race_test").unwrap();
+        let ytdlp_run = async { Err(DownloadError("yt-dlp exited with 1".to_string())) };
+        let browser_run = async {
+            sleep(Duration::from_millis(20)).await;
+            Some("https://scontent.cdninstagram.com/reel.mp4".to_string())
+        };
+        let outcome = race(ytdlp_run, browser_run, &folder).await;
+        assert!(matches!(outcome, RaceOutcome::Browser { ytdlp_error: Some(ref e), .. } if e.contains("exited with 1")));
+
+        let options = YtDlpOptions { folder: Some(&folder), ..Default::default() };
+        let result = ytdlp_fallback("https://www.instagram.com/reel/abc/", &options, DownloadOrder::Race, Some("yt-dlp exited with 1"), "❌ yt-dlp fallback also failed").await;
+        assert!(matches!(result, DownloadResult::Failed(ref e) if e.contains("had already failed")));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn video_url_is_found_in_a_later_json_ld_block() {
+        let blocks = [
+            r#"{"@type": "BreadcrumbList", "itemListElement": []}"#.to_string(),
+            "not json".to_string(),
+            r#"[{"@type": "Person"}, {"@type": "VideoObject", "video": {"contentUrl": "https://cdn/reel.mp4"}}]"#.to_string(),
+            r#"{"@type": "VideoObject", "contentUrl": "https://cdn/other.mp4"}"#.to_string(),
+        ];
+        assert_eq!(json_ld_video_url(&blocks).as_deref(), Some("https://cdn/reel.mp4"));
+        assert_eq!(json_ld_video_url(&blocks[..2]), None);
+        assert_eq!(json_ld_video_url(&[r#"{"contentUrl": "blob:https://www.instagram.com/x"}"#.to_string()]), None);
     }
 }
@@ -1,126 +1,358 @@
 use axum::extract::Json;
 use serde::{Deserialize};
-use chrono::Utc;
-use std::fs::{create_dir_all, File};
+use std::fs::File;
 use std::io::Write;
-use reqwest::Client;
-use crate::services::downloader::{download_media_with_retry, download_with_ytdlp};
-use crate::services::extractor::{create_browser_client, extract_stories};
+use crate::config::Config;
+use crate::services::manifest::write_manifest;
+use crate::services::metrics::{extraction_metrics, Method};
+use crate::utils::job::{create_job_folder, parse_output_name};
+use crate::utils::url_guard::expand_story_username;
+use crate::services::outcome::{DownloadResult, ExtractionOutcome};
+use crate::services::downloader::{convert_or_keep, download_media_with_retry, download_with_ytdlp, media_client_builder, parse_max_retries, DownloadLimiter, DownloadOrder, ImageConversion, YtDlpOptions, extension_for, DISK_FULL};
+use crate::services::browser_pool::checkout_browser_client;
+use crate::services::extractor::{apply_session_cookies, extract_stories, filter_media_by_type, goto_with_timeout, parse_media_filter, story_id_from_url};
 use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use std::future::Future;
 use tokio::sync::Semaphore;
+use tokio::task::JoinError;
 use std::sync::Arc;
 
+// Default number of concurrent story downloads
+const DEFAULT_STORY_CONCURRENCY: u32 = 8;
+// Files at least this large count as "large" and take half of the download slots
+const LARGE_STORY_BYTES: u64 = 10 * 1024 * 1024;
+
+// Parse STORY_CONCURRENCY, falling back to the default on missing or invalid values
+pub fn parse_story_concurrency(value: Option<&str>) -> u32 {
+    value
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_STORY_CONCURRENCY)
+}
+
+// Semaphore permits a download needs: large files take half the capacity so at most two run at once
+fn permits_for_size(content_length: Option<u64>, capacity: u32) -> u32 {
+    match content_length {
+        Some(len) if len >= LARGE_STORY_BYTES => (capacity / 2).max(1),
+        _ => 1,
+    }
+}
+
+// Spawn `download(index, item)` for each `(item, size)`, holding `permits_for_size` of the
+// `capacity` slots while it runs. Results come back in item order.
+async fn download_by_size<T, R, Fut>(
+    items: Vec<(T, Option<u64>)>,
+    capacity: u32,
+    mut download: impl FnMut(usize, T) -> Fut,
+) -> Vec<Result<R, JoinError>>
+where
+    Fut: Future<Output = R> + Send + 'static,
+    R: Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(capacity as usize));
+    let mut tasks = Vec::new();
+    for (i, (item, size)) in items.into_iter().enumerate() {
+        let permit = Arc::clone(&semaphore).acquire_many_owned(permits_for_size(size, capacity)).await.unwrap();
+        let task = download(i, item);
+        tasks.push(tokio::spawn(async move {
+            let result = task.await;
+            drop(permit);
+            result
+        }));
+    }
+    join_all(tasks).await
+}
+
 #[derive(Deserialize)]
 pub struct StoryDownloadRequest {
     pub url: String,
     pub browser: Option<String>,
+    /// Session cookies supplied by the caller (Netscape file or `name=value; ...` header form)
+    pub cookies: Option<String>,
+    /// Only download the first N stories of the active set
+    pub limit: Option<usize>,
+    /// `false` tries browser extraction before yt-dlp
+    pub use_ytdlp_first: Option<bool>,
+    /// `true` never runs yt-dlp
+    pub disable_ytdlp: Option<bool>,
+    /// Only keep media of this type (`"video"` or `"image"`)
+    pub only: Option<String>,
+    /// Re-encode downloaded images as `"jpg"` or `"png"`
+    pub convert_to: Option<String>,
+    /// JPEG quality (1 to 100) for `convert_to: "jpg"`, 85 by default
+    pub quality: Option<u64>,
+    /// Prefix for the downloaded files (`{filename}_001.jpg`, ...) instead of `story`
+    pub filename: Option<String>,
+    /// Download attempts per file (1 to 10) instead of the default 5
+    pub max_retries: Option<usize>,
+    /// Remove EXIF and other metadata from downloaded JPEG images
+    pub strip_metadata: Option<bool>,
+    /// For a `/stories/{username}/{story_id}/` URL, download the user's whole story set
+    /// instead of just that story
+    pub all_stories: Option<bool>,
 }
 
-pub async fn download(Json(request): Json<StoryDownloadRequest>) -> Json<String> {
-    let url = request.url;
+// Where downloaded stories are saved and under which names
+#[derive(Clone, Copy)]
+struct StoryOutput<'a> {
+    folder_name: &'a str,
+    // Files are named `{file_prefix}_001.mp4`, `{file_prefix}_002.jpg`, ...
+    file_prefix: &'a str,
+    convert_to: Option<ImageConversion>,
+    // Download attempts per file; the downloader's default when `None`
+    max_retries: Option<usize>,
+    strip_metadata: bool,
+    // Server-wide download slots shared with every other request
+    limiter: &'a DownloadLimiter,
+}
+
+pub async fn download(limiter: DownloadLimiter, Json(request): Json<StoryDownloadRequest>) -> DownloadResult {
+    // Accept a bare username or @username as well as a stories URL
+    let url = match expand_story_username(&request.url) {
+        Ok(url) => url,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    // Only a browser the caller named explicitly lends its profile cookies to the session
+    let cookie_browser = request.browser.clone();
     let browser = request.browser.unwrap_or_else(|| "chrome".to_string());
-    let timestamp = Utc::now().timestamp();
-    let folder_name = format!("insta_stories_{}", timestamp);
+    let cookies = request.cookies;
+    if request.limit == Some(0) {
+        return DownloadResult::Failed("❌ limit must be greater than 0".to_string());
+    }
+    let only = match parse_media_filter(request.only.as_deref()) {
+        Ok(only) => only,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let convert_to = match ImageConversion::parse(request.convert_to.as_deref(), request.quality) {
+        Ok(convert_to) => convert_to,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let output_name = match parse_output_name(request.filename.as_deref()) {
+        Ok(output_name) => output_name,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    let max_retries = match parse_max_retries(request.max_retries) {
+        Ok(max_retries) => max_retries,
+        Err(e) => return DownloadResult::Failed(format!("❌ {}", e)),
+    };
+    // A story id in the URL asks for just that story unless the whole set was requested,
+    // which is fetched from the plain /stories/{username}/ URL
+    let url = match story_id_from_url(&url) {
+        Some(_) if request.all_stories.unwrap_or(false) => {
+            format!("https://www.instagram.com/stories/{}/", story_username(&url))
+        }
+        _ => url,
+    };
+    let single_story = story_id_from_url(&url).is_some();
+    let file_prefix = output_name.as_deref().unwrap_or("story");
+    // Numbered like the browser downloads; only used when a filename was requested
+    let ytdlp_name = output_name.as_ref().map(|name| format!("{}_%(autonumber)03d", name));
+    let strip_metadata = request.strip_metadata.unwrap_or(false);
+    // yt-dlp can't filter by media type, convert images or strip their metadata, so those
+    // requests stay in the browser, as do single stories since yt-dlp fetches the whole set
+    let order = if only.is_some() || convert_to.is_some() || strip_metadata || single_story {
+        DownloadOrder::BrowserOnly
+    } else {
+        DownloadOrder::from_flags(request.use_ytdlp_first, request.disable_ytdlp)
+    };
+    let folder_name = match create_job_folder("stories") {
+        Ok(folder_name) => folder_name,
+        Err(e) => return DownloadResult::Failed(format!("❌ Failed to create folder: {}", e)),
+    };
+    let ytdlp = YtDlpOptions {
+        folder: Some(&folder_name),
+        browser: Some(&browser),
+        cookies: cookies.as_deref(),
+        max_items: request.limit,
+        output_name: ytdlp_name.as_deref(),
+        ..Default::default()
+    };
+
+    let username = story_username(&url);
 
-    if let Err(e) = create_dir_all(&folder_name) {
-        return Json(format!("❌ Failed to create folder: {}", e));
+    // yt-dlp is tried first for best reliability and speed unless the caller opted out
+    if order.ytdlp_first() {
+        println!("🔍 Attempting to download stories with yt-dlp first...");
+        if let Some(message) = download_stories_with_ytdlp(&url, username, &ytdlp).await {
+            return DownloadResult::done(message);
+        }
+        println!("⚠️ Trying browser extraction...");
     }
 
-    let username = url.split("/stories/")
+    let output = StoryOutput { folder_name: &folder_name, file_prefix, convert_to, max_retries, strip_metadata, limiter: &limiter };
+    let downloaded = download_stories_with_browser(&url, &browser, cookies.as_deref(), cookie_browser.as_deref(), request.limit, only, output).await;
+    extraction_metrics().record(Method::Browser, downloaded.is_done());
+    // yt-dlp would write to the same full disk, so only a plain failure falls back
+    let downloaded = match downloaded {
+        DownloadResult::Failed(message) if order == DownloadOrder::BrowserFirst => {
+            println!("⚠️ Browser extraction failed ({}). Trying yt-dlp...", message);
+            match download_stories_with_ytdlp(&url, username, &ytdlp).await {
+                Some(message) => DownloadResult::done(message),
+                None => DownloadResult::Failed(message),
+            }
+        }
+        downloaded => downloaded,
+    };
+    if !downloaded.is_done() {
+        extraction_metrics().record_all_failed();
+    }
+    downloaded
+}
+
+// The account name in a /stories/<username>/ URL
+fn story_username(url: &str) -> &str {
+    url.split("/stories/")
         .nth(1)
         .and_then(|s| s.split('/').next())
-        .unwrap_or("unknown");
-
-    // Always try yt-dlp first for best reliability and speed
-    println!("🔍 Attempting to download stories with yt-dlp first...");
-    match download_with_ytdlp(&url, Some(&folder_name), Some(&browser), true).await {
-        Ok(_) => {
-            if let Ok(entries) = std::fs::read_dir(&folder_name) {
-                let story_count = entries
-                    .filter(|entry| {
-                        if let Ok(entry) = entry {
-                            if let Some(name) = entry.file_name().to_str() {
-                                return name.starts_with("story_");
-                            }
-                        }
-                        false
-                    })
-                    .count();
-                if story_count > 0 {
-                    if let Ok(mut file) = File::create(format!("{}/metadata.txt", folder_name)) {
-                        let _ = writeln!(file, "Downloaded from: {}", url);
-                        let _ = writeln!(file, "User: {}", username);
-                        let _ = writeln!(file, "Stories downloaded: {}", story_count);
-                        let _ = writeln!(file, "Downloaded at: {}", chrono::Local::now());
-                    }
-                    return Json(format!("✅ Downloaded {} stories with yt-dlp. Saved to '{}'", story_count, folder_name));
+        .unwrap_or("unknown")
+}
+
+// Download the stories with yt-dlp, returning the success message if any story was saved
+async fn download_stories_with_ytdlp(url: &str, username: &str, ytdlp: &YtDlpOptions<'_>) -> Option<String> {
+    let folder_name = ytdlp.folder.unwrap_or(".");
+    match download_with_ytdlp(url, &YtDlpOptions { is_story: true, ..*ytdlp }).await {
+        Ok(paths) => {
+            let story_count = paths.len();
+            if story_count > 0 {
+                if let Ok(mut file) = File::create(format!("{}/metadata.txt", folder_name)) {
+                    let _ = writeln!(file, "Downloaded from: {}", url);
+                    let _ = writeln!(file, "User: {}", username);
+                    let _ = writeln!(file, "Stories downloaded: {}", story_count);
+                    let _ = writeln!(file, "Downloaded at: {}", chrono::Local::now());
                 }
+                write_manifest(folder_name, url, "story", &[]).await;
+                extraction_metrics().record(Method::Ytdlp, true);
+                return Some(format!("✅ Downloaded {} stories with yt-dlp. Saved to '{}'", story_count, folder_name));
             }
-            println!("⚠️ yt-dlp didn't download any stories. Trying browser extraction...");
+            println!("⚠️ yt-dlp didn't download any stories.");
         }
         Err(_) => {
-            println!("⚠️ yt-dlp failed. Trying browser extraction...");
+            println!("⚠️ yt-dlp failed.");
         }
     }
+    extraction_metrics().record(Method::Ytdlp, false);
+    None
+}
 
-    // Fallback: browser-based extraction if yt-dlp fails
-    let mut client = match create_browser_client(&browser).await {
+// Extract the stories in the browser and download them directly
+async fn download_stories_with_browser(
+    url: &str,
+    browser: &str,
+    cookies: Option<&str>,
+    cookie_browser: Option<&str>,
+    limit: Option<usize>,
+    only: Option<&str>,
+    output: StoryOutput<'_>,
+) -> DownloadResult {
+    let StoryOutput { folder_name, file_prefix, convert_to, max_retries, strip_metadata, limiter } = output;
+    let username = story_username(url);
+    let mut client = match checkout_browser_client(browser).await {
         Ok(c) => c,
         Err(e) => {
-            return Json(format!("❌ Failed to connect to browser: {}", e));
+            return DownloadResult::Failed(format!("❌ Failed to connect to browser: {}", e));
         }
     };
 
-    if let Err(e) = client.goto(&url).await {
-        return Json(format!("❌ Failed to navigate to URL: {}", e));
+    if let Err(e) = apply_session_cookies(&client, cookies, cookie_browser).await {
+        println!("⚠️ {}", e);
     }
 
-    let stories = match extract_stories(&mut client).await {
+    if let Err(e) = goto_with_timeout(&client, url).await {
+        client.release().await;
+        return DownloadResult::Failed(format!("❌ Failed to navigate to URL: {}", e));
+    }
+
+    let extracted = extract_stories(&mut client).await;
+    client.release().await;
+    let stories = match extracted {
         Ok(media_items) => media_items,
         Err(e) => {
-            return Json(format!("❌ Failed to extract stories: {}", e));
+            return DownloadResult::Failed(format!("❌ Failed to extract stories: {}", e));
         }
     };
-    let _ = client.close().await;
 
     if stories.is_empty() {
-        return Json(format!("❌ No stories found at URL: {}", url));
+        return DownloadResult::Failed(format!("❌ The story viewer opened but had no stories to download at URL: {}", url));
+    }
+    // The viewer opens on the story a /stories/{username}/{story_id}/ URL names, so it is the
+    // first item; that story alone is kept, whatever its type
+    let stories = match story_id_from_url(url) {
+        Some(story_id) => {
+            println!("🎯 Keeping only story {}", story_id);
+            stories.into_iter().take(1).collect()
+        }
+        None => stories,
+    };
+    let mut stories = filter_media_by_type(stories, only);
+    if let (Some(only), true) = (only, stories.is_empty()) {
+        return DownloadResult::Failed(format!("❌ No media of requested type '{}' in the active stories", only));
+    }
+    if let Some(limit) = limit {
+        stories.truncate(limit);
     }
 
     println!("✅ Found {} story items to download", stories.len());
-    let reqwest_client = match Client::builder()
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/96.0.4664.110 Safari/537.36")
+    let reqwest_client = match media_client_builder()
         .timeout(std::time::Duration::from_secs(30))
         .build() {
         Ok(client) => client,
-        Err(e) => return Json(format!("❌ Failed to create HTTP client: {}", e))
+        Err(e) => return DownloadResult::Failed(format!("❌ Failed to create HTTP client: {}", e))
     };
 
-    let semaphore = Arc::new(Semaphore::new(8)); // Increased concurrency for speed
-    let mut download_tasks = Vec::new();
-    for (i, (media_url, media_type)) in stories.iter().enumerate() {
-        let extension = if media_type == "video" { "mp4" } else { "jpg" };
-        let filename = format!("{}/story_{:03}.{}", folder_name, i + 1, extension);
-        let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+    // Probe sizes up front so large videos can take a bigger share of the download slots
+    let concurrency = Config::current().story_concurrency;
+    // The probes are requests too, so no more of them run at once than downloads would. They
+    // stay in story order to be paired back with the stories
+    let media_urls: Vec<String> = stories.iter().map(|(media_url, _)| media_url.clone()).collect();
+    let sizes: Vec<Option<u64>> = stream::iter(media_urls)
+        .map(|media_url| {
+            let reqwest_client = reqwest_client.clone();
+            async move {
+                reqwest_client.head(&media_url).send().await
+                    .ok()
+                    .and_then(|resp| resp.content_length())
+            }
+        })
+        .buffered(concurrency as usize)
+        .collect()
+        .await;
+
+    let sized_stories = stories.iter().cloned().zip(sizes).collect();
+    let results = download_by_size(sized_stories, concurrency, |i, (media_url, media_type)| {
+        let extension = if media_type == "video" { "mp4" } else { extension_for(&media_url, None) };
+        let filename = format!("{}/{}_{:03}.{}", folder_name, file_prefix, i + 1, extension);
         let reqwest_client = reqwest_client.clone();
-        let media_url = media_url.clone();
-        let task = tokio::spawn(async move {
-            let result = download_media_with_retry(&reqwest_client, &media_url, &filename).await;
-            drop(permit);
-            (filename, result)
-        });
-        download_tasks.push(task);
-    }
-    let results = join_all(download_tasks).await;
+        let limiter = limiter.clone();
+        let is_image = media_type == "image";
+        async move {
+            let result = match download_media_with_retry(&reqwest_client, &limiter, &media_url, &filename, max_retries, strip_metadata && is_image).await {
+                Ok(file) => match convert_to.filter(|_| is_image) {
+                    Some(to) => Ok(convert_or_keep(file, to).await),
+                    None => Ok(file),
+                },
+                Err(e) => Err(e),
+            };
+            (filename, media_url, result)
+        }
+    }).await;
     let mut success_count = 0;
+    let mut deduped_count = 0;
+    let mut known_sources = Vec::new();
+    let mut disk_full = false;
     for result in results {
         match result {
-            Ok((filename, Ok(_))) => {
+            Ok((filename, media_url, Ok(file))) => {
                 println!("⬇️ Downloaded: {}", filename);
                 success_count += 1;
+                if file.deduped {
+                    deduped_count += 1;
+                }
+                known_sources.push((file.path, media_url));
             },
-            Ok((filename, Err(e))) => {
+            Ok((filename, _, Err(e))) => {
                 println!("❌ Failed to download {}: {}", filename, e);
+                disk_full |= e.is_disk_full();
             },
             Err(e) => {
                 println!("❌ Download task failed: {}", e);
@@ -134,9 +366,52 @@ pub async fn download(Json(request): Json<StoryDownloadRequest>) -> Json<String>
         let _ = writeln!(file, "Stories successfully downloaded: {}", success_count);
         let _ = writeln!(file, "Downloaded at: {}", chrono::Local::now());
     }
-    if success_count > 0 {
-        Json(format!("✅ Downloaded {}/{} stories. Saved to '{}'", success_count, stories.len(), folder_name))
+    let outcome = ExtractionOutcome::classify(success_count, stories.len(), false, None);
+    if outcome.is_success() {
+        write_manifest(folder_name, url, "story", &known_sources).await;
+        let dedup_note = if deduped_count > 0 { format!(" ({} deduplicated)", deduped_count) } else { String::new() };
+        DownloadResult::Done {
+            message: format!("{} Downloaded {}/{} stories{}. Saved to '{}'", outcome.emoji(), success_count, stories.len(), dedup_note, folder_name),
+            deduped: deduped_count > 0,
+        }
+    } else if disk_full {
+        DownloadResult::DiskFull(DISK_FULL.to_string())
     } else {
-        Json(format!("❌ Failed to download any stories. Check logs for details."))
+        DownloadResult::Failed("❌ Failed to download any stories. Check logs for details.".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Most downloads `download_by_size` runs at once for items of these sizes
+    async fn peak_concurrency(sizes: &[Option<u64>], capacity: u32) -> usize {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let items = sizes.iter().map(|&size| ((), size)).collect();
+        let results = download_by_size(items, capacity, |i, ()| {
+            let (running, peak) = (running.clone(), peak.clone());
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                i
+            }
+        }).await;
+        let order: Vec<usize> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(order, (0..sizes.len()).collect::<Vec<_>>());
+        peak.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn large_stories_take_half_the_download_slots() {
+        let large = Some(LARGE_STORY_BYTES);
+        assert_eq!(peak_concurrency(&[large; 6], 8).await, 2);
+        assert_eq!(peak_concurrency(&[Some(1024); 8], 8).await, 8);
+        assert_eq!(peak_concurrency(&[None; 3], 8).await, 3);
+        assert_eq!(permits_for_size(large, 1), 1);
     }
 }
@@ -1,21 +1,27 @@
 use axum::{Router, routing::get, response::Html};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::{self, TraceLayer};
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use http::header::{AUTHORIZATION, CONTENT_TYPE};
 use http::Method;
 use tokio::runtime::Builder; // Add for custom runtime
+use tracing_subscriber::EnvFilter;
 use tower::ServiceBuilder;
-use tower_http::timeout::TimeoutLayer;
-use std::time::Duration;
 
+mod config;
+mod state;
 mod routes;
 mod handlers;
 mod services;
 mod utils;
 
+use config::{Config, LogFormat};
+use state::AppState;
+
 // Root handler that returns a basic HTML page with API status
 async fn root_handler() -> Html<&'static str> {
     Html("<html><head><title>Instagram Downloader API</title></head><body>
@@ -24,29 +30,80 @@ async fn root_handler() -> Html<&'static str> {
         <p>Available endpoints:</p>
         <ul>
             <li><code>POST /api/preview</code> - Preview Instagram content before downloading</li>
+            <li><code>GET /api/preview?url=...</code> - Same preview with the URL in the query string</li>
+            <li><code>GET /api/validate?url=...</code> - Check a URL and detect its type without fetching anything</li>
             <li><code>POST /api/download</code> - Download Instagram media (reels, stories, posts)</li>
+            <li><code>GET /api/jobs/{job_id}</code> - Status of a download queued with <code>?async=true</code></li>
+            <li><code>POST /api/download/batch</code> - Download several URLs (<code>?format=jsonl</code> streams results)</li>
+            <li><code>POST /api/stream</code> - Stream a single-item post straight from the CDN without saving it</li>
             <li><code>GET /api/media</code> - Proxy for media content</li>
+            <li><code>GET /api/thumbnail?url=...&amp;w=320</code> - Downscaled JPEG preview of an image</li>
+            <li><code>GET /api/files/{job_folder}/{filename}</code> - Retrieve a downloaded file</li>
+            <li><code>DELETE /api/files/{job_folder}</code> - Delete a finished download's files</li>
+            <li><code>GET /api/config</code> - Effective server configuration (secrets redacted)</li>
+            <li><code>GET /api/openapi.json</code> - OpenAPI description of the preview, download and media endpoints</li>
         </ul>
         </body></html>")
 }
 
-// Remove unused function or mark with #[allow(dead_code)]
-#[allow(dead_code)]
-fn build_runtime() -> tokio::runtime::Runtime {
-    // Increase worker threads for high concurrency
+fn build_runtime(worker_threads: usize) -> tokio::runtime::Runtime {
     Builder::new_multi_thread()
-        .worker_threads(8) // Adjust based on your CPU
+        .worker_threads(worker_threads)
         .enable_all()
         .build()
         .expect("Failed to build Tokio runtime")
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
-        .init();
-    info!("Starting Instagram Downloader Service");
+// Set up the global subscriber from LOG_FORMAT and RUST_LOG / LOG_LEVEL
+fn init_logging(config: &Config) {
+    let directives = &config.log_filter;
+    let (filter, invalid) = match EnvFilter::try_new(directives) {
+        Ok(filter) => (filter, None),
+        Err(e) => (EnvFilter::new("info"), Some(e)),
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        LogFormat::Json => builder.json().init(),
+        LogFormat::Pretty => builder.init(),
+    }
+    if let Some(e) = invalid {
+        warn!("Ignoring invalid log filter '{}' ({}), logging at info", directives, e);
+    }
+}
+
+// Every route of the API, before the tracing, CORS and panic layers shared by all of them
+fn app_routes(config: &Config) -> Router<AppState> {
+    Router::new()
+        .route("/", get(root_handler))
+        .merge(routes::download::routes(config))
+        .merge(routes::health::routes())
+        .merge(routes::thumbnail::routes())
+        .merge(routes::config::routes())
+        .merge(routes::openapi::routes())
+        .merge(routes::validate::routes())
+        .merge(routes::jobs::routes())
+        // gzip/br for JSON when the client sends Accept-Encoding. Only covers the routes above;
+        // the media ones below send already-compressed files
+        .layer(CompressionLayer::new())
+        .merge(routes::download::media_routes(config))
+        .merge(routes::files::routes())
+}
+
+fn main() {
+    // Resolved once; handlers read it from the app state, everything else from Config::current()
+    let config = match Config::from_env() {
+        Ok(config) => config.install(),
+        Err(e) => {
+            eprintln!("❌ Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    init_logging(&config);
+    info!("Starting Instagram Downloader Service with {} worker threads", config.worker_threads);
+    build_runtime(config.worker_threads).block_on(run(config));
+}
+
+async fn run(config: Arc<Config>) {
     use std::ffi::OsStr;
     let browser_args: Vec<&OsStr> = vec![
         OsStr::new("--no-sandbox"),
@@ -73,13 +130,27 @@ async fn main() {
     let browser = match headless_chrome::Browser::new(browser_options) {
         Ok(browser) => {
             info!("✅ Browser initialized successfully");
-            Arc::new(browser)
+            Some(Arc::new(browser))
         },
         Err(e) => {
-            eprintln!("❌ Failed to initialize browser: {}", e);
-            std::process::exit(1);
+            // yt-dlp paths don't need it, so keep serving; /api/health reports it as unavailable
+            warn!("⚠️ Failed to initialize browser, continuing without it: {}", e);
+            None
         }
     };
+    if let Err(e) = services::storage::init_storage(&config) {
+        eprintln!("❌ Failed to initialize storage backend: {}", e);
+        std::process::exit(1);
+    }
+    // Open the pooled WebDriver sessions in the background so startup isn't held up by chromedriver
+    if config.browser_pool_size > 0 {
+        tokio::spawn(services::browser_pool::browser_pool().warm());
+    }
+    let state = AppState {
+        browser,
+        download_limiter: services::downloader::DownloadLimiter::new(config.max_global_downloads),
+        config: config.clone(),
+    };
     info!("Initializing API routes...");
     let cors = CorsLayer::new()
         // Fix: Don't use wildcard "*" with credentials
@@ -89,13 +160,11 @@ async fn main() {
             "http://127.0.0.1:5173".parse::<http::HeaderValue>().unwrap(),
             "http://127.0.0.1:3000".parse::<http::HeaderValue>().unwrap(),
         ])
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::HEAD, Method::OPTIONS])
         .allow_headers([AUTHORIZATION, CONTENT_TYPE])
         .allow_credentials(true);
-    let app = Router::new()
-        .route("/", get(root_handler))
-        .merge(routes::download::routes())
-        .merge(routes::health::routes())
+    let addr = SocketAddr::new(config.bind_addr, config.port);
+    let app = app_routes(&config)
         .layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http()
@@ -103,10 +172,10 @@ async fn main() {
                     .on_response(trace::DefaultOnResponse::new().level(Level::INFO))
                 )
                 .layer(cors)
-                .layer(TimeoutLayer::new(Duration::from_secs(30))) // Add request timeout
+                // Panicking handlers answer with a JSON 500 instead of dropping the connection
+                .layer(CatchPanicLayer::custom(utils::error::panic_response))
         )
-        .with_state(browser);
-    let addr = SocketAddr::from(([0, 0, 0, 0], 9090));
+        .with_state(state);
     info!("🚀 Server running at http://{}", addr);
     // Use hyper server with keep-alive and TCP_NODELAY
     let server = axum::Server::bind(&addr)
@@ -118,3 +187,36 @@ async fn main() {
         Err(e) => eprintln!("Server error: {}", e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn json_is_compressed_on_request_but_media_is_not() {
+        let config = Config::current().clone();
+        let state = AppState {
+            browser: None,
+            config: Arc::new(config.clone()),
+            download_limiter: services::downloader::DownloadLimiter::new(1),
+        };
+        let app = app_routes(&config).with_state(state);
+        let gzip_request = |uri: &str| {
+            axum::http::Request::get(uri)
+                .header(http::header::ACCEPT_ENCODING, "gzip")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(gzip_request("/api/health")).await.unwrap();
+        assert_eq!(response.headers()[http::header::CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[http::header::CONTENT_ENCODING], "gzip");
+
+        // The proxy refuses this host with a JSON error, which would be compressed if the
+        // layer reached the media routes
+        let response = app.oneshot(gzip_request("/api/media?url=https://example.com/a.jpg")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+        assert!(response.headers().get(http::header::CONTENT_ENCODING).is_none());
+    }
+}
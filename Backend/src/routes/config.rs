@@ -0,0 +1,14 @@
+use axum::{extract::State, routing::get, Json, Router};
+use std::sync::Arc;
+use crate::config::Config;
+use crate::state::AppState;
+
+// Effective configuration, so operators can check what the running server resolved
+async fn config_handler(State(config): State<Arc<Config>>) -> Json<Config> {
+    Json(config.as_ref().clone())
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/config", get(config_handler))
+}
@@ -1,120 +1,506 @@
 use axum::{
     extract::{Json, Query, State},
-    http::StatusCode,
+    http::{header::{ACCEPT, HOST, LOCATION}, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::{get as axum_get, post as axum_post},
-    Router, body::Body,
+    routing::{get as axum_get, post as axum_post, MethodRouter},
+    Router, body::{boxed, Body, StreamBody},
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use futures::stream::{self, StreamExt};
+use std::convert::Infallible;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use headless_chrome::Browser;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::SemaphorePermit;
+use utoipa::{IntoParams, ToSchema};
 use crate::services::extractor::{
-    create_browser_client, 
-    extract_post_media, 
+    absolutize_media_url,
+    apply_cookies,
+    extract_cover_url,
+    extract_post_media_with_alt_text, 
     extract_stories, 
+    goto_with_timeout,
     extract_media_from_metadata,
     is_story_url, 
     is_reel_url,
+    is_feed_url,
+    FEED_URL_ERROR,
+    is_post_url,
     extract_reel_video_with_headless_chrome,
+    classify_unavailable_page,
+    is_age_gate,
+    is_blob_only,
+    is_webdriver_session_lost,
+    shortcode_from_url,
+    resolve_share_url,
+    parse_media_filter,
+    fetch_web_json,
+    original_images_from_web_json,
+    collab_media_from_web_json,
+    prefer_original_images,
+    prefer_original_videos,
+    original_videos_from_web_json,
+    VideoQuality,
+    reel_audio_from_web_json,
+    post_metadata_from_web_json,
+    PostMetadata,
+    ExtractedMedia,
+    Unavailability,
 };
 use chrono::Utc;
+use std::time::Duration;
+use tower_http::timeout::TimeoutLayer;
 use crate::handlers::story;
 use crate::handlers::insta_post;
 use crate::handlers::reel;
+use crate::routes::files::file_url;
+use crate::utils::url_guard::{expand_story_username, media_redirect_policy, normalize_instagram_url, validate_callback_url, validate_media_url, CallbackTarget, UrlAllowlist};
+use crate::services::downloader::{extension_for, media_client_builder, DownloadLimiter, MediaHeaders, YTDLP_COOKIE_BROWSERS};
+use crate::services::webhook::post_callback;
+use crate::services::jobs::{self, JobStatus};
+use crate::services::queue::{download_queue, DownloadQueue, QUEUE_RETRY_AFTER_SECS};
+use crate::services::browser_pool::checkout_browser_client;
+use crate::services::metrics::{extraction_metrics, Method};
+use crate::config::Config;
+use crate::state::AppState;
+use crate::services::outcome::{DownloadResult, ExtractionOutcome};
+use crate::utils::error::AppError;
+use crate::utils::job::{create_job_folder, job_folder_name, new_job_id};
+
+const MEDIA_TIMEOUT_SECS: u64 = 30;
+
+// Number of batch URLs processed at the same time
+const BATCH_CONCURRENCY: usize = 3;
+// Media files fetched at the same time for `?inline=true`
+const INLINE_MEDIA_CONCURRENCY: usize = 4;
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+const BLOB_ONLY_HINT: &str = "Only blob: video URLs were found (the video is streamed via MediaSource), \
+    so it can't be extracted directly. Use /api/download, which falls back to yt-dlp or headless Chrome network capture.";
+
+// Error for age-gated content, whose remedy differs from a plain login wall
+const AGE_GATE_HINT: &str = "This content is age-restricted. Send session cookies of an account \
+    whose age is confirmed to view it.";
 
 // Define MediaItem and PreviewResponse here since they're missing from handlers
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
 pub struct MediaItem {
     pub url: String,
     pub media_type: String,
+    // /api/thumbnail link to a lone video's poster image, when the page has one
     pub thumbnail_url: Option<String>,
+    // Where an item beyond the page's own media came from, e.g. "coauthor:<username>"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    // `url` is a base64 data: URI holding the file itself (preview with inline=true)
+    #[serde(default)]
+    pub inlined: bool,
+    // Instagram's generated image description ("May be an image of ..."), when the page has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct PreviewResponse {
     pub success: bool,
+    // complete / partial / empty / blocked / error, see ExtractionOutcome
+    pub outcome: String,
     pub content_type: Option<String>,
+    pub shortcode: Option<String>,
     pub media_items: Option<Vec<MediaItem>>,
     pub error: Option<String>,
+    #[schema(value_type = Option<Object>)]
     pub debug_info: Option<serde_json::Map<String, serde_json::Value>>,
+    // Separate audio stream of a reel (include_audio=true), when Instagram lists one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_url: Option<String>,
+    // The reel's music ("<song> - <artist>") or original sound title (include_audio=true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_title: Option<String>,
+    // Location and tagged users of a post or reel (include_metadata=true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<PostMetadata>,
 }
 
-#[derive(Debug, Deserialize)]
+// Body of POST /api/download
+#[derive(Deserialize, ToSchema)]
 pub struct DownloadRequest {
-    // We don't need to mark url as unused since we'll directly handle it in the improved handler
-    #[allow(dead_code)]
     pub url: String,
-    #[allow(dead_code)]
+    // Answer right away and POST the result here once the download finishes
+    pub callback_url: Option<String>,
+    #[serde(flatten)]
+    pub options: DownloadOptions,
+}
+
+// Options of a single download, shared by /api/download and every URL of a batch.
+// Not Debug: `cookies` holds the caller's session and must never end up in logs
+#[derive(Clone, Default, Deserialize, ToSchema)]
+pub struct DownloadOptions {
     pub browser: Option<String>,
-    #[allow(dead_code)]
     pub use_ytdlp_first: Option<bool>,
+    pub disable_ytdlp: Option<bool>,
+    // Reels only: run yt-dlp and browser extraction at the same time, first success wins
+    pub race: Option<bool>,
+    pub cookies: Option<String>,
+    // Only download the first N stories
+    #[schema(value_type = Option<u64>)]
+    pub limit: Option<usize>,
+    // "video" or "image" to only download media of that type
+    pub only: Option<String>,
+    // "jpg" or "png" to re-encode downloaded images
+    pub convert_to: Option<String>,
+    // JPEG quality (1 to 100) for convert_to "jpg", 85 by default
+    pub quality: Option<u64>,
+    // Base name (single item) or prefix (several items) of the saved files
+    pub filename: Option<String>,
+    // Also save a reel's poster image as cover.jpg
+    pub include_cover: Option<bool>,
+    // Download attempts per file (1 to 10) instead of the default 5
+    #[schema(value_type = Option<u64>)]
+    pub max_retries: Option<usize>,
+    // Remove EXIF and other metadata from downloaded post and story JPEGs
+    pub strip_metadata: Option<bool>,
+    // Download the whole story set of a /stories/{username}/{story_id}/ URL, not just that story
+    pub all_stories: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct BatchDownloadRequest {
+    pub urls: Vec<String>,
+    #[serde(flatten)]
+    pub options: DownloadOptions,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DownloadParams {
+    /// `true` queues the download and answers 202 with a job id to poll
+    #[serde(rename = "async")]
+    #[param(rename = "async")]
+    is_async: Option<bool>,
+}
+
+// Answer of an async download: where to poll for the result
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QueuedDownloadResponse {
+    pub job_id: String,
+    pub status: JobStatus,
+    pub status_url: String,
 }
 
 #[derive(Debug, Deserialize)]
+pub struct BatchParams {
+    format: Option<String>,
+}
+
+// Result of downloading a single URL
+#[derive(Debug, Serialize)]
+pub struct DownloadResponse {
+    pub url: String,
+    pub shortcode: Option<String>,
+    pub message: String,
+    // Some saved file matched an earlier download and was linked to it instead of stored again
+    pub deduped: bool,
+    // Set for downloads run in the background (async mode or a callback_url)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+}
+
+// Not Debug: `cookies` holds the caller's session and must never end up in logs
+#[derive(Deserialize, ToSchema)]
 pub struct PreviewRequest {
     pub url: String,
     #[allow(dead_code)]
     pub browser: Option<String>,
+    pub cookies: Option<String>,
+    // "video" or "image" to only return media of that type
+    pub only: Option<String>,
+    // Swap post images and post/reel videos for the best versions from the web JSON (slower)
+    pub prefer_original: Option<bool>,
+    // "highest" or "lowest": pick post/reel videos from the web JSON's video_versions (slower)
+    pub video_quality: Option<String>,
+    // Also return media the web JSON lists beyond the page, e.g. from collab post coauthors (slower)
+    pub include_collab: Option<bool>,
+    // Return a reel's audio stream URL and music title from the web JSON (slower)
+    pub include_audio: Option<bool>,
+    // Return a post's location and tagged users from the web JSON (slower)
+    pub include_metadata: Option<bool>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct PreviewParams {
+    /// `m3u` answers with an `audio/x-mpegurl` playlist instead of JSON
+    format: Option<String>,
+    // Embed media up to INLINE_MEDIA_MAX_BYTES as data: URIs
+    inline: Option<bool>,
+}
+
+// Query string of GET /api/preview; `url` arrives already percent-decoded
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct PreviewQuery {
+    url: String,
+    /// `m3u` answers with an `audio/x-mpegurl` playlist instead of JSON
+    format: Option<String>,
+    only: Option<String>,
+    prefer_original: Option<bool>,
+    video_quality: Option<String>,
+    include_collab: Option<bool>,
+    include_audio: Option<bool>,
+    include_metadata: Option<bool>,
+    inline: Option<bool>,
+}
+
+// Not Debug: `cookies` holds the caller's session and must never end up in logs
+#[derive(Deserialize)]
+pub struct StreamRequest {
+    pub url: String,
+    pub cookies: Option<String>,
+    // Send Content-Disposition: attachment so browsers save the file
+    pub download: Option<bool>,
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
 pub struct MediaProxyParams {
     url: String,
     download: Option<bool>,
     filename: Option<String>,
 }
 
+// Opt-in preview extras read from the post's web JSON; each costs another request
+#[derive(Debug, Clone, Copy, Default)]
+struct WebJsonExtras {
+    // Swap post images for their full-resolution versions
+    prefer_original: bool,
+    // Swap post and reel videos for this encode; `Highest` when only prefer_original is set
+    video_quality: Option<VideoQuality>,
+    // Add media the page leaves out, e.g. from collab post coauthors
+    include_collab: bool,
+    // Return a reel's audio stream URL and title
+    include_audio: bool,
+    // Return a post's location and tagged users
+    include_metadata: bool,
+}
+
+type PreviewFuture = Shared<BoxFuture<'static, Result<PreviewResponse, AppError>>>;
+type InFlightPreviews = Mutex<HashMap<String, PreviewFuture>>;
+
+// Extractions currently running for anonymous previews, keyed by normalized URL
+static IN_FLIGHT_PREVIEWS: OnceLock<InFlightPreviews> = OnceLock::new();
+
+// Share one extraction between concurrent identical previews (e.g. a trending reel requested by
+// many clients at once). Only used without cookies, since a logged-in result belongs to its caller.
+async fn coalesced_preview(url: &str, extras: WebJsonExtras) -> Result<PreviewResponse, AppError> {
+    let key = preview_key(url, &extras);
+    let url = url.to_string();
+    single_flight(IN_FLIGHT_PREVIEWS.get_or_init(Default::default), key, move || async move {
+        extract_instagram_media(&url, None, extras).await
+    })
+    .await
+}
+
+// Previews of the same page with the same extras share a key
+fn preview_key(url: &str, extras: &WebJsonExtras) -> String {
+    let normalized = normalize_instagram_url(url).unwrap_or_else(|_| url.to_string());
+    format!(
+        "{}|{}|{:?}|{}|{}|{}",
+        normalized, extras.prefer_original, extras.video_quality, extras.include_collab, extras.include_audio, extras.include_metadata
+    )
+}
+
+// Join the extraction running under `key`, or start `extract` for it. The extraction is its own
+// task, so it runs to the end and hands back its browser session even when every caller timed
+// out or hung up; its entry is removed when the task ends, panics included.
+fn single_flight<F>(
+    in_flight: &'static InFlightPreviews,
+    key: String,
+    extract: impl FnOnce() -> F,
+) -> PreviewFuture
+where
+    F: std::future::Future<Output = Result<PreviewResponse, AppError>> + Send + 'static,
+{
+    let mut previews = in_flight.lock().unwrap();
+    if let Some(future) = previews.get(&key) {
+        println!("🔗 Joining in-flight preview of {}", key);
+        return future.clone();
+    }
+    let extraction = extract();
+    let entry = InFlightEntry { in_flight, key: key.clone() };
+    let task = tokio::spawn(async move {
+        let _entry = entry;
+        extraction.await
+    });
+    let future = async move {
+        task.await.unwrap_or_else(|e| Err(AppError::InternalServerError(format!("Preview extraction failed: {}", e))))
+    }
+    .boxed()
+    .shared();
+    previews.insert(key, future.clone());
+    future
+}
+
+// Removes a single-flight entry once its extraction task is done with it
+struct InFlightEntry {
+    in_flight: &'static InFlightPreviews,
+    key: String,
+}
+
+impl Drop for InFlightEntry {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+// Extract a URL's media, retrying once in a fresh browser session if the WebDriver session died
+// mid-extraction. Only one recreate is attempted so a broken chromedriver can't loop a request.
+async fn extract_instagram_media(url: &str, cookies: Option<&str>, extras: WebJsonExtras) -> Result<PreviewResponse, AppError> {
+    let preview = extract_instagram_media_once(url, cookies, extras).await?;
+    if !preview.error.as_deref().is_some_and(is_webdriver_session_lost) {
+        return Ok(preview);
+    }
+    println!("🔁 WebDriver session lost ({}), retrying with a fresh browser session", preview.error.as_deref().unwrap_or_default());
+    let mut retried = extract_instagram_media_once(url, cookies, extras).await?;
+    if let Some(debug_info) = retried.debug_info.as_mut() {
+        debug_info.insert("browser_recreated".to_string(), serde_json::Value::Bool(true));
+    }
+    Ok(retried)
+}
+
+// Second try for a preview that found nothing: every media URL in the JSON-LD and Open Graph tags
+const ALT_METADATA_SCRIPT: &str = r#"
+    function findMedia() {
+        const media = [];
+        
+        // Try JSON-LD metadata (most reliable for login-restricted content)
+        document.querySelectorAll('script[type="application/ld+json"]').forEach(script => {
+            try {
+                const data = JSON.parse(script.textContent);
+                // Video content in JSON-LD
+                if(data.contentUrl && data.contentUrl.includes('.mp4')) {
+                    media.push({url: data.contentUrl, type: 'video'});
+                }
+                // Image content in JSON-LD (direct)
+                if(data.contentUrl && !data.contentUrl.includes('.mp4')) {
+                    media.push({url: data.contentUrl, type: 'image'});
+                }
+                // Nested video content
+                if(data.video && data.video.contentUrl) {
+                    media.push({url: data.video.contentUrl, type: 'video'});
+                }
+                // Image arrays
+                if(data.image) {
+                    const images = Array.isArray(data.image) ? data.image : [data.image];
+                    images.forEach(img => {
+                        const imgUrl = typeof img === 'string' ? img : img.url;
+                        if(imgUrl) media.push({url: imgUrl, type: 'image'});
+                    });
+                }
+                // Thumbnails might be useful when real content is restricted
+                if(data.thumbnailUrl) {
+                    const thumbs = Array.isArray(data.thumbnailUrl) 
+                        ? data.thumbnailUrl : [data.thumbnailUrl];
+                    thumbs.forEach(thumb => {
+                        if(thumb) media.push({url: thumb, type: 'image'});
+                    });
+                }
+            } catch(e) {
+                console.error('JSON-LD parse error:', e);
+            }
+        });
+        
+        // Try Open Graph metadata (works even with login walls)
+        const ogVideo = document.querySelector('meta[property="og:video"]')?.content;
+        const ogVideoUrl = document.querySelector('meta[property="og:video:url"]')?.content;
+        const ogVideoSecureUrl = document.querySelector('meta[property="og:video:secure_url"]')?.content;
+        
+        // OG Video tags
+        [ogVideo, ogVideoUrl, ogVideoSecureUrl].filter(Boolean).forEach(url => {
+            media.push({url, type: 'video'});
+        });
+        
+        // OG Image tags
+        const ogImage = document.querySelector('meta[property="og:image"]')?.content;
+        const ogImageUrl = document.querySelector('meta[property="og:image:url"]')?.content;
+        const ogImageSecureUrl = document.querySelector('meta[property="og:image:secure_url"]')?.content;
+        
+        [ogImage, ogImageUrl, ogImageSecureUrl].filter(Boolean).forEach(url => {
+            media.push({url, type: 'image'});
+        });
+        
+        return media.filter((item, index, self) => {
+            // Filter out duplicates
+            return index === self.findIndex(t => t.url === item.url);
+        });
+    }
+    return findMedia();
+"#;
+
 // Helper function to extract Instagram media
-async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> PreviewResponse {
+async fn extract_instagram_media_once(url: &str, cookies: Option<&str>, extras: WebJsonExtras) -> Result<PreviewResponse, AppError> {
+    let WebJsonExtras { prefer_original, video_quality, include_collab, include_audio, include_metadata } = extras;
     let mut content_type = "post";
+    let mut reel_audio = None;
+    let mut post_metadata = None;
     let mut error_message = None;
     let mut media_items: Option<Vec<MediaItem>> = None;
     let mut debug_info = serde_json::Map::new();
+    let job_id = new_job_id();
+    let shortcode = shortcode_from_url(url);
+    debug_info.insert("job_id".to_string(), serde_json::Value::String(job_id.clone()));
     
     // Detect content type from URL
     debug_info.insert("url".to_string(), serde_json::Value::String(url.to_string()));
     
     // Extract content based on URL pattern
-    if url.contains("/stories/") {
+    if is_story_url(url) {
         content_type = "story";
         debug_info.insert("detected_type".to_string(), serde_json::Value::String("story".to_string()));
-    } else if url.contains("/reel/") || url.contains("/reels/") {
+    } else if is_reel_url(url) {
         content_type = "reel";
         debug_info.insert("detected_type".to_string(), serde_json::Value::String("reel".to_string()));
-    } else if url.contains("/p/") {
+    } else if is_post_url(url) {
         content_type = "post";
         debug_info.insert("detected_type".to_string(), serde_json::Value::String("post".to_string()));
     } else {
         error_message = Some("Unsupported URL format".to_string());
         debug_info.insert("error".to_string(), serde_json::Value::String("unsupported_url_format".to_string()));
         
-        return PreviewResponse {
-            success: false,
+        let outcome = ExtractionOutcome::classify(0, 0, false, error_message.as_deref());
+        return Ok(PreviewResponse {
+            success: outcome.is_success(),
+            outcome: outcome.label().to_string(),
             content_type: Some(content_type.to_string()),
+            shortcode,
             media_items: None,
             error: error_message,
             debug_info: Some(debug_info),
-        };
+            audio_url: None,
+            audio_title: None,
+            metadata: None,
+        });
     }
     
     // Use the extractor service to get media
     println!("📥 Extracting media from URL: {}", url);
-    match create_browser_client("chrome").await {
+    match checkout_browser_client("chrome").await {
         Ok(mut client) => {
             debug_info.insert("browser_client_created".to_string(), serde_json::Value::Bool(true));
             
             // Capture user agent for debugging
-            match client.execute("return navigator.userAgent", vec![]).await {
-                Ok(agent) => {
-                    if let Some(agent_str) = agent.as_str() {
-                        debug_info.insert("user_agent".to_string(), serde_json::Value::String(agent_str.to_string()));
-                    }
-                },
-                Err(_) => {}
+            if let Ok(agent) = client.execute("return navigator.userAgent", vec![]).await {
+                if let Some(agent_str) = agent.as_str() {
+                    debug_info.insert("user_agent".to_string(), serde_json::Value::String(agent_str.to_string()));
+                }
+            }
+
+            // Log in as the caller when they sent their own session cookies (values are never logged)
+            if let Some(raw) = cookies {
+                let applied = apply_cookies(&client, raw).await.is_ok();
+                debug_info.insert("request_cookies_applied".to_string(), serde_json::Value::Bool(applied));
             }
             
             // Set a longer timeout for navigation to handle slow connections
-            match client.goto(url).await {
+            match goto_with_timeout(&client, url).await {
                 Ok(_) => {
                     debug_info.insert("navigation_success".to_string(), serde_json::Value::Bool(true));
                     
@@ -136,7 +522,7 @@ async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> Pre
 
                             // Check for content blocking messages
                             const contentBlocked = 
-                                document.body.textContent.includes('This content isn't available') ||
+                                document.body.textContent.includes("This content isn't available") ||
                                 document.body.textContent.includes('content is not available') ||
                                 document.body.textContent.includes('restricted your access');
 
@@ -181,6 +567,31 @@ async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> Pre
                         false
                     };
                     
+                    // Private accounts and deleted posts can't be extracted at all, so stop here
+                    let body_text = client.execute("return document.body ? document.body.innerText : '';", vec![])
+                        .await
+                        .ok()
+                        .and_then(|v| v.as_str().map(|t| t.to_string()))
+                        .unwrap_or_default();
+                    if let Some(reason) = classify_unavailable_page(&body_text) {
+                        println!("⚠️ Content unavailable: {:?}", reason);
+                        client.release().await;
+                        return Err(match reason {
+                            Unavailability::Private => AppError::PrivateAccount(
+                                "This account is private; its content can't be downloaded".to_string()),
+                            Unavailability::NotFound => AppError::NotFound(
+                                "This post isn't available; it may have been deleted".to_string()),
+                        });
+                    }
+                    
+                    // The age gate is a login wall too, but only an age-verified session gets past it
+                    let login_required = if is_age_gate(&body_text) {
+                        println!("⚠️ Age gate detected");
+                        debug_info.insert("age_restricted".to_string(), serde_json::Value::Bool(true));
+                        true
+                    } else {
+                        login_required
+                    };
                     if login_required {
                         println!("⚠️ Login wall detected, trying alternative extraction methods");
                         debug_info.insert("login_required".to_string(), serde_json::Value::Bool(true));
@@ -221,7 +632,7 @@ async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> Pre
                         // Save the screenshot to a debug file with timestamp
                         let timestamp = chrono::Utc::now().timestamp();
                         let screenshot_path = format!("debug_screenshot_{}.png", timestamp);
-                        if let Ok(_) = std::fs::write(&screenshot_path, &screenshot_data) {
+                        if std::fs::write(&screenshot_path, &screenshot_data).is_ok() {
                             debug_info.insert("debug_screenshot".to_string(), serde_json::Value::String(screenshot_path));
                         }
                     }
@@ -229,25 +640,80 @@ async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> Pre
                     // Try specific extraction based on content type and login status
                     let extraction_result = if login_required && content_type == "reel" {
                         // For reels behind login, try metadata extraction
-                        extract_media_from_metadata(&mut client).await
+                        extract_media_from_metadata(&mut client, &job_id).await.map(without_alt_text)
                     } else if content_type == "story" {
                         // Special handling for stories
-                        extract_stories(&mut client).await
+                        extract_stories(&mut client).await.map(without_alt_text)
                     } else {
-                        // Standard extraction for posts and public reels
-                        extract_post_media(&mut client).await
+                        // Standard extraction for posts and public reels, keeping image alt texts
+                        extract_post_media_with_alt_text(&mut client, &job_id).await
                     };
                     
                     match extraction_result {
-                        Ok(extracted_media) => {
+                        Ok(extracted) => {
+                            // Alt texts stay matched by position; prefer_original only swaps URLs in place
+                            let (mut extracted_media, alt_texts): (Vec<_>, Vec<_>) = extracted
+                                .into_iter()
+                                .map(|item| ((item.url, item.media_type), item.alt_text))
+                                .unzip();
                             if !extracted_media.is_empty() {
-                                let items = extracted_media.into_iter()
-                                    .map(|(url, media_type)| MediaItem {
+                                // Opt-in: the web JSON lists larger images than the DOM srcset, higher-bitrate
+                                // videos than the player's first .mp4 and collab media the page leaves out, at
+                                // the cost of another request
+                                let use_original = prefer_original && matches!(content_type, "post" | "reel");
+                                let pick_videos = (prefer_original || video_quality.is_some()) && matches!(content_type, "post" | "reel");
+                                let use_audio = include_audio && content_type == "reel";
+                                let mut collab_media = Vec::new();
+                                if let Some(shortcode) = shortcode.as_deref().filter(|_| use_original || pick_videos || include_collab || use_audio || include_metadata) {
+                                    match fetch_web_json(&client, shortcode).await {
+                                        Ok(web_json) => {
+                                            if include_metadata {
+                                                post_metadata = Some(post_metadata_from_web_json(&web_json));
+                                            }
+                                            if use_audio {
+                                                reel_audio = reel_audio_from_web_json(&web_json);
+                                                debug_info.insert("audio_found".to_string(), serde_json::Value::Bool(reel_audio.is_some()));
+                                            }
+                                            if include_collab {
+                                                collab_media = collab_media_from_web_json(&web_json, &extracted_media);
+                                                debug_info.insert("collab_media_found".to_string(), serde_json::Value::from(collab_media.len()));
+                                            }
+                                            if use_original {
+                                                let replaced = prefer_original_images(&mut extracted_media, original_images_from_web_json(&web_json));
+                                                debug_info.insert("original_images_replaced".to_string(), serde_json::Value::from(replaced));
+                                            }
+                                            if pick_videos {
+                                                let videos = original_videos_from_web_json(&web_json, video_quality.unwrap_or_default());
+                                                let replaced = prefer_original_videos(&mut extracted_media, videos);
+                                                debug_info.insert("original_videos_replaced".to_string(), serde_json::Value::from(replaced));
+                                            }
+                                        }
+                                        Err(e) => {
+                                            println!("⚠️ {}", e);
+                                            debug_info.insert("web_json_error".to_string(), serde_json::Value::String(e.to_string()));
+                                        }
+                                    }
+                                }
+                                let mut items = extracted_media.into_iter()
+                                    .zip(alt_texts)
+                                    .map(|((url, media_type), alt_text)| MediaItem {
                                         url,
                                         media_type,
                                         thumbnail_url: None,
+                                        source: None,
+                                        inlined: false,
+                                        alt_text,
                                     })
+                                    .chain(collab_media.into_iter().map(|media| MediaItem {
+                                        url: media.url,
+                                        media_type: media.media_type,
+                                        thumbnail_url: None,
+                                        source: Some(media.source),
+                                        inlined: false,
+                                        alt_text: None,
+                                    }))
                                     .collect::<Vec<_>>();
+                                backfill_video_thumbnail(&client, &mut items, &mut debug_info).await;
                                 
                                 println!("✅ Successfully extracted {} media items", items.len());
                                 debug_info.insert("extracted_count".to_string(), serde_json::Value::Number(serde_json::Number::from(items.len())));
@@ -264,90 +730,26 @@ async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> Pre
                                 tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                                 
                                 // Try alternate extraction method using OpenGraph and JSON-LD
-                                let alt_script = r#"
-                                    function findMedia() {
-                                        const media = [];
-                                        
-                                        // Try JSON-LD metadata (most reliable for login-restricted content)
-                                        document.querySelectorAll('script[type="application/ld+json"]').forEach(script => {
-                                            try {
-                                                const data = JSON.parse(script.textContent);
-                                                // Video content in JSON-LD
-                                                if(data.contentUrl && data.contentUrl.includes('.mp4')) {
-                                                    media.push({url: data.contentUrl, type: 'video'});
-                                                }
-                                                // Image content in JSON-LD (direct)
-                                                if(data.contentUrl && !data.contentUrl.includes('.mp4')) {
-                                                    media.push({url: data.contentUrl, type: 'image'});
-                                                }
-                                                // Nested video content
-                                                if(data.video && data.video.contentUrl) {
-                                                    media.push({url: data.video.contentUrl, type: 'video'});
-                                                }
-                                                // Image arrays
-                                                if(data.image) {
-                                                    const images = Array.isArray(data.image) ? data.image : [data.image];
-                                                    images.forEach(img => {
-                                                        const imgUrl = typeof img === 'string' ? img : img.url;
-                                                        if(imgUrl) media.push({url: imgUrl, type: 'image'});
-                                                    });
-                                                }
-                                                // Thumbnails might be useful when real content is restricted
-                                                if(data.thumbnailUrl) {
-                                                    const thumbs = Array.isArray(data.thumbnailUrl) 
-                                                        ? data.thumbnailUrl : [data.thumbnailUrl];
-                                                    thumbs.forEach(thumb => {
-                                                        if(thumb) media.push({url: thumb, type: 'image'});
-                                                    });
-                                                }
-                                            } catch(e) {
-                                                console.error('JSON-LD parse error:', e);
-                                            }
-                                        });
-                                        
-                                        // Try Open Graph metadata (works even with login walls)
-                                        const ogVideo = document.querySelector('meta[property="og:video"]')?.content;
-                                        const ogVideoUrl = document.querySelector('meta[property="og:video:url"]')?.content;
-                                        const ogVideoSecureUrl = document.querySelector('meta[property="og:video:secure_url"]')?.content;
-                                        
-                                        // OG Video tags
-                                        [ogVideo, ogVideoUrl, ogVideoSecureUrl].filter(Boolean).forEach(url => {
-                                            media.push({url, type: 'video'});
-                                        });
-                                        
-                                        // OG Image tags
-                                        const ogImage = document.querySelector('meta[property="og:image"]')?.content;
-                                        const ogImageUrl = document.querySelector('meta[property="og:image:url"]')?.content;
-                                        const ogImageSecureUrl = document.querySelector('meta[property="og:image:secure_url"]')?.content;
-                                        
-                                        [ogImage, ogImageUrl, ogImageSecureUrl].filter(Boolean).forEach(url => {
-                                            media.push({url, type: 'image'});
-                                        });
-                                        
-                                        return media.filter((item, index, self) => {
-                                            // Filter out duplicates
-                                            return index === self.findIndex(t => t.url === item.url);
-                                        });
-                                    }
-                                    return findMedia();
-                                "#;
-                                
-                                match client.execute(alt_script, vec![]).await {
+                                match client.execute(ALT_METADATA_SCRIPT, vec![]).await {
                                     Ok(alt_result) => {
                                         if let Some(arr) = alt_result.as_array() {
-                                            let items = arr.iter().filter_map(|item| {
+                                            let mut items = arr.iter().filter_map(|item| {
                                                 if let Some(obj) = item.as_object() {
-                                                    let url = obj.get("url")?.as_str()?.to_string();
+                                                    let url = absolutize_media_url(obj.get("url")?.as_str()?)?;
                                                     let media_type = obj.get("type")?.as_str()?.to_string();
                                                     Some(MediaItem {
                                                         url,
                                                         media_type,
                                                         thumbnail_url: None,
+                                                        source: None,
+                                                        inlined: false,
+                                                        alt_text: None,
                                                     })
                                                 } else {
                                                     None
                                                 }
                                             }).collect::<Vec<_>>();
+                                            backfill_video_thumbnail(&client, &mut items, &mut debug_info).await;
                                             
                                             if !items.is_empty() {
                                                 println!("✅ Alternate extraction successful: found {} items", items.len());
@@ -387,8 +789,27 @@ async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> Pre
                 }
             }
             
-            // Always close the client when done
-            let _ = client.close().await;
+            // If nothing was extracted, check whether the page only exposed MediaSource blob: URLs
+            if media_items.is_none() {
+                let candidates_script = r#"
+                    return Array.from(document.querySelectorAll('video, video > source'))
+                        .map(el => el.src)
+                        .filter(Boolean);
+                "#;
+                let candidates: Vec<String> = client.execute(candidates_script, vec![])
+                    .await
+                    .ok()
+                    .and_then(|v| serde_json::from_value(v).ok())
+                    .unwrap_or_default();
+                if is_blob_only(&candidates) {
+                    println!("⚠️ Only blob: video URLs found ({}), direct extraction can't fetch them", candidates.len());
+                    debug_info.insert("blob_only".to_string(), serde_json::Value::Bool(true));
+                    error_message = Some(BLOB_ONLY_HINT.to_string());
+                }
+            }
+            
+            // Always hand the session back when done
+            client.release().await;
         },
         Err(e) => {
             error_message = Some(format!("Failed to create browser client: {}", e));
@@ -397,70 +818,391 @@ async fn extract_instagram_media(url: &str, _browser_state: Arc<Browser>) -> Pre
         }
     }
 
+    let metrics = extraction_metrics();
+    metrics.record(Method::Browser, media_items.as_ref().is_some_and(|items| !items.is_empty()));
+
     // After all other extraction attempts for reels fail:
     if content_type == "reel" && (media_items.is_none() || media_items.as_ref().unwrap().is_empty()) {
         // Fallback: use headless_chrome direct extraction
-        let timestamp = Utc::now().timestamp();
-        let folder_name = format!("insta_reel_preview_{}", timestamp);
+        let folder_name = match create_job_folder("reel_preview") {
+            Ok(folder_name) => folder_name,
+            Err(e) => {
+                println!("⚠️ Failed to create preview folder: {}", e);
+                job_folder_name("reel_preview", Utc::now().timestamp())
+            }
+        };
         match extract_reel_video_with_headless_chrome(url, &folder_name).await {
             Ok(Some(video_path)) => {
-                // Return the file path as a media item (the frontend should handle file serving or you can serve it via a proxy endpoint)
-                let mut items = Vec::new();
-                items.push(MediaItem {
-                    url: video_path,
+                metrics.record(Method::HeadlessFallback, true);
+                // Expose the downloaded file through the /api/files endpoint instead of a local path
+                let filename = std::path::Path::new(&video_path)
+                    .file_name()
+                    .map(|f| f.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let items = vec![MediaItem {
+                    url: file_url(&folder_name, &filename),
                     media_type: "video".to_string(),
                     thumbnail_url: None,
-                });
+                    source: None,
+                    inlined: false,
+                    alt_text: None,
+                }];
                 debug_info.insert("headless_chrome_fallback".to_string(), serde_json::Value::Bool(true));
                 debug_info.insert("headless_chrome_video_found".to_string(), serde_json::Value::Bool(true));
-                return PreviewResponse {
-                    success: true,
+                let outcome = ExtractionOutcome::classify(items.len(), items.len(), false, None);
+                return Ok(PreviewResponse {
+                    success: outcome.is_success(),
+                    outcome: outcome.label().to_string(),
                     content_type: Some(content_type.to_string()),
+                    shortcode,
                     media_items: Some(items),
                     error: None,
                     debug_info: Some(debug_info),
-                };
+                    audio_url: None,
+                    audio_title: None,
+                    metadata: None,
+                });
             },
             Ok(None) => {
+                metrics.record(Method::HeadlessFallback, false);
                 debug_info.insert("headless_chrome_fallback".to_string(), serde_json::Value::Bool(true));
                 debug_info.insert("headless_chrome_video_found".to_string(), serde_json::Value::Bool(false));
             },
             Err(e) => {
+                metrics.record(Method::HeadlessFallback, false);
                 debug_info.insert("headless_chrome_fallback".to_string(), serde_json::Value::Bool(true));
                 debug_info.insert("headless_chrome_error".to_string(), serde_json::Value::String(e.to_string()));
             }
         }
     }
     
-    PreviewResponse {
-        success: media_items.is_some(),
+    let found = media_items.as_ref().map_or(0, |items| items.len());
+    if found == 0 {
+        metrics.record_all_failed();
+    }
+    let blocked = debug_info.get("login_required").and_then(|v| v.as_bool()).unwrap_or(false);
+    if found == 0 && debug_info.get("age_restricted").and_then(|v| v.as_bool()).unwrap_or(false) {
+        error_message = Some(AGE_GATE_HINT.to_string());
+    }
+    let outcome = ExtractionOutcome::classify(found, found, blocked, error_message.as_deref());
+    let (audio_url, audio_title) = reel_audio.map_or((None, None), |audio| (audio.url, audio.title));
+    Ok(PreviewResponse {
+        success: outcome.is_success(),
+        outcome: outcome.label().to_string(),
         content_type: Some(content_type.to_string()),
+        shortcode,
         media_items,
         error: error_message,
         debug_info: Some(debug_info),
-    }
+        audio_url,
+        audio_title,
+        metadata: post_metadata,
+    })
 }
 
 // Preview handler
+#[utoipa::path(
+    post,
+    path = "/api/preview",
+    tag = "preview",
+    params(PreviewParams),
+    request_body = PreviewRequest,
+    responses(
+        (status = 200, description = "Media found on the page: JSON, or an M3U playlist of /api/media URLs with format=m3u", content(
+            ("application/json" = PreviewResponse),
+            ("audio/x-mpegurl" = String),
+        )),
+        (status = 400, description = "Invalid or disallowed URL", body = ErrorResponse),
+        (status = 403, description = "Private account", body = ErrorResponse),
+    )
+)]
 async fn preview_handler(
-    State(browser_state): State<Arc<Browser>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<PreviewParams>,
+    headers: HeaderMap,
     Json(payload): Json<PreviewRequest>,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
     println!("Received preview request for URL: {}", payload.url);
-    // Now use the browser option if provided
-    let preview_result = extract_instagram_media(&payload.url, browser_state).await;
-    
-    (StatusCode::OK, Json(preview_result))
+    let url = allowed_url(&payload.url, &config.allowed_url_patterns).await.map_err(AppError::BadRequest)?;
+    run_preview(
+        &url,
+        payload.cookies.as_deref(),
+        payload.only.as_deref(),
+        WebJsonExtras {
+            prefer_original: payload.prefer_original.unwrap_or(false),
+            video_quality: VideoQuality::parse(payload.video_quality.as_deref()).map_err(AppError::BadRequest)?,
+            include_collab: payload.include_collab.unwrap_or(false),
+            include_audio: payload.include_audio.unwrap_or(false),
+            include_metadata: payload.include_metadata.unwrap_or(false),
+        },
+        PreviewOutput {
+            format: params.format.as_deref(),
+            inline_max_bytes: params.inline.unwrap_or(false).then_some(config.inline_media_max_bytes),
+            public_base_url: config.public_base_url.as_deref(),
+            media_headers: &config.media,
+        },
+        &headers,
+    ).await
 }
 
-async fn media_proxy_handler(
-    Query(params): Query<MediaProxyParams>,
-) -> impl IntoResponse {
-    let url = params.url;
-    println!("Proxying media from URL: {}", url);
+// GET variant for integrations and quick browser testing: /api/preview?url=...
+// Cookies are only accepted in the POST body so they never end up in URLs or access logs.
+#[utoipa::path(
+    get,
+    path = "/api/preview",
+    tag = "preview",
+    params(PreviewQuery),
+    responses(
+        (status = 200, description = "Media found on the page: JSON, or an M3U playlist of /api/media URLs with format=m3u", content(
+            ("application/json" = PreviewResponse),
+            ("audio/x-mpegurl" = String),
+        )),
+        (status = 400, description = "Invalid or disallowed URL", body = ErrorResponse),
+        (status = 403, description = "Private account", body = ErrorResponse),
+    )
+)]
+async fn preview_get_handler(
+    State(config): State<Arc<Config>>,
+    Query(params): Query<PreviewQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    println!("Received GET preview request for URL: {}", params.url);
+    let url = allowed_url(&params.url, &config.allowed_url_patterns).await.map_err(AppError::BadRequest)?;
+    run_preview(
+        &url,
+        None,
+        params.only.as_deref(),
+        WebJsonExtras {
+            prefer_original: params.prefer_original.unwrap_or(false),
+            video_quality: VideoQuality::parse(params.video_quality.as_deref()).map_err(AppError::BadRequest)?,
+            include_collab: params.include_collab.unwrap_or(false),
+            include_audio: params.include_audio.unwrap_or(false),
+            include_metadata: params.include_metadata.unwrap_or(false),
+        },
+        PreviewOutput {
+            format: params.format.as_deref(),
+            inline_max_bytes: params.inline.unwrap_or(false).then_some(config.inline_media_max_bytes),
+            public_base_url: config.public_base_url.as_deref(),
+            media_headers: &config.media,
+        },
+        &headers,
+    ).await
+}
+
+// How a preview is handed back to the caller
+struct PreviewOutput<'a> {
+    // "m3u" for a playlist instead of JSON
+    format: Option<&'a str>,
+    // Embed media up to this size as data: URIs
+    inline_max_bytes: Option<usize>,
+    // PUBLIC_BASE_URL, preferred over the request headers for playlist URLs
+    public_base_url: Option<&'a str>,
+    // Sent with the inline fetches, like every other CDN request
+    media_headers: &'a MediaHeaders,
+}
+
+// Shared by both preview verbs so their responses stay identical. `url` has been through
+// `allowed_url` already
+async fn run_preview(
+    url: &str,
+    cookies: Option<&str>,
+    only: Option<&str>,
+    extras: WebJsonExtras,
+    output: PreviewOutput<'_>,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let only = parse_media_filter(only).map_err(AppError::BadRequest)?;
+    // Players open playlist entries on their own, so they need absolute URLs; fail before
+    // extracting anything when there's no way to build them
+    let playlist_base = match output.format {
+        Some("m3u") => Some(playlist_base_url(output.public_base_url, headers).ok_or_else(|| {
+            AppError::BadRequest(
+                "Can't build absolute playlist URLs: set PUBLIC_BASE_URL or send a Host header".to_string(),
+            )
+        })?),
+        _ => None,
+    };
+    if is_feed_url(url) {
+        return Err(AppError::BadRequest(FEED_URL_ERROR.to_string()));
+    }
+    let mut preview_result = match cookies {
+        Some(_) => extract_instagram_media(url, cookies, extras).await?,
+        None => coalesced_preview(url, extras).await?,
+    };
+    if let Some(only) = only {
+        filter_preview_media(&mut preview_result, only);
+    }
+
+    // Optionally hand the media to external players as a playlist of proxied URLs
+    if let Some(base) = playlist_base {
+        if let Some(items) = preview_result.media_items.as_ref().filter(|items| !items.is_empty()) {
+            return Ok(Response::builder()
+                .header("Content-Type", "audio/x-mpegurl")
+                .header("Content-Disposition", "attachment; filename=\"instagram_media.m3u\"")
+                .body(boxed(Body::from(build_m3u_playlist(items, &base))))
+                .unwrap());
+        }
+    }
+
+    if let (Some(max_bytes), Some(items)) = (output.inline_max_bytes, preview_result.media_items.as_mut()) {
+        inline_small_media(items, max_bytes, output.media_headers).await;
+    }
     
+    Ok((StatusCode::OK, Json(preview_result)).into_response())
+}
+
+// Swap the URL of every item up to `max_bytes` for a base64 data: URI of the file, for clients
+// that can't make a second request to /api/media. Larger or unreachable items keep their URL.
+async fn inline_small_media(items: &mut [MediaItem], max_bytes: usize, media_headers: &MediaHeaders) {
+    let client = match media_proxy_client() {
+        Ok(client) => client,
+        Err(e) => {
+            println!("⚠️ Failed to build the inline media client: {}", e);
+            return;
+        }
+    };
+    stream::iter(items.iter_mut())
+        .for_each_concurrent(INLINE_MEDIA_CONCURRENCY, |item| inline_media_item(&client, media_headers, item, max_bytes))
+        .await;
+}
+
+async fn inline_media_item(client: &reqwest::Client, media_headers: &MediaHeaders, item: &mut MediaItem, max_bytes: usize) {
+    if validate_media_url(&item.url).is_err() {
+        return;
+    }
+    let response = match client.get(&item.url).headers(media_headers.headers().clone()).send().await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            println!("⚠️ Not inlining {}: upstream answered {}", item.url, response.status());
+            return;
+        }
+        Err(e) => {
+            println!("⚠️ Not inlining {}: {}", item.url, e);
+            return;
+        }
+    };
+    if response.content_length().is_some_and(|len| len > max_bytes as u64) {
+        return;
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| if item.media_type == "video" { "video/mp4" } else { "image/jpeg" }.to_string());
+    // Content-Length may be missing, so stop reading as soon as the limit is passed
+    let mut body = Vec::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        let Ok(chunk) = chunk else { return };
+        if body.len() + chunk.len() > max_bytes {
+            return;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    item.url = format!("data:{};base64,{}", content_type, BASE64.encode(&body));
+    item.inlined = true;
+}
+
+// Pair extracted `(url, type)` items with no alt text
+fn without_alt_text(items: Vec<(String, String)>) -> Vec<ExtractedMedia> {
+    items.into_iter().map(ExtractedMedia::from).collect()
+}
+
+// Drop media items that aren't of the requested type, turning the result into a failure if none are left
+fn filter_preview_media(preview: &mut PreviewResponse, only: &str) {
+    let Some(items) = preview.media_items.as_mut() else { return };
+    if items.is_empty() {
+        return;
+    }
+    items.retain(|item| item.media_type == only);
+    if items.is_empty() {
+        preview.success = false;
+        preview.outcome = ExtractionOutcome::Empty.label().to_string();
+        preview.error = Some(format!("No media of requested type '{}'", only));
+    }
+}
+
+// Best-effort preview image for a lone video item (a reel or single-video post): point its
+// `thumbnail_url` at the page's poster through /api/thumbnail. Carousels are left alone since
+// the page poster shows their first slide, which may not be the video.
+async fn backfill_video_thumbnail(
+    client: &fantoccini::Client,
+    items: &mut [MediaItem],
+    debug_info: &mut serde_json::Map<String, serde_json::Value>,
+) {
+    let Some(item) = lone_video_without_thumbnail(items) else { return };
+    if let Some(poster) = extract_cover_url(client).await {
+        item.thumbnail_url = Some(thumbnail_proxy_url(&poster));
+        debug_info.insert("thumbnail_backfilled".to_string(), serde_json::Value::Bool(true));
+    }
+}
+
+// The only item of `items` when it's a video that has no thumbnail yet
+fn lone_video_without_thumbnail(items: &mut [MediaItem]) -> Option<&mut MediaItem> {
+    match items {
+        [item] if item.media_type == "video" && item.thumbnail_url.is_none() => Some(item),
+        _ => None,
+    }
+}
+
+// Relative /api/thumbnail link that downscales the image at `url`
+fn thumbnail_proxy_url(url: &str) -> String {
+    let encoded: String = url::form_urlencoded::byte_serialize(url.as_bytes()).collect();
+    format!("/api/thumbnail?url={}", encoded)
+}
+
+// Absolute base URL of this server as the client reached it: PUBLIC_BASE_URL when set,
+// otherwise the scheme and host from the Forwarded, X-Forwarded-Proto / X-Forwarded-Host
+// and Host headers. None when no usable host is known.
+fn playlist_base_url(configured: Option<&str>, headers: &HeaderMap) -> Option<String> {
+    if let Some(base) = configured {
+        return Some(base.to_string());
+    }
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    // Only the first (client-facing) element of each header counts
+    let first = |value: &str| value.split(',').next().unwrap_or_default().trim().to_string();
+    let mut proto = None;
+    let mut host = None;
+    if let Some(forwarded) = header("forwarded") {
+        for pair in first(forwarded).split(';') {
+            if let Some((key, value)) = pair.split_once('=') {
+                let value = value.trim().trim_matches('"').to_string();
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "proto" => proto = Some(value),
+                    "host" => host = Some(value),
+                    _ => {}
+                }
+            }
+        }
+    }
+    let proto = proto
+        .or_else(|| header("x-forwarded-proto").map(first))
+        .unwrap_or_else(|| "http".to_string())
+        .to_ascii_lowercase();
+    let host = host
+        .or_else(|| header("x-forwarded-host").map(first))
+        .or_else(|| header(HOST.as_str()).map(str::to_string))?;
+    let valid_host = !host.is_empty()
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || ".-_:[]".contains(c));
+    (matches!(proto.as_str(), "http" | "https") && valid_host).then(|| format!("{}://{}", proto, host))
+}
+
+// Build an extended M3U playlist listing each media item through the /api/media proxy
+fn build_m3u_playlist(items: &[MediaItem], base: &str) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for (i, item) in items.iter().enumerate() {
+        let encoded: String = url::form_urlencoded::byte_serialize(item.url.as_bytes()).collect();
+        playlist.push_str(&format!("#EXTINF:-1,Instagram {} {}\n", item.media_type, i + 1));
+        playlist.push_str(&format!("{}/api/media?url={}\n", base, encoded));
+    }
+    playlist
+}
+
+// Map a /api/media `url` to the URL actually fetched upstream
+fn proxied_media_url(url: String) -> String {
     // Process URL to get best quality - handle video/image cases
-    let processed_url = if url.contains(".mp4") {
+    if url.contains(".mp4") {
         // It's already a direct video URL
         url
     } else if url.contains("/v/") && !url.ends_with(".mp4") {
@@ -468,7 +1210,7 @@ async fn media_proxy_handler(
         format!("{}/video/index.mp4", url.trim_end_matches('/'))
     } else if !url.contains(".jpg") && !url.contains(".mp4") {
         // URL without extension, try to get the video if it exists
-        if url.contains("/reel/") {
+        if is_reel_url(&url) {
             format!("{}/video/index.mp4", url.trim_end_matches('/'))
         } else {
             // Default to original URL if we can't determine type
@@ -477,121 +1219,420 @@ async fn media_proxy_handler(
     } else {
         // Return original URL for other cases
         url
-    };
+    }
+}
+
+// Media client shared by the GET and HEAD proxy handlers
+fn media_proxy_client() -> reqwest::Result<reqwest::Client> {
+    media_client_builder()
+        .redirect(media_redirect_policy())
+        .build()
+}
+
+// HEAD /api/media: lets players and download managers learn the size and type of a media
+// file without fetching it. Guarded by the same host allowlist as the GET handler.
+#[utoipa::path(
+    head,
+    path = "/api/media",
+    tag = "media",
+    params(MediaProxyParams),
+    responses(
+        (status = 200, description = "Upstream Content-Type, Content-Length and Accept-Ranges, no body"),
+        (status = 400, description = "Host not allowed", body = ErrorResponse),
+        (status = 502, description = "Upstream request failed; other upstream error statuses are passed on", body = ErrorResponse),
+    )
+)]
+async fn media_head_handler(
+    Query(params): Query<MediaProxyParams>,
+) -> Result<Response, AppError> {
+    let processed_url = proxied_media_url(params.url);
+    if let Err(reason) = validate_media_url(&processed_url) {
+        println!("⚠️ Refusing to proxy {}: {}", processed_url, reason);
+        return Err(AppError::BadRequest(reason));
+    }
+    let client = media_proxy_client()
+        .map_err(|e| AppError::InternalServerError(format!("Failed to build the media client: {}", e)))?;
+    head_media(&client, &processed_url).await
+}
+
+// Forward a HEAD request upstream and pass on the headers that describe the file
+async fn head_media(client: &reqwest::Client, url: &str) -> Result<Response, AppError> {
+    let response = client.head(url).send().await.map_err(|e| {
+        println!("Request error: {}", e);
+        AppError::NetworkError(format!("Error fetching from upstream server: {}", e))
+    })?;
+    if !response.status().is_success() {
+        return Err(upstream_error(&response));
+    }
+    let mut builder = Response::builder().status(StatusCode::OK);
+    for header in [reqwest::header::CONTENT_TYPE, reqwest::header::CONTENT_LENGTH, reqwest::header::ACCEPT_RANGES] {
+        if let Some(value) = response.headers().get(&header) {
+            builder = builder.header(header.as_str(), value.as_bytes());
+        }
+    }
+    Ok(builder.body(boxed(Body::empty())).unwrap())
+}
+
+// An error status from the CDN, passed on with its Retry-After so clients back off as long
+// as the CDN asked us to (429/503)
+fn upstream_error(response: &reqwest::Response) -> AppError {
+    println!("Upstream server error: {}", response.status());
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let retry_after = response.headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    AppError::Upstream(status, format!("Upstream server returned: {}", response.status()), retry_after)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/media",
+    tag = "media",
+    params(MediaProxyParams),
+    responses(
+        (status = 200, description = "The media file, streamed from Instagram's CDN", content_type = "application/octet-stream"),
+        (status = 400, description = "Host not allowed", body = ErrorResponse),
+        (status = 502, description = "Upstream request failed; other upstream error statuses are passed on", body = ErrorResponse),
+    )
+)]
+async fn media_proxy_handler(
+    Query(params): Query<MediaProxyParams>,
+) -> Result<Response, AppError> {
+    let url = params.url;
+    println!("Proxying media from URL: {}", url);
+    let processed_url = proxied_media_url(url);
     println!("Processed URL for proxy: {}", processed_url);
+    if let Err(reason) = validate_media_url(&processed_url) {
+        println!("⚠️ Refusing to proxy {}: {}", processed_url, reason);
+        return Err(AppError::BadRequest(reason));
+    }
     
     let download = params.download.unwrap_or(false);
     
     // Create a client with appropriate headers to access Instagram
-    let client = reqwest::Client::builder()
-        .user_agent("Mozilla/5.0 (iPhone; CPU iPhone OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/96.0.4664.110 Mobile/15E148 Safari/604.1")
-        .build()
-        .unwrap();
+    let client = media_proxy_client()
+        .map_err(|e| AppError::InternalServerError(format!("Failed to build the media client: {}", e)))?;
     // Make the request
-    match client.get(&processed_url).send().await {
-        Ok(response) => {
-            if response.status().is_success() {
-                // Get content type before consuming the response
-                let content_type = response.headers()
-                    .get(reqwest::header::CONTENT_TYPE)
-                    .and_then(|v| v.to_str().ok())
-                    .unwrap_or("application/octet-stream")
-                    .to_string();
-                // Now get the bytes
-                match response.bytes().await {
-                    Ok(bytes) => {
-                        // Determine content type based on URL extension or the header we saved earlier
-                        let content_type = if processed_url.ends_with(".mp4") {
-                            "video/mp4"
-                        } else if processed_url.ends_with(".jpg") || processed_url.ends_with(".jpeg") {
-                            "image/jpeg"
-                        } else if processed_url.ends_with(".png") {
-                            "image/png"
-                        } else {
-                            // Use the content type we extracted earlier
-                            &content_type
-                        };
-                        let mut response_builder = Response::builder()
-                            .header("Content-Type", content_type)
-                            .status(StatusCode::OK);
-                        // Add content disposition header for downloads
-                        if download {
-                            // Extract filename from URL or generate one
-                            let filename = params.filename.unwrap_or_else(|| {
-                                processed_url.split('/').last()
-                                    .unwrap_or("instagram_media")
-                                    .split('?').next()
-                                    .unwrap_or("instagram_media")
-                                    .to_string()
-                            });
-                            response_builder = response_builder.header(
-                                "Content-Disposition",
-                                format!("attachment; filename=\"{}\"", filename)
-                            );
-                        }
-                        // Build and return the response
-                        match response_builder.body(Body::from(bytes)) {
-                            Ok(response) => response,
-                            Err(_) => {
-                                Response::builder()
-                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                    .body(Body::from("Failed to create response"))
-                                    .unwrap()
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        println!("Error fetching bytes: {}", e);
-                        Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(Body::from(format!("Failed to fetch media bytes: {}", e)))
-                            .unwrap()
-                    }
-                }
-            } else {
-                println!("Upstream server error: {}", response.status());
-                Response::builder()
-                    .status(StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY))
-                    .body(Body::from(format!("Upstream server returned: {}", response.status())))
-                    .unwrap()
-            }
-        },
-        Err(e) => {
-            println!("Request error: {}", e);
-            Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
-                .body(Body::from(format!("Error fetching from upstream server: {}", e)))
-                .unwrap()
-        }
+    let response = client.get(&processed_url).send().await.map_err(|e| {
+        println!("Request error: {}", e);
+        AppError::NetworkError(format!("Error fetching from upstream server: {}", e))
+    })?;
+    if !response.status().is_success() {
+        return Err(upstream_error(&response));
+    }
+    // Get content type before consuming the response
+    let content_type = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    // Now get the bytes
+    let bytes = response.bytes().await.map_err(|e| {
+        println!("Error fetching bytes: {}", e);
+        AppError::InternalServerError(format!("Failed to fetch media bytes: {}", e))
+    })?;
+    // Determine content type based on URL extension or the header we saved earlier
+    let content_type = if processed_url.ends_with(".mp4") {
+        "video/mp4"
+    } else if processed_url.ends_with(".jpg") || processed_url.ends_with(".jpeg") {
+        "image/jpeg"
+    } else if processed_url.ends_with(".png") {
+        "image/png"
+    } else {
+        // Use the content type we extracted earlier
+        &content_type
+    };
+    let mut response_builder = Response::builder()
+        .header("Content-Type", content_type)
+        .status(StatusCode::OK);
+    // Add content disposition header for downloads
+    if download {
+        // Extract filename from URL or generate one
+        let filename = params.filename.unwrap_or_else(|| {
+            processed_url.split('/').next_back()
+                .unwrap_or("instagram_media")
+                .split('?').next()
+                .unwrap_or("instagram_media")
+                .to_string()
+        });
+        response_builder = response_builder.header("Content-Disposition", attachment_disposition(&filename));
+    }
+    // Build and return the response
+    response_builder
+        .body(boxed(Body::from(bytes)))
+        .map_err(|_| AppError::InternalServerError("Failed to create response".to_string()))
+}
+
+// Resolve a post or reel URL and stream its single media file from the CDN without writing it to disk
+async fn stream_handler(
+    State(config): State<Arc<Config>>,
+    Json(payload): Json<StreamRequest>,
+) -> Result<Response, AppError> {
+    println!("Received stream request for URL: {}", payload.url);
+    let url = allowed_url(&payload.url, &config.allowed_url_patterns).await.map_err(AppError::BadRequest)?;
+    if is_feed_url(&url) {
+        return Err(AppError::BadRequest(FEED_URL_ERROR.to_string()));
+    }
+    let preview = match payload.cookies.as_deref() {
+        Some(cookies) => extract_instagram_media(&url, Some(cookies), WebJsonExtras::default()).await?,
+        None => coalesced_preview(&url, WebJsonExtras::default()).await?,
+    };
+    let shortcode = preview.shortcode.clone();
+    let item = single_media_item(preview)?;
+    validate_media_url(&item.url).map_err(AppError::BadRequest)?;
+    let client = media_proxy_client()?;
+    let attachment = payload.download.unwrap_or(false).then_some(Attachment { filename: payload.filename, shortcode });
+    stream_media(&client, &item, attachment).await
+}
+
+// How a streamed file is offered for saving: the caller's name, or the shortcode plus an
+// extension matching the content
+struct Attachment {
+    filename: Option<String>,
+    shortcode: Option<String>,
+}
+
+// The only media item of a preview; /api/stream has no way to pick one of several
+fn single_media_item(preview: PreviewResponse) -> Result<MediaItem, AppError> {
+    let mut items = preview.media_items.unwrap_or_default();
+    match items.len() {
+        1 => Ok(items.remove(0)),
+        0 => Err(AppError::NotFound(preview.error.unwrap_or_else(|| "No media found at this URL".to_string()))),
+        n => Err(AppError::BadRequest(format!(
+            "This post has {} media items and /api/stream only streams single-item posts; use /api/download to save all of them",
+            n
+        ))),
     }
 }
 
+// Forward `item` from the CDN as it arrives
+async fn stream_media(client: &reqwest::Client, item: &MediaItem, attachment: Option<Attachment>) -> Result<Response, AppError> {
+    let response = client.get(&item.url).send().await?;
+    if !response.status().is_success() {
+        return Err(upstream_error(&response));
+    }
+    let content_type = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| if item.media_type == "video" { "video/mp4" } else { "image/jpeg" }.to_string());
+
+    let mut builder = Response::builder().header("Content-Type", &content_type);
+    if let Some(length) = response.content_length() {
+        builder = builder.header("Content-Length", length);
+    }
+    if let Some(Attachment { filename, shortcode }) = attachment {
+        let filename = filename.unwrap_or_else(|| {
+            let stem = shortcode.unwrap_or_else(|| "instagram_media".to_string());
+            format!("{}.{}", stem, extension_for(&item.url, Some(&content_type)))
+        });
+        builder = builder.header("Content-Disposition", attachment_disposition(&filename));
+    }
+    // Bytes are forwarded as they arrive, so nothing is buffered or persisted server-side
+    builder
+        .body(boxed(StreamBody::new(response.bytes_stream())))
+        .map_err(|e| AppError::InternalServerError(format!("Failed to create response: {}", e)))
+}
+
 // Routes for this module
-pub fn routes() -> Router<Arc<Browser>> {
+pub fn routes(config: &Config) -> Router<AppState> {
+    // Previews should fail fast, while downloads (stories, carousels) legitimately take longer
+    let preview_timeout = Duration::from_secs(config.preview_timeout_secs);
+    let download_timeout = Duration::from_secs(config.download_timeout_secs);
+    println!("⏱️ Request timeouts: preview {}s, download {}s", preview_timeout.as_secs(), download_timeout.as_secs());
+
+    timed_routes(
+        axum_post(handle_download),
+        axum_post(preview_handler).get(preview_get_handler),
+        download_timeout,
+        preview_timeout,
+    )
+    // No timeout here: batches run as long as their items take (use format=jsonl for incremental results)
+    .route("/api/download/batch", axum_post(batch_download_handler))
+}
+
+// `/api/download` and `/api/preview`, each cut off after its own budget
+fn timed_routes<S: Clone + Send + Sync + 'static>(
+    download: MethodRouter<S>,
+    preview: MethodRouter<S>,
+    download_timeout: Duration,
+    preview_timeout: Duration,
+) -> Router<S> {
+    Router::new()
+        .route("/api/download", download.layer(TimeoutLayer::new(download_timeout)))
+        .route("/api/preview", preview.layer(TimeoutLayer::new(preview_timeout)))
+}
+
+// Routes that send media bytes rather than JSON. Mounted apart from `routes` so the response
+// compression layer never touches them: the files are already compressed.
+pub fn media_routes(config: &Config) -> Router<AppState> {
+    let download_timeout = Duration::from_secs(config.download_timeout_secs);
     Router::new()
-        .route("/api/download", axum_post(handle_download))
-        .route("/api/preview", axum_post(preview_handler))
-        .route("/api/media", axum_get(media_proxy_handler))
+        .route("/api/stream", axum_post(stream_handler).layer(TimeoutLayer::new(download_timeout)))
+        .route("/api/media", axum_get(media_proxy_handler).head(media_head_handler).layer(TimeoutLayer::new(Duration::from_secs(MEDIA_TIMEOUT_SECS))))
 }
 
 // Improved handler that intelligently routes to the correct extractor based on URL
-async fn handle_download(payload: axum::extract::Json<serde_json::Value>) -> axum::extract::Json<String> {
-    // Extract URL from the request
-    let url = match payload.get("url").and_then(|v| v.as_str()) {
-        Some(url) => url,
-        None => return axum::extract::Json("❌ URL is required".to_string()),
+#[utoipa::path(
+    post,
+    path = "/api/download",
+    tag = "download",
+    request_body = DownloadRequest,
+    params(DownloadParams),
+    responses(
+        (status = 200, description = "Status message starting with ✅, ⚠️ or ❌", body = String),
+        (status = 202, description = "Async mode: queued, poll the status_url", body = QueuedDownloadResponse),
+        (status = 400, description = "Malformed body, disallowed URL or unsupported browser", body = ErrorResponse),
+        (status = 503, description = "Download queue full; retry after the Retry-After seconds", body = ErrorResponse),
+        (status = 507, description = "The server ran out of disk space while saving the download", body = ErrorResponse),
+    )
+)]
+async fn handle_download(
+    State(config): State<Arc<Config>>,
+    State(limiter): State<DownloadLimiter>,
+    Query(params): Query<DownloadParams>,
+    headers: HeaderMap,
+    payload: axum::extract::Json<serde_json::Value>,
+) -> Result<Response, AppError> {
+    // Keep the friendly status message for a missing URL; anything else malformed is a 400
+    if payload.get("url").and_then(|v| v.as_str()).is_none() {
+        return Ok(Json("❌ URL is required".to_string()).into_response());
+    }
+    let DownloadRequest { url, callback_url, mut options } = serde_json::from_value(payload.0)
+        .map_err(|e| AppError::BadRequest(format!("Invalid request body: {}", e)))?;
+    // A bare username or @username asks for that user's stories
+    let url = match expand_story_username(&url) {
+        Ok(url) => url,
+        Err(e) => return Ok(Json(format!("❌ {}", e)).into_response()),
     };
+    let url = allowed_url(&url, &config.allowed_url_patterns).await.map_err(AppError::BadRequest)?;
+    let url = url.as_str();
+    options.browser = options
+        .browser
+        .map(|browser| validate_browser(&browser).map(str::to_string))
+        .transpose()?;
 
-    // Get browser preference
-    let browser = payload.get("browser")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
-        
-    // Get yt-dlp preference
-    let use_ytdlp_first = payload.get("use_ytdlp_first")
-        .and_then(|v| v.as_bool());
+    // With a callback URL, answer right away and POST the result once the download finishes
+    if let Some(callback_url) = callback_url {
+        let callback_url = match validate_callback_url(&callback_url).await {
+            Ok(callback_url) => callback_url,
+            Err(reason) => return Ok(Json(format!("❌ Invalid callback_url: {}", reason)).into_response()),
+        };
+        let slot = enqueue_downloads(1)?;
+        let job_id = spawn_download_job(url.to_string(), options, limiter, slot, Some(callback_url));
+        if wants_async(params.is_async, &headers) {
+            return Ok(queued_response(job_id));
+        }
+        let queued = format!("✅ Download queued as job {}; the result will be POSTed to the callback URL", job_id);
+        return Ok(Json(queued).into_response());
+    }
+
+    // Async mode: answer 202 right away and let the client poll the job
+    if wants_async(params.is_async, &headers) {
+        let slot = enqueue_downloads(1)?;
+        let job_id = spawn_download_job(url.to_string(), options, limiter, slot, None);
+        return Ok(queued_response(job_id));
+    }
+
+    let _slot = enqueue_downloads(1)?;
+    sync_download_response(dispatch_download(url, options, limiter).await)
+}
+
+// A full disk is the server's problem, not the media's: answer 507 instead of a ❌ status
+fn sync_download_response(result: DownloadResult) -> Result<Response, AppError> {
+    match result {
+        DownloadResult::DiskFull(reason) => Err(AppError::InsufficientStorage(reason)),
+        result => Ok(Json(result.message()).into_response()),
+    }
+}
+
+// Whether the client asked for async mode, with `?async=true` or `Prefer: respond-async`
+fn wants_async(is_async: Option<bool>, headers: &HeaderMap) -> bool {
+    is_async.unwrap_or(false)
+        || headers
+            .get_all("prefer")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
+}
+
+// 202 Accepted pointing at the job's status endpoint
+fn queued_response(job_id: String) -> Response {
+    let status_url = format!("/api/jobs/{}", job_id);
+    (
+        StatusCode::ACCEPTED,
+        [(LOCATION, status_url.clone())],
+        Json(QueuedDownloadResponse { job_id, status: JobStatus::Queued, status_url }),
+    )
+        .into_response()
+}
+
+// Run a download in the background, tracked under a new job id for GET /api/jobs/{job_id}
+// and reported to `callback_url` when one was given. Returns the job id.
+fn spawn_download_job(
+    url: String,
+    options: DownloadOptions,
+    limiter: DownloadLimiter,
+    slot: SemaphorePermit<'static>,
+    callback_url: Option<CallbackTarget>,
+) -> String {
+    let job_id = new_job_id();
+    jobs::register(&job_id, &url);
+    let id = job_id.clone();
+    tokio::spawn(async move {
+        // Keep the queue slot until the background download is done
+        let _slot = slot;
+        jobs::mark_running(&id);
+        // A panic would otherwise leave the job running forever
+        let result = std::panic::AssertUnwindSafe(dispatch_download(&url, options, limiter))
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|_| DownloadResult::Failed("❌ Download failed with an unexpected server error".to_string()));
+        jobs::finish(&id, &result);
+        if let Some(callback_url) = callback_url {
+            let shortcode = shortcode_from_url(&url);
+            let result = DownloadResponse { url, shortcode, message: result.message(), deduped: result.deduped(), job_id: Some(id) };
+            post_callback(&callback_url, &result).await;
+        }
+    });
+    job_id
+}
+
+// Reserve download queue slots for `jobs` downloads, or the 503 to answer with when it's full
+fn enqueue_downloads(jobs: usize) -> Result<SemaphorePermit<'static>, AppError> {
+    enqueue_on(download_queue(), jobs)
+}
+
+fn enqueue_on(queue: &DownloadQueue, jobs: usize) -> Result<SemaphorePermit<'_>, AppError> {
+    // Retrying can't help a batch bigger than the whole queue
+    if jobs > queue.capacity() {
+        return Err(AppError::BadRequest(format!(
+            "{} downloads can never fit in the download queue ({} slots); send fewer URLs at once",
+            jobs, queue.capacity()
+        )));
+    }
+    queue.try_enqueue(jobs).ok_or_else(|| {
+        println!("🚦 Download queue full ({}/{}), refusing {} more", queue.depth(), queue.capacity(), jobs);
+        AppError::Overloaded(
+            format!(
+                "Download queue is full ({} of {} slots taken, {} needed), retry in {}s",
+                queue.depth(), queue.capacity(), jobs, QUEUE_RETRY_AFTER_SECS
+            ),
+            QUEUE_RETRY_AFTER_SECS,
+        )
+    })
+}
 
-    // Create the appropriate request object
+// Route a single URL, already through `allowed_url`, to the story, reel or post handler and
+// return its status message
+async fn dispatch_download(url: &str, options: DownloadOptions, limiter: DownloadLimiter) -> DownloadResult {
+    let DownloadOptions { browser, use_ytdlp_first, disable_ytdlp, race, cookies, limit, only, convert_to, quality, filename, include_cover, max_retries, strip_metadata, all_stories } = options;
+    // Feeds would otherwise fall through to the post handler and fail obscurely
+    if is_feed_url(url) {
+        return DownloadResult::Failed(format!("❌ {}", FEED_URL_ERROR));
+    }
     match (is_story_url(url), is_reel_url(url)) {
         (true, _) => {
             // Story URL
@@ -599,8 +1640,19 @@ async fn handle_download(payload: axum::extract::Json<serde_json::Value>) -> axu
             let story_request = story::StoryDownloadRequest {
                 url: url.to_string(),
                 browser,
+                cookies,
+                limit,
+                use_ytdlp_first,
+                disable_ytdlp,
+                only,
+                convert_to,
+                quality,
+                filename,
+                max_retries,
+                strip_metadata,
+                all_stories,
             };
-            return story::download(axum::extract::Json(story_request)).await;
+            story::download(limiter, axum::extract::Json(story_request)).await
         }
         (_, true) => {
             // Reel URL
@@ -609,8 +1661,16 @@ async fn handle_download(payload: axum::extract::Json<serde_json::Value>) -> axu
                 url: url.to_string(),
                 browser,
                 use_ytdlp_first,
+                disable_ytdlp,
+                race,
+                cookies,
+                only,
+                convert_to,
+                filename,
+                include_cover,
+                max_retries,
             };
-            return reel::download(axum::extract::Json(reel_request)).await;
+            reel::download(limiter, axum::extract::Json(reel_request)).await
         }
         _ => {
             // Regular post URL
@@ -618,8 +1678,582 @@ async fn handle_download(payload: axum::extract::Json<serde_json::Value>) -> axu
             let post_request = insta_post::PostDownloadRequest {
                 url: url.to_string(),
                 browser,
+                cookies,
+                only,
+                convert_to,
+                quality,
+                filename,
+                max_retries,
+                strip_metadata,
+                disable_ytdlp,
+            };
+            insta_post::download(limiter, axum::extract::Json(post_request)).await
+        }
+    }
+}
+
+// Batch download: buffered JSON array by default, or one JSON object per line
+// as each URL finishes when `?format=jsonl` / `Accept: application/x-ndjson` is used
+async fn batch_download_handler(
+    State(config): State<Arc<Config>>,
+    State(limiter): State<DownloadLimiter>,
+    Query(params): Query<BatchParams>,
+    headers: HeaderMap,
+    Json(request): Json<BatchDownloadRequest>,
+) -> Result<Response, AppError> {
+    let wants_jsonl = params.format.as_deref() == Some("jsonl")
+        || headers
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.contains(NDJSON_CONTENT_TYPE))
+            .unwrap_or(false);
+    println!("📦 Received batch download request for {} URLs (jsonl: {})", request.urls.len(), wants_jsonl);
+
+    let BatchDownloadRequest { urls, mut options } = request;
+    options.browser = options.browser.as_deref().map(validate_browser).transpose()?.map(str::to_string);
+    let urls = validate_batch_urls(urls, config.max_batch_urls).map_err(AppError::BadRequest)?;
+    // One queue slot per URL, held until the whole batch is done
+    let slots = enqueue_downloads(urls.len())?;
+    let results = stream::iter(urls).map(move |url| {
+        let options = options.clone();
+        let limiter = limiter.clone();
+        let config = config.clone();
+        async move {
+            let (message, deduped) = match allowed_url(&url, &config.allowed_url_patterns).await {
+                Ok(allowed) => {
+                    let result = dispatch_download(&allowed, options, limiter).await;
+                    (result.message(), result.deduped())
+                }
+                Err(reason) => (format!("❌ {}", reason), false),
             };
-            return insta_post::download(axum::extract::Json(post_request)).await;
+            let shortcode = shortcode_from_url(&url);
+            DownloadResponse { url, shortcode, message, deduped, job_id: None }
+        }
+    });
+
+    if wants_jsonl {
+        let lines = results
+            .buffer_unordered(BATCH_CONCURRENCY)
+            .map(move |result| {
+                // The response body outlives this handler, so it carries the queue slots
+                let _slots = &slots;
+                let mut line = serde_json::to_string(&result).unwrap_or_default();
+                line.push('\n');
+                Ok::<_, Infallible>(line)
+            });
+        return Ok(Response::builder()
+            .header("Content-Type", NDJSON_CONTENT_TYPE)
+            .body(boxed(StreamBody::new(lines)))
+            .unwrap());
+    }
+
+    let results: Vec<DownloadResponse> = results.buffered(BATCH_CONCURRENCY).collect().await;
+    Ok(Json(results).into_response())
+}
+
+// Check a request's `browser` against the browsers yt-dlp can read cookies from. yt-dlp's
+// `firefox:profile` / `chrome+gnomekeyring` forms are accepted; only the browser name is checked.
+fn validate_browser(browser: &str) -> Result<&str, AppError> {
+    let browser = browser.trim();
+    let name = browser.split([':', '+']).next().unwrap_or_default();
+    if YTDLP_COOKIE_BROWSERS.contains(&name) {
+        Ok(browser)
+    } else {
+        Err(AppError::BadRequest(format!(
+            "Unsupported browser '{}', expected one of: {}",
+            browser,
+            YTDLP_COOKIE_BROWSERS.join(", ")
+        )))
+    }
+}
+
+// `Content-Disposition: attachment` for a caller-chosen name. Quotes, path separators and control
+// characters are replaced so the name can't break out of the header or point into a directory.
+fn attachment_disposition(filename: &str) -> String {
+    let filename: String = filename
+        .chars()
+        .map(|c| if matches!(c, '"' | '/' | '\\') || c.is_control() { '_' } else { c })
+        .collect();
+    format!("attachment; filename=\"{}\"", filename)
+}
+
+/// Resolve a `/share/` link to the post it points at, normalize it and check the result against
+/// ALLOWED_URL_PATTERNS. Every endpoint that fetches a page goes through this, so the allowlist
+/// always sees the canonical link that will actually be loaded.
+async fn allowed_url(raw: &str, allowlist: &UrlAllowlist) -> Result<String, String> {
+    let resolved = resolve_share_url(raw.trim()).await.map_err(|e| e.to_string())?;
+    let url = normalize_instagram_url(&resolved)?;
+    allowlist.check(&url)?;
+    Ok(url)
+}
+
+// Normalize every batch URL, dropping repeats (first occurrence wins). The whole batch is
+// refused when it's too large or any URL isn't an Instagram link, naming the first bad one.
+// The allowlist is checked per URL once share links are resolved, see `allowed_url`.
+fn validate_batch_urls(urls: Vec<String>, max_urls: usize) -> Result<Vec<String>, String> {
+    if urls.is_empty() {
+        return Err("urls must contain at least one URL".to_string());
+    }
+    if urls.len() > max_urls {
+        return Err(format!("Too many URLs in batch: {} (at most {})", urls.len(), max_urls));
+    }
+    let mut unique = Vec::with_capacity(urls.len());
+    for url in &urls {
+        let url = normalize_instagram_url(&expand_story_username(url)?)?;
+        if !unique.contains(&url) {
+            unique.push(url);
+        }
+    }
+    Ok(unique)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn attachment_names_cannot_escape_the_header() {
+        assert_eq!(
+            attachment_disposition("a\"b/../c\\d\r\nSet-Cookie: x.jpg"),
+            "attachment; filename=\"a_b_.._c_d__Set-Cookie: x.jpg\"",
+        );
+        assert_eq!(attachment_disposition("café.mp4"), "attachment; filename=\"café.mp4\"");
+    }
+
+    #[tokio::test]
+    async fn allowlist_sees_the_normalized_url() {
+        let reels_only = UrlAllowlist::parse(Some(r"^https://www\.instagram\.com/reels?/")).unwrap();
+        assert_eq!(
+            allowed_url(" https://instagram.com/reel/abc123/?igsh=xyz ", &reels_only).await.as_deref(),
+            Ok("https://www.instagram.com/reel/abc123/"),
+        );
+        assert!(allowed_url("https://www.instagram.com/p/abc123/?next=/reel/x/", &reels_only).await.is_err());
+        assert!(allowed_url("https://example.com/reel/abc123/", &UrlAllowlist::default()).await.is_err());
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn playlist_base_prefers_the_configured_url() {
+        let headers = headers(&[("host", "internal:9090")]);
+        assert_eq!(playlist_base_url(Some("https://dl.example.com"), &headers).as_deref(), Some("https://dl.example.com"));
+    }
+
+    #[test]
+    fn playlist_base_follows_proxy_headers() {
+        assert_eq!(playlist_base_url(None, &headers(&[("host", "localhost:9090")])).as_deref(), Some("http://localhost:9090"));
+        let proxied = headers(&[("host", "backend:9090"), ("x-forwarded-proto", "https, http"), ("x-forwarded-host", "dl.example.com")]);
+        assert_eq!(playlist_base_url(None, &proxied).as_deref(), Some("https://dl.example.com"));
+        let forwarded = headers(&[("host", "backend:9090"), ("forwarded", "for=1.2.3.4;proto=https;host=\"dl.example.com\", for=10.0.0.1")]);
+        assert_eq!(playlist_base_url(None, &forwarded).as_deref(), Some("https://dl.example.com"));
+    }
+
+    #[test]
+    fn playlist_base_needs_a_usable_host() {
+        assert_eq!(playlist_base_url(None, &HeaderMap::new()), None);
+        assert_eq!(playlist_base_url(None, &headers(&[("host", "evil.com/x")])), None);
+        assert_eq!(playlist_base_url(None, &headers(&[("host", "a.com"), ("x-forwarded-proto", "javascript")])), None);
+    }
+
+    #[test]
+    fn m3u_entries_are_absolute_proxy_urls() {
+        let items = vec![MediaItem {
+            url: "https://cdn.example.com/a.mp4?x=1&y=2".to_string(),
+            media_type: "video".to_string(),
+            ..Default::default()
+        }];
+        assert_eq!(
+            build_m3u_playlist(&items, "https://dl.example.com"),
+            "#EXTM3U\n#EXTINF:-1,Instagram video 1\nhttps://dl.example.com/api/media?url=https%3A%2F%2Fcdn.example.com%2Fa.mp4%3Fx%3D1%26y%3D2\n"
+        );
+    }
+
+    #[test]
+    fn batch_urls_are_normalized_and_deduplicated() {
+        let urls = vec![
+            "https://instagram.com/p/abc/?igsh=1".to_string(),
+            "@someone".to_string(),
+            "https://www.instagram.com/p/abc/".to_string(),
+        ];
+        assert_eq!(
+            validate_batch_urls(urls, 5),
+            Ok(vec!["https://www.instagram.com/p/abc/".to_string(), "https://www.instagram.com/stories/someone/".to_string()]),
+        );
+        assert!(validate_batch_urls(Vec::new(), 5).is_err());
+        assert!(validate_batch_urls(vec!["https://www.instagram.com/p/a/".to_string(); 3], 2).is_err());
+        let err = validate_batch_urls(vec!["https://www.instagram.com/p/a/".to_string(), "https://example.com/p/b/".to_string()], 5).unwrap_err();
+        assert!(err.contains("example.com"), "{}", err);
+    }
+
+    fn found_preview() -> PreviewResponse {
+        PreviewResponse {
+            success: true,
+            outcome: ExtractionOutcome::Complete.label().to_string(),
+            content_type: Some("reel".to_string()),
+            shortcode: Some("abc".to_string()),
+            media_items: Some(Vec::new()),
+            error: None,
+            debug_info: None,
+            audio_url: None,
+            audio_title: None,
+            metadata: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_previews_share_one_extraction() {
+        static IN_FLIGHT: OnceLock<InFlightPreviews> = OnceLock::new();
+        let in_flight = IN_FLIGHT.get_or_init(Default::default);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let previews: Vec<_> = (0..8)
+            .map(|_| {
+                let runs = runs.clone();
+                single_flight(in_flight, "reel/abc".to_string(), move || async move {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(found_preview())
+                })
+            })
+            .collect();
+        let results = futures::future::join_all(previews).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|result| result.as_ref().is_ok_and(|preview| preview.success)));
+        assert!(in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_and_post_previews_answer_alike() {
+        use tower::ServiceExt;
+        let state = AppState {
+            browser: None,
+            config: Arc::new(Config::current().clone()),
+            download_limiter: DownloadLimiter::new(1),
+        };
+        let app = routes(&state.config).with_state(state);
+        // Both requests join this stand-in extraction instead of starting a browser
+        let url = "https://www.instagram.com/reel/abc/";
+        drop(single_flight(
+            IN_FLIGHT_PREVIEWS.get_or_init(Default::default),
+            preview_key(url, &WebJsonExtras::default()),
+            || async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(found_preview())
+            },
+        ));
+
+        let get = http::Request::get("/api/preview?url=https%3A%2F%2Fwww.instagram.com%2Freel%2Fabc%2F%3Figsh%3Dx")
+            .body(Body::empty())
+            .unwrap();
+        let post = http::Request::post("/api/preview")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::json!({ "url": url }).to_string()))
+            .unwrap();
+        let (get, post) = tokio::join!(app.clone().oneshot(get), app.oneshot(post));
+        let (get, post) = (get.unwrap(), post.unwrap());
+        assert_eq!(get.status(), StatusCode::OK);
+        assert_eq!(post.status(), StatusCode::OK);
+        let get: serde_json::Value = serde_json::from_slice(&crate::utils::test_server::body_bytes(get.into_response()).await).unwrap();
+        let post: serde_json::Value = serde_json::from_slice(&crate::utils::test_server::body_bytes(post.into_response()).await).unwrap();
+        assert_eq!(get, post);
+        assert_eq!(get["shortcode"], "abc");
+    }
+
+    #[tokio::test]
+    async fn abandoned_previews_still_finish_and_clear_their_entry() {
+        static IN_FLIGHT: OnceLock<InFlightPreviews> = OnceLock::new();
+        let in_flight = IN_FLIGHT.get_or_init(Default::default);
+        let finished = Arc::new(AtomicUsize::new(0));
+        let done = finished.clone();
+        // The only caller gives up right away, as a request timeout would
+        drop(single_flight(in_flight, "reel/abc".to_string(), move || async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            done.fetch_add(1, Ordering::SeqCst);
+            Ok(found_preview())
+        }));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        assert!(in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn head_passes_on_length_and_type_without_a_body() {
+        use crate::utils::test_server::{body_bytes, TestServer};
+        let server = TestServer::start(|request| {
+            if request.contains("/limited") {
+                b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 30\r\nContent-Length: 0\r\n\r\n".to_vec()
+            } else {
+                b"HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: 1234\r\nAccept-Ranges: bytes\r\n\r\n".to_vec()
+            }
+        }).await;
+        let client = reqwest::Client::new();
+
+        let response = head_media(&client, &server.url("/reel.mp4")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "video/mp4");
+        assert_eq!(response.headers()["content-length"], "1234");
+        assert_eq!(response.headers()["accept-ranges"], "bytes");
+        assert!(body_bytes(response).await.is_empty());
+
+        let response = head_media(&client, &server.url("/limited")).await.unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "30");
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["error_type"], "upstream_error");
+    }
+
+    #[test]
+    fn full_queues_refuse_with_503_and_oversized_batches_with_400() {
+        let queue = DownloadQueue::new(2);
+        let err = enqueue_on(&queue, 3).unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(queue.depth(), 0);
+
+        let held = enqueue_on(&queue, 2).unwrap();
+        let response = enqueue_on(&queue, 1).unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], QUEUE_RETRY_AFTER_SECS.to_string().as_str());
+        drop(held);
+        assert!(enqueue_on(&queue, 1).is_ok());
+    }
+
+    fn media_item(url: String) -> MediaItem {
+        MediaItem { url, media_type: "video".to_string(), thumbnail_url: None, source: None, inlined: false, alt_text: None }
+    }
+
+    #[tokio::test]
+    async fn single_video_posts_stream_their_bytes_through() {
+        use crate::utils::test_server::{body_bytes, response, TestServer};
+        let video = b"\x00\x00\x00\x18ftypmp42 not really a video".to_vec();
+        let served = video.clone();
+        let server = TestServer::start(move |request| {
+            if request.contains("/limited") {
+                response("429 Too Many Requests", &[("Retry-After", "12")], b"")
+            } else {
+                response("200 OK", &[("Content-Type", "video/mp4")], &served)
+            }
+        }).await;
+        let mut preview = found_preview();
+        preview.media_items = Some(vec![media_item(server.url("/reel.mp4"))]);
+        let item = single_media_item(preview).unwrap();
+        let client = reqwest::Client::new();
+
+        let attachment = Attachment { filename: None, shortcode: Some("abc".to_string()) };
+        let response = stream_media(&client, &item, Some(attachment)).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "video/mp4");
+        assert_eq!(response.headers()["content-length"], video.len().to_string().as_str());
+        assert_eq!(response.headers()["content-disposition"], "attachment; filename=\"abc.mp4\"");
+        assert_eq!(body_bytes(response).await, video);
+
+        let err = stream_media(&client, &media_item(server.url("/limited")), None).await.unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "12");
+
+        let mut carousel = found_preview();
+        carousel.media_items = Some(vec![media_item(server.url("/1.jpg")), media_item(server.url("/2.jpg"))]);
+        assert!(matches!(single_media_item(carousel), Err(AppError::BadRequest(_))));
+        assert!(matches!(single_media_item(found_preview()), Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn small_media_is_inlined_and_large_media_is_not() {
+        use crate::utils::test_server::{response, TestServer};
+        let server = TestServer::start(|request| {
+            if request.contains("/small.jpg") {
+                response("200 OK", &[("Content-Type", "image/jpeg")], b"tiny")
+            } else {
+                response("200 OK", &[("Content-Type", "image/jpeg")], &[0; 64])
+            }
+        }).await;
+        // Only Instagram hosts may be fetched, so point one at the test server
+        let host = "scontent.cdninstagram.com";
+        let client = reqwest::Client::builder().resolve(host, server.addr).build().unwrap();
+        let headers = MediaHeaders::parse(None, None).unwrap();
+        let item = |path: &str| MediaItem {
+            media_type: "image".to_string(),
+            ..media_item(format!("http://{}:{}{}", host, server.addr.port(), path))
+        };
+
+        let mut small = item("/small.jpg");
+        inline_media_item(&client, &headers, &mut small, 32).await;
+        assert_eq!(small.url, format!("data:image/jpeg;base64,{}", BASE64.encode(b"tiny")));
+        assert!(small.inlined);
+
+        let mut large = item("/large.jpg");
+        inline_media_item(&client, &headers, &mut large, 32).await;
+        assert!(large.url.ends_with("/large.jpg"));
+        assert!(!large.inlined);
+    }
+
+    #[test]
+    fn lone_videos_get_a_thumbnail_proxy_url() {
+        let poster = "https://scontent.cdninstagram.com/v/poster.jpg?a=1&b=2";
+        let mut reel = vec![media_item("https://scontent.cdninstagram.com/v/reel.mp4".to_string())];
+        let item = lone_video_without_thumbnail(&mut reel).unwrap();
+        item.thumbnail_url = Some(thumbnail_proxy_url(poster));
+        assert_eq!(
+            reel[0].thumbnail_url.as_deref(),
+            Some("/api/thumbnail?url=https%3A%2F%2Fscontent.cdninstagram.com%2Fv%2Fposter.jpg%3Fa%3D1%26b%3D2")
+        );
+        // Already has one now
+        assert!(lone_video_without_thumbnail(&mut reel).is_none());
+
+        let mut photo = vec![MediaItem { media_type: "image".to_string(), ..media_item("https://cdn/1.jpg".to_string()) }];
+        assert!(lone_video_without_thumbnail(&mut photo).is_none());
+        let mut carousel = vec![media_item("https://cdn/1.mp4".to_string()), media_item("https://cdn/2.mp4".to_string())];
+        assert!(lone_video_without_thumbnail(&mut carousel).is_none());
+    }
+
+    #[tokio::test]
+    async fn async_downloads_answer_202_and_the_job_finishes() {
+        use crate::utils::test_server::body_bytes;
+        use tower::ServiceExt;
+        let config = Config::current().clone();
+        let state = AppState { browser: None, config: Arc::new(config.clone()), download_limiter: DownloadLimiter::new(1) };
+        let app = routes(&config).merge(crate::routes::jobs::routes()).with_state(state);
+        // A feed is refused without opening a browser, so the job ends right away
+        let request = axum::http::Request::post("/api/download?async=true")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(r#"{"url": "https://www.instagram.com/explore/tags/sunset/"}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let queued: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        let status_url = queued["status_url"].as_str().unwrap().to_string();
+        assert_eq!(status_url, format!("/api/jobs/{}", queued["job_id"].as_str().unwrap()));
+
+        let mut job = serde_json::Value::Null;
+        for _ in 0..50 {
+            let request = axum::http::Request::get(&status_url).body(axum::body::Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            job = serde_json::from_slice(&body_bytes(response).await).unwrap();
+            if job["status"] == "completed" || job["status"] == "failed" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(job["status"], "failed");
+        assert!(job["message"].as_str().unwrap().contains(FEED_URL_ERROR));
+    }
+
+    #[tokio::test]
+    async fn full_disks_answer_507() {
+        use crate::utils::test_server::body_bytes;
+        let response = sync_download_response(DownloadResult::DiskFull(crate::services::downloader::DISK_FULL.to_string())).unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["error_type"], "insufficient_storage");
+
+        let response = sync_download_response(DownloadResult::Failed("❌ Nothing found".to_string())).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn previews_time_out_sooner_than_downloads() {
+        use tower::ServiceExt;
+        async fn slow() -> &'static str {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            "done"
         }
+        let app = timed_routes(axum_post(slow), axum_post(slow), Duration::from_secs(3), Duration::from_secs(1));
+        let request = |path: &str| http::Request::post(path).body(Body::empty()).unwrap();
+        let (preview, download) = tokio::join!(
+            app.clone().oneshot(request("/api/preview")),
+            app.oneshot(request("/api/download")),
+        );
+        assert_eq!(preview.unwrap().status(), StatusCode::REQUEST_TIMEOUT);
+        let download = download.unwrap();
+        assert_eq!(download.status(), StatusCode::OK);
+        assert_eq!(crate::utils::test_server::body_bytes(download.into_response()).await, b"done");
+    }
+
+    #[tokio::test]
+    async fn jsonl_batches_stream_one_json_object_per_url() {
+        let mut config = Config::current().clone();
+        // Refused before anything is fetched, so each URL finishes right away
+        config.allowed_url_patterns = UrlAllowlist::parse(Some(r"^https://www\.instagram\.com/reels?/")).unwrap();
+        let request: BatchDownloadRequest = serde_json::from_value(serde_json::json!({
+            "urls": ["https://www.instagram.com/p/abc/", "https://www.instagram.com/p/def/"],
+        }))
+        .unwrap();
+        let response = batch_download_handler(
+            State(Arc::new(config)),
+            State(DownloadLimiter::new(1)),
+            Query(BatchParams { format: None }),
+            headers(&[("accept", NDJSON_CONTENT_TYPE)]),
+            Json(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()["content-type"], NDJSON_CONTENT_TYPE);
+
+        let body = crate::utils::test_server::body_bytes(response).await;
+        let lines: Vec<serde_json::Value> = String::from_utf8(body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let mut shortcodes: Vec<&str> = lines.iter().map(|line| line["shortcode"].as_str().unwrap()).collect();
+        shortcodes.sort();
+        assert_eq!(shortcodes, ["abc", "def"]);
+        assert!(lines.iter().all(|line| line["message"].as_str().unwrap().starts_with("❌")));
+    }
+
+    #[test]
+    fn alt_metadata_script_pushes_every_og_image_url() {
+        let og = &ALT_METADATA_SCRIPT[ALT_METADATA_SCRIPT.find("const ogImage").unwrap()..ALT_METADATA_SCRIPT.find("return media").unwrap()];
+        assert!(og.contains("[ogImage, ogImageUrl, ogImageSecureUrl].filter(Boolean).forEach(url => {"));
+        assert_eq!(og.matches("media.push({url,").count(), og.matches("forEach(url =>").count());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn mixed_carousels_filter_down_to_one_media_type() {
+        let carousel = || PreviewResponse {
+            content_type: Some("post".to_string()),
+            media_items: Some(vec![
+                MediaItem { url: "https://cdn/1.jpg".to_string(), media_type: "image".to_string(), ..Default::default() },
+                MediaItem { url: "https://cdn/2.mp4".to_string(), media_type: "video".to_string(), ..Default::default() },
+                MediaItem { url: "https://cdn/3.jpg".to_string(), media_type: "image".to_string(), ..Default::default() },
+            ]),
+            ..found_preview()
+        };
+        let urls = |preview: &PreviewResponse| -> Vec<String> {
+            preview.media_items.iter().flatten().map(|item| item.url.clone()).collect()
+        };
+
+        let mut images = carousel();
+        filter_preview_media(&mut images, "image");
+        assert_eq!(urls(&images), ["https://cdn/1.jpg", "https://cdn/3.jpg"]);
+        assert!(images.success);
+
+        let mut videos = carousel();
+        filter_preview_media(&mut videos, "video");
+        assert_eq!(urls(&videos), ["https://cdn/2.mp4"]);
+
+        let mut nothing = PreviewResponse { media_items: Some(images.media_items.unwrap()), ..found_preview() };
+        filter_preview_media(&mut nothing, "video");
+        assert!(!nothing.success);
+        assert_eq!(nothing.error.as_deref(), Some("No media of requested type 'video'"));
+
+        // The download handlers filter (url, type) pairs the same way
+        let pairs = vec![("a.jpg".to_string(), "image".to_string()), ("b.mp4".to_string(), "video".to_string())];
+        assert_eq!(crate::services::extractor::filter_media_by_type(pairs.clone(), Some("video")), [pairs[1].clone()]);
+        assert_eq!(crate::services::extractor::filter_media_by_type(pairs.clone(), None), pairs);
+    }
+
+    #[test]
+    fn browser_profiles_and_keyrings_are_accepted() {
+        assert_eq!(validate_browser(" firefox:default-release ").unwrap(), "firefox:default-release");
+        assert_eq!(validate_browser("chrome+gnomekeyring").unwrap(), "chrome+gnomekeyring");
+        assert!(matches!(validate_browser("netscape"), Err(AppError::BadRequest(_))));
+        assert!(validate_browser("").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use axum::{
+    body::{boxed, Body},
+    extract::Path,
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::Serialize;
+use crate::services::storage::{reclaimable_bytes, storage};
+use crate::state::AppState;
+use crate::utils::error::AppError;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+// Download folders are created by the handlers with this prefix
+const JOB_FOLDER_PREFIX: &str = "insta_";
+
+// Everything but unreserved URL characters gets percent-encoded in a path segment
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+// Build the public URL under which a downloaded file can be fetched. yt-dlp names files
+// after the post title, so spaces, '#', '?' and non-ASCII characters are common.
+pub fn file_url(job_folder: &str, filename: &str) -> String {
+    format!(
+        "/api/files/{}/{}",
+        utf8_percent_encode(job_folder, PATH_SEGMENT),
+        utf8_percent_encode(filename, PATH_SEGMENT)
+    )
+}
+
+// Reject anything that could escape the job folder (separators, "..", hidden files)
+fn is_safe_path_component(component: &str) -> bool {
+    !component.is_empty()
+        && !component.starts_with('.')
+        && !component.contains('/')
+        && !component.contains('\\')
+        && !component.contains('\0')
+}
+
+async fn serve_file(
+    Path((job_folder, filename)): Path<(String, String)>,
+    request: Request<Body>,
+) -> Response {
+    if !job_folder.starts_with(JOB_FOLDER_PREFIX)
+        || !is_safe_path_component(&job_folder)
+        || !is_safe_path_component(&filename)
+    {
+        println!("⚠️ Rejected file request for '{}/{}'", job_folder, filename);
+        return (StatusCode::BAD_REQUEST, "Invalid file path").into_response();
+    }
+
+    // Path has already decoded both segments, so serve the file directly instead of
+    // rebuilding a URI for ServeDir that would need them encoded again
+    let path = std::path::Path::new(&job_folder).join(&filename);
+    match ServeFile::new(path).oneshot(request).await {
+        Ok(response) => response.map(boxed),
+        Err(e) => {
+            println!("Error serving file: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to serve file").into_response()
+        }
+    }
+}
+
+// Confirmation of a deleted job folder
+#[derive(Debug, Serialize)]
+pub struct DeleteJobResponse {
+    pub success: bool,
+    pub job_folder: String,
+    // Disk space given back; files still linked from other jobs (deduplicated) don't count
+    pub freed_bytes: u64,
+}
+
+// Delete a finished job's folder and everything in it
+async fn delete_job_folder(Path(job_folder): Path<String>) -> Result<Json<DeleteJobResponse>, AppError> {
+    if !job_folder.starts_with(JOB_FOLDER_PREFIX) || !is_safe_path_component(&job_folder) {
+        println!("⚠️ Rejected delete request for '{}'", job_folder);
+        return Err(AppError::BadRequest("Invalid job folder".to_string()));
+    }
+    // symlink_metadata so a link named like a job folder is never followed
+    let is_folder = tokio::fs::symlink_metadata(&job_folder).await.is_ok_and(|m| m.is_dir());
+    if !is_folder {
+        return Err(AppError::NotFound(format!("Job folder '{}' doesn't exist", job_folder)));
+    }
+    let folder = job_folder.clone();
+    let freed_bytes = tokio::task::spawn_blocking(move || {
+        let freed_bytes = reclaimable_bytes(std::path::Path::new(&folder))?;
+        std::fs::remove_dir_all(&folder)?;
+        Ok::<_, std::io::Error>(freed_bytes)
+    })
+    .await
+    .map_err(|e| AppError::InternalServerError(format!("Delete task failed: {}", e)))?
+    .map_err(|e| AppError::InternalServerError(format!("Failed to delete '{}': {}", job_folder, e)))?;
+    // The backend's copy, when it keeps one apart from the job folder (STORAGE_LOCAL_ROOT, S3)
+    let freed_bytes = freed_bytes + storage().remove_folder(&job_folder).await
+        .map_err(|e| AppError::InternalServerError(format!("Failed to delete the stored copy of '{}': {}", job_folder, e)))?;
+    println!("🗑️ Deleted job folder {} ({} bytes freed)", job_folder, freed_bytes);
+    Ok(Json(DeleteJobResponse { success: true, job_folder, freed_bytes }))
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/files/:job_folder/:filename", get(serve_file))
+        .route("/api/files/:job_folder", delete(delete_job_folder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_url_encodes_each_segment() {
+        assert_eq!(file_url("insta_1", "reel.mp4"), "/api/files/insta_1/reel.mp4");
+        assert_eq!(
+            file_url("insta_1", "My reel #1? été.mp4"),
+            "/api/files/insta_1/My%20reel%20%231%3F%20%C3%A9t%C3%A9.mp4"
+        );
+        assert_eq!(file_url("insta_1", "a/b"), "/api/files/insta_1/a%2Fb");
+    }
+
+    #[test]
+    fn unsafe_components_are_rejected() {
+        assert!(is_safe_path_component("reel.mp4"));
+        assert!(!is_safe_path_component(""));
+        assert!(!is_safe_path_component(".."));
+        assert!(!is_safe_path_component("a/b"));
+        assert!(!is_safe_path_component("a\\b"));
+    }
+
+    #[tokio::test]
+    async fn files_in_job_folders_are_served() {
+        use crate::utils::test_server::body_bytes;
+        let folder = format!("insta_test_serve_{}", crate::utils::job::new_job_id());
+        std::fs::create_dir(&folder).unwrap();
+        std::fs::write(format!("{}/My reel #1.mp4", folder), b"video bytes").unwrap();
+        let request = || Request::builder().uri("/").body(Body::empty()).unwrap();
+
+        let response = serve_file(Path((folder.clone(), "My reel #1.mp4".to_string())), request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "video/mp4");
+        assert_eq!(body_bytes(response).await, b"video bytes");
+
+        let response = serve_file(Path((folder.clone(), "missing.mp4".to_string())), request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = serve_file(Path((folder.clone(), "../Cargo.toml".to_string())), request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_folder_and_reports_freed_bytes() {
+        let folder = format!("insta_test_delete_{}", crate::utils::job::new_job_id());
+        std::fs::create_dir_all(format!("{}/nested", folder)).unwrap();
+        std::fs::write(format!("{}/reel.mp4", folder), vec![0u8; 1000]).unwrap();
+        std::fs::write(format!("{}/nested/cover.jpg", folder), vec![0u8; 24]).unwrap();
+
+        let Json(response) = delete_job_folder(Path(folder.clone())).await.unwrap();
+        assert!(response.success);
+        assert_eq!(response.job_folder, folder);
+        assert_eq!(response.freed_bytes, 1024);
+        assert!(!std::path::Path::new(&folder).exists());
+    }
+
+    #[tokio::test]
+    async fn delete_rejects_traversal_and_reports_missing_folders() {
+        for folder in ["../insta_x", "insta_../src", "insta_a\\..", "src", ".insta_x"] {
+            let err = delete_job_folder(Path(folder.to_string())).await.unwrap_err();
+            assert!(matches!(err, AppError::BadRequest(_)), "{}: {:?}", folder, err);
+        }
+        assert!(std::path::Path::new("src").exists());
+
+        let err = delete_job_folder(Path("insta_test_missing_folder".to_string())).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
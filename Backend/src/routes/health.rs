@@ -1,31 +1,152 @@
 use axum::{
     routing::get,
     Json, Router,
-    extract::State,
+    extract::{Query, State},
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use headless_chrome::Browser;
+use crate::state::AppState;
+use crate::services::extractor::create_browser_client;
+use crate::services::browser_pool::browser_pool;
+use crate::services::metrics::{extraction_metrics, MetricsSnapshot};
+use crate::services::queue::download_queue;
 
 #[derive(Serialize)]
 struct HealthResponse {
     status: String,
     version: String,
     browser_available: bool,
+    // Only reported by the deep probe (?deep=true)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webdriver_healthy: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webdriver_latency_ms: Option<u64>,
+    // Downloads holding a queue slot, out of MAX_QUEUE
+    queue_depth: usize,
+    queue_capacity: usize,
+    // Pooled WebDriver sessions waiting to be checked out, out of BROWSER_POOL_SIZE
+    browser_pool_idle: usize,
+    browser_pool_size: usize,
+    // Successes and failures of yt-dlp, the browser path and the headless reel fallback since startup
+    extraction: MetricsSnapshot,
 }
 
-async fn health_check(State(browser): State<Arc<Browser>>) -> Json<HealthResponse> {
+#[derive(Deserialize)]
+struct HealthParams {
+    deep: Option<bool>,
+}
+
+// A hung chromedriver must not hang the health check with it
+const WEBDRIVER_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Open a WebDriver session and run a trivial script, returning whether it worked and how long it took
+async fn probe_webdriver() -> (bool, u64) {
+    timed_probe(
+        async {
+            match create_browser_client("chrome").await {
+                Ok(client) => {
+                    let ok = client.execute("return 1", vec![]).await
+                        .map(|v| v.as_i64() == Some(1))
+                        .unwrap_or(false);
+                    let _ = client.close().await;
+                    ok
+                }
+                Err(_) => false,
+            }
+        },
+        WEBDRIVER_PROBE_TIMEOUT,
+    )
+    .await
+}
+
+// Run `probe`, counting it as unhealthy once it takes longer than `limit`
+async fn timed_probe(probe: impl Future<Output = bool>, limit: Duration) -> (bool, u64) {
+    let started = Instant::now();
+    let healthy = tokio::time::timeout(limit, probe).await.unwrap_or(false);
+    (healthy, started.elapsed().as_millis() as u64)
+}
+
+async fn health_check(
+    State(browser): State<Option<Arc<Browser>>>,
+    Query(params): Query<HealthParams>,
+) -> Json<HealthResponse> {
     // Check if the browser is available by attempting to get its version
-    let browser_status = browser.get_version().is_ok();
-    
-    Json(HealthResponse {
+    let browser_status = browser.is_some_and(|browser| browser.get_version().is_ok());
+
+    // The WebDriver round trip is expensive, so only run it when explicitly asked
+    let webdriver = if params.deep.unwrap_or(false) {
+        Some(probe_webdriver().await)
+    } else {
+        None
+    };
+    Json(health_response(browser_status, webdriver))
+}
+
+// The report for a browser availability and, from the deep probe, WebDriver health and latency
+fn health_response(browser_available: bool, webdriver: Option<(bool, u64)>) -> HealthResponse {
+    HealthResponse {
         status: "ok".to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
-        browser_available: browser_status,
-    })
+        browser_available,
+        webdriver_healthy: webdriver.map(|(healthy, _)| healthy),
+        webdriver_latency_ms: webdriver.map(|(_, latency)| latency),
+        queue_depth: download_queue().depth(),
+        queue_capacity: download_queue().capacity(),
+        browser_pool_idle: browser_pool().idle_count(),
+        browser_pool_size: browser_pool().size(),
+        extraction: extraction_metrics().snapshot(),
+    }
 }
 
-pub fn routes() -> Router<Arc<Browser>> {
+pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/api/health", get(health_check))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hung_probes_time_out_as_unhealthy() {
+        let (healthy, latency) = timed_probe(std::future::pending(), Duration::from_millis(50)).await;
+        assert!(!healthy);
+        assert!((50..1000).contains(&latency), "latency {}", latency);
+        assert!(timed_probe(async { true }, Duration::from_secs(1)).await.0);
+    }
+
+    #[test]
+    fn webdriver_fields_only_appear_after_a_deep_probe() {
+        let shallow = serde_json::to_value(health_response(false, None)).unwrap();
+        for field in ["status", "version", "browser_available", "queue_depth", "queue_capacity", "browser_pool_idle", "browser_pool_size", "extraction"] {
+            assert!(shallow.get(field).is_some(), "missing {}", field);
+        }
+        assert_eq!(shallow["status"], "ok");
+        assert!(shallow.get("webdriver_healthy").is_none());
+        assert!(shallow.get("webdriver_latency_ms").is_none());
+
+        let deep = serde_json::to_value(health_response(true, Some((false, 5000)))).unwrap();
+        assert_eq!(deep["webdriver_healthy"], false);
+        assert_eq!(deep["webdriver_latency_ms"], 5000);
+    }
+
+    #[tokio::test]
+    async fn health_is_served_without_a_browser() {
+        use tower::ServiceExt;
+        let state = AppState {
+            browser: None,
+            config: Arc::new(crate::config::Config::current().clone()),
+            download_limiter: crate::services::downloader::DownloadLimiter::new(1),
+        };
+        let request = axum::http::Request::get("/api/health").body(axum::body::Body::empty()).unwrap();
+        let response = routes().with_state(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::utils::test_server::body_bytes(response).await;
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["browser_available"], false);
+    }
+}
@@ -0,0 +1,26 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use crate::services::jobs::{self, Job};
+use crate::state::AppState;
+use crate::utils::error::AppError;
+
+// Status of a background download, for clients polling after `?async=true`
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{job_id}",
+    tag = "download",
+    params(("job_id" = String, Path, description = "Id returned when the download was queued")),
+    responses(
+        (status = 200, description = "The job's status, and its message once finished", body = Job),
+        (status = 404, description = "Unknown job, or finished more than an hour ago", body = ErrorResponse),
+    )
+)]
+pub async fn job_handler(Path(job_id): Path<String>) -> Result<Json<Job>, AppError> {
+    jobs::get(&job_id)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("No job '{}'", job_id)))
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/jobs/:job_id", get(job_handler))
+}
@@ -1,2 +1,8 @@
 pub mod download;
 pub mod health;
+pub mod files;
+pub mod thumbnail;
+pub mod config;
+pub mod openapi;
+pub mod validate;
+pub mod jobs;
@@ -0,0 +1,59 @@
+use axum::{routing::get, Json, Router};
+use utoipa::OpenApi;
+use crate::routes::download::{DownloadOptions, DownloadRequest, MediaItem, PreviewRequest, PreviewResponse, QueuedDownloadResponse};
+use crate::routes::validate::ValidateResponse;
+use crate::services::extractor::PostMetadata;
+use crate::services::jobs::{Job, JobStatus};
+use crate::state::AppState;
+use crate::utils::error::ErrorResponse;
+
+// Generated from the request/response types and handler annotations, so it can't drift from them
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Insta-Downloader API"),
+    paths(
+        crate::routes::download::preview_handler,
+        crate::routes::download::preview_get_handler,
+        crate::routes::download::handle_download,
+        crate::routes::jobs::job_handler,
+        crate::routes::download::media_proxy_handler,
+        crate::routes::download::media_head_handler,
+        crate::routes::validate::validate_handler,
+    ),
+    components(schemas(PreviewRequest, PreviewResponse, MediaItem, PostMetadata, DownloadRequest, DownloadOptions, QueuedDownloadResponse, Job, JobStatus, ErrorResponse, ValidateResponse))
+)]
+pub struct ApiDoc;
+
+async fn openapi_handler() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/openapi.json", get(openapi_handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn document_is_json_listing_preview_download_and_media() {
+        let state = AppState {
+            browser: None,
+            config: Arc::new(crate::config::Config::current().clone()),
+            download_limiter: crate::services::downloader::DownloadLimiter::new(1),
+        };
+        let request = axum::http::Request::get("/api/openapi.json").body(axum::body::Body::empty()).unwrap();
+        let response = routes().with_state(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::utils::test_server::body_bytes(response).await;
+        let document: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+        for path in ["/api/preview", "/api/download", "/api/media"] {
+            assert!(document["paths"].get(path).is_some(), "missing {}", path);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use axum::{
+    body::Body,
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use bytes::Bytes;
+use futures::StreamExt;
+use image::imageops::FilterType;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tower_http::timeout::TimeoutLayer;
+use crate::state::AppState;
+use crate::services::downloader::{encode_jpeg, media_client_builder, parse_jpeg_quality};
+use crate::utils::error::AppError;
+use crate::utils::url_guard::{media_redirect_policy, validate_media_url};
+
+const DEFAULT_THUMBNAIL_WIDTH: u32 = 320;
+const MAX_THUMBNAIL_WIDTH: u32 = 1080;
+// Refuse to decode anything bigger than this; Instagram images are a few MB at most
+const MAX_SOURCE_BYTES: u64 = 20 * 1024 * 1024;
+const THUMBNAIL_TIMEOUT_SECS: u64 = 30;
+// Thumbnails are cached briefly so a preview grid re-rendering doesn't refetch every image
+const THUMBNAIL_CACHE_TTL: Duration = Duration::from_secs(300);
+const THUMBNAIL_CACHE_CAPACITY: usize = 256;
+
+// Keyed by source URL, width and JPEG quality
+type ThumbnailCache = Mutex<HashMap<(String, u32, u8), (Instant, Bytes)>>;
+
+static CACHE: OnceLock<ThumbnailCache> = OnceLock::new();
+
+fn cache() -> &'static ThumbnailCache {
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn cached_thumbnail(url: &str, width: u32, quality: u8) -> Option<Bytes> {
+    let cache = cache().lock().unwrap();
+    cache
+        .get(&(url.to_string(), width, quality))
+        .filter(|(stored_at, _)| stored_at.elapsed() < THUMBNAIL_CACHE_TTL)
+        .map(|(_, bytes)| bytes.clone())
+}
+
+fn store_thumbnail(url: &str, width: u32, quality: u8, bytes: Bytes) {
+    let mut cache = cache().lock().unwrap();
+    cache.retain(|_, (stored_at, _)| stored_at.elapsed() < THUMBNAIL_CACHE_TTL);
+    if cache.len() < THUMBNAIL_CACHE_CAPACITY {
+        cache.insert((url.to_string(), width, quality), (Instant::now(), bytes));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailParams {
+    url: String,
+    w: Option<u32>,
+    // JPEG quality, 1 to 100
+    quality: Option<u64>,
+}
+
+/// Downscale `source` to at most `width` pixels wide (never upscaling) and encode it as a
+/// JPEG of the given quality.
+pub fn resize_to_jpeg(source: &[u8], width: u32, quality: u8) -> Result<Vec<u8>, String> {
+    let image = image::load_from_memory(source).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let image = if image.width() > width {
+        image.resize(width, u32::MAX, FilterType::Triangle)
+    } else {
+        image
+    };
+    encode_jpeg(&image, quality).map_err(|e| format!("Failed to encode thumbnail: {}", e))
+}
+
+fn jpeg_response(bytes: Bytes) -> Response {
+    Response::builder()
+        .header("Content-Type", "image/jpeg")
+        .header("Cache-Control", format!("public, max-age={}", THUMBNAIL_CACHE_TTL.as_secs()))
+        .body(Body::from(bytes))
+        .unwrap()
+        .into_response()
+}
+
+async fn thumbnail_handler(Query(params): Query<ThumbnailParams>) -> Result<Response, AppError> {
+    let width = params.w.unwrap_or(DEFAULT_THUMBNAIL_WIDTH).clamp(1, MAX_THUMBNAIL_WIDTH);
+    let quality = parse_jpeg_quality(params.quality).map_err(AppError::BadRequest)?;
+    let url = validate_media_url(&params.url).map_err(|reason| {
+        println!("⚠️ Refusing thumbnail for {}: {}", params.url, reason);
+        AppError::BadRequest(reason)
+    })?;
+
+    if let Some(bytes) = cached_thumbnail(url.as_str(), width, quality) {
+        return Ok(jpeg_response(bytes));
+    }
+
+    let client = media_client_builder()
+        .redirect(media_redirect_policy())
+        .build()
+        .map_err(|e| AppError::InternalServerError(format!("Failed to build the media client: {}", e)))?;
+    let response = client.get(url.clone()).send().await.map_err(|e| {
+        println!("Request error: {}", e);
+        AppError::NetworkError(format!("Error fetching from upstream server: {}", e))
+    })?;
+    if !response.status().is_success() {
+        println!("Upstream server error: {}", response.status());
+        return Err(AppError::NetworkError(format!("Upstream server returned: {}", response.status())));
+    }
+    let source = read_image(response, MAX_SOURCE_BYTES).await?;
+
+    // Decoding and resizing is CPU-bound, keep it off the async workers
+    let jpeg = tokio::task::spawn_blocking(move || resize_to_jpeg(&source, width, quality))
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("Thumbnail task failed: {}", e)))?
+        .map_err(|e| AppError::UnprocessableMedia(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let bytes = Bytes::from(jpeg);
+    store_thumbnail(url.as_str(), width, quality, bytes.clone());
+    Ok(jpeg_response(bytes))
+}
+
+// Read an image body, giving up once it passes `max_bytes`: Content-Length may be missing
+// or understate what the server actually sends
+async fn read_image(response: reqwest::Response, max_bytes: u64) -> Result<Vec<u8>, AppError> {
+    let too_large = || AppError::UnprocessableMedia(StatusCode::PAYLOAD_TOO_LARGE, "Image is too large to thumbnail".to_string());
+    // Only images can be thumbnailed; videos go through /api/media
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    if !content_type.starts_with("image/") {
+        return Err(AppError::UnprocessableMedia(StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Not an image: '{}'", content_type)));
+    }
+    if response.content_length().is_some_and(|len| len > max_bytes) {
+        return Err(too_large());
+    }
+    let mut source = Vec::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| {
+            println!("Error fetching bytes: {}", e);
+            AppError::NetworkError(format!("Failed to fetch image bytes: {}", e))
+        })?;
+        if (source.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large());
+        }
+        source.extend_from_slice(&chunk);
+    }
+    Ok(source)
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/thumbnail",
+            get(thumbnail_handler).layer(TimeoutLayer::new(Duration::from_secs(THUMBNAIL_TIMEOUT_SECS))),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{response, TestServer};
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, 128])));
+        let mut bytes = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn images_are_resized_to_the_requested_width() {
+        let jpeg = resize_to_jpeg(&png(800, 600), 320, 80).unwrap();
+        let thumbnail = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!(thumbnail.width(), 320);
+        assert!(thumbnail.height().abs_diff(240) <= 1, "height {}", thumbnail.height());
+
+        // Narrower images are never upscaled
+        let jpeg = resize_to_jpeg(&png(100, 50), 320, 80).unwrap();
+        assert_eq!(image::load_from_memory(&jpeg).unwrap().width(), 100);
+        assert!(resize_to_jpeg(b"not an image", 320, 80).is_err());
+    }
+
+    #[tokio::test]
+    async fn image_reads_stop_at_the_size_limit() {
+        let server = TestServer::start(|request| {
+            if request.contains("/chunked") {
+                // No Content-Length, so only the streamed byte count can catch it
+                let mut raw = b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+                for _ in 0..4 {
+                    raw.extend_from_slice(format!("{:x}\r\n", 512).as_bytes());
+                    raw.extend_from_slice(&[0u8; 512]);
+                    raw.extend_from_slice(b"\r\n");
+                }
+                raw.extend_from_slice(b"0\r\n\r\n");
+                raw
+            } else if request.contains("/video") {
+                response("200 OK", &[("Content-Type", "video/mp4")], b"mp4")
+            } else {
+                response("200 OK", &[("Content-Type", "image/png")], &[0u8; 1000])
+            }
+        }).await;
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(server.url(path)).send();
+
+        assert_eq!(read_image(get("/small").await.unwrap(), 1024).await.unwrap().len(), 1000);
+        for path in ["/small", "/chunked"] {
+            let err = read_image(get(path).await.unwrap(), 999).await.unwrap_err();
+            assert_eq!(err.into_response().status(), StatusCode::PAYLOAD_TOO_LARGE, "{}", path);
+        }
+        let err = read_image(get("/video").await.unwrap(), 1024).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -0,0 +1,133 @@
+use axum::{extract::{Query, State}, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use crate::config::Config;
+use crate::services::extractor::{is_feed_url, is_post_url, is_reel_url, is_share_url, is_story_url, shortcode_from_url, FEED_URL_ERROR};
+use crate::state::AppState;
+use crate::utils::url_guard::{expand_story_username, normalize_instagram_url};
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ValidateParams {
+    url: String,
+}
+
+/// Result of checking a URL offline: what a preview or download would treat it as
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ValidateResponse {
+    pub valid: bool,
+    // "post", "reel", "story", or "share" for /share/ links (resolved when downloading)
+    pub content_type: Option<String>,
+    pub shortcode: Option<String>,
+    // The canonical https://www.instagram.com/<path> form
+    pub normalized_url: Option<String>,
+    // Why the URL was rejected
+    pub reason: Option<String>,
+}
+
+impl ValidateResponse {
+    fn invalid(reason: String, normalized_url: Option<String>) -> Self {
+        Self { valid: false, content_type: None, shortcode: None, normalized_url, reason: Some(reason) }
+    }
+}
+
+/// Classify `raw` the way the preview and download endpoints would, without any network call.
+pub fn validate_url(raw: &str, config: &Config) -> ValidateResponse {
+    let expanded = match expand_story_username(raw) {
+        Ok(expanded) => expanded,
+        Err(reason) => return ValidateResponse::invalid(reason, None),
+    };
+    let raw = expanded.as_str();
+    let normalized = match normalize_instagram_url(raw) {
+        Ok(normalized) => normalized,
+        Err(reason) => return ValidateResponse::invalid(reason, None),
+    };
+    if let Err(reason) = config.allowed_url_patterns.check(&normalized) {
+        return ValidateResponse::invalid(reason, Some(normalized));
+    }
+    if is_feed_url(&normalized) {
+        return ValidateResponse::invalid(FEED_URL_ERROR.to_string(), Some(normalized));
+    }
+    let content_type = if is_story_url(&normalized) {
+        "story"
+    } else if is_reel_url(&normalized) {
+        "reel"
+    } else if is_post_url(&normalized) {
+        "post"
+    } else if is_share_url(&normalized) {
+        "share"
+    } else {
+        return ValidateResponse::invalid(
+            format!("'{}' doesn't point at a post, reel or story", raw.trim()),
+            Some(normalized),
+        );
+    };
+    let shortcode = shortcode_from_url(&normalized);
+    if matches!(content_type, "post" | "reel") && shortcode.is_none() {
+        return ValidateResponse::invalid(format!("'{}' has no valid shortcode", raw.trim()), Some(normalized));
+    }
+    ValidateResponse {
+        valid: true,
+        content_type: Some(content_type.to_string()),
+        shortcode,
+        normalized_url: Some(normalized),
+        reason: None,
+    }
+}
+
+// Cheap enough to call on every (debounced) keystroke: no browser, no HTTP request
+#[utoipa::path(
+    get,
+    path = "/api/validate",
+    tag = "preview",
+    params(ValidateParams),
+    responses(
+        (status = 200, description = "Whether the URL can be previewed or downloaded, and as what", body = ValidateResponse),
+    )
+)]
+pub async fn validate_handler(
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ValidateParams>,
+) -> Json<ValidateResponse> {
+    Json(validate_url(&params.url, &config))
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/validate", get(validate_handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reels_posts_and_stories_are_recognized() {
+        let config = Config::current();
+        let reel = validate_url("https://www.instagram.com/reel/C1a2B3c4D5e/?igsh=abc", config);
+        assert!(reel.valid, "{:?}", reel);
+        assert_eq!(reel.content_type.as_deref(), Some("reel"));
+        assert_eq!(reel.shortcode.as_deref(), Some("C1a2B3c4D5e"));
+        assert_eq!(reel.reason, None);
+
+        let post = validate_url("https://instagram.com/p/C1a2B3c4D5e/", config);
+        assert!(post.valid, "{:?}", post);
+        assert_eq!(post.content_type.as_deref(), Some("post"));
+        assert!(post.normalized_url.unwrap().starts_with("https://www.instagram.com/p/C1a2B3c4D5e"));
+
+        let story = validate_url("https://www.instagram.com/stories/natgeo/3300000000000000000/", config);
+        assert!(story.valid, "{:?}", story);
+        assert_eq!(story.content_type.as_deref(), Some("story"));
+    }
+
+    #[test]
+    fn other_inputs_are_rejected_with_a_reason() {
+        let config = Config::current();
+        for raw in ["", "not a url", "https://example.com/p/C1a2B3c4D5e/", "https://www.instagram.com/", "https://www.instagram.com/explore/"] {
+            let result = validate_url(raw, config);
+            assert!(!result.valid, "{} was accepted: {:?}", raw, result);
+            assert!(result.reason.is_some_and(|reason| !reason.is_empty()), "{}", raw);
+            assert_eq!(result.content_type, None);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use async_trait::async_trait;
+use fantoccini::Client;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::config::Config;
+use crate::services::downloader::DownloadError;
+use crate::services::extractor::create_browser_client;
+
+// Pooled WebDriver sessions; 0 keeps the old behaviour of one fresh session per request
+const DEFAULT_BROWSER_POOL_SIZE: usize = 0;
+
+/// What the pool does with a session; implemented for fantoccini's `Client`.
+#[async_trait]
+pub trait PooledSession: Send + Sync + Sized + 'static {
+    /// Open a new session for `browser`
+    async fn open(browser: &str) -> Result<Self, DownloadError>;
+    /// Whether the session still answers; chromedriver may drop idle ones
+    async fn is_alive(&self) -> bool;
+    /// Wipe what a request left behind so the next one starts clean
+    async fn reset(&self) -> Result<(), String>;
+    async fn close(self);
+}
+
+#[async_trait]
+impl PooledSession for Client {
+    async fn open(browser: &str) -> Result<Self, DownloadError> {
+        create_browser_client(browser).await
+    }
+
+    async fn is_alive(&self) -> bool {
+        self.current_url().await.is_ok()
+    }
+
+    async fn reset(&self) -> Result<(), String> {
+        reset_session(self).await.map_err(|e| e.to_string())
+    }
+
+    async fn close(self) {
+        let _ = Client::close(self).await;
+    }
+}
+
+/// Pre-warmed WebDriver sessions shared between requests, so extractions skip the connection
+/// and stealth setup. At most `size` sessions are checked out at once; requests beyond that get
+/// an on-demand session that is closed after use.
+pub struct BrowserPool<S: PooledSession = Client> {
+    idle: Mutex<Vec<S>>,
+    slots: Arc<Semaphore>,
+    size: usize,
+}
+
+static BROWSER_POOL: OnceLock<BrowserPool> = OnceLock::new();
+
+// Parse BROWSER_POOL_SIZE, falling back to the default on missing or invalid values
+pub fn parse_browser_pool_size(value: Option<&str>) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_BROWSER_POOL_SIZE)
+}
+
+/// The server-wide session pool, sized from BROWSER_POOL_SIZE on first use.
+pub fn browser_pool() -> &'static BrowserPool {
+    BROWSER_POOL.get_or_init(|| BrowserPool::new(Config::current().browser_pool_size))
+}
+
+/// A WebDriver session checked out for one request. Derefs to the fantoccini `Client`;
+/// hand it back with `release` instead of closing it.
+pub struct BrowserSession<S: PooledSession = Client> {
+    client: S,
+    // The pool a pooled session goes back to, and the slot it holds meanwhile; `None` for
+    // on-demand sessions
+    slot: Option<(&'static BrowserPool<S>, OwnedSemaphorePermit)>,
+}
+
+impl<S: PooledSession> Deref for BrowserSession<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.client
+    }
+}
+
+impl<S: PooledSession> DerefMut for BrowserSession<S> {
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.client
+    }
+}
+
+impl<S: PooledSession> BrowserPool<S> {
+    pub fn new(size: usize) -> Self {
+        Self { idle: Mutex::new(Vec::new()), slots: Arc::new(Semaphore::new(size)), size }
+    }
+
+    /// Open sessions until `size` are idle. Failures are logged and leave the pool smaller;
+    /// the missing sessions are created on first checkout instead.
+    pub async fn warm(&self) {
+        let missing = self.size.saturating_sub(self.idle_count());
+        for _ in 0..missing {
+            match S::open("chrome").await {
+                Ok(client) => self.idle.lock().unwrap().push(client),
+                Err(e) => {
+                    println!("⚠️ Failed to pre-warm a browser session: {}", e);
+                    break;
+                }
+            }
+        }
+        if self.size > 0 {
+            println!("🧰 Browser pool ready with {}/{} sessions", self.idle_count(), self.size);
+        }
+    }
+
+    /// Take an idle session (or open one for a free slot), or an on-demand session when
+    /// every pooled one is in use or pooling is off.
+    pub async fn checkout(&'static self, browser: &str) -> Result<BrowserSession<S>, DownloadError> {
+        let slot = match Arc::clone(&self.slots).try_acquire_owned() {
+            Ok(slot) => slot,
+            Err(_) => {
+                if self.size > 0 {
+                    println!("🧰 Browser pool exhausted, opening an extra session");
+                }
+                return Ok(BrowserSession { client: S::open(browser).await?, slot: None });
+            }
+        };
+        loop {
+            let Some(client) = self.idle.lock().unwrap().pop() else { break };
+            // chromedriver may have dropped an idle session; don't hand out a dead one
+            if client.is_alive().await {
+                return Ok(BrowserSession { client, slot: Some((self, slot)) });
+            }
+            println!("⚠️ Discarding a dead pooled browser session");
+            client.close().await;
+        }
+        Ok(BrowserSession { client: S::open(browser).await?, slot: Some((self, slot)) })
+    }
+
+    /// Sessions waiting to be checked out
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl<S: PooledSession> BrowserSession<S> {
+    /// Return a pooled session after wiping what the request left behind (storage, cookies,
+    /// the open page), or close it if it's an on-demand session or the reset fails.
+    pub async fn release(self) {
+        let BrowserSession { client, slot } = self;
+        let Some((pool, slot)) = slot else {
+            client.close().await;
+            return;
+        };
+        match client.reset().await {
+            Ok(()) => pool.idle.lock().unwrap().push(client),
+            Err(e) => {
+                println!("⚠️ Failed to reset a pooled browser session, closing it: {}", e);
+                client.close().await;
+            }
+        }
+        drop(slot);
+    }
+}
+
+// Clear the caller's session state so the next request starts logged out on a blank page.
+// Cookies are cleared before leaving the page since WebDriver only reaches the current domain's.
+async fn reset_session(client: &Client) -> Result<(), fantoccini::error::CmdError> {
+    let _ = client.execute("try { localStorage.clear(); sessionStorage.clear(); } catch (e) {}", vec![]).await;
+    client.delete_all_cookies().await?;
+    client.goto("about:blank").await
+}
+
+/// Check out a browser session from the shared pool; see `BrowserPool::checkout`.
+pub async fn checkout_browser_client(browser: &str) -> Result<BrowserSession, DownloadError> {
+    browser_pool().checkout(browser).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts what the pool does with it instead of talking to chromedriver
+    struct MockSession {
+        id: usize,
+        alive: bool,
+        resets: Arc<AtomicUsize>,
+        closed: Arc<AtomicUsize>,
+    }
+
+    static OPENED: AtomicUsize = AtomicUsize::new(0);
+
+    #[async_trait]
+    impl PooledSession for MockSession {
+        async fn open(_browser: &str) -> Result<Self, DownloadError> {
+            let id = 100 + OPENED.fetch_add(1, Ordering::SeqCst);
+            Ok(Self { id, alive: true, resets: Default::default(), closed: Default::default() })
+        }
+
+        async fn is_alive(&self) -> bool {
+            self.alive
+        }
+
+        async fn reset(&self) -> Result<(), String> {
+            self.resets.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn close(self) {
+            self.closed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_are_reset_and_reused_within_the_pool_size() {
+        let pool: &'static BrowserPool<MockSession> = Box::leak(Box::new(BrowserPool::new(1)));
+        let resets = Arc::new(AtomicUsize::new(0));
+        let closed = Arc::new(AtomicUsize::new(0));
+        pool.idle.lock().unwrap().push(MockSession { id: 1, alive: true, resets: resets.clone(), closed: closed.clone() });
+
+        let session = pool.checkout("chrome").await.unwrap();
+        assert_eq!(session.id, 1);
+        assert_eq!(pool.idle_count(), 0);
+        // The only slot is taken, so this one is opened on demand and closed on release
+        let extra = pool.checkout("chrome").await.unwrap();
+        assert!(extra.id >= 100);
+        let extra_closed = extra.closed.clone();
+        extra.release().await;
+        assert_eq!(extra_closed.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.idle_count(), 0);
+
+        session.release().await;
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+        assert_eq!(closed.load(Ordering::SeqCst), 0);
+        assert_eq!(pool.idle_count(), 1);
+        assert_eq!(pool.checkout("chrome").await.unwrap().id, 1);
+    }
+
+    #[tokio::test]
+    async fn dead_idle_sessions_are_replaced() {
+        let pool: &'static BrowserPool<MockSession> = Box::leak(Box::new(BrowserPool::new(1)));
+        let closed = Arc::new(AtomicUsize::new(0));
+        pool.idle.lock().unwrap().push(MockSession { id: 1, alive: false, resets: Default::default(), closed: closed.clone() });
+
+        let session = pool.checkout("chrome").await.unwrap();
+        assert_ne!(session.id, 1);
+        assert_eq!(closed.load(Ordering::SeqCst), 1);
+        session.release().await;
+        assert_eq!(pool.idle_count(), 1);
+    }
+}
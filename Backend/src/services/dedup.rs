@@ -0,0 +1,67 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::time::Duration;
+use tokio::task;
+use tracing::{info, warn};
+use crate::config::Config;
+use crate::services::downloader::DownloadError;
+
+type Result<T> = std::result::Result<T, DownloadError>;
+
+// SQLite index mapping content hashes to the first file downloaded with that content
+const DEFAULT_INDEX_PATH: &str = "download_index.sqlite";
+
+// Parse DEDUP_INDEX_PATH, falling back to the default when it's unset
+pub fn parse_index_path(value: Option<&str>) -> String {
+    value.unwrap_or(DEFAULT_INDEX_PATH).to_string()
+}
+
+fn open_index() -> rusqlite::Result<Connection> {
+    let conn = Connection::open(&Config::current().dedup_index_path)?;
+    // Concurrent downloads finish at the same time, so wait for the lock instead of failing
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS media_hashes (hash TEXT PRIMARY KEY, path TEXT NOT NULL)",
+        [],
+    )?;
+    Ok(conn)
+}
+
+/// Record `path` under its SHA-256 `hash`, before the download is moved there. If identical
+/// content was already downloaded, hard-link the existing file at `path` and return `true`:
+/// the new copy then never lands in the job folder, though it was still fetched (and spooled
+/// to its `.part`) to be hashed.
+pub async fn dedupe_file(hash: String, path: String) -> Result<bool> {
+    task::spawn_blocking(move || {
+        let conn = open_index()
+            .map_err(|e| DownloadError(format!("Failed to open dedup index: {}", e)))?;
+        let existing: Option<String> = conn
+            .query_row("SELECT path FROM media_hashes WHERE hash = ?1", params![hash], |row| row.get(0))
+            .optional()
+            .map_err(|e| DownloadError(format!("Failed to query dedup index: {}", e)))?;
+
+        if let Some(existing) = existing {
+            if existing != path && Path::new(&existing).exists() {
+                // Swap in the link via a temp name so a half-made link never shows up at `path`
+                let tmp = format!("{}.dedup", path);
+                match std::fs::hard_link(&existing, &tmp).and_then(|_| std::fs::rename(&tmp, &path)) {
+                    Ok(_) => {
+                        info!("♻️ {} duplicates {}, linked instead of storing twice", path, existing);
+                        return Ok(true);
+                    }
+                    Err(e) => {
+                        let _ = std::fs::remove_file(&tmp);
+                        warn!("Failed to link duplicate {} to {}: {}", path, existing, e);
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+
+        conn.execute(
+            "INSERT OR REPLACE INTO media_hashes (hash, path) VALUES (?1, ?2)",
+            params![hash, path],
+        ).map_err(|e| DownloadError(format!("Failed to update dedup index: {}", e)))?;
+        Ok(false)
+    }).await.map_err(|e| DownloadError(format!("JoinError: {}", e)))?
+}
@@ -1,12 +1,25 @@
-use std::fs::{self, File};
-use std::io::copy;
-use std::path::Path;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Arc;
 use std::time::Duration;
 use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONNECTION, CONTENT_RANGE, RANGE, REFERER, RETRY_AFTER, USER_AGENT};
+use reqwest::StatusCode;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{Semaphore, SemaphorePermit};
 use tokio::time::sleep;
 use tracing::{info, warn, error};
 use rand::random;
+use crate::config::Config;
+use crate::services::{dedup, storage};
+use crate::services::extractor::parse_cookie_header;
+use crate::services::http_client::client_builder;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 // Define DownloadError here instead of importing it from crate root
 #[derive(Debug)]
@@ -20,20 +33,172 @@ impl std::fmt::Display for DownloadError {
 
 impl std::error::Error for DownloadError {} // Implement Error trait for better error handling
 
+/// Message every disk-full `DownloadError` starts with
+pub const DISK_FULL: &str = "Not enough disk space to save the download";
+
+impl DownloadError {
+    /// The disk filled up while writing `path`; retrying won't help
+    pub fn disk_full(path: &Path) -> Self {
+        Self(format!("{} ({})", DISK_FULL, path.display()))
+    }
+
+    pub fn is_disk_full(&self) -> bool {
+        self.0.starts_with(DISK_FULL)
+    }
+}
+
+// Whether a failed write means there is no space left (ENOSPC, quota, or a write that stored nothing)
+fn is_disk_full_error(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(e.kind(), ErrorKind::StorageFull | ErrorKind::QuotaExceeded | ErrorKind::WriteZero)
+}
+
+// Map an IO error from writing `path`, keeping disk-full errors distinct from other failures
+fn write_error(context: &str, path: &Path, e: std::io::Error) -> DownloadError {
+    if is_disk_full_error(&e) {
+        DownloadError::disk_full(path)
+    } else {
+        DownloadError(format!("{}: {}", context, e))
+    }
+}
+
 // Update the Result type to include the Error type
 type Result<T = ()> = std::result::Result<T, DownloadError>;
 
 // Constants
 const MAX_RETRY: usize = 5; // Increased from 3
+/// Highest `max_retries` a request may ask for
+pub const MAX_RETRY_LIMIT: usize = 10;
 const BASE_BACKOFF_MS: u64 = 300; // Base backoff time in milliseconds
-#[allow(dead_code)]
+const DEFAULT_MAX_BACKOFF_MS: u64 = 10_000; // Upper bound for a single retry delay
+const BACKOFF_JITTER_FRACTION: f64 = 0.3; // Up to 30% random jitter on top of the backoff (partial jitter)
+const MAX_RETRY_AFTER_SECS: u64 = 60; // Longest Retry-After wait we honour before retrying
+const DEFAULT_MAX_GLOBAL_DOWNLOADS: usize = 32; // Media fetches in flight across all requests
+const DEFAULT_YTDLP_CONCURRENT_FRAGMENTS: usize = 5; // Fragments yt-dlp fetches in parallel
+const MAX_YTDLP_CONCURRENT_FRAGMENTS: usize = 16; // Higher values mostly trigger rate limits
+
+const DEFAULT_MEDIA_USER_AGENT: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/110.0.5481.177 Mobile/15E148 Safari/604.1";
+const DEFAULT_MEDIA_REFERER: &str = "https://www.instagram.com/";
 const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunks for better memory management
 
-/// Download media from a direct URL with retries.
-pub async fn download_media_with_retry(client: &Client, url: &str, filename: &str) -> Result<()> {
-    let mut retry_count = 0;
-    let mut last_error = None;
-    
+/// Headers sent with every media fetch: the Instagram defaults, with the Referer replaced by
+/// `MEDIA_REFERER` and any header overridden or added by `MEDIA_HEADERS`.
+#[derive(Clone)]
+pub struct MediaHeaders {
+    headers: HeaderMap,
+}
+
+// Names only: MEDIA_HEADERS values may carry tokens
+impl std::fmt::Debug for MediaHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.headers.keys()).finish()
+    }
+}
+
+impl MediaHeaders {
+    /// Build the headers from a Referer and a `|`-separated list of `Name: value` entries.
+    /// Errors name the entry that isn't a valid header.
+    pub fn parse(referer: Option<&str>, extra: Option<&str>) -> std::result::Result<Self, String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_MEDIA_USER_AGENT));
+        headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+        headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+        let referer = referer.map(str::trim).filter(|v| !v.is_empty()).unwrap_or(DEFAULT_MEDIA_REFERER);
+        let referer = HeaderValue::from_str(referer)
+            .map_err(|_| format!("MEDIA_REFERER '{}' is not a valid header value", referer))?;
+        headers.insert(REFERER, referer);
+        for entry in extra.unwrap_or_default().split('|').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || format!("MEDIA_HEADERS entry '{}' is not a valid 'Name: value' header", entry);
+            let (name, value) = entry.split_once(':').ok_or_else(invalid)?;
+            let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid())?;
+            let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+            headers.insert(name, value);
+        }
+        Ok(Self { headers })
+    }
+
+    /// The headers to send with a CDN request
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn referer(&self) -> &str {
+        self.headers.get(REFERER).and_then(|v| v.to_str().ok()).unwrap_or_default()
+    }
+
+    /// A client builder sending these headers with every request
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        client_builder().default_headers(self.headers.clone())
+    }
+
+    /// Names of the headers sent, without their values (which may hold tokens)
+    pub fn names(&self) -> Vec<String> {
+        self.headers.keys().map(|name| name.to_string()).collect()
+    }
+}
+
+// The media headers resolved at startup
+fn media_headers() -> &'static MediaHeaders {
+    &Config::current().media
+}
+
+/// Builder for clients that fetch from the CDN: sends the configured media headers by default
+pub fn media_client_builder() -> reqwest::ClientBuilder {
+    media_headers().client_builder()
+}
+
+/// A media file written to disk by `download_media_with_retry`.
+#[derive(Debug, Clone)]
+pub struct DownloadedFile {
+    pub path: String,
+    /// The content matched an earlier download and was linked to it instead of stored twice
+    pub deduped: bool,
+}
+
+// A failed download attempt: the HTTP status when the server answered with an error,
+// the wait it asked for via Retry-After (429/503), and whether the `.part` file holds a
+// valid prefix of the body that the next attempt can resume from
+#[derive(Debug)]
+struct FailedAttempt {
+    error: DownloadError,
+    status: Option<StatusCode>,
+    retry_after: Option<Duration>,
+    resumable: bool,
+}
+
+impl From<DownloadError> for FailedAttempt {
+    fn from(error: DownloadError) -> Self {
+        Self { error, status: None, retry_after: None, resumable: false }
+    }
+}
+
+/// Whether a download that failed with `status` is worth retrying. Client errors
+/// (404, 403, ...) are permanent, except 429 which asks us to come back later.
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    !status.is_client_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Validate a request's `max_retries` (download attempts per file, 1 to MAX_RETRY_LIMIT).
+pub fn parse_max_retries(max_retries: Option<usize>) -> std::result::Result<Option<usize>, String> {
+    match max_retries {
+        Some(n) if !(1..=MAX_RETRY_LIMIT).contains(&n) => {
+            Err(format!("max_retries must be between 1 and {}", MAX_RETRY_LIMIT))
+        }
+        other => Ok(other),
+    }
+}
+
+/// Download media from a direct URL, making up to `max_retries` attempts (MAX_RETRY when `None`).
+/// With `strip_metadata`, a JPEG's EXIF/XMP, IPTC and comment segments are removed before the
+/// file is deduplicated and stored; other content is saved as downloaded.
+pub async fn download_media_with_retry(
+    client: &Client,
+    limiter: &DownloadLimiter,
+    url: &str,
+    filename: &str,
+    max_retries: Option<usize>,
+    strip_metadata: bool,
+) -> Result<DownloadedFile> {
     // Create the directory if it doesn't exist
     if let Some(parent) = Path::new(filename).parent() {
         if !parent.exists() {
@@ -42,72 +207,511 @@ pub async fn download_media_with_retry(client: &Client, url: &str, filename: &st
         }
     }
     
-    while retry_count < MAX_RETRY {
-        match download_media_with_client(client, url, filename).await {
-            Ok(_) => {
+    // Kept across attempts so a dropped connection resumes where it stopped. A leftover from
+    // an earlier run may belong to other content, so only this call's attempts are resumed.
+    let part_path = part_path_for(filename)
+        .map_err(|e| DownloadError(format!("Failed to prepare temp file: {}", e)))?;
+    let _ = tokio::fs::remove_file(&part_path).await;
+    retry_download(client, limiter, url, filename, &part_path, max_retries, strip_metadata).await
+}
+
+// The attempts of `download_media_with_retry`, spooling to `part_path`
+async fn retry_download(
+    client: &Client,
+    limiter: &DownloadLimiter,
+    url: &str,
+    filename: &str,
+    part_path: &Path,
+    max_retries: Option<usize>,
+    strip_metadata: bool,
+) -> Result<DownloadedFile> {
+    let max_retries = max_retries.unwrap_or(MAX_RETRY).clamp(1, MAX_RETRY_LIMIT);
+    let mut retry_count = 0;
+    let mut last_error = None;
+    while retry_count < max_retries {
+        // Held for this attempt only, so a download waiting out its backoff doesn't take a slot
+        let permit = limiter.acquire().await?;
+        let attempt = download_media_with_client(client, url, filename, part_path, strip_metadata).await;
+        drop(permit);
+        match attempt {
+            Ok(deduped) => {
                 info!("✅ Successfully downloaded media from {}", url);
-                return Ok(());
+                storage::persist_file(filename).await?;
+                return Ok(DownloadedFile { path: filename.to_string(), deduped });
             },
-            Err(e) => {
+            Err(FailedAttempt { error, .. }) if error.is_disk_full() => {
+                // Another attempt would run into the same full disk
+                error!("Not retrying download of {}: {}", url, error);
+                let _ = tokio::fs::remove_file(part_path).await;
+                return Err(error);
+            }
+            Err(FailedAttempt { error, status: Some(status), .. }) if !is_retryable_status(status) => {
+                // Media that is gone or forbidden won't come back on a retry
+                error!("Not retrying download of {}: {}", url, error);
+                let _ = tokio::fs::remove_file(part_path).await;
+                return Err(error);
+            }
+            Err(FailedAttempt { error, retry_after, .. }) => {
                 retry_count += 1;
-                last_error = Some(e);
+                last_error = Some(error);
                 
-                // Exponential backoff with jitter for better retry strategy
-                let backoff = BASE_BACKOFF_MS * 2u64.pow(retry_count as u32);
-                let jitter = (backoff as f64 * (random::<f64>() * 0.3)).round() as u64;
-                let sleep_time = backoff + jitter;
+                // Honour the server's Retry-After when it sent one, otherwise
+                // exponential backoff with jitter
+                let sleep_time = match retry_after {
+                    Some(wait) => wait.min(Duration::from_secs(MAX_RETRY_AFTER_SECS)),
+                    None => backoff_delay(retry_count as u32, BASE_BACKOFF_MS, Config::current().max_backoff_ms, Config::current().backoff_jitter),
+                };
                 
                 warn!("Download attempt {} failed, retrying in {}ms: {:?}", 
-                    retry_count, sleep_time, last_error);
+                    retry_count, sleep_time.as_millis(), last_error);
                 
-                sleep(Duration::from_millis(sleep_time)).await;
+                sleep(sleep_time).await;
             }
         }
     }
     
-    error!("Failed to download after {} retries: {:?}", MAX_RETRY, last_error);
-    Err(DownloadError(format!("Failed after {} retries: {:?}", MAX_RETRY, last_error)))
+    let _ = tokio::fs::remove_file(part_path).await;
+    error!("Failed to download after {} retries: {:?}", max_retries, last_error);
+    Err(DownloadError(format!("Failed after {} retries: {:?}", max_retries, last_error)))
+}
+
+// Parse MAX_GLOBAL_DOWNLOADS, falling back to the default on missing or invalid values
+pub fn parse_max_global_downloads(value: Option<&str>) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_GLOBAL_DOWNLOADS)
+}
+
+// Parse YTDLP_CONCURRENT_FRAGMENTS, falling back to the default on missing, invalid or
+// out-of-range (outside 1..=16) values
+pub fn parse_ytdlp_concurrent_fragments(value: Option<&str>) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|n| (1..=MAX_YTDLP_CONCURRENT_FRAGMENTS).contains(n))
+        .unwrap_or(DEFAULT_YTDLP_CONCURRENT_FRAGMENTS)
+}
+
+/// Server-wide limit on media downloads in flight, whatever the number of requests.
+/// Per-handler semaphores only bound a single request. Lives in the app state; clones share
+/// the same permits.
+#[derive(Clone)]
+pub struct DownloadLimiter(Arc<Semaphore>);
+
+impl DownloadLimiter {
+    pub fn new(permits: usize) -> Self {
+        Self(Arc::new(Semaphore::new(permits)))
+    }
+
+    async fn acquire(&self) -> Result<SemaphorePermit<'_>> {
+        self.0
+            .acquire()
+            .await
+            .map_err(|e| DownloadError(format!("Download limiter closed: {}", e)))
+    }
+}
+
+// Parse MAX_BACKOFF_MS, falling back to the default on missing or invalid values
+pub fn parse_max_backoff_ms(value: Option<&str>) -> u64 {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|&ms| ms > 0)
+        .unwrap_or(DEFAULT_MAX_BACKOFF_MS)
+}
+
+/// How retry delays are randomised (`BACKOFF_JITTER`), so clients that failed together
+/// don't all retry at the same moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JitterStrategy {
+    /// The backoff plus up to BACKOFF_JITTER_FRACTION of it (the default)
+    Partial,
+    /// A uniformly random delay between 0 and the backoff, which spreads retries the most
+    Full,
+    /// Exactly the exponential backoff
+    None,
+}
+
+impl JitterStrategy {
+    /// Parse `BACKOFF_JITTER` (`partial`, `full` or `none`), falling back to `partial` on
+    /// missing or unknown values
+    pub fn parse(value: Option<&str>) -> Self {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            Some("full") => Self::Full,
+            Some("none") => Self::None,
+            _ => Self::Partial,
+        }
+    }
+
+    /// Randomise `backoff` milliseconds with `sample`, a random number in `[0, 1)`.
+    pub fn apply(self, backoff: u64, sample: f64) -> u64 {
+        match self {
+            Self::Partial => backoff.saturating_add((backoff as f64 * sample * BACKOFF_JITTER_FRACTION).round() as u64),
+            Self::Full => (backoff as f64 * sample).floor() as u64,
+            Self::None => backoff,
+        }
+    }
+}
+
+/// Delay before retry `attempt`: `base * 2^attempt` randomised by `jitter`, never exceeding
+/// `max` milliseconds.
+pub fn backoff_delay(attempt: u32, base: u64, max: u64, jitter: JitterStrategy) -> Duration {
+    let backoff = base
+        .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+        .min(max);
+    Duration::from_millis(jitter.apply(backoff, random::<f64>()).min(max))
+}
+
+/// Parse a `Retry-After` header value, either delay-seconds (`120`) or an HTTP-date
+/// (`Wed, 21 Oct 2015 07:28:00 GMT`) relative to `now`. Dates in the past mean "retry now".
+pub fn parse_retry_after(header: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let header = header.trim();
+    if let Ok(secs) = header.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(header).ok()?.with_timezone(&Utc);
+    Some((at - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Directory for in-progress downloads (`TEMP_DIR`); `None` keeps them next to their final path.
+pub fn parse_temp_dir(value: Option<&str>) -> Option<PathBuf> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+// Where `filename` is written while it downloads: `{filename}.part`, or a uniquely named
+// `.part` file in TEMP_DIR when one is configured
+fn part_path_for(filename: &str) -> std::io::Result<PathBuf> {
+    match Config::current().temp_dir.as_deref().map(Path::new) {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            let name = Path::new(filename).file_name().and_then(|n| n.to_str()).unwrap_or("media");
+            Ok(dir.join(format!("{:08x}_{}.part", random::<u32>(), name)))
+        }
+        None => Ok(PathBuf::from(format!("{}.part", filename))),
+    }
+}
+
+/// What the leading bytes of a downloaded file say it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
+}
+
+/// Smaller "videos" are truncated or placeholder files, however short the clip
+pub const MIN_REEL_BYTES: u64 = 10 * 1024;
+
+// ISO media brands of still images (HEIC/HEIF, AVIF); any other `ftyp` file is a video
+const IMAGE_FTYP_BRANDS: &[&[u8]] = &[b"heic", b"heix", b"heim", b"heis", b"mif1", b"msf1", b"avif"];
+
+/// Identify an image or video format Instagram serves from the first bytes of a file (JPEG,
+/// PNG, GIF, WebP, or an ISO media container such as MP4/HEIC). `None` for HTML error pages
+/// and other junk a CDN may answer with a 200.
+pub fn detect_media_kind(head: &[u8]) -> Option<MediaKind> {
+    if head.get(4..8) == Some(b"ftyp") {
+        let brand = head.get(8..12)?;
+        return Some(if IMAGE_FTYP_BRANDS.contains(&brand) { MediaKind::Image } else { MediaKind::Video });
+    }
+    let image = head.starts_with(&[0xFF, 0xD8, 0xFF])
+        || head.starts_with(b"\x89PNG")
+        || head.starts_with(b"GIF8")
+        || (head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP"));
+    image.then_some(MediaKind::Image)
+}
+
+/// Whether the first bytes of a file are those of an image or video, see `detect_media_kind`
+pub fn looks_like_media(head: &[u8]) -> bool {
+    detect_media_kind(head).is_some()
+}
+
+/// `detect_media_kind` of a file on disk, or `None` if it can't be read
+pub fn file_media_kind(path: &str) -> Option<MediaKind> {
+    use std::io::Read;
+    let mut head = Vec::with_capacity(16);
+    fs::File::open(path).ok()?.take(16).read_to_end(&mut head).ok()?;
+    detect_media_kind(&head)
+}
+
+/// Parse a `Content-Range: bytes <start>-<end>/<total>` header into its start offset and
+/// total length (`None` when the server sent `*`).
+pub fn parse_content_range(header: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = header.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-')?.0.trim().parse::<u64>().ok()?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse::<u64>().ok()?),
+    };
+    Some((start, total))
+}
+
+// JPEG segments that describe the photo rather than encode it: APP1 (EXIF, XMP),
+// APP13 (IPTC) and comments. APP0 (JFIF), APP2 (ICC profile) and APP14 (Adobe) are kept
+// since decoders need them to show the right colours.
+const JPEG_METADATA_MARKERS: &[u8] = &[0xE1, 0xED, 0xFE];
+
+/// Copy of a JPEG without its metadata segments (see `JPEG_METADATA_MARKERS`). The compressed
+/// image data is copied byte for byte, so the pixels are unchanged. `None` when `bytes` isn't
+/// a well-formed JPEG.
+pub fn strip_jpeg_metadata(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut stripped = Vec::with_capacity(bytes.len());
+    stripped.extend_from_slice(&bytes[..2]);
+    let mut pos = 2;
+    loop {
+        if *bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        // A marker may be preceded by any number of 0xFF fill bytes
+        let mut marker_at = pos + 1;
+        while *bytes.get(marker_at)? == 0xFF {
+            marker_at += 1;
+        }
+        let marker = bytes[marker_at];
+        let body = marker_at + 1;
+        match marker {
+            // End of image
+            0xD9 => {
+                stripped.extend_from_slice(&bytes[pos..body]);
+                return Some(stripped);
+            }
+            // Standalone markers (TEM, RSTn) carry no length
+            0x01 | 0xD0..=0xD7 => {
+                stripped.extend_from_slice(&bytes[pos..body]);
+                pos = body;
+                continue;
+            }
+            _ => {}
+        }
+        let len = u16::from_be_bytes([*bytes.get(body)?, *bytes.get(body + 1)?]) as usize;
+        let end = body + len;
+        if len < 2 || end > bytes.len() {
+            return None;
+        }
+        if marker == 0xDA {
+            // Start of scan: the entropy-coded data and everything after it is kept as is
+            stripped.extend_from_slice(&bytes[pos..]);
+            return Some(stripped);
+        }
+        if !JPEG_METADATA_MARKERS.contains(&marker) {
+            stripped.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+    }
+}
+
+// Strip the metadata of a downloaded JPEG in place and return the hash of what is left, or
+// `None` if the file isn't a JPEG or had nothing to remove
+async fn strip_part_file(path: &Path) -> Result<Option<String>> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|e| DownloadError(format!("Failed to read {}: {}", path.display(), e)))?;
+    let stripped = match strip_jpeg_metadata(&bytes) {
+        Some(stripped) if stripped.len() < bytes.len() => stripped,
+        _ => return Ok(None),
+    };
+    tokio::fs::write(path, &stripped)
+        .await
+        .map_err(|e| write_error("Failed to write stripped image", path, e))?;
+    info!("Removed {} bytes of metadata from {}", bytes.len() - stripped.len(), path.display());
+    Ok(Some(format!("{:x}", Sha256::digest(&stripped))))
 }
 
 /// Actual HTTP media download function with streaming support for large files.
-async fn download_media_with_client(client: &Client, url: &str, filename: &str) -> Result<()> {
+/// The body is written to `part_path`, which is only renamed to `filename` once it passed
+/// verification, so an interrupted download never leaves a complete-looking file behind.
+/// The content is hashed while streaming; when it matches an earlier download, that file is
+/// linked at `filename` and the `.part` is dropped instead of moved into place. Returns
+/// whether that happened.
+async fn download_media_with_client(
+    client: &Client,
+    url: &str,
+    filename: &str,
+    part_path: &Path,
+    strip_metadata: bool,
+) -> std::result::Result<bool, FailedAttempt> {
+    let mut hash = match fetch_to_file(client, url, part_path).await {
+        Ok(hash) => hash,
+        Err(attempt) => {
+            // A connection dropped mid-body leaves a prefix the next attempt can resume from
+            if !attempt.resumable {
+                let _ = tokio::fs::remove_file(part_path).await;
+            }
+            return Err(attempt);
+        }
+    };
+    if strip_metadata {
+        match strip_part_file(part_path).await {
+            Ok(Some(stripped_hash)) => hash = stripped_hash,
+            Ok(None) => {}
+            Err(e) => {
+                // Never save the file with the metadata the caller asked to remove
+                let _ = tokio::fs::remove_file(part_path).await;
+                return Err(e.into());
+            }
+        }
+    }
+    let deduped = dedup::dedupe_file(hash, filename.to_string())
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Deduplication skipped for {}: {}", filename, e);
+            false
+        });
+    if deduped {
+        let _ = tokio::fs::remove_file(part_path).await;
+        return Ok(true);
+    }
+    if let Err(e) = tokio::fs::rename(part_path, filename).await {
+        // TEMP_DIR on another filesystem can't be renamed across; copy instead
+        let copied = tokio::fs::copy(part_path, filename).await;
+        let _ = tokio::fs::remove_file(part_path).await;
+        if let Err(copy_error) = copied {
+            // Don't leave a truncated copy behind
+            let _ = tokio::fs::remove_file(filename).await;
+            return Err(if is_disk_full_error(&copy_error) {
+                DownloadError::disk_full(Path::new(filename))
+            } else {
+                DownloadError(format!("Failed to move download into place: {} (after rename failed: {})", copy_error, e))
+            }.into());
+        }
+    }
+    Ok(false)
+}
+
+// Feed the bytes already in `path` to `hasher` and `head`, before a resumed download appends
+async fn read_partial(path: &Path, hasher: &mut Sha256, head: &mut Vec<u8>) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..n]);
+        if head.len() < 16 {
+            head.extend(buf[..n].iter().take(16 - head.len()));
+        }
+    }
+}
+
+// Stream `url` into `path`, verifying the size and the leading bytes. When `path` already
+// holds the start of the body (from an interrupted attempt), only the rest is requested with
+// a Range header and appended; servers that ignore the range send everything again.
+async fn fetch_to_file(
+    client: &Client,
+    url: &str,
+    path: &Path,
+) -> std::result::Result<String, FailedAttempt> {
+    let offset = tokio::fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
     // Set proper headers to avoid detection
-    let response = client.get(url)
-        .header("User-Agent", "Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/110.0.5481.177 Mobile/15E148 Safari/604.1")
-        .header("Referer", "https://www.instagram.com/")
-        .header("Accept", "*/*")
-        .header("Accept-Language", "en-US,en;q=0.9")
-        .header("Connection", "keep-alive")
+    let mut request = client.get(url).headers(media_headers().headers.clone());
+    if offset > 0 {
+        info!("Resuming download of {} from byte {}", url, offset);
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let response = request
         .send()
         .await
         .map_err(|e| DownloadError(format!("HTTP request failed: {}", e)))?;
     
+    if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file doesn't fit the current content; the next attempt starts over
+        return Err(DownloadError("Server refused to resume the partial download".to_string()).into());
+    }
     if !response.status().is_success() {
-        return Err(DownloadError(format!("HTTP error: {}", response.status())));
+        let status = response.status();
+        let retry_after = if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| parse_retry_after(v, Utc::now()))
+        } else {
+            None
+        };
+        return Err(FailedAttempt {
+            error: DownloadError(format!("HTTP error: {}", status)),
+            status: Some(status),
+            retry_after,
+            resumable: false,
+        });
     }
 
-    // Get the content length if available
-    let content_length = response.content_length();
+    let mut hasher = Sha256::new();
+    // First bytes of the body, for the format check
+    let mut head = Vec::with_capacity(16);
+    let resumed = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    // Size of the whole file: a 206's Content-Length only covers the remaining bytes
+    let content_length = if resumed {
+        let range = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_range);
+        match range {
+            Some((start, total)) if start == offset => {
+                total.or_else(|| response.content_length().map(|len| offset + len))
+            }
+            _ => return Err(DownloadError("Server resumed the download at an unexpected offset".to_string()).into()),
+        }
+    } else {
+        if offset > 0 {
+            info!("Server doesn't support ranges for {}, restarting the download", url);
+        }
+        response.content_length()
+    };
     if let Some(len) = content_length {
         info!("Downloading file: {}MB", len / 1024 / 1024);
     }
 
-    // Open the file for writing
-    let mut file = File::create(filename)
-        .map_err(|e| DownloadError(format!("Failed to create file: {}", e)))?;
+    // Open the file for writing; tokio::fs hands the blocking writes to its thread pool
+    let mut file = if resumed {
+        read_partial(path, &mut hasher, &mut head)
+            .await
+            .map_err(|e| DownloadError(format!("Failed to read partial download: {}", e)))?;
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| write_error("Failed to open partial download", path, e))?
+    } else {
+        tokio::fs::File::create(path)
+            .await
+            .map_err(|e| write_error("Failed to create file", path, e))?
+    };
     
     // Stream the download in chunks to handle large files efficiently
     let mut stream = response.bytes_stream();
     use futures_util::StreamExt;
     
-    let mut downloaded: u64 = 0;
+    let mut downloaded: u64 = if resumed { offset } else { 0 };
     while let Some(chunk_result) = stream.next().await {
-        let chunk = chunk_result
-            .map_err(|e| DownloadError(format!("Error while downloading file: {}", e)))?;
+        let chunk = match chunk_result {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // What was written so far is intact; push it out so the next attempt resumes after it
+                let resumable = file.flush().await.is_ok() && downloaded > 0;
+                return Err(FailedAttempt {
+                    error: DownloadError(format!("Error while downloading file: {}", e)),
+                    status: None,
+                    retry_after: None,
+                    resumable,
+                });
+            }
+        };
+        hasher.update(&chunk);
+        if head.len() < 16 {
+            head.extend(chunk.iter().take(16 - head.len()));
+        }
         
-        // Write chunk to file
-        copy(&mut chunk.as_ref(), &mut file)
-            .map_err(|e| DownloadError(format!("Failed to write data to file: {}", e)))?;
+        // Write chunk to file; the next chunk is only pulled once this one is written
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| write_error("Failed to write data to file", path, e))?;
         
         // Update progress for large files
         downloaded += chunk.len() as u64;
@@ -118,72 +722,462 @@ async fn download_media_with_client(client: &Client, url: &str, filename: &str)
         }
     }
     
+    // Push out buffered data before checking the size on disk
+    file.flush()
+        .await
+        .map_err(|e| write_error("Failed to write data to file", path, e))?;
+    drop(file);
+
     // Verify the file was successfully written
-    let file_size = fs::metadata(filename)
+    let file_size = tokio::fs::metadata(path)
+        .await
         .map_err(|e| DownloadError(format!("Failed to read file metadata: {}", e)))?
         .len();
     
     if let Some(len) = content_length {
         if file_size != len {
-            return Err(DownloadError(format!("File size mismatch. Expected: {}, Got: {}", len, file_size)));
+            return Err(DownloadError(format!("File size mismatch. Expected: {}, Got: {}", len, file_size)).into());
         }
     }
     
     if file_size == 0 {
-        return Err(DownloadError("Downloaded file is empty".to_string()));
+        return Err(DownloadError("Downloaded file is empty".to_string()).into());
+    }
+
+    if !looks_like_media(&head) {
+        return Err(DownloadError("Downloaded content is not a recognized image or video".to_string()).into());
     }
 
-    Ok(())
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
-/// Downloads media using `yt-dlp`, optionally with browser cookies.
-pub async fn download_with_ytdlp(
-    url: &str,
-    folder: Option<&str>,
-    browser: Option<&str>,
-    is_story: bool,
-) -> Result<()> {
-    info!("Downloading with yt-dlp: {}", url);
-    
-    let output = match folder {
-        Some(f) => format!("{}/%(title)s_%(id)s.%(ext)s", f), // Better naming convention
-        None => "downloaded_media_%(id)s.%(ext)s".to_string(),
+/// Pick a file extension for downloaded media from its content type or URL.
+/// Falls back to `jpg` only when neither gives a usable hint.
+pub fn extension_for(url: &str, content_type: Option<&str>) -> &'static str {
+    if let Some(ct) = content_type {
+        let ct = ct.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        match ct.as_str() {
+            "image/webp" => return "webp",
+            "image/heic" | "image/heif" => return "heic",
+            "image/png" => return "png",
+            "image/jpeg" | "image/jpg" => return "jpg",
+            "video/mp4" => return "mp4",
+            _ => {}
+        }
+    }
+
+    // Only look at the path for the real extension; the query carries CDN hints
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let path = path.to_ascii_lowercase();
+    if let Some(ext) = path.rsplit('/').next().and_then(|name| name.rsplit_once('.')).map(|(_, ext)| ext) {
+        match ext {
+            "webp" => return "webp",
+            "heic" | "heif" => return "heic",
+            "png" => return "png",
+            "jpg" | "jpeg" => return "jpg",
+            "mp4" => return "mp4",
+            _ => {}
+        }
+    }
+
+    // Instagram CDN URLs encode the served format in the `stp` parameter (e.g. dst-webp)
+    let query = query.to_ascii_lowercase();
+    if query.contains("dst-webp") {
+        "webp"
+    } else if query.contains("dst-heic") {
+        "heic"
+    } else {
+        "jpg"
+    }
+}
+
+/// JPEG quality used when re-encoding images, unless the request asks for another
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// Validate a request's JPEG `quality` (1 to 100), falling back to DEFAULT_JPEG_QUALITY.
+pub fn parse_jpeg_quality(quality: Option<u64>) -> std::result::Result<u8, String> {
+    match quality {
+        None => Ok(DEFAULT_JPEG_QUALITY),
+        Some(q @ 1..=100) => Ok(q as u8),
+        Some(_) => Err("quality must be between 1 and 100".to_string()),
+    }
+}
+
+/// Encode `image` as a JPEG of the given quality, dropping any alpha channel (JPEG has none).
+pub fn encode_jpeg(image: &image::DynamicImage, quality: u8) -> std::result::Result<Vec<u8>, image::ImageError> {
+    let mut encoded = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, quality).encode_image(&image.to_rgb8())?;
+    Ok(encoded)
+}
+
+/// Format a downloaded image can be re-encoded to (`convert_to`), for tools that can't read WebP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageConversion {
+    Jpeg { quality: u8 },
+    Png,
+}
+
+impl ImageConversion {
+    /// Parse a request's `convert_to` value (`"jpg"`, `"jpeg"` or `"png"`) and JPEG `quality`;
+    /// `None` means no conversion. The quality is validated even when it doesn't apply (PNG).
+    pub fn parse(raw: Option<&str>, quality: Option<u64>) -> std::result::Result<Option<Self>, String> {
+        let quality = parse_jpeg_quality(quality)?;
+        match raw.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") => Ok(None),
+            Some("jpg") | Some("jpeg") => Ok(Some(Self::Jpeg { quality })),
+            Some("png") => Ok(Some(Self::Png)),
+            Some(other) => Err(format!("Unsupported convert_to '{}', expected \"jpg\" or \"png\"", other)),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg { .. } => "jpg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// Re-encode the image at `path` as `to`, replacing it with a file of the matching extension
+/// (which is also handed to the storage backend). Returns the new path; already converted
+/// files are left alone.
+pub async fn convert_downloaded_image(path: &str, to: ImageConversion) -> Result<String> {
+    let source = PathBuf::from(path);
+    let target = source.with_extension(to.extension());
+    if source == target {
+        return Ok(path.to_string());
+    }
+    let (from, into) = (source.clone(), target.clone());
+    tokio::task::spawn_blocking(move || {
+        let image = image::open(&from)
+            .map_err(|e| DownloadError(format!("Failed to decode {}: {}", from.display(), e)))?;
+        match to {
+            ImageConversion::Jpeg { quality } => {
+                let encoded = encode_jpeg(&image, quality)
+                    .map_err(|e| DownloadError(format!("Failed to encode {}: {}", into.display(), e)))?;
+                fs::write(&into, encoded).map_err(|e| write_error(&format!("Failed to write {}", into.display()), &into, e))
+            }
+            ImageConversion::Png => image
+                .save(&into)
+                .map_err(|e| DownloadError(format!("Failed to write {}: {}", into.display(), e))),
+        }
+    })
+    .await
+    .map_err(|e| DownloadError(format!("Conversion task failed: {}", e)))??;
+    fs::remove_file(&source)
+        .map_err(|e| DownloadError(format!("Failed to remove {}: {}", source.display(), e)))?;
+    let target = target.to_string_lossy().to_string();
+    storage::persist_file(&target).await?;
+    info!("Converted {} to {}", path, target);
+    Ok(target)
+}
+
+/// Convert a downloaded image, keeping the original file if the conversion fails.
+pub async fn convert_or_keep(file: DownloadedFile, to: ImageConversion) -> DownloadedFile {
+    match convert_downloaded_image(&file.path, to).await {
+        Ok(path) => DownloadedFile { path, ..file },
+        Err(e) => {
+            warn!("Keeping {} unconverted: {}", file.path, e);
+            file
+        }
+    }
+}
+
+/// Caller-supplied cookies written to a temporary Netscape cookie file for yt-dlp.
+/// The file is only readable by the current user and is removed when dropped.
+struct CookieFile {
+    path: PathBuf,
+}
+
+impl CookieFile {
+    fn create(raw: &str) -> Result<Self> {
+        let cookies = parse_cookie_header(raw);
+        if cookies.is_empty() {
+            return Err(DownloadError("No usable cookies in request".to_string()));
+        }
+
+        let mut contents = String::from("# Netscape HTTP Cookie File\n");
+        for (name, value) in cookies {
+            contents.push_str(&format!(".instagram.com\tTRUE\t/\tTRUE\t0\t{}\t{}\n", name, value));
+        }
+
+        let path = std::env::temp_dir().join(format!("insta_cookies_{:016x}.txt", random::<u64>()));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&path)
+            .map_err(|e| DownloadError(format!("Failed to create cookie file: {}", e)))?;
+        file.write_all(contents.as_bytes())
+            .map_err(|e| DownloadError(format!("Failed to write cookie file: {}", e)))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for CookieFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Order in which a handler tries yt-dlp and browser extraction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadOrder {
+    /// yt-dlp first, browser extraction as the fallback (the default)
+    YtDlpFirst,
+    /// Browser extraction first, yt-dlp only as the fallback
+    BrowserFirst,
+    /// Never run yt-dlp
+    BrowserOnly,
+    /// yt-dlp and browser extraction at the same time; the first to succeed wins
+    Race,
+}
+
+impl DownloadOrder {
+    /// Pick the order from a request's `use_ytdlp_first` / `disable_ytdlp` flags.
+    /// `disable_ytdlp: true` wins; `use_ytdlp_first: false` only swaps the order.
+    pub fn from_flags(use_ytdlp_first: Option<bool>, disable_ytdlp: Option<bool>) -> Self {
+        if disable_ytdlp == Some(true) {
+            Self::BrowserOnly
+        } else if use_ytdlp_first == Some(false) {
+            Self::BrowserFirst
+        } else {
+            Self::YtDlpFirst
+        }
+    }
+
+    /// Switch to `Race` when the request's `race` flag is set, unless yt-dlp is disabled
+    pub fn racing(self, race: Option<bool>) -> Self {
+        if race == Some(true) && self != Self::BrowserOnly {
+            Self::Race
+        } else {
+            self
+        }
+    }
+
+    pub fn ytdlp_first(self) -> bool {
+        self == Self::YtDlpFirst
+    }
+
+    pub fn ytdlp_allowed(self) -> bool {
+        self != Self::BrowserOnly
+    }
+}
+
+/// Browsers yt-dlp can load cookies from with `--cookies-from-browser`.
+pub const YTDLP_COOKIE_BROWSERS: &[&str] = &[
+    "brave", "chrome", "chromium", "edge", "firefox", "opera", "safari", "vivaldi", "whale",
+];
+
+/// Per-call settings for `download_with_ytdlp`.
+/// Deliberately not `Debug`: `cookies` holds the caller's session.
+#[derive(Clone, Copy, Default)]
+pub struct YtDlpOptions<'a> {
+    /// Job folder to write into; the working directory when unset
+    pub folder: Option<&'a str>,
+    /// Browser whose profile cookies are loaded (defaults to chrome)
+    pub browser: Option<&'a str>,
+    /// Load cookies from the browser profile, which stories require
+    pub is_story: bool,
+    /// Cookies supplied with the request; take precedence over the browser profile
+    pub cookies: Option<&'a str>,
+    /// Only fetch the first N playlist items (e.g. the most recent N stories)
+    pub max_items: Option<usize>,
+    /// File name without extension, replacing `%(title)s_%(id)s`; may use yt-dlp template fields
+    pub output_name: Option<&'a str>,
+    /// Also save the video's thumbnail as `cover.jpg` next to it
+    pub write_cover: bool,
+    /// `--concurrent-fragments` value; YTDLP_CONCURRENT_FRAGMENTS when unset
+    pub concurrent_fragments: Option<usize>,
+}
+
+/// Paths of the files yt-dlp saved, from its `--print after_move:filepath` stdout.
+/// Progress output that may share stdout (`\r`-overwritten lines, `[download]` logs) is skipped.
+pub fn parse_printed_filepaths(stdout: &str) -> Vec<PathBuf> {
+    stdout
+        .lines()
+        .filter_map(|line| line.rsplit('\r').next())
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('['))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// File name of a reel's poster image inside its job folder
+pub const COVER_FILE: &str = "cover.jpg";
+
+// Finished media files currently in `folder`. Partial downloads and the cover image, which
+// yt-dlp can write even when the video itself was skipped, don't count.
+fn media_files_in(folder: &str) -> HashSet<PathBuf> {
+    fs::read_dir(folder)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file())
+                .filter(|path| {
+                    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+                    !name.ends_with(".part") && name != COVER_FILE
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Exported `cookies.txt` for yt-dlp (`COOKIES_FILE`), for servers without a browser profile.
+/// `Err` when it is set but the file can't be opened for reading.
+pub fn parse_cookies_file(value: Option<&str>) -> std::result::Result<Option<PathBuf>, String> {
+    let Some(path) = value.map(str::trim).filter(|v| !v.is_empty()) else {
+        return Ok(None);
     };
+    let path = PathBuf::from(path);
+    check_cookies_file(&path)?;
+    Ok(Some(path))
+}
 
-    let browser_arg = browser.unwrap_or("chrome");
+// `Err` when the COOKIES_FILE at `path` can't be opened for reading
+fn check_cookies_file(path: &Path) -> std::result::Result<(), String> {
+    match fs::File::open(path) {
+        Ok(file) if file.metadata().is_ok_and(|m| m.is_file()) => Ok(()),
+        Ok(_) => Err(format!("COOKIES_FILE '{}' is not a file", path.display())),
+        Err(e) => Err(format!("COOKIES_FILE '{}' can't be read: {}", path.display(), e)),
+    }
+}
+
+/// The yt-dlp cookie arguments for a call: the request's own cookies first, then the server's
+/// COOKIES_FILE, then (for stories only) the local browser profile.
+pub fn cookie_args<'a>(
+    request_cookies: Option<&'a str>,
+    cookies_file: Option<&'a str>,
+    is_story: bool,
+    browser: &'a str,
+) -> Option<[&'a str; 2]> {
+    match (request_cookies, cookies_file) {
+        (Some(path), _) | (None, Some(path)) => Some(["--cookies", path]),
+        (None, None) if is_story => Some(["--cookies-from-browser", browser]),
+        (None, None) => None,
+    }
+}
+
+// The yt-dlp command line for `url`, with the cookie arguments already chosen
+fn ytdlp_args(url: &str, options: &YtDlpOptions<'_>, cookies: Option<[&str; 2]>, concurrent_fragments: usize) -> Vec<String> {
+    let output = match (options.folder, options.output_name) {
+        (Some(f), Some(name)) => format!("{}/{}.%(ext)s", f, name),
+        (Some(f), None) => format!("{}/%(title)s_%(id)s.%(ext)s", f), // Better naming convention
+        (None, Some(name)) => format!("{}.%(ext)s", name),
+        (None, None) => "downloaded_media_%(id)s.%(ext)s".to_string(),
+    };
+    let concurrent_fragments = concurrent_fragments.clamp(1, MAX_YTDLP_CONCURRENT_FRAGMENTS).to_string();
 
     // Common arguments for all yt-dlp commands
     let mut args = vec![
         "--no-warnings",
-        "--concurrent-fragments", "5", // Download in 5 parallel fragments
+        "--concurrent-fragments", &concurrent_fragments, // Parallel fragment downloads
         "--add-metadata",              // Add metadata to the file
         "--retry-sleep", "3",          // Sleep 3 seconds between retries
         "--retries", "10",             // Retry up to 10 times
         "--no-playlist",               // Don't download playlists
         "--progress",
+        "--print", "after_move:filepath", // Report each final file path on stdout
         "-o", &output,
     ];
 
     // Add cookies for authenticated content
-    if is_story {
-        args.push("--cookies-from-browser");
-        args.push(browser_arg);
+    if let Some(cookie_args) = cookies {
+        args.extend(cookie_args);
+    }
+
+    let playlist_items = options.max_items.map(|n| format!("1:{}", n));
+    if let Some(range) = &playlist_items {
+        args.push("--playlist-items");
+        args.push(range);
+    }
+
+    // Thumbnails get their own output template so they land as cover.jpg
+    let cover_output = format!("thumbnail:{}/{}", options.folder.unwrap_or("."), COVER_FILE.trim_end_matches(".jpg"));
+    if options.write_cover {
+        args.extend(["--write-thumbnail", "--convert-thumbnails", "jpg", "-o", &cover_output]);
     }
 
     // Add URL as the last argument
     args.push(url);
+    args.into_iter().map(str::to_string).collect()
+}
+
+/// Downloads media using `yt-dlp`, optionally with cookies.
+/// Cookies supplied with the request take precedence over COOKIES_FILE, which takes precedence
+/// over the local browser profile. Returns the paths of the files yt-dlp reported saving, in
+/// download order.
+pub async fn download_with_ytdlp(url: &str, options: &YtDlpOptions<'_>) -> Result<Vec<PathBuf>> {
+    info!("Downloading with yt-dlp: {}", url);
+
+    let browser_arg = options.browser.unwrap_or("chrome");
+    let cookie_file = options.cookies.map(CookieFile::create).transpose()?;
+    let cookie_path = cookie_file.as_ref().map(|f| f.path.to_string_lossy().to_string());
+    // Checked again on every call since the file may have been removed after startup
+    let server_cookies = Config::current().cookies_file.as_deref().filter(|path| {
+        match check_cookies_file(Path::new(path)) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("{}, falling back to browser cookies", e);
+                false
+            }
+        }
+    });
+    let concurrent_fragments = options
+        .concurrent_fragments
+        .unwrap_or(Config::current().ytdlp_concurrent_fragments);
+    let args = ytdlp_args(
+        url,
+        options,
+        cookie_args(cookie_path.as_deref(), server_cookies, options.is_story, browser_arg),
+        concurrent_fragments,
+    );
+    run_ytdlp("yt-dlp", url, &args, options.folder).await
+}
+
+// Run `program` (yt-dlp) with `args` and return the media files it saved in `folder`
+async fn run_ytdlp(program: &str, url: &str, args: &[String], output_folder: Option<&str>) -> Result<Vec<PathBuf>> {
+    // yt-dlp exits 0 even when it skipped every item, so compare the folder before and after
+    let folder = output_folder.unwrap_or(".");
+    let files_before = media_files_in(folder);
 
     // Create a command builder with improved error messages
-    let command_result = tokio::process::Command::new("yt-dlp")
-        .args(&args)
+    let command_result = tokio::process::Command::new(program)
+        .args(args)
+        // A cancelled download (e.g. the losing side of a race) must not keep running
+        .kill_on_drop(true)
         .output()
         .await;
 
     match command_result {
         Ok(output) => {
             if output.status.success() {
-                info!("✅ yt-dlp download complete for {}", url);
-                Ok(())
+                let new_files: Vec<PathBuf> = media_files_in(folder).difference(&files_before).cloned().collect();
+                if new_files.is_empty() {
+                    warn!("yt-dlp exited successfully but wrote no files for {}", url);
+                    return Err(DownloadError(format!(
+                        "yt-dlp finished without writing any files (nothing to download or every item was skipped): {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )));
+                }
+                let mut paths: Vec<PathBuf> = parse_printed_filepaths(&String::from_utf8_lossy(&output.stdout))
+                    .into_iter()
+                    .filter(|path| path.is_file())
+                    .collect();
+                // Older yt-dlp builds may not print the final paths
+                if paths.is_empty() {
+                    paths = new_files;
+                    paths.sort();
+                }
+                info!("✅ yt-dlp download complete for {}: {:?}", url, paths);
+                if let Some(f) = output_folder {
+                    storage::persist_folder(f).await?;
+                }
+                Ok(paths)
             } else {
                 let stderr = String::from_utf8_lossy(&output.stderr);
                 let stdout = String::from_utf8_lossy(&output.stdout);
@@ -201,7 +1195,7 @@ pub async fn download_with_ytdlp(
             error!("Failed to execute yt-dlp: {}", e);
             
             // Check if yt-dlp is installed
-            if let Err(_) = Command::new("which").arg("yt-dlp").output() {
+            if Command::new("which").arg("yt-dlp").output().is_err() {
                 return Err(DownloadError(
                     "yt-dlp is not installed. Please install it with 'pip install yt-dlp' or your system's package manager.".to_string()
                 ));
@@ -217,7 +1211,8 @@ pub async fn download_with_ytdlp(
 pub async fn download_with_fallback(
     url: &str, 
     folder: &str, 
-    client: &Client
+    client: &Client,
+    limiter: &DownloadLimiter,
 ) -> Result<String> {
     info!("Attempting fallback download for: {}", url);
     
@@ -231,26 +1226,455 @@ pub async fn download_with_fallback(
     let file_ext = if url.contains(".mp4") { "mp4" } else { "jpg" };
     let filename = format!("{}/direct_download.{}", folder, file_ext);
     
-    if let Ok(_) = download_media_with_retry(client, url, &filename).await {
+    if download_media_with_retry(client, limiter, url, &filename, None, false).await.is_ok() {
         info!("✅ Direct download successful");
         return Ok(filename);
     }
     
     // Second try: use yt-dlp as fallback
     info!("Direct download failed, trying yt-dlp...");
-    if let Ok(_) = download_with_ytdlp(url, Some(folder), Some("chrome"), false).await {
-        // Find the downloaded file (yt-dlp might have renamed it)
-        if let Ok(entries) = fs::read_dir(folder) {
-            for entry in entries.filter_map(|e| e.ok()) {
-                let path = entry.path();
-                if path.is_file() && path.to_string_lossy() != format!("{}/direct_download.{}", folder, file_ext) {
-                    info!("✅ yt-dlp download successful: {:?}", path);
-                    return Ok(path.to_string_lossy().to_string());
-                }
-            }
+    if let Ok(paths) = download_with_ytdlp(url, &YtDlpOptions { folder: Some(folder), ..Default::default() }).await {
+        if let Some(path) = paths.first() {
+            info!("✅ yt-dlp download successful: {:?}", path);
+            return Ok(path.to_string_lossy().to_string());
         }
     }
     
     error!("All download attempts failed for {}", url);
     Err(DownloadError("All download methods failed".to_string()))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_within_its_bounds() {
+        for sample in [0.0, 0.25, 0.5, 0.999_999] {
+            assert_eq!(JitterStrategy::None.apply(1000, sample), 1000);
+            assert!(JitterStrategy::Full.apply(1000, sample) < 1000);
+            let partial = JitterStrategy::Partial.apply(1000, sample);
+            assert!((1000..=1300).contains(&partial), "partial jitter gave {}", partial);
+        }
+        assert_eq!(JitterStrategy::Full.apply(1000, 0.0), 0);
+        assert_eq!(JitterStrategy::Partial.apply(1000, 0.5), 1150);
+        assert_eq!(JitterStrategy::Partial.apply(u64::MAX, 0.999), u64::MAX);
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_cap() {
+        let none = |attempt| backoff_delay(attempt, 300, 10_000, JitterStrategy::None).as_millis();
+        assert_eq!(none(1), 600);
+        assert_eq!(none(3), 2400);
+        assert_eq!(none(6), 10_000);
+        // Shifts past 64 bits saturate instead of wrapping
+        assert_eq!(none(200), 10_000);
+        for strategy in [JitterStrategy::None, JitterStrategy::Full, JitterStrategy::Partial] {
+            for attempt in 0..70 {
+                assert!(backoff_delay(attempt, 300, 10_000, strategy) <= Duration::from_millis(10_000));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn limiter_clones_share_the_same_slots() {
+        let limiter = DownloadLimiter::new(1);
+        let other = limiter.clone();
+        let permit = limiter.acquire().await.unwrap();
+        assert!(other.0.try_acquire().is_err());
+        drop(permit);
+        assert!(other.0.try_acquire().is_ok());
+    }
+
+    #[test]
+    fn extension_comes_from_content_type_then_path_then_cdn_hint() {
+        assert_eq!(extension_for("https://cdn/a.jpg", Some("image/webp; charset=binary")), "webp");
+        assert_eq!(extension_for("https://cdn/a.HEIC?x=1.mp4", None), "heic");
+        assert_eq!(extension_for("https://cdn/v/clip.mp4?efg=abc", Some("application/octet-stream")), "mp4");
+        assert_eq!(extension_for("https://cdn/v/123_n?stp=dst-webp_s1080x1080", None), "webp");
+        assert_eq!(extension_for("https://cdn/v/123_n", None), "jpg");
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_retry_after(" 120 ", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now), Some(Duration::from_secs(30)));
+        // A date in the past means "retry now"
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+    }
+
+    #[test]
+    fn download_order_follows_the_request_flags() {
+        assert_eq!(DownloadOrder::from_flags(None, None), DownloadOrder::YtDlpFirst);
+        assert_eq!(DownloadOrder::from_flags(Some(false), None), DownloadOrder::BrowserFirst);
+        // disable_ytdlp wins over use_ytdlp_first
+        assert_eq!(DownloadOrder::from_flags(Some(true), Some(true)), DownloadOrder::BrowserOnly);
+        assert!(DownloadOrder::BrowserFirst.ytdlp_allowed() && !DownloadOrder::BrowserFirst.ytdlp_first());
+        assert!(!DownloadOrder::BrowserOnly.ytdlp_allowed());
+    }
+
+    #[test]
+    fn image_conversion_parses_jpg_and_png() {
+        assert_eq!(ImageConversion::parse(None, None), Ok(None));
+        assert_eq!(ImageConversion::parse(Some(" JPEG "), Some(70)), Ok(Some(ImageConversion::Jpeg { quality: 70 })));
+        assert_eq!(ImageConversion::parse(Some("jpg"), None), Ok(Some(ImageConversion::Jpeg { quality: DEFAULT_JPEG_QUALITY })));
+        assert_eq!(ImageConversion::parse(Some("png"), None), Ok(Some(ImageConversion::Png)));
+        assert!(ImageConversion::parse(Some("webp"), None).is_err());
+        // The quality is validated even when it doesn't apply
+        assert!(ImageConversion::parse(Some("png"), Some(0)).is_err());
+    }
+
+    #[test]
+    fn printed_filepaths_skip_progress_output() {
+        let stdout = "[download] Destination: x.mp4\n\
+                      [download]  50.0% of 1MiB\r[download] 100% of 1MiB\r/tmp/job/reel.mp4\n\
+                      \n  /tmp/job/cover.jpg  \n";
+        assert_eq!(
+            parse_printed_filepaths(stdout),
+            vec![PathBuf::from("/tmp/job/reel.mp4"), PathBuf::from("/tmp/job/cover.jpg")]
+        );
+        assert!(parse_printed_filepaths("").is_empty());
+    }
+
+    #[test]
+    fn cookie_sources_are_tried_in_precedence_order() {
+        assert_eq!(cookie_args(Some("req.txt"), Some("server.txt"), true, "firefox"), Some(["--cookies", "req.txt"]));
+        assert_eq!(cookie_args(None, Some("server.txt"), true, "firefox"), Some(["--cookies", "server.txt"]));
+        assert_eq!(cookie_args(None, None, true, "firefox"), Some(["--cookies-from-browser", "firefox"]));
+        assert_eq!(cookie_args(None, None, false, "firefox"), None);
+    }
+
+    #[test]
+    fn story_limits_become_a_playlist_items_range() {
+        let options = YtDlpOptions { folder: Some("insta_story_1"), is_story: true, max_items: Some(3), ..Default::default() };
+        let args = ytdlp_args("https://www.instagram.com/stories/someone/", &options, Some(["--cookies-from-browser", "chrome"]), 4);
+        let range = args.iter().position(|arg| arg == "--playlist-items").unwrap();
+        assert_eq!(args[range + 1], "1:3");
+        assert!(args.windows(2).any(|pair| pair == ["--cookies-from-browser", "chrome"]));
+        assert_eq!(args.last().unwrap(), "https://www.instagram.com/stories/someone/");
+
+        let unlimited = ytdlp_args("https://www.instagram.com/stories/someone/", &YtDlpOptions::default(), None, 4);
+        assert!(!unlimited.iter().any(|arg| arg == "--playlist-items"));
+    }
+
+    #[test]
+    fn cover_is_requested_only_when_asked_for() {
+        let url = "https://www.instagram.com/reel/abc123/";
+        let with_cover = YtDlpOptions { folder: Some("insta_reel_1"), write_cover: true, ..Default::default() };
+        let args = ytdlp_args(url, &with_cover, None, 4);
+        assert!(args.iter().any(|arg| arg == "--write-thumbnail"));
+        assert!(args.windows(2).any(|pair| pair == ["-o", "thumbnail:insta_reel_1/cover"]));
+
+        let without_cover = YtDlpOptions { folder: Some("insta_reel_1"), ..Default::default() };
+        let args = ytdlp_args(url, &without_cover, None, 4);
+        assert!(!args.iter().any(|arg| arg == "--write-thumbnail" || arg.starts_with("thumbnail:")));
+    }
+
+    #[tokio::test]
+    async fn successful_runs_that_write_nothing_are_errors() {
+        let folder = std::env::temp_dir().join(format!("insta_test_{}", crate::utils::job::new_job_id()));
+        fs::create_dir_all(&folder).unwrap();
+        let folder_name = folder.to_string_lossy().to_string();
+        let url = "https://www.instagram.com/reel/abc123/";
+
+        // Exits 0 without touching the folder, like yt-dlp skipping an already-downloaded reel
+        let err = run_ytdlp("true", url, &[], Some(&folder_name)).await.unwrap_err();
+        assert!(err.to_string().contains("without writing any files"), "{}", err);
+
+        let script = format!("touch '{}/reel.mp4'", folder_name);
+        let paths = run_ytdlp("sh", url, &["-c".to_string(), script], Some(&folder_name)).await.unwrap();
+        assert_eq!(paths, [folder.join("reel.mp4")]);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn configured_concurrent_fragments_reach_the_args() {
+        let fragments = |value: usize| {
+            let args = ytdlp_args("https://www.instagram.com/reel/abc123/", &YtDlpOptions::default(), None, value);
+            let flag = args.iter().position(|arg| arg == "--concurrent-fragments").unwrap();
+            args[flag + 1].clone()
+        };
+        assert_eq!(fragments(parse_ytdlp_concurrent_fragments(Some("3"))), "3");
+        assert_eq!(fragments(parse_ytdlp_concurrent_fragments(None)), DEFAULT_YTDLP_CONCURRENT_FRAGMENTS.to_string());
+        assert_eq!(parse_ytdlp_concurrent_fragments(Some("0")), DEFAULT_YTDLP_CONCURRENT_FRAGMENTS);
+        assert_eq!(parse_ytdlp_concurrent_fragments(Some("17")), DEFAULT_YTDLP_CONCURRENT_FRAGMENTS);
+        assert_eq!(fragments(100), MAX_YTDLP_CONCURRENT_FRAGMENTS.to_string());
+    }
+
+    #[test]
+    fn media_kind_comes_from_the_leading_bytes() {
+        assert_eq!(detect_media_kind(b"\0\0\0\x18ftypisom\0\0\x02\0"), Some(MediaKind::Video));
+        assert_eq!(detect_media_kind(b"\0\0\0\x18ftypheic\0\0\0\0"), Some(MediaKind::Image));
+        assert_eq!(detect_media_kind(&[0xFF, 0xD8, 0xFF, 0xE0]), Some(MediaKind::Image));
+        assert_eq!(detect_media_kind(b"\x89PNG\r\n\x1a\n"), Some(MediaKind::Image));
+        assert_eq!(detect_media_kind(b"RIFF\0\0\0\0WEBPVP8 "), Some(MediaKind::Image));
+        assert_eq!(detect_media_kind(b"RIFF\0\0\0\0WAVEfmt "), None);
+        assert_eq!(detect_media_kind(b"<!DOCTYPE html>"), None);
+        assert_eq!(detect_media_kind(b"\0\0\0\x18ftyp"), None);
+    }
+
+    #[test]
+    fn content_range_gives_start_and_total() {
+        assert_eq!(parse_content_range("bytes 100-199/1000"), Some((100, Some(1000))));
+        assert_eq!(parse_content_range(" bytes 0-0/* "), Some((0, None)));
+        assert_eq!(parse_content_range("bytes */1000"), None);
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+        assert_eq!(parse_content_range("bytes 0-9/ten"), None);
+    }
+
+    #[test]
+    fn jpeg_metadata_segments_are_dropped() {
+        let app0 = [0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46];
+        let exif = [0xFF, 0xE1, 0x00, 0x04, 0xAA, 0xBB];
+        let comment = [0xFF, 0xFE, 0x00, 0x03, 0x21];
+        let scan = [0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9];
+        let jpeg = [&[0xFF, 0xD8][..], &app0, &exif, &comment, &scan].concat();
+        assert_eq!(strip_jpeg_metadata(&jpeg), Some([&[0xFF, 0xD8][..], &app0, &scan].concat()));
+
+        assert_eq!(strip_jpeg_metadata(b"\x89PNG\r\n\x1a\n"), None);
+        // A segment length running past the end of the file
+        assert_eq!(strip_jpeg_metadata(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x40, 0x00]), None);
+    }
+
+    #[test]
+    fn media_headers_add_extra_entries_and_reject_bad_ones() {
+        let headers = MediaHeaders::parse(None, None).unwrap();
+        assert_eq!(headers.referer(), DEFAULT_MEDIA_REFERER);
+
+        let headers = MediaHeaders::parse(Some(" https://example.com/ "), Some("X-Token: abc | Origin: https://example.com")).unwrap();
+        assert_eq!(headers.referer(), "https://example.com/");
+        assert_eq!(headers.headers().get("x-token").unwrap(), "abc");
+        assert!(headers.names().contains(&"origin".to_string()));
+
+        let err = MediaHeaders::parse(None, Some("X-Ok: 1|no colon here")).unwrap_err();
+        assert!(err.contains("'no colon here'"), "{}", err);
+        assert!(MediaHeaders::parse(Some("bad\nreferer"), None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn identical_downloads_are_stored_once() {
+        use crate::utils::test_server::{response, TestServer};
+        use std::os::unix::fs::MetadataExt;
+        // Unique content, so earlier runs' index entries can't match
+        let body = [&b"\x89PNG\r\n\x1a\n"[..], crate::utils::job::new_job_id().as_bytes()].concat();
+        let served = body.clone();
+        let server = TestServer::start(move |_| response("200 OK", &[("Content-Type", "image/png")], &served)).await;
+        let scratch = std::env::temp_dir().join(format!("insta_dedup_test_{}", crate::utils::job::new_job_id()));
+        let client = Client::new();
+        let limiter = DownloadLimiter::new(2);
+
+        let mut downloads = Vec::new();
+        for job in ["insta_first", "insta_second"] {
+            let path = scratch.join(job).join("image.png").display().to_string();
+            downloads.push(download_media_with_retry(&client, &limiter, &server.url("/image.png"), &path, Some(1), false).await.unwrap());
+        }
+        assert!(!downloads[0].deduped);
+        assert!(downloads[1].deduped);
+        let inodes: Vec<u64> = downloads.iter().map(|file| fs::metadata(&file.path).unwrap().ino()).collect();
+        assert_eq!(inodes[0], inodes[1]);
+        assert_eq!(fs::read(&downloads[1].path).unwrap(), body);
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    // A PNG-looking body of `len` bytes, so the format check passes
+    fn png_body(len: usize) -> Vec<u8> {
+        let mut body = b"\x89PNG\r\n\x1a\n".to_vec();
+        body.extend((0..len - body.len()).map(|i| (i % 251) as u8));
+        body
+    }
+
+    // Headers promising `total` bytes followed by only the first `sent`, then the connection closes
+    fn truncated_response(body: &[u8], sent: usize) -> Vec<u8> {
+        let head = format!("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+        [head.as_bytes(), &body[..sent]].concat()
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_leave_no_file_behind() {
+        use crate::utils::test_server::TestServer;
+        let body = png_body(4096);
+        let served = body.clone();
+        let server = TestServer::start(move |_| truncated_response(&served, 1000)).await;
+        let scratch = std::env::temp_dir().join(format!("insta_interrupted_test_{}", crate::utils::job::new_job_id()));
+        let filename = scratch.join("image.png").display().to_string();
+
+        let result = download_media_with_retry(&Client::new(), &DownloadLimiter::new(1), &server.url("/image.png"), &filename, Some(2), false).await;
+        assert!(result.is_err());
+        assert_eq!(server.requests().len(), 2);
+        assert!(!Path::new(&filename).exists());
+        assert!(!Path::new(&format!("{}.part", filename)).exists());
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_downloads_resume_with_a_range_request() {
+        use crate::utils::test_server::{header, response, TestServer};
+        let body = png_body(4096);
+        let served = body.clone();
+        let server = TestServer::start(move |request| match header(request, "range") {
+            Some("bytes=1000-") => {
+                let range = format!("bytes 1000-{}/{}", served.len() - 1, served.len());
+                response("206 Partial Content", &[("Content-Type", "image/png"), ("Content-Range", &range)], &served[1000..])
+            }
+            Some(other) => panic!("unexpected range {}", other),
+            None => truncated_response(&served, 1000),
+        }).await;
+        let scratch = std::env::temp_dir().join(format!("insta_resume_test_{}", crate::utils::job::new_job_id()));
+        let filename = scratch.join("image.png").display().to_string();
+
+        download_media_with_retry(&Client::new(), &DownloadLimiter::new(1), &server.url("/image.png"), &filename, Some(2), false).await.unwrap();
+        assert_eq!(fs::read(&filename).unwrap(), body);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(header(&requests[1], "range"), Some("bytes=1000-"));
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[tokio::test]
+    async fn servers_ignoring_ranges_restart_the_download() {
+        use crate::utils::test_server::{header, response, TestServer};
+        let body = png_body(4096);
+        let served = body.clone();
+        // The retry asks for a range but gets the whole body again with a 200
+        let server = TestServer::start(move |request| match header(request, "range") {
+            Some(_) => response("200 OK", &[("Content-Type", "image/png")], &served),
+            None => truncated_response(&served, 1000),
+        }).await;
+        let scratch = std::env::temp_dir().join(format!("insta_restart_test_{}", crate::utils::job::new_job_id()));
+        let filename = scratch.join("image.png").display().to_string();
+
+        download_media_with_retry(&Client::new(), &DownloadLimiter::new(1), &server.url("/image.png"), &filename, Some(2), false).await.unwrap();
+        // Not the first 1000 bytes twice
+        assert_eq!(fs::read(&filename).unwrap(), body);
+        assert_eq!(header(&server.requests()[1], "range"), Some("bytes=1000-"));
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[tokio::test]
+    async fn multi_chunk_bodies_are_written_intact() {
+        use crate::utils::test_server::{response, TestServer};
+        // Large enough to arrive in many chunks
+        let body = png_body(2 * 1024 * 1024 + 17);
+        let served = body.clone();
+        let server = TestServer::start(move |_| response("200 OK", &[("Content-Type", "image/png")], &served)).await;
+        let scratch = std::env::temp_dir().join(format!("insta_write_test_{}", crate::utils::job::new_job_id()));
+        fs::create_dir_all(&scratch).unwrap();
+        let filename = scratch.join("image.png").display().to_string();
+        let part_path = scratch.join("image.png.part");
+
+        let deduped = download_media_with_client(&Client::new(), &server.url("/image.png"), &filename, &part_path, false).await.unwrap();
+        assert!(!deduped);
+        assert_eq!(fs::read(&filename).unwrap(), body);
+        assert!(!part_path.exists());
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_media_fails_at_once_while_unavailable_media_is_retried() {
+        use crate::utils::test_server::{response, TestServer};
+        let server = TestServer::start(|request| match request.starts_with("GET /gone") {
+            true => response("404 Not Found", &[], b""),
+            false => response("503 Service Unavailable", &[("Retry-After", "0")], b""),
+        }).await;
+        let scratch = std::env::temp_dir().join(format!("insta_status_test_{}", crate::utils::job::new_job_id()));
+        let filename = scratch.join("image.png").display().to_string();
+        let limiter = DownloadLimiter::new(1);
+
+        download_media_with_retry(&Client::new(), &limiter, &server.url("/gone.png"), &filename, Some(3), false).await.unwrap_err();
+        assert_eq!(server.requests().len(), 1);
+        download_media_with_retry(&Client::new(), &limiter, &server.url("/busy.png"), &filename, Some(3), false).await.unwrap_err();
+        assert_eq!(server.requests().len(), 4);
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_requested_retry_count_is_honored() {
+        use crate::utils::test_server::{response, TestServer};
+        let server = TestServer::start(|_| response("503 Service Unavailable", &[("Retry-After", "0")], b"")).await;
+        let scratch = std::env::temp_dir().join(format!("insta_retry_test_{}", crate::utils::job::new_job_id()));
+        let filename = scratch.join("image.png").display().to_string();
+        let limiter = DownloadLimiter::new(1);
+
+        let mut attempts_so_far = 0;
+        for max_retries in [1, 2] {
+            let err = download_media_with_retry(&Client::new(), &limiter, &server.url("/busy.png"), &filename, Some(max_retries), false).await.unwrap_err();
+            assert!(err.to_string().starts_with(&format!("Failed after {} retries", max_retries)), "{}", err);
+            attempts_so_far += max_retries;
+            assert_eq!(server.requests().len(), attempts_so_far);
+        }
+        assert_eq!(parse_max_retries(Some(MAX_RETRY_LIMIT)), Ok(Some(MAX_RETRY_LIMIT)));
+        assert!(parse_max_retries(Some(0)).is_err());
+        assert!(parse_max_retries(Some(MAX_RETRY_LIMIT + 1)).is_err());
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn full_disks_stop_retries_and_drop_the_part_file() {
+        use crate::utils::test_server::{response, TestServer};
+        let body = png_body(4096);
+        let server = TestServer::start(move |_| response("200 OK", &[("Content-Type", "image/png")], &body)).await;
+        let scratch = std::env::temp_dir().join(format!("insta_disk_full_test_{}", crate::utils::job::new_job_id()));
+        fs::create_dir_all(&scratch).unwrap();
+        let filename = scratch.join("image.png").display().to_string();
+        // Every write through the link fails with ENOSPC
+        let part_path = scratch.join("image.png.part");
+        std::os::unix::fs::symlink("/dev/full", &part_path).unwrap();
+
+        let err = retry_download(&Client::new(), &DownloadLimiter::new(1), &server.url("/image.png"), &filename, &part_path, Some(3), false).await.unwrap_err();
+        assert!(err.is_disk_full(), "{}", err);
+        assert_eq!(server.requests().len(), 1);
+        assert!(part_path.symlink_metadata().is_err());
+        assert!(!Path::new(&filename).exists());
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[tokio::test]
+    async fn media_clients_send_the_configured_headers() {
+        use crate::utils::test_server::{header, response, TestServer};
+        let server = TestServer::start(|_| response("200 OK", &[], b"ok")).await;
+        let headers = MediaHeaders::parse(Some("https://example.com/"), Some("X-Token: abc")).unwrap();
+        let client = headers.client_builder().build().unwrap();
+        client.get(server.url("/media.jpg")).send().await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(header(&requests[0], "x-token"), Some("abc"));
+        assert_eq!(header(&requests[0], "referer"), Some("https://example.com/"));
+        assert_eq!(header(&requests[0], "user-agent"), Some(DEFAULT_MEDIA_USER_AGENT));
+        assert!(header(&requests[0], "accept-language").is_some());
+    }
+
+    #[test]
+    fn race_flag_switches_order_unless_ytdlp_is_off() {
+        assert_eq!(DownloadOrder::YtDlpFirst.racing(Some(true)), DownloadOrder::Race);
+        assert_eq!(DownloadOrder::BrowserFirst.racing(Some(true)), DownloadOrder::Race);
+        assert_eq!(DownloadOrder::BrowserOnly.racing(Some(true)), DownloadOrder::BrowserOnly);
+        assert_eq!(DownloadOrder::BrowserFirst.racing(Some(false)), DownloadOrder::BrowserFirst);
+        assert_eq!(DownloadOrder::YtDlpFirst.racing(None), DownloadOrder::YtDlpFirst);
+    }
+
+    #[test]
+    fn jpeg_quality_defaults_and_stays_in_range() {
+        assert_eq!(parse_jpeg_quality(None), Ok(DEFAULT_JPEG_QUALITY));
+        assert_eq!(parse_jpeg_quality(Some(1)), Ok(1));
+        assert_eq!(parse_jpeg_quality(Some(100)), Ok(100));
+        assert!(parse_jpeg_quality(Some(0)).is_err());
+        assert!(parse_jpeg_quality(Some(101)).is_err());
+    }
+
+    #[test]
+    fn lower_jpeg_quality_gives_smaller_files() {
+        // A gradient with noise, so quality actually changes what the encoder keeps
+        let image = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 256, |x, y| {
+            image::Rgb([x as u8, y as u8, ((x * 31) ^ (y * 17)) as u8])
+        }));
+        let low = encode_jpeg(&image, 20).unwrap();
+        let high = encode_jpeg(&image, 95).unwrap();
+        assert!(low.len() < high.len(), "quality 20: {} bytes, quality 95: {} bytes", low.len(), high.len());
+        assert_eq!(image::load_from_memory(&low).unwrap().width(), 256);
+    }
+}
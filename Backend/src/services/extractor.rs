@@ -1,32 +1,158 @@
 use fantoccini::{ClientBuilder, Client};
+use fantoccini::error::CmdError;
+use fantoccini::wd::TimeoutConfiguration;
+use fantoccini::cookies::Cookie;
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use crate::services::downloader::DownloadError;
+use utoipa::ToSchema;
+use crate::config::Config;
+use crate::services::downloader::{detect_media_kind, DownloadError, MediaKind, MIN_REEL_BYTES};
+use crate::services::http_client::{accept_language, chrome_lang_arg, client_builder};
 use std::result::Result as StdResult;
 use tokio::time::{sleep, Duration};
 use tokio::task;
 use rusqlite::{Connection};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use crate::utils::debug_dump::dump_debug;
 
 type Result<T> = StdResult<T, DownloadError>;
 
 // Default loading timeout in seconds
 const LOAD_TIMEOUT: u64 = 8;
-const MAX_EXTRACTION_RETRIES: usize = 2;
+const DEFAULT_EXTRACTION_RETRIES: usize = 2;
+const DEFAULT_EXTRACTION_RETRY_DELAY_SECS: u64 = 2;
 
-/// Extract Instagram cookies from the default Chrome profile on Linux
-#[allow(dead_code)]
+// Longest a single page load may take before navigation gives up
+const DEFAULT_NAV_TIMEOUT_SECS: u64 = 20;
+// Extra time the client-side guard allows on top of WebDriver's own page-load timeout
+const NAV_TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+// Instagram carousels hold at most 20 slides
+const DEFAULT_MAX_CAROUSEL_SLIDES: usize = 20;
+// Consecutive "Next" clicks without new media before the carousel walk gives up
+const CAROUSEL_STALE_LIMIT: usize = 2;
+// Polls (250ms apart) for a carousel slide's video to swap its blob: src for a real URL
+const CAROUSEL_VIDEO_POLLS: usize = 8;
+
+// Page origin relative media URLs are resolved against
+const INSTAGRAM_BASE_URL: &str = "https://www.instagram.com/";
+
+// Resolved /share/ links are reused for a while; the redirect target never changes
+const SHARE_CACHE_TTL: Duration = Duration::from_secs(600);
+
+// Most redirects followed when resolving a share link; longer chains are refused
+const MAX_SHARE_REDIRECTS: usize = 3;
+static SHARE_CACHE: OnceLock<Mutex<HashMap<String, (Instant, String)>>> = OnceLock::new();
+
+// Parse MAX_CAROUSEL_SLIDES, keeping the default on missing or invalid values
+pub fn parse_max_carousel_slides(value: Option<&str>) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_CAROUSEL_SLIDES)
+}
+
+// Parse NAV_TIMEOUT_SECS, keeping the default on missing or invalid values
+pub fn parse_nav_timeout_secs(value: Option<&str>) -> u64 {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_NAV_TIMEOUT_SECS)
+}
+
+/// Error message for a page that didn't finish loading within NAV_TIMEOUT_SECS
+pub fn nav_timeout_error(url: &str, secs: u64) -> DownloadError {
+    DownloadError(format!("Navigation timed out after {}s loading {}", secs, url))
+}
+
+/// Navigate to `url`, giving up after NAV_TIMEOUT_SECS instead of waiting for the whole
+/// request to time out. WebDriver's page-load timeout is set on each session; this also
+/// guards against a driver that stops answering altogether.
+pub async fn goto_with_timeout(client: &Client, url: &str) -> Result<()> {
+    let secs = Config::current().nav_timeout_secs;
+    navigate_within(url, secs, Duration::from_secs(secs) + NAV_TIMEOUT_GRACE, client.goto(url)).await
+}
+
+// Await a navigation for at most `limit`, reporting both WebDriver's own page-load
+// timeout and a hung driver as a navigation timeout
+async fn navigate_within(
+    url: &str,
+    secs: u64,
+    limit: Duration,
+    navigation: impl std::future::Future<Output = std::result::Result<(), CmdError>>,
+) -> Result<()> {
+    match tokio::time::timeout(limit, navigation).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(CmdError::Standard(e))) if e.error() == "timeout" => Err(nav_timeout_error(url, secs)),
+        Ok(Err(e)) => Err(DownloadError(e.to_string())),
+        Err(_) => Err(nav_timeout_error(url, secs)),
+    }
+}
+
+/// How often (and how patiently) extraction is retried when it finds nothing
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractionRetryConfig {
+    pub retries: usize,
+    pub delay: Duration,
+}
+
+impl ExtractionRetryConfig {
+    /// Parse `EXTRACTION_RETRIES` and `EXTRACTION_RETRY_DELAY_SECS`, keeping the defaults on bad input
+    pub fn parse(retries: Option<&str>, delay_secs: Option<&str>) -> Self {
+        let retries = retries
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or(DEFAULT_EXTRACTION_RETRIES);
+        let delay_secs = delay_secs
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_EXTRACTION_RETRY_DELAY_SECS);
+        Self {
+            retries,
+            delay: Duration::from_secs(delay_secs),
+        }
+    }
+}
+
+/// Candidate locations of the Chrome/Chromium cookie DB for the current platform
+fn chrome_cookie_db_candidates() -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    if cfg!(target_os = "windows") {
+        if let Ok(local_app_data) = std::env::var("LOCALAPPDATA") {
+            let profile = PathBuf::from(local_app_data).join("Google\\Chrome\\User Data\\Default");
+            // Chrome 96+ moved the DB under Network\
+            candidates.push(profile.join("Network\\Cookies"));
+            candidates.push(profile.join("Cookies"));
+        }
+    } else if let Ok(home) = std::env::var("HOME") {
+        let home = PathBuf::from(home);
+        if cfg!(target_os = "macos") {
+            candidates.push(home.join("Library/Application Support/Google/Chrome/Default/Cookies"));
+            candidates.push(home.join("Library/Application Support/Chromium/Default/Cookies"));
+        } else {
+            candidates.push(home.join(".config/google-chrome/Default/Cookies"));
+            candidates.push(home.join(".config/chromium/Default/Cookies"));
+        }
+    }
+    candidates
+}
+
+/// Extract Instagram cookies from the default Chrome profile.
+///
+/// On macOS and Windows (and recent Linux builds) Chrome stores cookie values
+/// encrypted in `encrypted_value`, leaving `value` empty; those cookies are
+/// skipped since decrypting them needs the OS keychain.
 pub async fn get_instagram_cookies_from_chrome() -> Option<Vec<(String, String)>> {
     task::spawn_blocking(|| {
-        let mut cookie_db = PathBuf::from(std::env::var("HOME").ok()?);
-        cookie_db.push(".config/google-chrome/Default/Cookies");
-        if !cookie_db.exists() {
-            cookie_db = PathBuf::from(std::env::var("HOME").ok()?);
-            cookie_db.push(".config/chromium/Default/Cookies");
-            if !cookie_db.exists() {
+        let cookie_db = match chrome_cookie_db_candidates().into_iter().find(|p| p.exists()) {
+            Some(path) => path,
+            None => {
                 println!("❌ Chrome/Chromium cookie DB not found");
                 return None;
             }
-        }
+        };
         let conn = Connection::open(cookie_db).ok()?;
         let mut stmt = conn.prepare(
             "SELECT name, value FROM cookies WHERE host_key LIKE '%instagram.com'"
@@ -39,8 +165,16 @@ pub async fn get_instagram_cookies_from_chrome() -> Option<Vec<(String, String)>
             })
             .ok()?;
         let mut cookies = Vec::new();
-        for cookie in cookies_iter.flatten() {
-            cookies.push(cookie);
+        let mut encrypted = 0;
+        for (name, value) in cookies_iter.flatten() {
+            if value.is_empty() {
+                encrypted += 1;
+            } else {
+                cookies.push((name, value));
+            }
+        }
+        if encrypted > 0 {
+            println!("⚠️ Skipped {} encrypted Instagram cookies in Chrome DB", encrypted);
         }
         if cookies.is_empty() {
             println!("❌ No Instagram cookies found in Chrome DB");
@@ -51,6 +185,205 @@ pub async fn get_instagram_cookies_from_chrome() -> Option<Vec<(String, String)>
     }).await.map_err(|e| DownloadError(format!("JoinError: {}", e))).ok().flatten()
 }
 
+/// Extract Instagram cookies from the default Firefox profile on Linux
+pub async fn get_instagram_cookies_from_firefox() -> Option<Vec<(String, String)>> {
+    task::spawn_blocking(|| {
+        let profiles_dir = PathBuf::from(std::env::var("HOME").ok()?).join(".mozilla/firefox");
+        read_firefox_cookies(&profiles_dir)
+    }).await.map_err(|e| DownloadError(format!("JoinError: {}", e))).ok().flatten()
+}
+
+// Instagram cookies from the default profile under a Firefox profiles directory
+fn read_firefox_cookies(profiles_dir: &Path) -> Option<Vec<(String, String)>> {
+    // Profiles are named "<random>.default" or "<random>.default-release"; prefer the release one
+    let mut candidates: Vec<PathBuf> = std::fs::read_dir(profiles_dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.contains(".default"))
+                .unwrap_or(false)
+                && path.join("cookies.sqlite").exists()
+        })
+        .collect();
+    candidates.sort_by_key(|path| {
+        !path.to_string_lossy().ends_with(".default-release")
+    });
+    let cookie_db = match candidates.first() {
+        Some(profile) => profile.join("cookies.sqlite"),
+        None => {
+            println!("❌ Firefox cookie DB not found");
+            return None;
+        }
+    };
+
+    let conn = Connection::open(cookie_db).ok()?;
+    let mut stmt = conn.prepare(
+        "SELECT name, value FROM moz_cookies WHERE host LIKE '%instagram.com'"
+    ).ok()?;
+    let cookies_iter = stmt
+        .query_map([], |row| {
+            let name: String = row.get(0)?;
+            let value: String = row.get(1)?;
+            Ok((name, value))
+        })
+        .ok()?;
+    let cookies: Vec<(String, String)> = cookies_iter.flatten().collect();
+    if cookies.is_empty() {
+        println!("❌ No Instagram cookies found in Firefox DB");
+        None
+    } else {
+        Some(cookies)
+    }
+}
+
+/// Extract Instagram cookies from the given browser's default profile. Accepts the
+/// yt-dlp `BROWSER[+KEYRING][:PROFILE]` syntax but only looks at the browser name.
+pub async fn get_instagram_cookies(browser: &str) -> Option<Vec<(String, String)>> {
+    let name = browser.split([':', '+']).next().unwrap_or_default();
+    match name.trim().to_ascii_lowercase().as_str() {
+        "firefox" => get_instagram_cookies_from_firefox().await,
+        "chrome" | "chromium" => get_instagram_cookies_from_chrome().await,
+        other => {
+            println!("❌ Reading cookies from '{}' is not supported", other);
+            None
+        }
+    }
+}
+
+/// Parse caller-supplied cookies into `(name, value)` pairs.
+/// Accepts either a Netscape cookie file (tab-separated, 7 fields per line) or a
+/// `name=value; name=value` Cookie header. Lines or pairs that can't be parsed are skipped.
+pub fn parse_cookie_header(raw: &str) -> Vec<(String, String)> {
+    let mut cookies = Vec::new();
+    for line in raw.lines() {
+        // Only strip line endings; tabs are significant in the Netscape format (empty values)
+        let line = line.trim_end_matches('\r');
+        // curl/yt-dlp mark HttpOnly cookies with a prefix on an otherwise commented-out line
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() == 7 {
+            let name = fields[5].trim();
+            if !name.is_empty() {
+                cookies.push((name.to_string(), fields[6].trim().to_string()));
+            }
+            continue;
+        }
+
+        for pair in line.split(';') {
+            if let Some((name, value)) = pair.split_once('=') {
+                let name = name.trim();
+                if !name.is_empty() {
+                    cookies.push((name.to_string(), value.trim().to_string()));
+                }
+            }
+        }
+    }
+    cookies
+}
+
+/// Inject caller-supplied cookies into the WebDriver session for instagram.com.
+/// Cookie values are never logged.
+pub async fn apply_cookies(client: &Client, raw: &str) -> Result<()> {
+    let cookies = parse_cookie_header(raw);
+    if cookies.is_empty() {
+        println!("⚠️ Request cookies were supplied but none could be parsed");
+        return Ok(());
+    }
+    apply_cookie_pairs(client, cookies).await
+}
+
+/// Log the WebDriver session in: with the caller's `cookies` when they sent some, otherwise
+/// with the Instagram cookies of the local `browser` profile they named, if any
+pub async fn apply_session_cookies(client: &Client, cookies: Option<&str>, browser: Option<&str>) -> Result<()> {
+    if let Some(raw) = cookies {
+        return apply_cookies(client, raw).await;
+    }
+    let Some(browser) = browser else {
+        return Ok(());
+    };
+    match get_instagram_cookies(browser).await {
+        Some(cookies) => apply_cookie_pairs(client, cookies).await,
+        None => Ok(()),
+    }
+}
+
+// Add `(name, value)` cookies to the session for instagram.com. Values are never logged.
+async fn apply_cookie_pairs(client: &Client, cookies: Vec<(String, String)>) -> Result<()> {
+    // WebDriver only accepts cookies for the domain currently loaded
+    goto_with_timeout(client, "https://www.instagram.com/").await
+        .map_err(|e| DownloadError(format!("Failed to open Instagram for cookies: {}", e)))?;
+
+    let count = cookies.len();
+    for (name, value) in cookies {
+        let mut cookie = Cookie::new(name, value);
+        cookie.set_domain(".instagram.com");
+        cookie.set_path("/");
+        cookie.set_secure(true);
+        if let Err(e) = client.add_cookie(cookie).await {
+            println!("⚠️ Failed to set a request cookie: {}", e);
+        }
+    }
+    println!("🍪 Applied {} Instagram cookies", count);
+    Ok(())
+}
+
+// Anti-detection script run in every new browser session unless STEALTH_JS_FILE replaces it
+const DEFAULT_STEALTH_SCRIPT: &str = r#"
+    Object.defineProperty(navigator, 'webdriver', {get: () => undefined});
+    if (!('ontouchstart' in window)) {
+        Object.defineProperty(navigator, 'maxTouchPoints', {get: () => 5});
+        window.ontouchstart = function(){};
+    }
+    if (navigator.userAgentData) {
+        Object.defineProperty(navigator.userAgentData, 'mobile', {get: () => true});
+    }
+"#;
+
+/// Read a stealth script; `Err` when the file can't be read, isn't UTF-8 text or is blank.
+pub fn read_stealth_script(path: &Path) -> StdResult<String, String> {
+    let script = std::fs::read_to_string(path)
+        .map_err(|e| format!("STEALTH_JS_FILE '{}' can't be read as text: {}", path.display(), e))?;
+    if script.trim().is_empty() {
+        return Err(format!("STEALTH_JS_FILE '{}' is empty", path.display()));
+    }
+    Ok(script)
+}
+
+/// Custom stealth script file (`STEALTH_JS_FILE`), checked at startup.
+/// `Err` when it is set but doesn't hold a usable script.
+pub fn parse_stealth_js_file(value: Option<&str>) -> StdResult<Option<PathBuf>, String> {
+    match value.map(str::trim).filter(|v| !v.is_empty()).map(PathBuf::from) {
+        Some(path) => read_stealth_script(&path).map(|_| Some(path)),
+        None => Ok(None),
+    }
+}
+
+/// The script a new session runs: STEALTH_JS_FILE, re-read for every session so edits apply
+/// without a restart, or the built-in default when it's unset or has become unreadable.
+pub fn stealth_script() -> Cow<'static, str> {
+    stealth_script_from(Config::current().stealth_js_file.as_deref())
+}
+
+fn stealth_script_from(file: Option<&str>) -> Cow<'static, str> {
+    let Some(path) = file else {
+        return Cow::Borrowed(DEFAULT_STEALTH_SCRIPT);
+    };
+    match read_stealth_script(Path::new(path)) {
+        Ok(script) => Cow::Owned(script),
+        Err(e) => {
+            println!("⚠️ {}, using the built-in stealth script", e);
+            Cow::Borrowed(DEFAULT_STEALTH_SCRIPT)
+        }
+    }
+}
+
 pub async fn create_browser_client(_browser: &str) -> Result<Client> {
     println!("🌐 Creating browser client...");
     
@@ -72,7 +405,8 @@ pub async fn create_browser_client(_browser: &str) -> Result<Client> {
         "--disable-gpu",
         "--disable-extensions",
         "--mute-audio",
-        "--hide-scrollbars"
+        "--hide-scrollbars",
+        chrome_lang_arg()
     ]);
     
     chrome_options.insert("args".to_string(), args);
@@ -81,7 +415,9 @@ pub async fn create_browser_client(_browser: &str) -> Result<Client> {
     let prefs = serde_json::json!({
         "profile.default_content_setting_values.notifications": 2,
         "credentials_enable_service": false,
-        "profile.password_manager_enabled": false
+        "profile.password_manager_enabled": false,
+        // Render pages in the configured language so the text-based login/unavailable checks match
+        "intl.accept_languages": accept_language()
     });
     chrome_options.insert("prefs".to_string(), prefs);
     
@@ -117,18 +453,15 @@ pub async fn create_browser_client(_browser: &str) -> Result<Client> {
                 println!("✅ Successfully connected to WebDriver at: {}", webdriver_url);
                 
                 // Execute JavaScript to help avoid detection
-                let stealth_script = r#"
-                    Object.defineProperty(navigator, 'webdriver', {get: () => undefined});
-                    if (!('ontouchstart' in window)) {
-                        Object.defineProperty(navigator, 'maxTouchPoints', {get: () => 5});
-                        window.ontouchstart = function(){};
-                    }
-                    if (navigator.userAgentData) {
-                        Object.defineProperty(navigator.userAgentData, 'mobile', {get: () => true});
-                    }
-                "#;
-                
-                let _ = client.execute(stealth_script, vec![]).await;
+                if let Err(e) = client.execute(&stealth_script(), vec![]).await {
+                    println!("⚠️ Stealth script failed: {}", e);
+                }
+
+                // Abort page loads after NAV_TIMEOUT_SECS instead of WebDriver's 300s default
+                let timeouts = TimeoutConfiguration::new(None, Some(Duration::from_secs(Config::current().nav_timeout_secs)), None);
+                if let Err(e) = client.update_timeouts(timeouts).await {
+                    println!("⚠️ Failed to set the page load timeout: {}", e);
+                }
                 
                 return Ok(client);
             },
@@ -150,28 +483,77 @@ pub async fn create_browser_client(_browser: &str) -> Result<Client> {
     Err(DownloadError(error_msg))
 }
 
+/// A media item found on a post page. Images keep the alt text Instagram generates for them
+/// ("May be an image of 2 people"); videos and items from other sources have none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedMedia {
+    pub url: String,
+    pub media_type: String,
+    pub alt_text: Option<String>,
+}
+
+impl From<(String, String)> for ExtractedMedia {
+    fn from((url, media_type): (String, String)) -> Self {
+        Self { url, media_type, alt_text: None }
+    }
+}
+
 // Robust post media extraction with retries
-pub async fn extract_post_media(client: &mut Client) -> Result<Vec<(String, String)>> {
-    for attempt in 0..=MAX_EXTRACTION_RETRIES {
-        let result = extract_post_media_once(client).await;
+pub async fn extract_post_media(client: &mut Client, job_id: &str) -> Result<Vec<(String, String)>> {
+    let items = extract_post_media_with_alt_text(client, job_id).await?;
+    Ok(items.into_iter().map(|item| (item.url, item.media_type)).collect())
+}
+
+/// `extract_post_media`, keeping each image's alt text.
+pub async fn extract_post_media_with_alt_text(client: &mut Client, job_id: &str) -> Result<Vec<ExtractedMedia>> {
+    with_retries(&Config::current().extraction_retry(), "Extraction", || {
+        let mut client = client.clone();
+        async move {
+            extract_post_media_once(&mut client, job_id).await.map(|items| {
+                items
+                    .into_iter()
+                    .filter_map(|item| Some(ExtractedMedia { url: absolutize_media_url(&item.url)?, ..item }))
+                    .collect::<Vec<_>>()
+            })
+        }
+    })
+    .await
+}
+
+// Run `attempt` until it finds something, retrying empty or failed results `retry.retries`
+// times with `retry.delay` in between
+async fn with_retries<T, Fut>(
+    retry: &ExtractionRetryConfig,
+    what: &str,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<Vec<T>>
+where
+    Fut: std::future::Future<Output = Result<Vec<T>>>,
+{
+    for n in 0..=retry.retries {
+        let result = attempt().await;
         match &result {
             Ok(items) if !items.is_empty() => return result,
-            Ok(_) | Err(_) if attempt < MAX_EXTRACTION_RETRIES => {
-                println!("🔁 Extraction attempt {} failed, retrying...", attempt + 1);
-                sleep(Duration::from_secs(2)).await;
+            Ok(_) | Err(_) if n < retry.retries => {
+                println!("🔁 {} attempt {} failed, retrying...", what, n + 1);
+                sleep(retry.delay).await;
             }
             _ => return result,
         }
     }
-    Err(DownloadError("All extraction attempts failed".to_string()))
+    Err(DownloadError(format!("All {} attempts failed", what.to_lowercase())))
 }
 
 // The original extraction logic, now private
-async fn extract_post_media_once(client: &mut Client) -> Result<Vec<(String, String)>> {
+async fn extract_post_media_once(client: &mut Client, job_id: &str) -> Result<Vec<ExtractedMedia>> {
     // --- Try Reels first: only use direct video.src if not blob and not empty ---
     let reel_script = r#"
         let debug = { elements: {}, errors: [] };
         try {
+            // Carousels can hold several videos; leave them to the post walk below
+            debug.elements.isCarousel = !!document.querySelector('article svg[aria-label="Next"]');
+            if (debug.elements.isCarousel) return { media: [], debug };
+
             debug.elements.video = document.querySelector('video') ? true : false;
             let video = document.querySelector('video');
             if (video) {
@@ -213,7 +595,7 @@ async fn extract_post_media_once(client: &mut Client) -> Result<Vec<(String, Str
     if let Some(result_obj) = reel_result.as_object() {
         // Extract debug info for logging
         if let Some(debug) = result_obj.get("debug") {
-            println!("🔍 Debug info: {}", serde_json::to_string_pretty(debug).unwrap_or_default());
+            dump_debug(job_id, "reel", debug);
         }
         
         // Process media items if available
@@ -231,7 +613,7 @@ async fn extract_post_media_once(client: &mut Client) -> Result<Vec<(String, Str
 
             if !items.is_empty() {
                 println!("✅ Reel video found");
-                return Ok(items);
+                return Ok(items.into_iter().map(ExtractedMedia::from).collect());
             }
         }
     }
@@ -241,249 +623,368 @@ async fn extract_post_media_once(client: &mut Client) -> Result<Vec<(String, Str
 
     // Similarly enhance the post script for better debugging
     let post_script = r#"
-        const media = [];
-        const debug = { elements: {}, errors: [] };
+        // WebDriver's synchronous execute waits for a returned promise, so the body runs as an
+        // async function. Arrow functions have no `arguments`, so read them out here. `slide` 0
+        // reads the page as loaded; any other value clicks "Next" and reads the slide it shows.
+        // `known` holds the URLs earlier calls already found.
+        const [slide, videoPolls, known] = arguments;
+        return (async () => {
+            const media = [];
+            const debug = { elements: {}, errors: [] };
+            // Carousel slides to click through after this one (0 when there's nothing to walk)
+            let slides = 0;
 
-        function push(url, type) {
-            if (url && !url.startsWith("blob:") && !media.some(m => m.url === url)) {
-                media.push({ url, type });
+            // alt is the <img> alt text Instagram generates ("May be an image of ..."), if any
+            function push(url, type, alt) {
+                if (!url || url.startsWith("blob:")) return;
+                const existing = media.find(m => m.url === url);
+                if (existing) {
+                    if (!existing.alt && alt) existing.alt = alt;
+                    return;
+                }
+                media.push({ url, type, alt: alt || null });
             }
-        }
 
-        try {
-            const article = document.querySelector('article');
-            debug.elements.hasArticle = !!article;
-            if (!article) return { media, debug };
-
-            debug.elements.videoCount = article.querySelectorAll('video').length;
-            article.querySelectorAll('video').forEach((v, i) => {
-                debug.elements[`video_${i}_src`] = v.src || 'none';
-                push(v.src, 'video');
-            });
+            // Best non-blob URL for a <video>: its src, currentSrc or a <source> child
+            function videoUrl(v) {
+                const candidates = [v.src, v.currentSrc, ...Array.from(v.querySelectorAll('source')).map(s => s.src)];
+                return candidates.find(u => u && !u.startsWith("blob:")) || null;
+            }
 
-            debug.elements.imgCount = article.querySelectorAll('img[srcset], img').length;
-            article.querySelectorAll('img[srcset], img').forEach((img, i) => {
-                const src = img.src;
-                const srcset = img.srcset;
-                const alt = img.alt || "";
-                debug.elements[`img_${i}_hasSrcset`] = !!srcset;
-                
-                if (src && !src.startsWith("data:") &&
-                    (alt.toLowerCase().includes("photo") || img.width > 150)) {
-
-                    if (srcset) {
-                        // Original srcset processing logic...
-                        const sets = srcset.split(',').map(s => s.trim());
-                        let highestQuality = '';
-                        let highestWidth = 0;
-                        
-                        sets.forEach(set => {
-                            const parts = set.split(' ');
-                            if (parts.length >= 2) {
-                                const url = parts[0];
-                                const width = parseInt(parts[1].replace('w', ''));
-                                if (width > highestWidth) {
-                                    highestWidth = width;
-                                    highestQuality = url;
-                                }
-                            }
-                        });
-                        
-                        if (highestQuality) {
-                            push(highestQuality, 'image');
-                        } else {
-                            // Fallback to previous method
-                            const best = sets[sets.length - 1];
-                            const url = best.split(' ')[0];
-                            push(url, 'image');
-                        }
-                    } else {
-                        push(src, 'image');
+            // mp4 requests the page made that aren't in the media list yet
+            function newMp4Resources() {
+                return performance.getEntriesByType('resource')
+                    .map(e => e.name)
+                    .filter(n => /\.mp4($|\?)/.test(n) && !known.includes(n) && !media.some(m => m.url === n));
+            }
+
+            // Videos on the slide currently in view
+            function visibleVideos(root) {
+                const box = root.getBoundingClientRect();
+                return Array.from(root.querySelectorAll('video')).filter(v => {
+                    const r = v.getBoundingClientRect();
+                    return r.width > 0 && r.left < box.right && r.right > box.left;
+                });
+            }
+
+            // Wait for the visible slide's video to resolve and record it. Videos that never
+            // leave blob: fall back to the newest mp4 the page fetched.
+            async function collectSlideVideo(root, slide, polls) {
+                for (let attempt = 0; attempt <= polls; attempt++) {
+                    const videos = visibleVideos(root);
+                    if (videos.length === 0) return;
+                    const urls = videos.map(videoUrl);
+                    if (urls.every(u => u)) {
+                        urls.forEach(u => push(u, 'video'));
+                        debug.elements[`slide_${slide}_videoPolls`] = attempt;
+                        return;
                     }
+                    if (attempt < polls) await new Promise(r => setTimeout(r, 250));
                 }
-            });
+                visibleVideos(root).map(videoUrl).filter(u => u).forEach(u => push(u, 'video'));
+                const fallback = newMp4Resources().pop();
+                debug.elements[`slide_${slide}_videoFallback`] = fallback || 'none';
+                if (fallback) push(fallback, 'video');
+            }
 
-            // Try advanced media extraction techniques
-            // Look for JSON-LD data
-            try {
-                const scripts = document.querySelectorAll('script[type="application/ld+json"]');
-                debug.elements.jsonLdScripts = scripts.length;
-                
-                scripts.forEach((script, idx) => {
-                    try {
-                        const data = JSON.parse(script.textContent);
-                        debug.elements[`jsonLd_${idx}_type`] = data["@type"] || 'unknown';
-                        
-                        if (data.contentUrl) {
-                            push(data.contentUrl, data.contentUrl.includes('.mp4') ? 'video' : 'image');
-                        }
-                        
-                        if (data.video && data.video.contentUrl) {
-                            push(data.video.contentUrl, 'video');
-                        }
+            // Largest image of every sizeable <img> under root
+            function collectImages(root) {
+                root.querySelectorAll('img[srcset], img').forEach((img, i) => {
+                    const src = img.src;
+                    const srcset = img.srcset;
+                    const alt = img.alt || "";
+                    debug.elements[`img_${i}_hasSrcset`] = !!srcset;
+
+                    if (src && !src.startsWith("data:") &&
+                        (alt.toLowerCase().includes("photo") || img.width > 150)) {
+
+                        if (srcset) {
+                            const sets = srcset.split(',').map(s => s.trim());
+                            let highestQuality = '';
+                            let highestWidth = 0;
                         
-                        // Handle image array
-                        if (data.image && Array.isArray(data.image)) {
-                            data.image.forEach(img => {
-                                if (typeof img === 'string') {
-                                    push(img, 'image');
-                                } else if (img.url) {
-                                    push(img.url, 'image');
+                            sets.forEach(set => {
+                                const parts = set.split(' ');
+                                if (parts.length >= 2) {
+                                    const url = parts[0];
+                                    const width = parseInt(parts[1].replace('w', ''));
+                                    if (width > highestWidth) {
+                                        highestWidth = width;
+                                        highestQuality = url;
+                                    }
                                 }
                             });
+                        
+                            if (highestQuality) {
+                                push(highestQuality, 'image', alt);
+                            } else {
+                                // Fallback to previous method
+                                const best = sets[sets.length - 1];
+                                const url = best.split(' ')[0];
+                                push(url, 'image', alt);
+                            }
+                        } else {
+                            push(src, 'image', alt);
                         }
-                    } catch (parseErr) {
-                        debug.errors.push(`Error parsing JSON-LD ${idx}: ${parseErr.toString()}`);
                     }
                 });
-            } catch (jsonErr) {
-                debug.errors.push(`JSON-LD extraction error: ${jsonErr.toString()}`);
             }
-            
-            // Check Open Graph meta tags
+
             try {
-                const ogImage = document.querySelector('meta[property="og:image"]')?.content;
-                const ogVideo = document.querySelector('meta[property="og:video"]')?.content;
-                debug.elements.hasOgImage = !!ogImage;
-                debug.elements.hasOgVideo = !!ogVideo;
-                
-                if (ogImage) push(ogImage, 'image');
-                if (ogVideo) push(ogVideo, 'video');
-            } catch (ogErr) {
-                debug.errors.push(`OG tag extraction error: ${ogErr.toString()}`);
-            }
+                const article = document.querySelector('article');
+                debug.elements.hasArticle = !!article;
+                if (!article) return { media, debug, slides: 0 };
 
-            // Check for carousel
-            const carouselDots = article.querySelectorAll('div[role="button"] > div > div > div');
-            const isCarousel = carouselDots.length > 1;
-            debug.elements.isCarousel = isCarousel;
-            debug.elements.carouselDots = carouselDots.length;
+                if (slide > 0) {
+                    const nextButton = Array.from(article.querySelectorAll('button'))
+                        .find(btn => btn.querySelector('svg[aria-label="Next"]'));
+                    if (!nextButton) return { media, debug, slides: 0 };
+                    nextButton.click();
+                    await new Promise(r => setTimeout(r, 500));
+                    await collectSlideVideo(article, slide, videoPolls);
+                    article.querySelectorAll('video').forEach(v => push(videoUrl(v), 'video'));
+                    collectImages(article);
+                    return { media, debug, slides: 0 };
+                }
+
+                debug.elements.videoCount = article.querySelectorAll('video').length;
+                article.querySelectorAll('video').forEach((v, i) => {
+                    debug.elements[`video_${i}_src`] = v.src || 'none';
+                    push(v.src, 'video');
+                });
 
-            if (isCarousel) {
-                // Existing carousel logic...
-                const nextButton = Array.from(article.querySelectorAll('button'))
-                    .find(btn => btn.querySelector('svg[aria-label="Next"]'));
+                debug.elements.imgCount = article.querySelectorAll('img[srcset], img').length;
+                collectImages(article);
 
-                debug.elements.hasNextButton = !!nextButton;
+                // Try advanced media extraction techniques
+                // Look for JSON-LD data
+                try {
+                    const scripts = document.querySelectorAll('script[type="application/ld+json"]');
+                    debug.elements.jsonLdScripts = scripts.length;
                 
-                if (nextButton) {
-                    const totalSlides = carouselDots.length;
-                    debug.elements.totalSlides = totalSlides;
-                    
-                    for (let i = 1; i < totalSlides; i++) {
+                    scripts.forEach((script, idx) => {
                         try {
-                            nextButton.click();
-                            await new Promise(r => setTimeout(r, 500));
-                            
-                            // Process videos and images for each slide
-                            // (Similar to above but for carousel slides)
-                            article.querySelectorAll('video').forEach(v => push(v.src, 'video'));
-                            article.querySelectorAll('img[srcset], img').forEach(img => {
-                                const src = img.src;
-                                const srcset = img.srcset;
-                                const alt = img.alt || "";
-
-                                if (src && !src.startsWith("data:") &&
-                                    (alt.toLowerCase().includes("photo") || img.width > 150)) {
-
-                                    if (srcset) {
-                                        const sets = srcset.split(',').map(s => s.trim());
-                                        let highestQuality = '';
-                                        let highestWidth = 0;
-                                        
-                                        sets.forEach(set => {
-                                            const parts = set.split(' ');
-                                            if (parts.length >= 2) {
-                                                const url = parts[0];
-                                                const width = parseInt(parts[1].replace('w', ''));
-                                                if (width > highestWidth) {
-                                                    highestWidth = width;
-                                                    highestQuality = url;
-                                                }
-                                            }
-                                        });
-                                        
-                                        if (highestQuality) {
-                                            push(highestQuality, 'image');
-                                        } else {
-                                            // Fallback to previous method
-                                            const best = sets[sets.length - 1];
-                                            const url = best.split(' ')[0];
-                                            push(url, 'image');
-                                        }
-                                    } else {
-                                        push(src, 'image');
+                            const data = JSON.parse(script.textContent);
+                            debug.elements[`jsonLd_${idx}_type`] = data["@type"] || 'unknown';
+                        
+                            if (data.contentUrl) {
+                                push(data.contentUrl, data.contentUrl.includes('.mp4') ? 'video' : 'image');
+                            }
+                        
+                            if (data.video && data.video.contentUrl) {
+                                push(data.video.contentUrl, 'video');
+                            }
+                        
+                            // Handle image array
+                            if (data.image && Array.isArray(data.image)) {
+                                data.image.forEach(img => {
+                                    if (typeof img === 'string') {
+                                        push(img, 'image');
+                                    } else if (img.url) {
+                                        push(img.url, 'image');
                                     }
-                                }
-                            });
-                        } catch (slideErr) {
-                            debug.errors.push(`Error processing slide ${i}: ${slideErr.toString()}`);
+                                });
+                            }
+                        } catch (parseErr) {
+                            debug.errors.push(`Error parsing JSON-LD ${idx}: ${parseErr.toString()}`);
                         }
-                    }
+                    });
+                } catch (jsonErr) {
+                    debug.errors.push(`JSON-LD extraction error: ${jsonErr.toString()}`);
+                }
+            
+                // Check Open Graph meta tags
+                try {
+                    const ogImage = document.querySelector('meta[property="og:image"]')?.content;
+                    const ogVideo = document.querySelector('meta[property="og:video"]')?.content;
+                    debug.elements.hasOgImage = !!ogImage;
+                    debug.elements.hasOgVideo = !!ogVideo;
+                
+                    if (ogImage) push(ogImage, 'image');
+                    if (ogVideo) push(ogVideo, 'video');
+                } catch (ogErr) {
+                    debug.errors.push(`OG tag extraction error: ${ogErr.toString()}`);
+                }
+
+                // Check for carousel
+                const carouselDots = article.querySelectorAll('div[role="button"] > div > div > div');
+                const isCarousel = carouselDots.length > 1;
+                debug.elements.isCarousel = isCarousel;
+                debug.elements.carouselDots = carouselDots.length;
+
+                if (isCarousel) {
+                    const nextButton = Array.from(article.querySelectorAll('button'))
+                        .find(btn => btn.querySelector('svg[aria-label="Next"]'));
+                    debug.elements.hasNextButton = !!nextButton;
+                    // The caller walks the remaining slides one call at a time
+                    if (nextButton) slides = carouselDots.length;
                 }
+            } catch (e) {
+                debug.errors.push(`Main extraction failed: ${e.toString()}`);
             }
-        } catch (e) {
-            debug.errors.push(`Main extraction failed: ${e.toString()}`);
-        }
 
-        return { media, debug };
+            return { media, debug, slides };
+        })();
     "#;
 
+    let (media, slides) = run_post_script(client, job_id, post_script, 0, &[]).await?;
+    let slides = slides.min(Config::current().max_carousel_slides);
+    let media_array = walk_carousel(media, slides, CAROUSEL_STALE_LIMIT, |slide, known| {
+        let mut client = client.clone();
+        async move { Ok(run_post_script(&mut client, job_id, post_script, slide, &known).await?.0) }
+    })
+    .await;
+
+    if media_array.is_empty() {
+        println!("⚠️ No media found after all extraction attempts");
+    } else {
+        println!("✅ Found {} media items", media_array.len());
+    }
+
+    Ok(media_array)
+}
+
+// Run the post script for `slide` (0 for the page as loaded), returning the media it found and
+// how many carousel slides the page has to walk through
+async fn run_post_script(
+    client: &mut Client,
+    job_id: &str,
+    script: &str,
+    slide: usize,
+    known: &[String],
+) -> Result<(Vec<ExtractedMedia>, usize)> {
+    let args = vec![Value::from(slide), Value::from(CAROUSEL_VIDEO_POLLS), Value::from(known.to_vec())];
     let post_result = client
-        .execute(post_script, vec![])
+        .execute(script, args)
         .await
         .map_err(|e| DownloadError(format!("Failed to execute post script: {}", e)))?;
 
-    // Process post results with debug info
-    let media_array = if let Some(result_obj) = post_result.as_object() {
-        // Extract and log debug info
-        if let Some(debug) = result_obj.get("debug") {
-            println!("📝 Post extraction debug: {}", serde_json::to_string_pretty(debug).unwrap_or_default());
-        }
-        
-        // Process media items
-        if let Some(media_arr) = result_obj.get("media").and_then(|m| m.as_array()) {
-            media_arr.iter().filter_map(|item| {
-                if let Some(obj) = item.as_object() {
-                    let url = obj.get("url")?.as_str()?;
-                    let media_type = obj.get("type")?.as_str()?;
-                    if !url.is_empty() && !url.starts_with("blob:") {
-                        // Return both videos and images for posts
-                        return Some((url.to_string(), media_type.to_string()));
+    // Extract and log debug info
+    if let Some(debug) = post_result.get("debug") {
+        let stage = if slide == 0 { "post".to_string() } else { format!("post_slide_{}", slide) };
+        dump_debug(job_id, &stage, debug);
+    }
+    let slides = post_result.get("slides").and_then(Value::as_u64).unwrap_or(0) as usize;
+    Ok((parse_post_media(&post_result), slides))
+}
+
+// The `media` items of a post script result, each paired with its image's alt text
+fn parse_post_media(post_result: &Value) -> Vec<ExtractedMedia> {
+    post_result
+        .get("media")
+        .and_then(|m| m.as_array())
+        .map(|items| {
+            items.iter().filter_map(|item| {
+                let url = item.get("url")?.as_str()?;
+                let media_type = item.get("type")?.as_str()?;
+                if url.is_empty() || url.starts_with("blob:") {
+                    return None;
+                }
+                // Return both videos and images for posts
+                let alt_text = item.get("alt").and_then(|alt| alt.as_str()).map(str::trim).filter(|alt| !alt.is_empty());
+                Some(ExtractedMedia {
+                    url: url.to_string(),
+                    media_type: media_type.to_string(),
+                    alt_text: alt_text.map(|alt| alt.to_string()),
+                })
+            }).collect()
+        })
+        .unwrap_or_default()
+}
+
+// Click through slides 1..slides with `next_slide`, adding what each shows to `media`. Stops
+// early once `stale_limit` slides in a row add nothing new, as when "Next" stops changing the
+// slide. `next_slide` gets the slide number and the URLs found so far.
+async fn walk_carousel<Fut>(
+    mut media: Vec<ExtractedMedia>,
+    slides: usize,
+    stale_limit: usize,
+    mut next_slide: impl FnMut(usize, Vec<String>) -> Fut,
+) -> Vec<ExtractedMedia>
+where
+    Fut: std::future::Future<Output = Result<Vec<ExtractedMedia>>>,
+{
+    let mut stale_rounds = 0;
+    for slide in 1..slides {
+        let before = media.len();
+        let known = media.iter().map(|item| item.url.clone()).collect();
+        match next_slide(slide, known).await {
+            Ok(items) => {
+                for item in items {
+                    match media.iter_mut().find(|m| m.url == item.url) {
+                        Some(existing) => {
+                            if existing.alt_text.is_none() {
+                                existing.alt_text = item.alt_text;
+                            }
+                        }
+                        None => media.push(item),
                     }
                 }
-                None
-            }).collect::<Vec<_>>()
-        } else {
-            Vec::new()
+            }
+            Err(e) => println!("⚠️ Error processing carousel slide {}: {}", slide, e),
+        }
+        stale_rounds = if media.len() > before { 0 } else { stale_rounds + 1 };
+        if stale_rounds >= stale_limit {
+            println!("ℹ️ Carousel stopped changing at slide {}", slide);
+            break;
         }
-    } else {
-        Vec::new()
-    };
-
-    if media_array.is_empty() {
-        println!("⚠️ No media found after all extraction attempts");
-    } else {
-        println!("✅ Found {} media items", media_array.len());
     }
+    media
+}
 
-    Ok(media_array)
+/// Error message when a story URL didn't open the story viewer
+pub const NO_STORY_VIEWER: &str = "The URL didn't load a story viewer; the story may have expired or require login";
+
+/// Whether the page at `path` is a story viewer: still on a `/stories/` URL and showing the
+/// viewer's playback controls.
+pub fn is_story_viewer(path: &str, has_controls: bool) -> bool {
+    path.contains("/stories/") && has_controls
 }
 
 // Robust story extraction with retries
 pub async fn extract_stories(client: &mut Client) -> Result<Vec<(String, String)>> {
-    for attempt in 0..=MAX_EXTRACTION_RETRIES {
-        let result = extract_stories_once(client).await;
-        match &result {
-            Ok(items) if !items.is_empty() => return result,
-            Ok(_) | Err(_) if attempt < MAX_EXTRACTION_RETRIES => {
-                println!("🔁 Story extraction attempt {} failed, retrying...", attempt + 1);
-                sleep(Duration::from_secs(2)).await;
-            }
-            _ => return result,
+    with_retries(&Config::current().extraction_retry(), "Story extraction", || {
+        let mut client = client.clone();
+        async move { extract_stories_once(&mut client).await.map(absolutize_media) }
+    })
+    .await
+}
+
+// `Err` with NO_STORY_VIEWER unless the viewer script's `{ path, hasControls }` describes a
+// loaded story viewer
+fn check_story_viewer(viewer: &Value) -> Result<()> {
+    let path = viewer.get("path").and_then(|p| p.as_str()).unwrap_or_default();
+    let has_controls = viewer.get("hasControls").and_then(|c| c.as_bool()).unwrap_or(false);
+    if !is_story_viewer(path, has_controls) {
+        return Err(DownloadError(format!("{} (ended up on '{}')", NO_STORY_VIEWER, path)));
+    }
+    Ok(())
+}
+
+// The `(url, media_type)` of the story the viewer script found, or `None` when there was
+// nothing to download (no media, or a video only streamed through a blob: URL)
+fn story_item(story_data: &Value) -> Option<(String, String)> {
+    let url = story_data.get("url")?.as_str()?;
+    let media_type = story_data.get("type")?.as_str()?;
+    if url.is_empty() || url.starts_with("blob:") {
+        if media_type == "video" {
+            println!("⚠️ Skipping a video story that is only streamed through a blob: URL");
         }
+        return None;
+    }
+    Some((url.to_string(), media_type.to_string()))
+}
+
+// `{url, type}` of the story from the media the story script found on the page. A video story
+// also shows its poster as an <img>, so any <video> makes it a video story, even when its
+// source is a blob: URL that can't be downloaded.
+fn classify_story(page: &Value) -> Value {
+    let first = |key: &str| page.get(key).and_then(Value::as_array).and_then(|found| found.first()).cloned();
+    match (first("videos"), first("images")) {
+        (Some(video), _) => serde_json::json!({ "url": video, "type": "video" }),
+        (None, Some(image)) => serde_json::json!({ "url": image, "type": "image" }),
+        (None, None) => Value::Null,
     }
-    Err(DownloadError("All story extraction attempts failed".to_string()))
 }
 
 // The original story extraction logic, now private
@@ -492,17 +993,33 @@ async fn extract_stories_once(client: &mut Client) -> Result<Vec<(String, String
     
     // Wait for stories to load
     sleep(Duration::from_secs(LOAD_TIMEOUT)).await;
+
+    // An expired story or a login wall redirects away or renders no viewer at all; tell that
+    // apart from a viewer that loaded but had nothing to extract
+    let viewer_script = r#"
+        const controls = document.querySelector(
+            'svg[aria-label="Pause"], svg[aria-label="Play"], button[aria-label="Next"], button[aria-label="Previous"]'
+        );
+        return { path: window.location.pathname, hasControls: !!controls };
+    "#;
+    let viewer = client.execute(viewer_script, vec![])
+        .await
+        .map_err(|e| DownloadError(format!("Failed to execute story viewer script: {}", e)))?;
+    check_story_viewer(&viewer)?;
     
     // Story extraction script similar to fullcode.rs
     let extract_script = r#"
-        function extractCurrentStory() {
-            // Try to find video first
-            let video = document.querySelector('video[src]');
-            if (video && video.src && !video.src.startsWith('blob:')) {
-                return { url: video.src, type: 'video' };
-            }
-            
-            // Then look for image
+        // Every <video> and the best image on the page; which one the story is gets decided
+        // on the Rust side
+        function currentStoryMedia() {
+            const videos = Array.from(document.querySelectorAll('video')).map(video => {
+                const source = video.querySelector('source[src]');
+                return video.currentSrc || video.src || (source && source.src) || '';
+            });
+            return { videos, images: [bestImage()].filter(Boolean) };
+        }
+
+        function bestImage() {
             let img = document.querySelector('img[srcset]');
             if (img && img.srcset) {
                 // Try to get highest quality from srcset
@@ -523,20 +1040,20 @@ async fn extract_stories_once(client: &mut Client) -> Result<Vec<(String, String
                 });
                 
                 if (highestQuality) {
-                    return { url: highestQuality, type: 'image' };
+                    return highestQuality;
                 }
             }
             
             // Fallback to basic image
             img = document.querySelector('img[src]');
             if (img && img.src && !img.src.startsWith("data:")) {
-                return { url: img.src, type: 'image' };
+                return img.src;
             }
             
             return null;
         }
         
-        return extractCurrentStory();
+        return currentStoryMedia();
     "#;
 
     // First try to get the current story
@@ -545,15 +1062,7 @@ async fn extract_stories_once(client: &mut Client) -> Result<Vec<(String, String
         .map_err(|e| DownloadError(format!("Failed to execute story script: {}", e)))?;
     
     let mut result = Vec::new();
-    
-    if let Some(obj) = story_data.as_object() {
-        if let (Some(url), Some(media_type)) = (obj.get("url").and_then(|u| u.as_str()), 
-                                               obj.get("type").and_then(|t| t.as_str())) {
-            if !url.is_empty() && !url.starts_with("blob:") {
-                result.push((url.to_string(), media_type.to_string()));
-            }
-        }
-    }
+    result.extend(story_item(&classify_story(&story_data)));
 
     // Check if we have a next story button
     let next_story_script = r#"
@@ -565,44 +1074,31 @@ async fn extract_stories_once(client: &mut Client) -> Result<Vec<(String, String
         return false;
     "#;
 
-    // Try to extract up to 20 stories to avoid infinite loop
+    // Look at up to 20 stories to avoid an infinite loop. A story that can't be downloaded
+    // (a blob: video) is skipped rather than ending the walk through the set.
     let max_stories = 20;
-    let mut story_count = 1;
-    
-    // If the first story was found, try to find more
-    if !result.is_empty() {
-        while story_count < max_stories {
-            // Try to navigate to next story
-            sleep(Duration::from_millis(1000)).await;
-            
-            let next_result = client.execute(next_story_script, vec![])
-                .await
-                .map_err(|e| DownloadError(format!("Failed to execute next story script: {}", e)))?;
-                
-            let has_more = next_result.as_bool().unwrap_or(false);
-            
-            if !has_more {
-                break;
-            }
-            
-            // Wait for next story to load
-            sleep(Duration::from_millis(1500)).await;
+    for _ in 1..max_stories {
+        // Try to navigate to next story
+        sleep(Duration::from_millis(1000)).await;
+        
+        let next_result = client.execute(next_story_script, vec![])
+            .await
+            .map_err(|e| DownloadError(format!("Failed to execute next story script: {}", e)))?;
             
-            // Extract current story media
-            let story_data = client.execute(extract_script, vec![])
-                .await
-                .map_err(|e| DownloadError(format!("Failed to execute story script: {}", e)))?;
-            
-            if let Some(obj) = story_data.as_object() {
-                if let (Some(url), Some(media_type)) = (obj.get("url").and_then(|u| u.as_str()), 
-                                                       obj.get("type").and_then(|t| t.as_str())) {
-                    if !url.is_empty() && !url.starts_with("blob:") {
-                        story_count += 1;
-                        result.push((url.to_string(), media_type.to_string()));
-                    }
-                }
-            }
+        let has_more = next_result.as_bool().unwrap_or(false);
+        
+        if !has_more {
+            break;
         }
+        
+        // Wait for next story to load
+        sleep(Duration::from_millis(1500)).await;
+        
+        // Extract current story media
+        let story_data = client.execute(extract_script, vec![])
+            .await
+            .map_err(|e| DownloadError(format!("Failed to execute story script: {}", e)))?;
+        result.extend(story_item(&classify_story(&story_data)));
     }
 
     if !result.is_empty() {
@@ -615,6 +1111,26 @@ async fn extract_stories_once(client: &mut Client) -> Result<Vec<(String, String
 }
 
 // --- New: Robust video extraction using headless_chrome network interception ---
+// Write `bytes` to `filename` if they are a video (not a login page or the poster image) and
+// not a truncated placeholder, returning the path of the saved file
+fn save_reel_video(filename: &str, bytes: &[u8]) -> Option<String> {
+    if detect_media_kind(bytes) != Some(MediaKind::Video) {
+        println!("⚠️ Downloaded reel is not a video ({:?})", detect_media_kind(bytes));
+        return None;
+    }
+    if (bytes.len() as u64) < MIN_REEL_BYTES {
+        println!("⚠️ Downloaded video is too small ({} bytes)", bytes.len());
+        return None;
+    }
+    match std::fs::write(filename, bytes) {
+        Ok(()) => Some(filename.to_string()),
+        Err(e) => {
+            println!("Failed to write video file: {}", e);
+            None
+        }
+    }
+}
+
 pub async fn extract_reel_video_with_headless_chrome(
     url: &str,
     folder_name: &str,
@@ -631,9 +1147,11 @@ pub async fn extract_reel_video_with_headless_chrome(
     // Updated implementation with proper API usage
     let video_urls = Arc::new(Mutex::new(Vec::<String>::new()));
     
+    let lang_arg = chrome_lang_arg();
     let launch_options = LaunchOptionsBuilder::default()
         .headless(true)
         .window_size(Some((1280, 800)))
+        .args(vec![std::ffi::OsStr::new(&lang_arg)])
         .build()
         .map_err(|e| DownloadError(format!("Failed to build launch options: {}", e)))?;
     
@@ -682,17 +1200,18 @@ pub async fn extract_reel_video_with_headless_chrome(
                 });
                 
                 // Look for mp4 links in JSON-LD script tags
-                try {
-                    document.querySelectorAll('script[type="application/ld+json"]').forEach(script => {
+                // Each block is parsed on its own so one malformed block doesn't hide the others
+                document.querySelectorAll('script[type="application/ld+json"]').forEach(script => {
+                    try {
                         const json = JSON.parse(script.textContent);
                         if (json.contentUrl && json.contentUrl.includes('.mp4'))
                             links.push(json.contentUrl);
                         if (json.video && json.video.contentUrl && json.video.contentUrl.includes('.mp4'))
                             links.push(json.video.contentUrl);
-                    });
-                } catch (e) {
-                    console.error('Error parsing JSON-LD:', e);
-                }
+                    } catch (e) {
+                        console.error('Error parsing JSON-LD:', e);
+                    }
+                });
                 
                 // Look for Open Graph meta tags
                 const ogVideoTag = document.querySelector('meta[property="og:video"]');
@@ -730,17 +1249,7 @@ pub async fn extract_reel_video_with_headless_chrome(
             match reqwest::blocking::get(video_url) {
                 Ok(resp) => {
                     match resp.bytes() {
-                        Ok(bytes) => {
-                            // Write video file
-                            if let Err(e) = std::fs::write(&filename, &bytes) {
-                                println!("Failed to write video file: {}", e);
-                            } else if bytes.len() > 200_000 {
-                                result.video_path = Some(filename);
-                            } else {
-                                // File is too small, likely not a valid video
-                                let _ = std::fs::remove_file(&filename);
-                            }
-                        }
+                        Ok(bytes) => result.video_path = save_reel_video(&filename, &bytes),
                         Err(e) => println!("Failed to read video bytes: {}", e),
                     }
                 }
@@ -778,18 +1287,16 @@ pub async fn extract_reel_video_with_headless_chrome(
     Ok(blocking_result.video_path)
 }
 
-// Function to extract media from metadata (Open Graph, JSON-LD) when direct extraction fails
-pub async fn extract_media_from_metadata(client: &mut Client) -> Result<Vec<(String, String)>> {
-    println!("🧩 Trying metadata extraction for login-protected content...");
-    
-    // Execute script to extract data from meta tags and JSON-LD
-    let metadata_script = r#"
-        function extractMetadata() {
-            const media = [];
-            const debug = { elements: {}, errors: [] };
-            
+// Open Graph and JSON-LD media on the page, for when direct extraction fails
+const METADATA_SCRIPT: &str = r#"
+    function extractMetadata() {
+        const media = [];
+        const debug = { elements: {}, errors: [] };
+        
+        try {
+            // 1. Check Open Graph meta tags (these are often available even when login is required)
+            // Kept in its own try so a failure here doesn't skip the remaining sources
             try {
-                // 1. Check Open Graph meta tags (these are often available even when login is required)
                 const ogImage = document.querySelector('meta[property="og:image"]')?.content;
                 const ogVideo = document.querySelector('meta[property="og:video"]')?.content;
                 const ogVideoUrl = document.querySelector('meta[property="og:video:url"]')?.content;
@@ -809,92 +1316,99 @@ pub async fn extract_media_from_metadata(client: &mut Client) -> Result<Vec<(Str
                 
                 // Process image URLs
                 if (ogImage && !media.some(m => m.url === ogImage)) {
-                    media.push({ url, type: 'image' });
+                    media.push({ url: ogImage, type: 'image' });
                 }
-                
-                // 2. Check JSON-LD data (often contains high-quality media references)
-                const jsonLdScripts = document.querySelectorAll('script[type="application/ld+json"]');
-                debug.elements.jsonLdScriptCount = jsonLdScripts.length;
-                
-                jsonLdScripts.forEach((script, index) => {
-                    try {
-                        const data = JSON.parse(script.textContent);
-                        debug.elements[`jsonLd_${index}_type`] = data["@type"] || 'unknown';
-                        
-                        // Check for video content URL
-                        if (data.contentUrl && data.contentUrl.includes('.mp4')) {
-                            const url = data.contentUrl;
-                            if (!media.some(m => m.url === url)) {
-                                media.push({ url, type: 'video' });
-                            }
-                        }
-                        
-                        // Check for nested video content
-                        if (data.video && data.video.contentUrl) {
-                            const url = data.video.contentUrl;
-                            if (!media.some(m => m.url === url)) {
-                                media.push({ url, type: 'video' });
-                            }
-                        }
-                        
-                        // Process image arrays or single images
-                        if (data.image) {
-                            const images = Array.isArray(data.image) ? data.image : [data.image];
-                            images.forEach(img => {
-                                const url = typeof img === 'string' ? img : (img.url || null);
-                                if (url && !media.some(m => m.url === url)) {
-                                    media.push({ url, type: 'image' });
-                                }
-                            });
+            } catch (ogErr) {
+                debug.errors.push(`OG tag extraction error: ${ogErr.toString()}`);
+            }
+            
+            // 2. Check JSON-LD data (often contains high-quality media references)
+            const jsonLdScripts = document.querySelectorAll('script[type="application/ld+json"]');
+            debug.elements.jsonLdScriptCount = jsonLdScripts.length;
+            
+            jsonLdScripts.forEach((script, index) => {
+                try {
+                    const data = JSON.parse(script.textContent);
+                    debug.elements[`jsonLd_${index}_type`] = data["@type"] || 'unknown';
+                    
+                    // Check for video content URL
+                    if (data.contentUrl && data.contentUrl.includes('.mp4')) {
+                        const url = data.contentUrl;
+                        if (!media.some(m => m.url === url)) {
+                            media.push({ url, type: 'video' });
                         }
-                        
-                        // Try to get thumbnails as a last resort
-                        if (data.thumbnailUrl) {
-                            const thumbs = Array.isArray(data.thumbnailUrl) ? data.thumbnailUrl : [data.thumbnailUrl];
-                            thumbs.forEach(url => {
-                                if (url && !media.some(m => m.url === url)) {
-                                    media.push({ url, type: 'image' });
-                                }
-                            });
+                    }
+                    
+                    // Check for nested video content
+                    if (data.video && data.video.contentUrl) {
+                        const url = data.video.contentUrl;
+                        if (!media.some(m => m.url === url)) {
+                            media.push({ url, type: 'video' });
                         }
-                    } catch (e) {
-                        debug.errors.push(`Error parsing JSON-LD ${index}: ${e.toString()}`);
                     }
-                });
-                
-                // 3. Check if there are any images with data-src attributes (sometimes Instagram uses these)
-                const dataSrcImages = document.querySelectorAll('img[data-src]');
-                debug.elements.dataSrcImageCount = dataSrcImages.length;
-                
-                dataSrcImages.forEach((img, index) => {
-                    const url = img.getAttribute('data-src');
-                    if (url && !url.startsWith("data:") && !media.some(m => m.url === url)) {
-                        media.push({ url, type: 'image' });
+                    
+                    // Process image arrays or single images
+                    if (data.image) {
+                        const images = Array.isArray(data.image) ? data.image : [data.image];
+                        images.forEach(img => {
+                            const url = typeof img === 'string' ? img : (img.url || null);
+                            if (url && !media.some(m => m.url === url)) {
+                                media.push({ url, type: 'image' });
+                            }
+                        });
                     }
-                });
-                
-                // 4. For reels specifically, try to find preload links that might contain video URLs
-                const preloadLinks = document.querySelectorAll('link[rel="preload"][as="video"]');
-                debug.elements.preloadLinkCount = preloadLinks.length;
-                
-                preloadLinks.forEach((link, index) => {
-                    const url = link.href;
-                    if (url && !url.startsWith("blob:") && !media.some(m => m.url === url)) {
-                        media.push({ url, type: 'video' });
+                    
+                    // Try to get thumbnails as a last resort
+                    if (data.thumbnailUrl) {
+                        const thumbs = Array.isArray(data.thumbnailUrl) ? data.thumbnailUrl : [data.thumbnailUrl];
+                        thumbs.forEach(url => {
+                            if (url && !media.some(m => m.url === url)) {
+                                media.push({ url, type: 'image' });
+                            }
+                        });
                     }
-                });
-            } catch (e) {
-                debug.errors.push(`Main extraction error: ${e.toString()}`);
-            }
+                } catch (e) {
+                    debug.errors.push(`Error parsing JSON-LD ${index}: ${e.toString()}`);
+                }
+            });
+            
+            // 3. Check if there are any images with data-src attributes (sometimes Instagram uses these)
+            const dataSrcImages = document.querySelectorAll('img[data-src]');
+            debug.elements.dataSrcImageCount = dataSrcImages.length;
             
-            return { media, debug };
+            dataSrcImages.forEach((img, index) => {
+                const url = img.getAttribute('data-src');
+                if (url && !url.startsWith("data:") && !media.some(m => m.url === url)) {
+                    media.push({ url, type: 'image' });
+                }
+            });
+            
+            // 4. For reels specifically, try to find preload links that might contain video URLs
+            const preloadLinks = document.querySelectorAll('link[rel="preload"][as="video"]');
+            debug.elements.preloadLinkCount = preloadLinks.length;
+            
+            preloadLinks.forEach((link, index) => {
+                const url = link.href;
+                if (url && !url.startsWith("blob:") && !media.some(m => m.url === url)) {
+                    media.push({ url, type: 'video' });
+                }
+            });
+        } catch (e) {
+            debug.errors.push(`Main extraction error: ${e.toString()}`);
         }
         
-        return extractMetadata();
-    "#;
+        return { media, debug };
+    }
+    
+    return extractMetadata();
+"#;
+
+// Function to extract media from metadata (Open Graph, JSON-LD) when direct extraction fails
+pub async fn extract_media_from_metadata(client: &mut Client, job_id: &str) -> Result<Vec<(String, String)>> {
+    println!("🧩 Trying metadata extraction for login-protected content...");
     
     let result = client
-        .execute(metadata_script, vec![])
+        .execute(METADATA_SCRIPT, vec![])
         .await
         .map_err(|e| DownloadError(format!("Failed to execute metadata extraction script: {}", e)))?;
         
@@ -902,7 +1416,7 @@ pub async fn extract_media_from_metadata(client: &mut Client) -> Result<Vec<(Str
     if let Some(result_obj) = result.as_object() {
         // Log debug info
         if let Some(debug) = result_obj.get("debug") {
-            println!("🔍 Metadata extraction debug: {}", serde_json::to_string_pretty(debug).unwrap_or_default());
+            dump_debug(job_id, "metadata", debug);
         }
         
         // Extract media items
@@ -920,7 +1434,7 @@ pub async fn extract_media_from_metadata(client: &mut Client) -> Result<Vec<(Str
             });
             if let Some(first_video) = videos.next() {
                 println!("✅ Found reel video through metadata extraction");
-                return Ok(vec![first_video]);
+                return Ok(absolutize_media(vec![first_video]));
             }
             // Otherwise, fallback to all images (for non-reel cases)
             let items = media_arr.iter().filter_map(|item| {
@@ -935,7 +1449,7 @@ pub async fn extract_media_from_metadata(client: &mut Client) -> Result<Vec<(Str
             }).collect::<Vec<_>>();
             if !items.is_empty() {
                 println!("✅ Found {} media items through metadata extraction", items.len());
-                return Ok(items);
+                return Ok(absolutize_media(items));
             }
         }
     }
@@ -944,12 +1458,1281 @@ pub async fn extract_media_from_metadata(client: &mut Client) -> Result<Vec<(Str
     Ok(Vec::new())
 }
 
+// Error texts fantoccini and chromedriver produce once the browser or its driver has died
+const SESSION_LOST_MARKERS: &[&str] = &[
+    "webdriver connection lost",
+    "invalid session id",
+    "session deleted",
+    "no such window",
+    "target window already closed",
+    "chrome not reachable",
+];
+
+// True when an extraction error means the WebDriver session is gone (crashed chromedriver or
+// Chrome), so further commands on the same client can't succeed
+pub fn is_webdriver_session_lost(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    SESSION_LOST_MARKERS.iter().any(|marker| message.contains(marker))
+}
+
+// True when media candidates were found but every one is a MediaSource blob: URL
+pub fn is_blob_only(candidates: &[String]) -> bool {
+    !candidates.is_empty() && candidates.iter().all(|url| url.starts_with("blob:"))
+}
+
+/// Poster image of the video on the open page: `og:image`, else the first JSON-LD
+/// `thumbnailUrl`. `None` when the page has neither.
+pub async fn extract_cover_url(client: &Client) -> Option<String> {
+    let script = r#"
+        const og = document.querySelector('meta[property="og:image"]')?.content;
+        if (og) return og;
+        for (const script of document.querySelectorAll('script[type="application/ld+json"]')) {
+            try {
+                const parsed = JSON.parse(script.textContent);
+                for (const data of (Array.isArray(parsed) ? parsed : [parsed])) {
+                    const thumbs = [data.thumbnailUrl, data.video?.thumbnailUrl].flat().filter(Boolean);
+                    if (thumbs.length > 0) return thumbs[0];
+                }
+            } catch (e) {}
+        }
+        return null;
+    "#;
+    match client.execute(script, vec![]).await {
+        Ok(value) => value.as_str().and_then(absolutize_media_url),
+        Err(e) => {
+            println!("⚠️ Failed to look up the cover image: {}", e);
+            None
+        }
+    }
+}
+
+/// Pick the first `.mp4` from a list of loaded resource URLs (Resource Timing entries).
+/// `bytestart`/`byteend` range parameters are dropped so the whole file is fetched
+/// instead of the segment the player happened to buffer.
+pub fn first_mp4_resource(resources: &[String]) -> Option<String> {
+    resources.iter().find_map(|resource| {
+        let mut url = url::Url::parse(resource).ok()?;
+        if !url.path().ends_with(".mp4") {
+            return None;
+        }
+        let query: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != "bytestart" && key != "byteend")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        if query.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(query);
+        }
+        Some(url.to_string())
+    })
+}
+
+// Web app id Instagram's own frontend sends; the JSON endpoints answer 4xx without it
+const INSTAGRAM_WEB_APP_ID: &str = "936619743392459";
+
+/// Pick the largest image from a web JSON candidates array. Accepts both the v1 API shape
+/// (`{url, width, height}`) and the GraphQL `display_resources` shape (`{src, config_width, config_height}`).
+pub fn best_image_candidate(candidates: &Value) -> Option<String> {
+    candidates
+        .as_array()?
+        .iter()
+        .filter_map(|candidate| {
+            let url = candidate.get("url").or_else(|| candidate.get("src"))?.as_str()?;
+            let dimension = |keys: [&str; 2]| {
+                keys.iter().find_map(|key| candidate.get(*key).and_then(|v| v.as_u64())).unwrap_or(0)
+            };
+            let area = dimension(["width", "config_width"]) * dimension(["height", "config_height"]);
+            Some((area, url))
+        })
+        .max_by_key(|(area, _)| *area)
+        .map(|(_, url)| url.to_string())
+}
+
+/// Which encode of a video to pick from the web JSON's `video_versions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoQuality {
+    /// The largest frame, then the highest bitrate (the default)
+    #[default]
+    Highest,
+    /// The smallest frame, then the lowest bitrate, for clients on slow or metered connections
+    Lowest,
+}
+
+impl VideoQuality {
+    /// Parse a request's `video_quality` (`highest` or `lowest`); `None` when it wasn't sent.
+    pub fn parse(value: Option<&str>) -> std::result::Result<Option<Self>, String> {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            None => Ok(None),
+            Some("highest") => Ok(Some(Self::Highest)),
+            Some("lowest") => Ok(Some(Self::Lowest)),
+            Some(other) => Err(format!("Unsupported video_quality '{}': use 'highest' or 'lowest'", other)),
+        }
+    }
+}
+
+/// Pick a video from a web JSON `video_versions` array, ranked by frame size and, among equal
+/// sizes, by bitrate (`bandwidth`, when listed). Instagram lists several encodes of the same
+/// clip and the first is not always the best.
+///
+/// `dash_info` / `video_dash_manifest` is deliberately not used: its representations are
+/// video-only DASH tracks with the sound in a separate stream, so picking one would save a
+/// silent file. `video_versions` entries are complete mp4s.
+pub fn pick_video_version(versions: &Value, quality: VideoQuality) -> Option<String> {
+    let ranked = versions.as_array()?.iter().filter_map(|version| {
+        let url = version.get("url")?.as_str()?;
+        let number = |key: &str| version.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+        Some(((number("width") * number("height"), number("bandwidth")), url))
+    });
+    let picked = match quality {
+        VideoQuality::Highest => ranked.max_by_key(|(rank, _)| *rank),
+        VideoQuality::Lowest => ranked.min_by_key(|(rank, _)| *rank),
+    };
+    picked.map(|(_, url)| url.to_string())
+}
+
+/// Video URLs of a post or reel from its web JSON at the requested quality, in carousel order.
+/// Images are skipped, so the result lines up with the item's video entries. The older
+/// GraphQL shape only lists a single `video_url` per video.
+pub fn original_videos_from_web_json(json: &Value, quality: VideoQuality) -> Vec<String> {
+    if let Some(item) = json.pointer("/items/0") {
+        let children = item.get("carousel_media").and_then(|c| c.as_array()).cloned().unwrap_or_else(|| vec![item.clone()]);
+        return children
+            .iter()
+            .filter(|child| child.get("media_type").and_then(|t| t.as_u64()) == Some(2))
+            .filter_map(|child| pick_video_version(child.get("video_versions")?, quality))
+            .collect();
+    }
+    if let Some(media) = json.pointer("/graphql/shortcode_media") {
+        let children = match media.pointer("/edge_sidecar_to_children/edges").and_then(|e| e.as_array()) {
+            Some(edges) => edges.iter().filter_map(|edge| edge.get("node").cloned()).collect(),
+            None => vec![media.clone()],
+        };
+        return children
+            .iter()
+            .filter(|child| child.get("is_video").and_then(|v| v.as_bool()).unwrap_or(false))
+            .filter_map(|child| child.get("video_url")?.as_str().map(|url| url.to_string()))
+            .collect();
+    }
+    Vec::new()
+}
+
+/// Full-resolution image URLs of a post from its web JSON (`?__a=1`), in carousel order.
+/// Videos are skipped, so the result lines up with the post's image items.
+pub fn original_images_from_web_json(json: &Value) -> Vec<String> {
+    // v1 API shape: items[0], with carousel_media for sidecars
+    if let Some(item) = json.pointer("/items/0") {
+        let children = item.get("carousel_media").and_then(|c| c.as_array()).cloned().unwrap_or_else(|| vec![item.clone()]);
+        return children
+            .iter()
+            // media_type 1 is an image, 2 a video
+            .filter(|child| child.get("media_type").and_then(|t| t.as_u64()) == Some(1))
+            .filter_map(|child| best_image_candidate(child.pointer("/image_versions2/candidates")?))
+            .collect();
+    }
+    // Older GraphQL shape: graphql.shortcode_media, with edge_sidecar_to_children for sidecars
+    if let Some(media) = json.pointer("/graphql/shortcode_media") {
+        let children = match media.pointer("/edge_sidecar_to_children/edges").and_then(|e| e.as_array()) {
+            Some(edges) => edges.iter().filter_map(|edge| edge.get("node").cloned()).collect(),
+            None => vec![media.clone()],
+        };
+        return children
+            .iter()
+            .filter(|child| !child.get("is_video").and_then(|v| v.as_bool()).unwrap_or(false))
+            .filter_map(|child| {
+                child.get("display_resources").and_then(best_image_candidate)
+                    .or_else(|| child.get("display_url")?.as_str().map(|url| url.to_string()))
+            })
+            .collect();
+    }
+    Vec::new()
+}
+
+/// Fetch a post's web JSON (`?__a=1`) from inside the browser session, so its cookies apply.
+pub async fn fetch_web_json(client: &Client, shortcode: &str) -> Result<Value> {
+    let script = r#"
+        const [shortcode, appId, done] = arguments;
+        fetch(`/p/${shortcode}/?__a=1&__d=dis`, {
+            credentials: 'include',
+            headers: { 'X-IG-App-ID': appId, 'Accept': 'application/json' }
+        })
+            .then(r => r.ok ? r.json() : { error: `HTTP ${r.status}` })
+            .then(done)
+            .catch(e => done({ error: String(e) }));
+    "#;
+    let json = client
+        .execute_async(script, vec![Value::from(shortcode), Value::from(INSTAGRAM_WEB_APP_ID)])
+        .await
+        .map_err(|e| DownloadError(format!("Failed to fetch web JSON: {}", e)))?;
+    if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
+        return Err(DownloadError(format!("Web JSON unavailable: {}", error)));
+    }
+    Ok(json)
+}
+
+/// A media item found in the web JSON but not on the page, e.g. one contributed by a
+/// collaborator of the post.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollabMedia {
+    pub url: String,
+    pub media_type: String,
+    /// `"coauthor:<username>"` for media owned by a listed collaborator, `"web_json"` otherwise
+    pub source: String,
+}
+
+// Usernames of a web JSON user list such as `coauthor_producers`
+fn usernames(users: Option<&Value>) -> Vec<String> {
+    users
+        .and_then(|u| u.as_array())
+        .map(|users| users.iter().filter_map(|user| user.get("username")?.as_str().map(|name| name.to_string())).collect())
+        .unwrap_or_default()
+}
+
+// A web JSON media item as (url, media type, owner username)
+type WebJsonMedia = (String, String, Option<String>);
+
+// Every media item of the web JSON, plus the post's coauthor usernames
+fn web_json_media(json: &Value) -> (Vec<WebJsonMedia>, Vec<String>) {
+    // v1 API shape: items[0], with carousel_media for sidecars
+    if let Some(item) = json.pointer("/items/0") {
+        let owner = item.pointer("/user/username").and_then(|u| u.as_str());
+        let children = item.get("carousel_media").and_then(|c| c.as_array()).cloned().unwrap_or_else(|| vec![item.clone()]);
+        let media = children
+            .iter()
+            .filter_map(|child| {
+                // media_type 1 is an image, 2 a video
+                let (url, media_type) = match child.get("media_type").and_then(|t| t.as_u64()) {
+                    Some(2) => (pick_video_version(child.get("video_versions")?, VideoQuality::Highest)?, "video"),
+                    _ => (best_image_candidate(child.pointer("/image_versions2/candidates")?)?, "image"),
+                };
+                let child_owner = child.pointer("/user/username").and_then(|u| u.as_str()).or(owner);
+                Some((url, media_type.to_string(), child_owner.map(|o| o.to_string())))
+            })
+            .collect();
+        return (media, usernames(item.get("coauthor_producers")));
+    }
+    // Older GraphQL shape: graphql.shortcode_media, with edge_sidecar_to_children for sidecars
+    if let Some(post) = json.pointer("/graphql/shortcode_media") {
+        let owner = post.pointer("/owner/username").and_then(|u| u.as_str());
+        let children = match post.pointer("/edge_sidecar_to_children/edges").and_then(|e| e.as_array()) {
+            Some(edges) => edges.iter().filter_map(|edge| edge.get("node").cloned()).collect(),
+            None => vec![post.clone()],
+        };
+        let media = children
+            .iter()
+            .filter_map(|child| {
+                let (url, media_type) = if child.get("is_video").and_then(|v| v.as_bool()).unwrap_or(false) {
+                    (child.get("video_url")?.as_str()?.to_string(), "video")
+                } else {
+                    let url = child.get("display_resources").and_then(best_image_candidate)
+                        .or_else(|| child.get("display_url")?.as_str().map(|url| url.to_string()))?;
+                    (url, "image")
+                };
+                let child_owner = child.pointer("/owner/username").and_then(|u| u.as_str()).or(owner);
+                Some((url, media_type.to_string(), child_owner.map(|o| o.to_string())))
+            })
+            .collect();
+        return (media, usernames(post.get("coauthor_producers")));
+    }
+    (Vec::new(), Vec::new())
+}
+
+// The CDN file name of a media URL; stable across the query parameters Instagram rotates
+fn media_file_name(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.path_segments()?.next_back().map(|name| name.to_string())
+}
+
+/// Media listed in a post's web JSON that the page extraction didn't find (`known`), such as
+/// collaborator media on collab posts. Items owned by one of the post's `coauthor_producers`
+/// are tagged with that collaborator.
+pub fn collab_media_from_web_json(json: &Value, known: &[(String, String)]) -> Vec<CollabMedia> {
+    let (media, coauthors) = web_json_media(json);
+    let known_files: Vec<String> = known.iter().filter_map(|(url, _)| media_file_name(url)).collect();
+    let mut found: Vec<CollabMedia> = Vec::new();
+    for (url, media_type, owner) in media {
+        let Some(file_name) = media_file_name(&url) else { continue };
+        if known_files.contains(&file_name) || found.iter().any(|m| media_file_name(&m.url).as_deref() == Some(file_name.as_str())) {
+            continue;
+        }
+        let source = match owner.filter(|owner| coauthors.contains(owner)) {
+            Some(coauthor) => format!("coauthor:{}", coauthor),
+            None => "web_json".to_string(),
+        };
+        found.push(CollabMedia { url, media_type, source });
+    }
+    found
+}
+
+/// The soundtrack of a reel as listed in its web JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReelAudio {
+    /// Separate audio-only stream, when Instagram offers one
+    pub url: Option<String>,
+    /// `"<song> - <artist>"` for licensed music, the original sound's title otherwise
+    pub title: Option<String>,
+}
+
+// A non-empty string field of a web JSON value
+fn str_at<'a>(value: &'a Value, pointer: &str) -> Option<&'a str> {
+    value.pointer(pointer).and_then(|v| v.as_str()).map(str::trim).filter(|s| !s.is_empty())
+}
+
+// Join a track title and its artist, skipping whichever is missing
+fn audio_title(title: Option<&str>, artist: Option<&str>) -> Option<String> {
+    match (title, artist) {
+        (Some(title), Some(artist)) => Some(format!("{} - {}", title, artist)),
+        (Some(title), None) => Some(title.to_string()),
+        (None, artist) => artist.map(|a| a.to_string()),
+    }
+}
+
+/// Music or original-audio info of a reel from its web JSON, or `None` when it has neither.
+/// The v1 shape (`clips_metadata`) also carries the audio stream URL; the older GraphQL
+/// shape (`clips_music_attribution_info`) only names the track.
+pub fn reel_audio_from_web_json(json: &Value) -> Option<ReelAudio> {
+    if let Some(clips) = json.pointer("/items/0/clips_metadata") {
+        let audio = if let Some(music) = clips.pointer("/music_info/music_asset_info") {
+            ReelAudio {
+                url: str_at(music, "/progressive_download_url")
+                    .or_else(|| str_at(music, "/fast_start_progressive_download_url"))
+                    .map(|url| url.to_string()),
+                title: audio_title(str_at(music, "/title"), str_at(music, "/display_artist")),
+            }
+        } else if let Some(original) = clips.get("original_sound_info").filter(|o| !o.is_null()) {
+            ReelAudio {
+                url: str_at(original, "/progressive_download_url").map(|url| url.to_string()),
+                title: audio_title(str_at(original, "/original_audio_title"), str_at(original, "/ig_artist/username")),
+            }
+        } else {
+            return None;
+        };
+        return (audio.url.is_some() || audio.title.is_some()).then_some(audio);
+    }
+    let attribution = json.pointer("/graphql/shortcode_media/clips_music_attribution_info")?;
+    let title = audio_title(str_at(attribution, "/song_name"), str_at(attribution, "/artist_name"))?;
+    Some(ReelAudio { url: None, title: Some(title) })
+}
+
+/// Where a post was taken, who is tagged in it and whether it is sponsored, from its web JSON.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PostMetadata {
+    /// Name of the location attached to the post
+    pub location: Option<String>,
+    /// Usernames tagged in the post or any of its carousel items, without duplicates
+    pub tagged_users: Vec<String>,
+    /// The post carries Instagram's "Paid partnership" label
+    pub is_paid_partnership: bool,
+    /// Username of the brand named in the paid partnership label
+    pub sponsor: Option<String>,
+}
+
+// Username of the first branded-content sponsor of a post: `sponsor_tags` in the v1 shape,
+// `edge_media_to_sponsor_user` in the GraphQL one
+fn sponsor_username(media: &Value) -> Option<String> {
+    let v1 = media
+        .get("sponsor_tags")
+        .and_then(|t| t.as_array())
+        .into_iter()
+        .flatten()
+        .find_map(|tag| str_at(tag, "/sponsor/username").or_else(|| str_at(tag, "/username")));
+    let graphql = || {
+        media
+            .pointer("/edge_media_to_sponsor_user/edges")
+            .and_then(|e| e.as_array())
+            .into_iter()
+            .flatten()
+            .find_map(|edge| str_at(edge, "/node/sponsor/username"))
+    };
+    v1.or_else(graphql).map(str::to_string)
+}
+
+/// Location, tagged users and paid partnership label of a post from its web JSON. Both the
+/// v1 shape (`usertags`, per carousel item too) and the older GraphQL shape
+/// (`edge_media_to_tagged_user`) are read; missing fields leave the location `None`, the user
+/// list empty and the post unsponsored. A sponsor tag without the `is_paid_partnership` flag
+/// still counts as a paid partnership.
+pub fn post_metadata_from_web_json(json: &Value) -> PostMetadata {
+    let mut metadata = PostMetadata::default();
+    let mut tag = |username: Option<&str>| {
+        if let Some(username) = username.filter(|u| !u.is_empty()) {
+            if !metadata.tagged_users.iter().any(|u| u == username) {
+                metadata.tagged_users.push(username.to_string());
+            }
+        }
+    };
+    let location;
+    let sponsored;
+    if let Some(item) = json.pointer("/items/0") {
+        let carousel = item.get("carousel_media").and_then(|c| c.as_array()).cloned().unwrap_or_default();
+        for media in std::iter::once(item).chain(carousel.iter()) {
+            for tagged in media.pointer("/usertags/in").and_then(|t| t.as_array()).into_iter().flatten() {
+                tag(tagged.pointer("/user/username").and_then(|u| u.as_str()));
+            }
+        }
+        location = str_at(item, "/location/name");
+        sponsored = item;
+    } else if let Some(post) = json.pointer("/graphql/shortcode_media") {
+        let children: Vec<&Value> = post
+            .pointer("/edge_sidecar_to_children/edges")
+            .and_then(|e| e.as_array())
+            .map(|edges| edges.iter().filter_map(|edge| edge.get("node")).collect())
+            .unwrap_or_default();
+        for media in std::iter::once(post).chain(children) {
+            for edge in media.pointer("/edge_media_to_tagged_user/edges").and_then(|e| e.as_array()).into_iter().flatten() {
+                tag(edge.pointer("/node/user/username").and_then(|u| u.as_str()));
+            }
+        }
+        location = str_at(post, "/location/name");
+        sponsored = post;
+    } else {
+        return metadata;
+    }
+    metadata.location = location.map(|name| name.to_string());
+    metadata.sponsor = sponsor_username(sponsored);
+    metadata.is_paid_partnership = sponsored.get("is_paid_partnership").and_then(|v| v.as_bool()).unwrap_or(false)
+        || metadata.sponsor.is_some();
+    metadata
+}
+
+/// Swap the image entries of `items` for their full-resolution versions, in order. Only done
+/// when the counts match, since otherwise the originals can't be paired with the DOM results.
+/// Returns how many items were replaced.
+pub fn prefer_original_images(items: &mut [(String, String)], originals: Vec<String>) -> usize {
+    prefer_originals(items, "image", originals)
+}
+
+/// Swap the video entries of `items` for their highest-quality versions, the same way
+/// `prefer_original_images` does for images.
+pub fn prefer_original_videos(items: &mut [(String, String)], originals: Vec<String>) -> usize {
+    prefer_originals(items, "video", originals)
+}
+
+fn prefer_originals(items: &mut [(String, String)], kind: &str, originals: Vec<String>) -> usize {
+    let matching: Vec<&mut (String, String)> = items.iter_mut().filter(|(_, media_type)| media_type == kind).collect();
+    if matching.is_empty() || matching.len() != originals.len() {
+        return 0;
+    }
+    let replaced = matching.len();
+    for ((url, _), original) in matching.into_iter().zip(originals) {
+        *url = original;
+    }
+    replaced
+}
+
+/// Validate a request's `only` media type filter (`"video"` or `"image"`).
+pub fn parse_media_filter(only: Option<&str>) -> StdResult<Option<&'static str>, String> {
+    match only.map(|o| o.trim().to_ascii_lowercase()).as_deref() {
+        None | Some("") => Ok(None),
+        Some("video") => Ok(Some("video")),
+        Some("image") => Ok(Some("image")),
+        Some(other) => Err(format!("Unsupported media type '{}', expected \"video\" or \"image\"", other)),
+    }
+}
+
+/// Make a media URL taken from the page absolute, so reqwest accepts it: protocol-relative
+/// `//host/...` URLs get `https:` and relative ones are resolved against instagram.com.
+/// Absolute http(s) URLs are returned untouched; anything else (`blob:`, `data:`, garbage)
+/// gives `None`.
+pub fn absolutize_media_url(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return None;
+    }
+    if let Some(rest) = raw.strip_prefix("//") {
+        return Some(format!("https://{}", rest));
+    }
+    match url::Url::parse(raw) {
+        Ok(parsed) => matches!(parsed.scheme(), "http" | "https").then(|| raw.to_string()),
+        Err(url::ParseError::RelativeUrlWithoutBase) => url::Url::parse(INSTAGRAM_BASE_URL)
+            .and_then(|base| base.join(raw))
+            .ok()
+            .map(|url| url.to_string()),
+        Err(_) => None,
+    }
+}
+
+/// Apply `absolutize_media_url` to extracted `(url, type)` pairs, dropping unusable URLs.
+pub fn absolutize_media(items: Vec<(String, String)>) -> Vec<(String, String)> {
+    items
+        .into_iter()
+        .filter_map(|(url, media_type)| Some((absolutize_media_url(&url)?, media_type)))
+        .collect()
+}
+
+/// Keep only the extracted `(url, type)` pairs of the requested type; all of them when `only` is `None`.
+pub fn filter_media_by_type(items: Vec<(String, String)>, only: Option<&str>) -> Vec<(String, String)> {
+    match only {
+        Some(only) => items.into_iter().filter(|(_, media_type)| media_type == only).collect(),
+        None => items,
+    }
+}
+
+/// Extract the shortcode (the `XXXX` in `/p/XXXX/`) from a post, reel or IGTV URL.
+/// Story and profile URLs have no shortcode and return `None`.
+pub fn shortcode_from_url(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    let segments: Vec<&str> = parsed.path_segments()?.filter(|s| !s.is_empty()).collect();
+    // Also matches the "/{username}/p/{code}/" form Instagram sometimes links to
+    let code = segments
+        .windows(2)
+        .find(|pair| matches!(pair[0], "p" | "reel" | "reels" | "tv"))
+        .map(|pair| pair[1])?;
+    let valid = code.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Some(code.to_string())
+    } else {
+        None
+    }
+}
+
+/// The numeric id of the single story a `/stories/{username}/{story_id}/` URL points at.
+/// `None` for a user's whole story set (`/stories/{username}/`) and non-story URLs.
+pub fn story_id_from_url(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    let segments: Vec<&str> = parsed.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let id = segments
+        .windows(3)
+        // /stories/highlights/{id}/ is a highlight reel, not one story
+        .find(|window| window[0] == "stories" && window[1] != "highlights")
+        .map(|window| window[2])?;
+    if id.chars().all(|c| c.is_ascii_digit()) {
+        Some(id.to_string())
+    } else {
+        None
+    }
+}
+
+// Whether `url` is an http(s) URL on instagram.com or one of its subdomains
+fn is_instagram_host(url: &url::Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some_and(|host| host == "instagram.com" || host.ends_with(".instagram.com"))
+}
+
+// Helper function to check if a URL is one of the newer instagram.com/share/... links,
+// or an l.instagram.com link shim wrapping one
+pub fn is_share_url(url: &str) -> bool {
+    url::Url::parse(url)
+        .map(|parsed| {
+            is_instagram_host(&parsed)
+                && (parsed.path().starts_with("/share/") || parsed.host_str() == Some("l.instagram.com"))
+        })
+        .unwrap_or(false)
+}
+
+/// Follow the redirects of a share link one hop at a time until they reach a URL that isn't
+/// a share link, which is returned without being requested. Every hop must stay on
+/// instagram.com, and at most `max_redirects` are followed, so a looping or hijacked chain
+/// fails instead of being chased.
+pub async fn follow_share_redirects(client: &reqwest::Client, url: &str, max_redirects: usize) -> Result<String> {
+    let mut current = url::Url::parse(url).map_err(|e| DownloadError(format!("Invalid share link: {}", e)))?;
+    for _ in 0..max_redirects {
+        let response = client.get(current.clone()).send().await
+            .map_err(|e| DownloadError(format!("Failed to resolve share link: {}", e)))?;
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .filter(|_| response.status().is_redirection())
+            .ok_or_else(|| DownloadError(format!("Share link did not redirect (status {})", response.status())))?;
+        // Location may be relative to the current hop
+        let next = current
+            .join(location)
+            .map_err(|e| DownloadError(format!("Invalid share redirect: {}", e)))?;
+        if !is_instagram_host(&next) {
+            return Err(DownloadError(format!("Share link redirects off Instagram, to {}", next.host_str().unwrap_or(next.as_str()))));
+        }
+        if !is_share_url(next.as_str()) {
+            return Ok(next.to_string());
+        }
+        current = next;
+    }
+    Err(DownloadError(format!("Share link redirected more than {} times", max_redirects)))
+}
+
+/// Resolve an `instagram.com/share/...` (or `l.instagram.com`) link to the canonical `/p/` or
+/// `/reel/` URL it redirects to. Any other URL is returned unchanged.
+pub async fn resolve_share_url(url: &str) -> Result<String> {
+    if !is_share_url(url) {
+        return Ok(url.to_string());
+    }
+
+    let cache = SHARE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some((resolved_at, resolved)) = cache.lock().unwrap().get(url) {
+        if resolved_at.elapsed() < SHARE_CACHE_TTL {
+            return Ok(resolved.clone());
+        }
+    }
+
+    // Redirects are followed by hand so every hop can be checked
+    let client = client_builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent("Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/110.0.5481.177 Mobile/15E148 Safari/604.1")
+        .timeout(Duration::from_secs(LOAD_TIMEOUT))
+        .build()
+        .map_err(|e| DownloadError(format!("Failed to build HTTP client: {}", e)))?;
+    let resolved = follow_share_redirects(&client, url, MAX_SHARE_REDIRECTS).await?;
+
+    println!("🔗 Resolved share link to {}", resolved);
+    let mut cache = cache.lock().unwrap();
+    cache.retain(|_, (resolved_at, _)| resolved_at.elapsed() < SHARE_CACHE_TTL);
+    cache.insert(url.to_string(), (Instant::now(), resolved.clone()));
+    Ok(resolved)
+}
+
+// Non-empty path segments of `url`. The query and fragment are never looked at, so links like
+// `/p/abc/?next=/reel/xyz` are classified by their real path. Scheme-less links get https://.
+fn url_path_segments(url: &str) -> Vec<String> {
+    let url = url.trim();
+    url::Url::parse(url)
+        .or_else(|_| url::Url::parse(&format!("https://{}", url)))
+        .ok()
+        .and_then(|parsed| parsed.path_segments().map(|segments| {
+            segments.filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+        }))
+        .unwrap_or_default()
+}
+
+// Whether any path segment of `url` is one of `names`
+fn has_path_segment(url: &str, names: &[&str]) -> bool {
+    url_path_segments(url).iter().any(|segment| names.contains(&segment.as_str()))
+}
+
 // Helper function to check if a URL is a story URL
 pub fn is_story_url(url: &str) -> bool {
-    url.contains("/stories/")
+    has_path_segment(url, &["stories"])
 }
 
-// Helper function to check if a URL is a reel URL
+// Helper function to check if a URL is a reel URL (`/reel/{code}` or `/reels/{code}`)
 pub fn is_reel_url(url: &str) -> bool {
-    url.contains("/reel/")
+    has_path_segment(url, &["reel", "reels"]) && !is_feed_url(url)
+}
+
+/// Why feed URLs are refused by the preview and download endpoints
+pub const FEED_URL_ERROR: &str = "This is a feed, not a single item: open one of its reels or posts and use that link instead";
+
+/// Whether `url` is a page listing many reels or posts rather than a single item: a reels audio
+/// page (`/reels/audio/{id}/`), a hashtag feed (`/explore/tags/{tag}/`) or a location feed
+/// (`/explore/locations/{id}/`).
+pub fn is_feed_url(url: &str) -> bool {
+    url_path_segments(url).windows(2).any(|pair| {
+        matches!((pair[0].as_str(), pair[1].as_str()), ("reels", "audio") | ("explore", "tags") | ("explore", "locations"))
+    })
+}
+
+// Helper function to check if a URL is a post URL (`/p/{code}`, also under a username)
+pub fn is_post_url(url: &str) -> bool {
+    has_path_segment(url, &["p"])
+}
+
+/// Why Instagram refused to show a page's content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unavailability {
+    /// The owning account is private
+    Private,
+    /// The post was deleted or the link is broken
+    NotFound,
+}
+
+// Phrases of Instagram's age gate, shown instead of media that is restricted to adults
+const AGE_GATE_MARKERS: &[&str] = &[
+    "log in to confirm your age",
+    "you must be 18 years old or over",
+    "you must be 18 or older",
+    "restricted to people aged 18 and over",
+    "this content is age-restricted",
+];
+
+// True when the page text is Instagram's age gate rather than a plain login wall; only a
+// logged-in account whose age is confirmed can see the content
+pub fn is_age_gate(body_text: &str) -> bool {
+    let text = body_text.to_lowercase().replace('\u{2019}', "'");
+    AGE_GATE_MARKERS.iter().any(|marker| text.contains(marker))
+}
+
+// Classify Instagram's "private account" / "page isn't available" interstitials from the page text
+pub fn classify_unavailable_page(body_text: &str) -> Option<Unavailability> {
+    // Instagram uses both straight and curly apostrophes in these messages
+    let text = body_text.to_lowercase().replace('\u{2019}', "'");
+    if text.contains("this account is private") {
+        Some(Unavailability::Private)
+    } else if text.contains("sorry, this page isn't available")
+        || text.contains("the link you followed may be broken")
+        || text.contains("the page may have been removed")
+    {
+        Some(Unavailability::NotFound)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_versions_are_ranked_by_size_then_bitrate() {
+        let versions = serde_json::json!([
+            { "width": 640, "height": 1136, "url": "https://cdn/640.mp4" },
+            { "width": 1080, "height": 1920, "bandwidth": 2_000_000, "url": "https://cdn/1080-low.mp4" },
+            { "width": 1080, "height": 1920, "bandwidth": 5_000_000, "url": "https://cdn/1080-high.mp4" },
+            { "width": 480, "height": 852 },
+        ]);
+        assert_eq!(pick_video_version(&versions, VideoQuality::Highest).as_deref(), Some("https://cdn/1080-high.mp4"));
+        assert_eq!(pick_video_version(&versions, VideoQuality::Lowest).as_deref(), Some("https://cdn/640.mp4"));
+        assert_eq!(pick_video_version(&serde_json::json!([]), VideoQuality::Highest), None);
+    }
+
+    #[test]
+    fn video_quality_accepts_highest_and_lowest() {
+        assert_eq!(VideoQuality::parse(None), Ok(None));
+        assert_eq!(VideoQuality::parse(Some(" Lowest ")), Ok(Some(VideoQuality::Lowest)));
+        assert!(VideoQuality::parse(Some("1080p")).is_err());
+    }
+
+    // Empty directory under the system temp dir, unique to this test run
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("insta_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn firefox_cookies_come_from_the_release_profile() {
+        let profiles = temp_dir("firefox");
+        for (profile, value) in [("abc.default", "old"), ("xyz.default-release", "current")] {
+            std::fs::create_dir(profiles.join(profile)).unwrap();
+            // Subset of Firefox's moz_cookies schema
+            let conn = Connection::open(profiles.join(profile).join("cookies.sqlite")).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE moz_cookies (id INTEGER PRIMARY KEY, originAttributes TEXT NOT NULL DEFAULT '',
+                    name TEXT, value TEXT, host TEXT, path TEXT, expiry INTEGER, isSecure INTEGER);",
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO moz_cookies (name, value, host, path) VALUES ('sessionid', ?1, '.instagram.com', '/'),
+                    ('csrftoken', 'token', 'www.instagram.com', '/'), ('sid', 'other', '.example.com', '/')",
+                [value],
+            )
+            .unwrap();
+        }
+
+        let mut cookies = read_firefox_cookies(&profiles).unwrap();
+        cookies.sort();
+        assert_eq!(cookies, vec![
+            ("csrftoken".to_string(), "token".to_string()),
+            ("sessionid".to_string(), "current".to_string()),
+        ]);
+        std::fs::remove_dir_all(&profiles).unwrap();
+    }
+
+    #[test]
+    fn missing_firefox_profile_yields_none() {
+        let profiles = temp_dir("firefox_empty");
+        assert!(read_firefox_cookies(&profiles).is_none());
+        assert!(read_firefox_cookies(&profiles.join("missing")).is_none());
+        std::fs::remove_dir_all(&profiles).unwrap();
+    }
+
+    #[tokio::test]
+    async fn extraction_is_retried_the_configured_number_of_times() {
+        let retry = ExtractionRetryConfig { retries: 3, delay: Duration::ZERO };
+        let attempts = std::cell::Cell::new(0);
+        let empty = with_retries(&retry, "Extraction", || {
+            attempts.set(attempts.get() + 1);
+            async { Ok(Vec::<String>::new()) }
+        })
+        .await;
+        assert_eq!(empty.unwrap(), Vec::<String>::new());
+        assert_eq!(attempts.get(), 4);
+
+        attempts.set(0);
+        let found = with_retries(&retry, "Extraction", || {
+            attempts.set(attempts.get() + 1);
+            let n = attempts.get();
+            async move {
+                if n < 2 { Err(DownloadError("no page".to_string())) } else { Ok(vec![n]) }
+            }
+        })
+        .await;
+        assert_eq!(found.unwrap(), vec![2]);
+        assert_eq!(attempts.get(), 2);
+    }
+
+    fn image(url: &str) -> ExtractedMedia {
+        ExtractedMedia { url: url.to_string(), media_type: "image".to_string(), alt_text: None }
+    }
+
+    #[tokio::test]
+    async fn carousel_walk_stops_when_slides_stop_changing() {
+        let visited = std::cell::RefCell::new(Vec::new());
+        // Slides 1-3 show a new image, after that "Next" keeps showing the last one
+        let media = walk_carousel(vec![image("https://cdn/0.jpg")], 20, 2, |slide, known| {
+            visited.borrow_mut().push((slide, known.len()));
+            async move { Ok(vec![image(&format!("https://cdn/{}.jpg", slide.min(3)))]) }
+        })
+        .await;
+        assert_eq!(media.len(), 4);
+        assert_eq!(*visited.borrow(), [(1, 1), (2, 2), (3, 3), (4, 4), (5, 4)]);
+
+        visited.borrow_mut().clear();
+        let media = walk_carousel(Vec::new(), 3, 2, |slide, _| {
+            visited.borrow_mut().push((slide, 0));
+            async move { Ok(vec![image(&format!("https://cdn/{}.jpg", slide))]) }
+        })
+        .await;
+        assert_eq!(media.len(), 2);
+        assert_eq!(visited.borrow().len(), 2);
+    }
+
+    #[test]
+    fn metadata_script_pushes_the_og_image_url() {
+        let og = &METADATA_SCRIPT[METADATA_SCRIPT.find("og:image").unwrap()..METADATA_SCRIPT.find("ogErr").unwrap()];
+        assert!(og.contains("media.push({ url: ogImage, type: 'image' })"));
+        // Shorthand `url` pushes only appear inside callbacks that bind `url`
+        assert_eq!(og.matches("media.push({ url,").count(), og.matches("forEach(url =>").count());
+    }
+
+    #[test]
+    fn missing_story_viewers_are_told_apart_from_empty_ones() {
+        // Redirected to the login page, or on a story URL without the viewer's controls
+        for viewer in [
+            serde_json::json!({"path": "/accounts/login/", "hasControls": true}),
+            serde_json::json!({"path": "/stories/someone/", "hasControls": false}),
+            serde_json::json!(null),
+        ] {
+            let err = check_story_viewer(&viewer).unwrap_err();
+            assert!(err.to_string().contains(NO_STORY_VIEWER), "{}", err);
+        }
+
+        // A viewer that loaded but showed nothing downloadable is fine, it just yields no item
+        check_story_viewer(&serde_json::json!({"path": "/stories/someone/", "hasControls": true})).unwrap();
+        assert_eq!(story_item(&serde_json::json!({"url": "", "type": "image"})), None);
+        assert_eq!(story_item(&serde_json::json!({"url": "blob:https://www.instagram.com/x", "type": "video"})), None);
+        assert_eq!(
+            story_item(&serde_json::json!({"url": "https://cdn/s.jpg", "type": "image"})),
+            Some(("https://cdn/s.jpg".to_string(), "image".to_string())),
+        );
+    }
+
+    #[test]
+    fn video_stories_with_a_poster_img_are_videos() {
+        // A video story's DOM: the <video> and the poster <img> drawn over it before playback
+        let video_story = serde_json::json!({
+            "videos": ["https://cdn/story.mp4"],
+            "images": ["https://cdn/story_poster.jpg"],
+        });
+        assert_eq!(
+            story_item(&classify_story(&video_story)),
+            Some(("https://cdn/story.mp4".to_string(), "video".to_string())),
+        );
+        // Streamed through a blob: URL, it's skipped rather than saved as its poster
+        let blob_story = serde_json::json!({ "videos": ["blob:https://www.instagram.com/x"], "images": ["https://cdn/poster.jpg"] });
+        assert_eq!(story_item(&classify_story(&blob_story)), None);
+
+        let photo_story = serde_json::json!({ "videos": [], "images": ["https://cdn/photo.jpg"] });
+        assert_eq!(
+            story_item(&classify_story(&photo_story)),
+            Some(("https://cdn/photo.jpg".to_string(), "image".to_string())),
+        );
+        assert_eq!(story_item(&classify_story(&serde_json::json!({ "videos": [], "images": [] }))), None);
+    }
+
+    #[tokio::test]
+    async fn every_video_of_a_mixed_carousel_is_collected() {
+        // Slide 0 (read before the walk) and the odd slides are photos, slides 2 and 4 videos
+        let media = walk_carousel(vec![image("https://cdn/0.jpg")], 5, 2, |slide, _| async move {
+            Ok(match slide % 2 {
+                0 => vec![ExtractedMedia::from((format!("https://cdn/{}.mp4", slide), "video".to_string()))],
+                _ => vec![image(&format!("https://cdn/{}.jpg", slide))],
+            })
+        })
+        .await;
+        let videos: Vec<&str> = media.iter().filter(|m| m.media_type == "video").map(|m| m.url.as_str()).collect();
+        assert_eq!(videos, ["https://cdn/2.mp4", "https://cdn/4.mp4"]);
+        assert_eq!(media.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn hung_navigation_yields_the_timeout_error() {
+        let url = "https://www.instagram.com/p/abc123/";
+        let err = navigate_within(url, 20, Duration::from_millis(50), std::future::pending()).await.unwrap_err();
+        assert_eq!(err.to_string(), nav_timeout_error(url, 20).to_string());
+        assert!(err.to_string().starts_with("Navigation timed out after 20s"));
+        assert!(navigate_within(url, 20, Duration::from_secs(1), async { Ok(()) }).await.is_ok());
+    }
+
+    #[test]
+    fn only_the_path_decides_the_content_type() {
+        for url in [
+            "https://www.instagram.com/p/abc123/?caption=/reel/xyz",
+            "https://www.instagram.com/p/abc123/#/stories/someone/",
+            "https://www.instagram.com/p/abc123/?next=https://www.instagram.com/reels/xyz/",
+            "https://www.instagram.com/reelish/abc123/",
+        ] {
+            assert!(!is_reel_url(url), "{} taken for a reel", url);
+            assert!(!is_story_url(url), "{} taken for a story", url);
+        }
+        assert!(is_reel_url("https://www.instagram.com/reel/abc123/?utm_source=/p/xyz"));
+        assert!(is_story_url("instagram.com/stories/someone/123/#/reel/xyz"));
+        assert_eq!(url_path_segments("https://www.instagram.com/p/abc123/?x=/reel/y#/stories/"), ["p", "abc123"]);
+    }
+
+    #[test]
+    fn protocol_relative_and_relative_urls_become_absolute_https() {
+        let extracted = vec![
+            ("//scontent.cdninstagram.com/v/photo.jpg?x=1".to_string(), "image".to_string()),
+            ("/static/images/poster.jpg".to_string(), "image".to_string()),
+            ("media/reel.mp4".to_string(), "video".to_string()),
+            ("https://scontent.cdninstagram.com/v/ok.jpg".to_string(), "image".to_string()),
+            ("blob:https://www.instagram.com/1234".to_string(), "video".to_string()),
+        ];
+        let urls: Vec<String> = absolutize_media(extracted).into_iter().map(|(url, _)| url).collect();
+        assert_eq!(urls, [
+            "https://scontent.cdninstagram.com/v/photo.jpg?x=1",
+            "https://www.instagram.com/static/images/poster.jpg",
+            "https://www.instagram.com/media/reel.mp4",
+            "https://scontent.cdninstagram.com/v/ok.jpg",
+        ]);
+    }
+
+    #[test]
+    fn alt_text_stays_with_its_image() {
+        let payload = serde_json::json!({
+            "media": [
+                { "url": "https://cdn/1.jpg", "type": "image", "alt": " May be an image of 2 people " },
+                { "url": "https://cdn/2.mp4", "type": "video", "alt": null },
+                { "url": "https://cdn/3.jpg", "type": "image", "alt": "" },
+                { "url": "blob:https://www.instagram.com/x", "type": "video" },
+            ],
+            "slides": 3,
+        });
+        let media = parse_post_media(&payload);
+        let alt_texts: Vec<(&str, Option<&str>)> = media.iter().map(|m| (m.url.as_str(), m.alt_text.as_deref())).collect();
+        assert_eq!(alt_texts, [
+            ("https://cdn/1.jpg", Some("May be an image of 2 people")),
+            ("https://cdn/2.mp4", None),
+            ("https://cdn/3.jpg", None),
+        ]);
+    }
+
+    #[test]
+    fn a_custom_stealth_file_replaces_the_default_script() {
+        let path = std::env::temp_dir().join(format!("insta_test_stealth_{}.js", crate::utils::job::new_job_id()));
+        let custom = "Object.defineProperty(navigator, 'webdriver', { get: () => false });";
+        std::fs::write(&path, custom).unwrap();
+        let path_value = path.display().to_string();
+
+        assert_eq!(parse_stealth_js_file(Some(&path_value)), Ok(Some(path.clone())));
+        assert_eq!(stealth_script_from(Some(&path_value)), custom);
+        assert_eq!(stealth_script_from(None), DEFAULT_STEALTH_SCRIPT);
+
+        std::fs::write(&path, "  \n").unwrap();
+        assert!(parse_stealth_js_file(Some(&path_value)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        // Removed after startup: back to the built-in script
+        assert_eq!(stealth_script_from(Some(&path_value)), DEFAULT_STEALTH_SCRIPT);
+    }
+
+    #[test]
+    fn headless_reels_are_kept_by_content_not_size() {
+        let folder = std::env::temp_dir().join(format!("insta_test_{}", crate::utils::job::new_job_id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let filename = folder.join("reel_video.mp4").display().to_string();
+
+        // A short clip well under the old 200 KB cutoff
+        let mut small_mp4 = b"\0\0\0\x18ftypisom\0\0\x02\0".to_vec();
+        small_mp4.resize(50 * 1024, 0);
+        assert_eq!(save_reel_video(&filename, &small_mp4), Some(filename.clone()));
+        assert_eq!(std::fs::read(&filename).unwrap(), small_mp4);
+        std::fs::remove_file(&filename).unwrap();
+
+        // A login page served with a 200, far over the old cutoff
+        let mut login_page = b"<!DOCTYPE html><html><body>Log in".to_vec();
+        login_page.resize(500 * 1024, b' ');
+        assert_eq!(save_reel_video(&filename, &login_page), None);
+        let mut placeholder = small_mp4.clone();
+        placeholder.truncate(1024);
+        assert_eq!(save_reel_video(&filename, &placeholder), None);
+        assert!(!std::path::Path::new(&filename).exists());
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn chrome_cookie_db_has_candidates_on_this_platform() {
+        let candidates = chrome_cookie_db_candidates();
+        assert!(!candidates.is_empty());
+        for path in &candidates {
+            assert!(path.is_absolute(), "{}", path.display());
+            assert_eq!(path.file_name().and_then(|n| n.to_str()), Some("Cookies"));
+        }
+        if cfg!(target_os = "macos") {
+            assert!(candidates[0].to_string_lossy().contains("Library/Application Support/Google/Chrome"));
+        } else if cfg!(target_os = "linux") {
+            assert!(candidates[0].ends_with(".config/google-chrome/Default/Cookies"));
+        }
+    }
+
+    #[test]
+    fn unavailable_pages_are_classified_from_their_text() {
+        assert!(matches!(classify_unavailable_page("This Account is Private\nFollow to see"), Some(Unavailability::Private)));
+        assert!(matches!(classify_unavailable_page("Sorry, this page isn\u{2019}t available."), Some(Unavailability::NotFound)));
+        assert!(matches!(classify_unavailable_page("The link you followed may be broken"), Some(Unavailability::NotFound)));
+        assert!(classify_unavailable_page("Liked by 12 others").is_none());
+    }
+
+    #[test]
+    fn blob_only_needs_candidates_that_are_all_blobs() {
+        assert!(is_blob_only(&["blob:https://www.instagram.com/1".to_string()]));
+        assert!(!is_blob_only(&["blob:https://www.instagram.com/1".to_string(), "https://cdn/v.mp4".to_string()]));
+        assert!(!is_blob_only(&[]));
+    }
+
+    #[test]
+    fn cookies_parse_from_a_header_or_a_netscape_file() {
+        let pairs = |raw| parse_cookie_header(raw);
+        assert_eq!(
+            pairs(" sessionid=abc; csrftoken = x=y ;junk; =nameless"),
+            vec![("sessionid".to_string(), "abc".to_string()), ("csrftoken".to_string(), "x=y".to_string())],
+        );
+        let file = "# Netscape HTTP Cookie File\r\n\
+            .instagram.com\tTRUE\t/\tTRUE\t0\tds_user_id\t42\r\n\
+            #HttpOnly_.instagram.com\tTRUE\t/\tTRUE\t0\tsessionid\tsecret\n\
+            .instagram.com\tTRUE\t/\tTRUE\t0\tempty\t\n";
+        assert_eq!(
+            pairs(file),
+            vec![
+                ("ds_user_id".to_string(), "42".to_string()),
+                ("sessionid".to_string(), "secret".to_string()),
+                ("empty".to_string(), String::new()),
+            ],
+        );
+    }
+
+    #[test]
+    fn shortcodes_come_from_post_reel_and_tv_urls() {
+        assert_eq!(shortcode_from_url("https://www.instagram.com/p/Cx_1-aB/?igsh=x").as_deref(), Some("Cx_1-aB"));
+        assert_eq!(shortcode_from_url("https://www.instagram.com/someone/reel/ABC123/").as_deref(), Some("ABC123"));
+        assert_eq!(shortcode_from_url("https://instagram.com/tv/XYZ").as_deref(), Some("XYZ"));
+        assert_eq!(shortcode_from_url("https://www.instagram.com/stories/someone/123/"), None);
+        assert_eq!(shortcode_from_url("https://www.instagram.com/p/bad%20code/"), None);
+        assert_eq!(shortcode_from_url("not a url"), None);
+    }
+
+    #[test]
+    fn largest_image_candidate_wins_in_either_json_shape() {
+        let v1 = serde_json::json!([
+            { "url": "https://cdn/320.jpg", "width": 320, "height": 320 },
+            { "url": "https://cdn/1080.jpg", "width": 1080, "height": 1350 },
+            { "width": 2000, "height": 2000 },
+        ]);
+        assert_eq!(best_image_candidate(&v1).as_deref(), Some("https://cdn/1080.jpg"));
+        let graphql = serde_json::json!([
+            { "src": "https://cdn/640.jpg", "config_width": 640, "config_height": 800 },
+            { "src": "https://cdn/750.jpg", "config_width": 750, "config_height": 937 },
+        ]);
+        assert_eq!(best_image_candidate(&graphql).as_deref(), Some("https://cdn/750.jpg"));
+        assert_eq!(best_image_candidate(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn first_mp4_drops_byte_range_parameters() {
+        let resources = [
+            "https://www.instagram.com/api/graphql".to_string(),
+            "https://cdn.fbcdn.net/v/clip.mp4?_nc_ht=x&bytestart=0&byteend=1024&oh=abc".to_string(),
+            "https://cdn.fbcdn.net/v/other.mp4".to_string(),
+        ];
+        assert_eq!(first_mp4_resource(&resources).as_deref(), Some("https://cdn.fbcdn.net/v/clip.mp4?_nc_ht=x&oh=abc"));
+        let ranged_only = ["https://cdn.fbcdn.net/v/clip.mp4?bytestart=0&byteend=9".to_string()];
+        assert_eq!(first_mp4_resource(&ranged_only).as_deref(), Some("https://cdn.fbcdn.net/v/clip.mp4"));
+        assert_eq!(first_mp4_resource(&["https://cdn/image.jpg".to_string()]), None);
+    }
+
+    #[test]
+    fn collab_media_skips_known_files_and_tags_coauthors() {
+        let json = serde_json::json!({ "items": [{
+            "user": { "username": "owner" },
+            "coauthor_producers": [{ "username": "partner" }],
+            "carousel_media": [
+                { "media_type": 1, "image_versions2": { "candidates": [{ "url": "https://cdn/a.jpg?sig=new", "width": 1, "height": 1 }] } },
+                { "media_type": 2, "user": { "username": "partner" }, "video_versions": [{ "url": "https://cdn/b.mp4", "width": 1, "height": 1 }] },
+                { "media_type": 1, "image_versions2": { "candidates": [{ "url": "https://cdn/c.jpg", "width": 1, "height": 1 }] } },
+            ],
+        }]});
+        let known = [("https://cdn/a.jpg?sig=old".to_string(), "image".to_string())];
+        assert_eq!(
+            collab_media_from_web_json(&json, &known),
+            vec![
+                CollabMedia { url: "https://cdn/b.mp4".to_string(), media_type: "video".to_string(), source: "coauthor:partner".to_string() },
+                CollabMedia { url: "https://cdn/c.jpg".to_string(), media_type: "image".to_string(), source: "web_json".to_string() },
+            ],
+        );
+        assert!(collab_media_from_web_json(&serde_json::json!({}), &known).is_empty());
+    }
+
+    #[test]
+    fn lost_webdriver_sessions_are_recognised() {
+        assert!(is_webdriver_session_lost("WebDriver connection lost"));
+        assert!(is_webdriver_session_lost("Standard error: invalid session id (session deleted because of page crash)"));
+        assert!(is_webdriver_session_lost("unknown error: Chrome not reachable"));
+        assert!(!is_webdriver_session_lost("no such element: Unable to locate element"));
+    }
+
+    #[test]
+    fn age_gate_is_told_apart_from_a_login_wall() {
+        assert!(is_age_gate("Log in to confirm your age\nYou must be 18 or older to see this."));
+        assert!(is_age_gate("THIS CONTENT IS AGE-RESTRICTED"));
+        assert!(!is_age_gate("Log in to see photos and videos from friends."));
+        assert!(!is_age_gate("Sorry, this page isn\u{2019}t available."));
+    }
+
+    #[test]
+    fn reel_audio_reads_music_original_sound_and_graphql_shapes() {
+        let music = serde_json::json!({"items": [{"clips_metadata": {"music_info": {"music_asset_info": {
+            "fast_start_progressive_download_url": "https://cdn/a.m4a", "title": "Song", "display_artist": "Band"
+        }}}}]});
+        assert_eq!(reel_audio_from_web_json(&music), Some(ReelAudio {
+            url: Some("https://cdn/a.m4a".to_string()),
+            title: Some("Song - Band".to_string()),
+        }));
+
+        let original = serde_json::json!({"items": [{"clips_metadata": {"music_info": null, "original_sound_info": {
+            "original_audio_title": "Original audio", "ig_artist": {"username": "someone"}
+        }}}]});
+        assert_eq!(reel_audio_from_web_json(&original).unwrap().title.as_deref(), Some("Original audio - someone"));
+
+        let graphql = serde_json::json!({"graphql": {"shortcode_media": {"clips_music_attribution_info": {"artist_name": "Band"}}}});
+        assert_eq!(reel_audio_from_web_json(&graphql), Some(ReelAudio { url: None, title: Some("Band".to_string()) }));
+
+        let silent = serde_json::json!({"items": [{"clips_metadata": {"original_sound_info": null}}]});
+        assert_eq!(reel_audio_from_web_json(&silent), None);
+    }
+
+    #[test]
+    fn post_metadata_collects_location_and_tags_from_both_shapes() {
+        let v1 = serde_json::json!({"items": [{
+            "location": {"name": "Lisbon"},
+            "usertags": {"in": [{"user": {"username": "ana"}}]},
+            "carousel_media": [
+                {"usertags": {"in": [{"user": {"username": "ana"}}, {"user": {"username": "rui"}}]}},
+                {"usertags": {"in": [{"user": {"username": ""}}]}}
+            ]
+        }]});
+        let metadata = post_metadata_from_web_json(&v1);
+        assert_eq!(metadata.location.as_deref(), Some("Lisbon"));
+        assert_eq!(metadata.tagged_users, ["ana", "rui"]);
+
+        let graphql = serde_json::json!({"graphql": {"shortcode_media": {
+            "edge_media_to_tagged_user": {"edges": [{"node": {"user": {"username": "ana"}}}]},
+            "edge_sidecar_to_children": {"edges": [{"node": {
+                "edge_media_to_tagged_user": {"edges": [{"node": {"user": {"username": "rui"}}}]}
+            }}]}
+        }}});
+        let metadata = post_metadata_from_web_json(&graphql);
+        assert_eq!(metadata.location, None);
+        assert_eq!(metadata.tagged_users, ["ana", "rui"]);
+
+        assert_eq!(post_metadata_from_web_json(&serde_json::json!({})), PostMetadata::default());
+    }
+
+    #[test]
+    fn story_id_is_read_from_single_story_urls() {
+        assert_eq!(
+            story_id_from_url("https://www.instagram.com/stories/natgeo/3141592653589793238/?igsh=x").as_deref(),
+            Some("3141592653589793238")
+        );
+        assert_eq!(story_id_from_url("https://www.instagram.com/stories/natgeo/"), None);
+        assert_eq!(story_id_from_url("https://www.instagram.com/stories/highlights/17890000000000000/"), None);
+        assert_eq!(story_id_from_url("https://www.instagram.com/stories/natgeo/abc/"), None);
+        assert_eq!(story_id_from_url("not a url"), None);
+    }
+
+    #[test]
+    fn feed_urls_are_told_apart_from_single_items() {
+        assert!(is_feed_url("https://www.instagram.com/reels/audio/1234567890/"));
+        assert!(is_feed_url("https://www.instagram.com/explore/tags/sunset/"));
+        assert!(is_feed_url("https://www.instagram.com/explore/locations/213385402/lisbon/"));
+        assert!(!is_feed_url("https://www.instagram.com/reels/Cabc123/"));
+        assert!(!is_feed_url("https://www.instagram.com/p/Cabc123/"));
+        assert!(!is_feed_url("https://www.instagram.com/explore/"));
+    }
+
+    #[test]
+    fn paid_partnership_is_flagged_with_its_sponsor() {
+        let v1 = serde_json::json!({"items": [{
+            "is_paid_partnership": true,
+            "sponsor_tags": [{"sponsor": {"username": "brand"}}]
+        }]});
+        let metadata = post_metadata_from_web_json(&v1);
+        assert!(metadata.is_paid_partnership);
+        assert_eq!(metadata.sponsor.as_deref(), Some("brand"));
+
+        // A sponsor tag alone still marks the post as a paid partnership
+        let graphql = serde_json::json!({"graphql": {"shortcode_media": {
+            "edge_media_to_sponsor_user": {"edges": [{"node": {"sponsor": {"username": "brand"}}}]}
+        }}});
+        let metadata = post_metadata_from_web_json(&graphql);
+        assert!(metadata.is_paid_partnership);
+        assert_eq!(metadata.sponsor.as_deref(), Some("brand"));
+
+        let unsponsored = post_metadata_from_web_json(&serde_json::json!({"items": [{"is_paid_partnership": false}]}));
+        assert!(!unsponsored.is_paid_partnership);
+        assert_eq!(unsponsored.sponsor, None);
+    }
+
+    #[tokio::test]
+    async fn share_redirects_are_followed_on_instagram_only() {
+        use crate::utils::test_server::{response, TestServer};
+        let server = TestServer::start(|request| {
+            let location = if request.starts_with("GET /share/reel/abc") {
+                "/reel/XYZ123/"
+            } else if request.starts_with("GET /share/loop") {
+                "/share/loop"
+            } else {
+                "http://evil.example/reel/XYZ123/"
+            };
+            response("302 Found", &[("Location", location)], b"")
+        }).await;
+        // Every instagram.com hop lands on the mock server
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .resolve("www.instagram.com", server.addr)
+            .build()
+            .unwrap();
+        let share = |path: &str| format!("http://www.instagram.com:{}{}", server.addr.port(), path);
+
+        let resolved = follow_share_redirects(&client, &share("/share/reel/abc"), 3).await.unwrap();
+        assert_eq!(resolved, share("/reel/XYZ123/"));
+
+        let err = follow_share_redirects(&client, &share("/share/loop"), 3).await.unwrap_err();
+        assert!(err.to_string().contains("more than 3 times"), "{}", err);
+
+        let before = server.requests().len();
+        let err = follow_share_redirects(&client, &share("/share/evil"), 3).await.unwrap_err();
+        assert!(err.to_string().contains("off Instagram, to evil.example"), "{}", err);
+        // Only the share link itself was requested, never the off-host target
+        assert_eq!(server.requests().len(), before + 1);
+    }
+
+    #[tokio::test]
+    async fn share_links_are_detected_and_resolved_from_the_cache() {
+        assert!(is_share_url("https://www.instagram.com/share/BAabc123/"));
+        assert!(is_share_url("https://instagram.com/share/reel/BAabc123/"));
+        assert!(!is_share_url("https://www.instagram.com/reel/XYZ123/"));
+        assert!(!is_share_url("https://example.com/share/reel/BAabc123/"));
+        // Anything else passes through untouched
+        assert_eq!(resolve_share_url("https://www.instagram.com/p/XYZ123/").await.unwrap(), "https://www.instagram.com/p/XYZ123/");
+
+        // A cached resolution is used without any request
+        let share = "https://www.instagram.com/share/reel/BAcached/";
+        SHARE_CACHE
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap()
+            .insert(share.to_string(), (Instant::now(), "https://www.instagram.com/reel/XYZ123/".to_string()));
+        let resolved = resolve_share_url(share).await.unwrap();
+        assert_eq!(resolved, "https://www.instagram.com/reel/XYZ123/");
+        assert!(is_reel_url(&resolved) && !is_share_url(&resolved));
+    }
 }
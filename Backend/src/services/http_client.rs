@@ -0,0 +1,152 @@
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE};
+use reqwest::ClientBuilder;
+use std::sync::OnceLock;
+use crate::config::Config;
+use std::time::Duration;
+
+// Defaults sized for many concurrent CDN downloads against a handful of hosts
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 32;
+const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const TCP_KEEPALIVE_SECS: u64 = 60;
+// Instagram localizes interstitials by language; the text-based detection expects English
+const DEFAULT_ACCEPT_LANGUAGE: &str = "en-US";
+
+/// Connection pool, TCP and language settings applied to every outgoing reqwest client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpClientConfig {
+    /// Idle keep-alive connections kept open per host
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle pooled connection is kept before being closed
+    pub pool_idle_timeout: Duration,
+    /// Time allowed to establish a TCP (and TLS) connection
+    pub connect_timeout: Duration,
+    /// `Accept-Language` sent with every request (and used by the WebDriver sessions)
+    pub accept_language: String,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout: Duration::from_secs(DEFAULT_POOL_IDLE_TIMEOUT_SECS),
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            accept_language: DEFAULT_ACCEPT_LANGUAGE.to_string(),
+        }
+    }
+}
+
+// Parse a positive number, ignoring missing or invalid values
+fn positive<T: std::str::FromStr + PartialOrd + Default>(value: Option<&str>) -> Option<T> {
+    value
+        .and_then(|v| v.trim().parse::<T>().ok())
+        .filter(|n| *n > T::default())
+}
+
+impl HttpClientConfig {
+    /// Parse `HTTP_POOL_MAX_IDLE_PER_HOST`, `HTTP_POOL_IDLE_TIMEOUT_SECS`, `HTTP_CONNECT_TIMEOUT_SECS`
+    /// and `ACCEPT_LANGUAGE` as returned by `var`, keeping the defaults for missing or invalid values.
+    pub fn parse(var: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = Self::default();
+        Self {
+            pool_max_idle_per_host: positive(var("HTTP_POOL_MAX_IDLE_PER_HOST").as_deref())
+                .unwrap_or(defaults.pool_max_idle_per_host),
+            pool_idle_timeout: positive(var("HTTP_POOL_IDLE_TIMEOUT_SECS").as_deref())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.pool_idle_timeout),
+            connect_timeout: positive(var("HTTP_CONNECT_TIMEOUT_SECS").as_deref())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.connect_timeout),
+            accept_language: var("ACCEPT_LANGUAGE")
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty() && HeaderValue::from_str(v).is_ok())
+                .unwrap_or(defaults.accept_language),
+        }
+    }
+
+    /// A client builder with these settings applied; callers add their own user agent,
+    /// timeouts and redirect policy on top.
+    pub fn builder(&self) -> ClientBuilder {
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&self.accept_language) {
+            headers.insert(ACCEPT_LANGUAGE, value);
+        }
+        reqwest::Client::builder()
+            .default_headers(headers)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .connect_timeout(self.connect_timeout)
+            .tcp_nodelay(true)
+            .tcp_keepalive(Duration::from_secs(TCP_KEEPALIVE_SECS))
+    }
+}
+
+static CONFIG: OnceLock<HttpClientConfig> = OnceLock::new();
+
+fn config() -> &'static HttpClientConfig {
+    CONFIG.get_or_init(|| Config::current().http_client())
+}
+
+/// Starting point for every outgoing reqwest client, using the settings resolved at startup.
+pub fn client_builder() -> ClientBuilder {
+    config().builder()
+}
+
+/// The configured `Accept-Language`, for browser sessions that should match the HTTP clients.
+pub fn accept_language() -> &'static str {
+    &config().accept_language
+}
+
+/// Chrome's `--lang` switch for the configured language: its first tag, without any `;q=` weight.
+pub fn chrome_lang_arg() -> String {
+    lang_arg(accept_language())
+}
+
+fn lang_arg(accept_language: &str) -> String {
+    let primary = accept_language.split(',').next().unwrap_or(DEFAULT_ACCEPT_LANGUAGE);
+    format!("--lang={}", primary.split(';').next().unwrap_or(DEFAULT_ACCEPT_LANGUAGE).trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{header, response, TestServer};
+    use std::collections::HashMap;
+
+    fn parse(vars: &[(&str, &str)]) -> HttpClientConfig {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        HttpClientConfig::parse(|name| vars.get(name).cloned())
+    }
+
+    #[tokio::test]
+    async fn clients_build_from_representative_settings() {
+        let config = parse(&[
+            ("HTTP_POOL_MAX_IDLE_PER_HOST", "256"),
+            ("HTTP_POOL_IDLE_TIMEOUT_SECS", "30"),
+            ("HTTP_CONNECT_TIMEOUT_SECS", "not a number"),
+            ("ACCEPT_LANGUAGE", "de-DE,de;q=0.9"),
+        ]);
+        assert_eq!(config.pool_max_idle_per_host, 256);
+        assert_eq!(config.pool_idle_timeout, Duration::from_secs(30));
+        assert_eq!(config.connect_timeout, HttpClientConfig::default().connect_timeout);
+        assert_eq!(parse(&[("HTTP_POOL_MAX_IDLE_PER_HOST", "0")]), HttpClientConfig::default());
+
+        let server = TestServer::start(|_| response("200 OK", &[], b"")).await;
+        let client = config.builder().build().unwrap();
+        client.get(server.url("/")).send().await.unwrap();
+        assert_eq!(header(&server.requests()[0], "accept-language"), Some("de-DE,de;q=0.9"));
+    }
+
+    #[tokio::test]
+    async fn english_is_requested_unless_another_language_is_set() {
+        let server = TestServer::start(|_| response("200 OK", &[], b"")).await;
+        for config in [HttpClientConfig::default(), parse(&[("ACCEPT_LANGUAGE", "en\nUS")])] {
+            config.builder().build().unwrap().get(server.url("/")).send().await.unwrap();
+        }
+        for request in server.requests() {
+            assert_eq!(header(&request, "accept-language"), Some("en-US"));
+        }
+        assert_eq!(lang_arg("en-US"), "--lang=en-US");
+        assert_eq!(lang_arg("fr-CA;q=0.9, en;q=0.5"), "--lang=fr-CA");
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use utoipa::ToSchema;
+use crate::services::outcome::DownloadResult;
+
+// Finished jobs are kept this long for clients to poll, then forgotten
+const FINISHED_JOB_TTL_SECS: i64 = 3600;
+
+/// Where a background download stands. `completed` and `failed` are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Accepted, waiting to start
+    Queued,
+    Running,
+    Completed,
+    /// Nothing was saved, or the disk filled up
+    Failed,
+}
+
+/// A download run in the background (`?async=true` or a `callback_url`), as served by
+/// `GET /api/jobs/{job_id}`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Job {
+    pub job_id: String,
+    pub url: String,
+    pub status: JobStatus,
+    /// The download's status message, once it finished
+    pub message: Option<String>,
+    /// Unix timestamps
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+static JOBS: OnceLock<Mutex<HashMap<String, Job>>> = OnceLock::new();
+
+fn jobs() -> &'static Mutex<HashMap<String, Job>> {
+    JOBS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Record a newly accepted job as `queued`, dropping finished jobs past their TTL.
+pub fn register(job_id: &str, url: &str) {
+    let now = Utc::now().timestamp();
+    let mut jobs = jobs().lock().unwrap();
+    jobs.retain(|_, job| job.finished_at.is_none_or(|at| now - at < FINISHED_JOB_TTL_SECS));
+    jobs.insert(job_id.to_string(), Job {
+        job_id: job_id.to_string(),
+        url: url.to_string(),
+        status: JobStatus::Queued,
+        message: None,
+        created_at: now,
+        finished_at: None,
+    });
+}
+
+pub fn mark_running(job_id: &str) {
+    if let Some(job) = jobs().lock().unwrap().get_mut(job_id) {
+        job.status = JobStatus::Running;
+    }
+}
+
+/// Store the download's result: anything but `Done` marks the job `failed`.
+pub fn finish(job_id: &str, result: &DownloadResult) {
+    if let Some(job) = jobs().lock().unwrap().get_mut(job_id) {
+        job.status = if result.is_done() { JobStatus::Completed } else { JobStatus::Failed };
+        job.message = Some(result.message());
+        job.finished_at = Some(Utc::now().timestamp());
+    }
+}
+
+pub fn get(job_id: &str) -> Option<Job> {
+    jobs().lock().unwrap().get(job_id).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_state_follows_the_result_variant() {
+        register("job-done", "https://www.instagram.com/p/abc/");
+        finish("job-done", &DownloadResult::done("⚠️ Downloaded 1/2 media items".to_string()));
+        assert_eq!(get("job-done").unwrap().status, JobStatus::Completed);
+
+        register("job-disk", "https://www.instagram.com/p/abc/");
+        finish("job-disk", &DownloadResult::DiskFull("Not enough disk space".to_string()));
+        let job = get("job-disk").unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.message.as_deref(), Some("❌ Not enough disk space"));
+    }
+}
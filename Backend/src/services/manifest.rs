@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use crate::services::extractor::shortcode_from_url;
+use crate::services::storage::persist_bytes;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+// Bookkeeping files that live next to the media but aren't part of the download
+const NON_MEDIA_FILES: &[&str] = &[MANIFEST_FILE, "metadata.txt", "debug_screenshot.png"];
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "mov", "mkv"];
+
+/// One downloaded file listed in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// File name inside the job folder
+    pub file: String,
+    pub size: u64,
+    /// `"video"` or `"image"`
+    pub media_type: String,
+    /// CDN URL the file was fetched from; `None` for files written by yt-dlp
+    pub source_url: Option<String>,
+}
+
+/// Machine-readable record of a job folder, written next to the human-readable `metadata.txt`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub source_url: String,
+    /// `"post"`, `"reel"` or `"story"`
+    pub content_type: String,
+    pub shortcode: Option<String>,
+    /// RFC 3339 timestamp of when the manifest was written
+    pub created_at: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Describe the media currently in `folder`. `known_sources` pairs downloaded file paths
+    /// with the CDN URLs they came from; other files are listed without one.
+    pub fn from_folder(folder: &str, source_url: &str, content_type: &str, known_sources: &[(String, String)]) -> std::io::Result<Self> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(folder)? {
+            let path = entry?.path();
+            let Some(file) = path.file_name().and_then(|name| name.to_str()).map(|name| name.to_string()) else {
+                continue;
+            };
+            // Skip bookkeeping, hidden and partial (yt-dlp .part) files
+            if !path.is_file() || NON_MEDIA_FILES.contains(&file.as_str()) || file.starts_with('.') || file.ends_with(".part") {
+                continue;
+            }
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+            let media_type = if VIDEO_EXTENSIONS.contains(&extension.as_str()) { "video" } else { "image" };
+            let source_url = known_sources
+                .iter()
+                .find(|(known, _)| Path::new(known).file_name().and_then(|n| n.to_str()) == Some(file.as_str()))
+                .map(|(_, url)| url.clone());
+            files.push(ManifestEntry {
+                size: path.metadata()?.len(),
+                file,
+                media_type: media_type.to_string(),
+                source_url,
+            });
+        }
+        files.sort_by(|a, b| a.file.cmp(&b.file));
+        Ok(Self {
+            source_url: source_url.to_string(),
+            content_type: content_type.to_string(),
+            shortcode: shortcode_from_url(source_url),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            files,
+        })
+    }
+
+    /// Write the manifest as `manifest.json` in `folder`, returning its path and contents.
+    pub fn write(&self, folder: &str) -> std::io::Result<(PathBuf, String)> {
+        let path = Path::new(folder).join(MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, &json)?;
+        Ok((path, json))
+    }
+}
+
+/// Write `manifest.json` for a finished job and hand it to the storage backend, logging
+/// instead of failing the download on errors.
+pub async fn write_manifest(folder: &str, source_url: &str, content_type: &str, known_sources: &[(String, String)]) {
+    match Manifest::from_folder(folder, source_url, content_type, known_sources).and_then(|manifest| manifest.write(folder)) {
+        Ok((path, json)) => {
+            println!("🧾 Wrote manifest to {}", path.display());
+            if let Err(e) = persist_bytes(&path, json.into()).await {
+                println!("⚠️ Failed to store manifest: {}", e);
+            }
+        }
+        Err(e) => println!("⚠️ Failed to write manifest for {}: {}", folder, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifests_list_the_media_and_round_trip() {
+        let folder = std::env::temp_dir().join(format!("insta_test_manifest_{}", crate::utils::job::new_job_id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        for (name, bytes) in [("media_1.jpg", 3), ("media_2.MP4", 5), ("metadata.txt", 1), ("clip.mp4.part", 1), (".hidden", 1)] {
+            std::fs::write(folder.join(name), vec![0u8; bytes]).unwrap();
+        }
+        let folder = folder.display().to_string();
+        let sources = [(format!("{}/media_1.jpg", folder), "https://cdn/1.jpg".to_string())];
+
+        let manifest = Manifest::from_folder(&folder, "https://www.instagram.com/p/abc123/", "post", &sources).unwrap();
+        assert_eq!(manifest.shortcode.as_deref(), Some("abc123"));
+        assert_eq!(manifest.files, vec![
+            ManifestEntry { file: "media_1.jpg".to_string(), size: 3, media_type: "image".to_string(), source_url: Some("https://cdn/1.jpg".to_string()) },
+            ManifestEntry { file: "media_2.MP4".to_string(), size: 5, media_type: "video".to_string(), source_url: None },
+        ]);
+
+        let (path, json) = manifest.write(&folder).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), json);
+        assert_eq!(serde_json::from_str::<Manifest>(&json).unwrap(), manifest);
+        // The manifest itself is never listed
+        let again = Manifest::from_folder(&folder, "https://www.instagram.com/p/abc123/", "post", &sources).unwrap();
+        assert_eq!(again.files, manifest.files);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How a download or preview tried to get the media
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Ytdlp,
+    Browser,
+    // The headless_chrome reel fallback of the preview
+    HeadlessFallback,
+}
+
+/// Success and failure counts of each extraction method since startup, for tuning the
+/// yt-dlp / browser fallback order. Relaxed atomics: the counts are independent tallies.
+pub struct ExtractionMetrics {
+    ytdlp_success: AtomicU64,
+    ytdlp_failure: AtomicU64,
+    browser_success: AtomicU64,
+    browser_failure: AtomicU64,
+    headless_fallback_success: AtomicU64,
+    headless_fallback_failure: AtomicU64,
+    all_failed: AtomicU64,
+}
+
+/// A point-in-time copy of the counters, as reported by `/api/health`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MetricsSnapshot {
+    pub ytdlp_success: u64,
+    pub ytdlp_failure: u64,
+    pub browser_success: u64,
+    pub browser_failure: u64,
+    pub headless_fallback_success: u64,
+    pub headless_fallback_failure: u64,
+    /// Requests where every method tried came up empty
+    pub all_failed: u64,
+}
+
+static METRICS: ExtractionMetrics = ExtractionMetrics::new();
+
+/// The server-wide counters
+pub fn extraction_metrics() -> &'static ExtractionMetrics {
+    &METRICS
+}
+
+impl ExtractionMetrics {
+    const fn new() -> Self {
+        Self {
+            ytdlp_success: AtomicU64::new(0),
+            ytdlp_failure: AtomicU64::new(0),
+            browser_success: AtomicU64::new(0),
+            browser_failure: AtomicU64::new(0),
+            headless_fallback_success: AtomicU64::new(0),
+            headless_fallback_failure: AtomicU64::new(0),
+            all_failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Count one attempt with `method` and whether it produced media
+    pub fn record(&self, method: Method, success: bool) {
+        let counter = match (method, success) {
+            (Method::Ytdlp, true) => &self.ytdlp_success,
+            (Method::Ytdlp, false) => &self.ytdlp_failure,
+            (Method::Browser, true) => &self.browser_success,
+            (Method::Browser, false) => &self.browser_failure,
+            (Method::HeadlessFallback, true) => &self.headless_fallback_success,
+            (Method::HeadlessFallback, false) => &self.headless_fallback_failure,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a request that no method could serve
+    pub fn record_all_failed(&self) {
+        self.all_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            ytdlp_success: load(&self.ytdlp_success),
+            ytdlp_failure: load(&self.ytdlp_failure),
+            browser_success: load(&self.browser_success),
+            browser_failure: load(&self.browser_failure),
+            headless_fallback_success: load(&self.headless_fallback_success),
+            headless_fallback_failure: load(&self.headless_fallback_failure),
+            all_failed: load(&self.all_failed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_outcome_lands_in_its_own_counter() {
+        // Counted apart from the server-wide counters, which handler tests also bump
+        let metrics = ExtractionMetrics::new();
+        // Browser found nothing, yt-dlp saved the post
+        metrics.record(Method::Browser, false);
+        metrics.record(Method::Ytdlp, true);
+        // Preview that only the headless_chrome fallback could serve
+        metrics.record(Method::Ytdlp, false);
+        metrics.record(Method::HeadlessFallback, true);
+        // Nothing worked
+        metrics.record(Method::Browser, false);
+        metrics.record(Method::Ytdlp, false);
+        metrics.record(Method::HeadlessFallback, false);
+        metrics.record_all_failed();
+        metrics.record(Method::Browser, true);
+
+        let counts = metrics.snapshot();
+        assert_eq!((counts.ytdlp_success, counts.ytdlp_failure), (1, 2));
+        assert_eq!((counts.browser_success, counts.browser_failure), (1, 2));
+        assert_eq!((counts.headless_fallback_success, counts.headless_fallback_failure), (1, 1));
+        assert_eq!(counts.all_failed, 1);
+    }
+}
@@ -1,2 +1,12 @@
 pub mod extractor;
 pub mod downloader;
+pub mod storage;
+pub mod dedup;
+pub mod webhook;
+pub mod outcome;
+pub mod http_client;
+pub mod manifest;
+pub mod queue;
+pub mod browser_pool;
+pub mod metrics;
+pub mod jobs;
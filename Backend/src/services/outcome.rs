@@ -0,0 +1,120 @@
+/// Summary of an extraction or download run, shared by the handlers and the preview
+/// endpoint so "success" means the same thing everywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractionOutcome {
+    /// Everything that was expected was obtained
+    Complete,
+    /// Only some of the expected items were obtained
+    Partial { got: usize, expected: usize },
+    /// Nothing was found, without any error
+    Empty,
+    /// Instagram refused to show the content (login wall)
+    Blocked,
+    /// Nothing was obtained and something went wrong along the way
+    Error(String),
+}
+
+impl ExtractionOutcome {
+    /// Classify a run that obtained `got` of `expected` items.
+    /// Any result at all counts as (partial) success; without one, a login wall wins over
+    /// an error message, which wins over a plain empty result.
+    pub fn classify(got: usize, expected: usize, blocked: bool, error: Option<&str>) -> Self {
+        if got > 0 {
+            if got < expected {
+                Self::Partial { got, expected }
+            } else {
+                Self::Complete
+            }
+        } else if blocked {
+            Self::Blocked
+        } else if let Some(error) = error {
+            Self::Error(error.to_string())
+        } else {
+            Self::Empty
+        }
+    }
+
+    /// Whether the caller got anything usable.
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Complete | Self::Partial { .. })
+    }
+
+    /// Short machine-readable name, as reported in responses.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Complete => "complete",
+            Self::Partial { .. } => "partial",
+            Self::Empty => "empty",
+            Self::Blocked => "blocked",
+            Self::Error(_) => "error",
+        }
+    }
+
+    /// Status emoji used at the start of handler messages.
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            Self::Complete => "✅",
+            Self::Partial { .. } => "⚠️",
+            Self::Empty | Self::Blocked | Self::Error(_) => "❌",
+        }
+    }
+}
+
+/// How a download request ended. Handlers report it; the download endpoint picks the HTTP
+/// status and the job tracker the job state from the variant, never from the message text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadResult {
+    /// Some or all of the media was saved; the message starts with ✅, ⚠️ or 🎉. `deduped` is
+    /// set when a file matched an earlier download and was linked to it
+    Done { message: String, deduped: bool },
+    /// Nothing was saved; the message starts with ❌
+    Failed(String),
+    /// The disk filled up while saving. Holds the reason, without the ❌ prefix
+    DiskFull(String),
+}
+
+impl DownloadResult {
+    /// A finished download with nothing deduplicated (yt-dlp runs, which aren't hashed)
+    pub fn done(message: String) -> Self {
+        Self::Done { message, deduped: false }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self, Self::Done { .. })
+    }
+
+    /// Whether any saved file was linked to an earlier identical download
+    pub fn deduped(&self) -> bool {
+        matches!(self, Self::Done { deduped: true, .. })
+    }
+
+    /// The status message shown to clients
+    pub fn message(&self) -> String {
+        match self {
+            Self::Done { message, .. } | Self::Failed(message) => message.clone(),
+            Self::DiskFull(reason) => format!("❌ {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_map_to_one_outcome_each() {
+        assert_eq!(ExtractionOutcome::classify(3, 3, false, None), ExtractionOutcome::Complete);
+        // Anything found wins over a login wall or an error further along
+        assert_eq!(ExtractionOutcome::classify(2, 5, true, Some("timeout")), ExtractionOutcome::Partial { got: 2, expected: 5 });
+        assert_eq!(ExtractionOutcome::classify(0, 4, true, Some("timeout")), ExtractionOutcome::Blocked);
+        assert_eq!(ExtractionOutcome::classify(0, 4, false, Some("timeout")), ExtractionOutcome::Error("timeout".to_string()));
+        assert_eq!(ExtractionOutcome::classify(0, 0, false, None), ExtractionOutcome::Empty);
+
+        let partial = ExtractionOutcome::classify(1, 2, false, None);
+        assert!(partial.is_success());
+        assert_eq!((partial.label(), partial.emoji()), ("partial", "⚠️"));
+        let blocked = ExtractionOutcome::Blocked;
+        assert!(!blocked.is_success());
+        assert_eq!((blocked.label(), blocked.emoji()), ("blocked", "❌"));
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::OnceLock;
+use crate::config::Config;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+// Downloads accepted (running or waiting for a download slot) before new ones are refused
+const DEFAULT_MAX_QUEUE: usize = 100;
+/// Seconds a refused client is told to wait before retrying
+pub const QUEUE_RETRY_AFTER_SECS: u64 = 5;
+
+/// Bounded set of accepted download jobs. Each job holds a slot until it finishes; when all
+/// slots are taken, new jobs are refused instead of piling up behind the running ones.
+pub struct DownloadQueue {
+    slots: Semaphore,
+    capacity: usize,
+}
+
+static DOWNLOAD_QUEUE: OnceLock<DownloadQueue> = OnceLock::new();
+
+// Parse MAX_QUEUE, falling back to the default on missing or invalid values
+pub fn parse_max_queue(value: Option<&str>) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_QUEUE)
+}
+
+/// The server-wide download queue, sized from MAX_QUEUE on first use.
+pub fn download_queue() -> &'static DownloadQueue {
+    DOWNLOAD_QUEUE.get_or_init(|| {
+        DownloadQueue::new(Config::current().max_queue)
+    })
+}
+
+impl DownloadQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self { slots: Semaphore::new(capacity), capacity }
+    }
+
+    /// Reserve `jobs` slots at once, or `None` when the queue can't take them all right now.
+    /// The slots are released when the returned permit is dropped.
+    pub fn try_enqueue(&self, jobs: usize) -> Option<SemaphorePermit<'_>> {
+        let jobs = u32::try_from(jobs).ok()?;
+        self.slots.try_acquire_many(jobs).ok()
+    }
+
+    /// Jobs currently holding a slot
+    pub fn depth(&self) -> usize {
+        self.capacity - self.slots.available_permits()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
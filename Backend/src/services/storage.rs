@@ -0,0 +1,297 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tracing::info;
+use crate::config::Config;
+use crate::services::downloader::DownloadError;
+
+type Result<T> = std::result::Result<T, DownloadError>;
+
+// Backend chosen at startup; handlers reach it through `storage()`
+static STORAGE: OnceLock<Arc<dyn Storage>> = OnceLock::new();
+
+/// Destination for downloaded media once a job has written it to its local folder.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Store `bytes` under `key`, returning where they were written.
+    async fn put(&self, key: &str, bytes: Bytes) -> Result<String>;
+    /// Store the file at `path` under `key` without reading it into memory.
+    async fn put_file(&self, key: &str, path: &Path) -> Result<String>;
+    /// Remove everything stored below the `folder` prefix, returning the local disk space freed.
+    async fn remove_folder(&self, folder: &str) -> Result<u64>;
+    /// Whether something is already stored under `key`.
+    async fn exists(&self, key: &str) -> bool;
+}
+
+/// Stores files on the local filesystem below `root`.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Bytes) -> Result<String> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| DownloadError(format!("Failed to create directory: {}", e)))?;
+        }
+        tokio::fs::write(&path, &bytes)
+            .await
+            .map_err(|e| DownloadError(format!("Failed to write {}: {}", path.display(), e)))?;
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<String> {
+        let target = self.root.join(key);
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| DownloadError(format!("Failed to create directory: {}", e)))?;
+        }
+        // Linking keeps a single copy on disk; a root on another filesystem needs a real copy
+        if tokio::fs::hard_link(path, &target).await.is_err() {
+            tokio::fs::copy(path, &target)
+                .await
+                .map_err(|e| DownloadError(format!("Failed to write {}: {}", target.display(), e)))?;
+        }
+        Ok(target.to_string_lossy().to_string())
+    }
+
+    async fn remove_folder(&self, folder: &str) -> Result<u64> {
+        let dir = self.root.join(folder);
+        let removed = tokio::task::spawn_blocking(move || {
+            let freed_bytes = reclaimable_bytes(&dir)?;
+            std::fs::remove_dir_all(&dir)?;
+            Ok::<_, std::io::Error>(freed_bytes)
+        })
+        .await
+        .map_err(|e| DownloadError(format!("Delete task failed: {}", e)))?;
+        match removed {
+            Ok(freed_bytes) => Ok(freed_bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(DownloadError(format!("Failed to delete '{}': {}", folder, e))),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> bool {
+        tokio::fs::try_exists(self.root.join(key)).await.unwrap_or(false)
+    }
+}
+
+/// Stores files in an S3 (or S3-compatible) bucket.
+#[cfg(feature = "s3")]
+pub struct S3Storage {
+    bucket: Box<s3::Bucket>,
+}
+
+#[cfg(feature = "s3")]
+impl S3Storage {
+    /// Build from `S3_BUCKET`, `S3_REGION` (default us-east-1) and an optional `S3_ENDPOINT`.
+    /// Credentials come from the usual AWS environment variables / profile.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let bucket_name = config.s3_bucket.as_deref()
+            .ok_or_else(|| DownloadError("S3_BUCKET must be set for the s3 storage backend".to_string()))?;
+        let region_name = config.s3_region.clone();
+        let endpoint = config.s3_endpoint.clone();
+        let region = match &endpoint {
+            Some(endpoint) => s3::Region::Custom { region: region_name, endpoint: endpoint.clone() },
+            None => region_name
+                .parse()
+                .map_err(|e| DownloadError(format!("Invalid S3 region: {}", e)))?,
+        };
+        let credentials = s3::creds::Credentials::default()
+            .map_err(|e| DownloadError(format!("Failed to load S3 credentials: {}", e)))?;
+        let mut bucket = s3::Bucket::new(bucket_name, region, credentials)
+            .map_err(|e| DownloadError(format!("Failed to configure S3 bucket: {}", e)))?;
+        // Custom endpoints (MinIO and friends) usually need path-style addressing
+        if endpoint.is_some() {
+            bucket = bucket.with_path_style();
+        }
+        Ok(Self { bucket })
+    }
+}
+
+#[cfg(feature = "s3")]
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, bytes: Bytes) -> Result<String> {
+        let response = self.bucket
+            .put_object(key, &bytes)
+            .await
+            .map_err(|e| DownloadError(format!("S3 upload failed: {}", e)))?;
+        if !(200..300).contains(&response.status_code()) {
+            return Err(DownloadError(format!("S3 upload failed with status {}", response.status_code())));
+        }
+        Ok(format!("s3://{}/{}", self.bucket.name(), key))
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<String> {
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| DownloadError(format!("Failed to read {}: {}", path.display(), e)))?;
+        let response = self.bucket
+            .put_object_stream(&mut file, key)
+            .await
+            .map_err(|e| DownloadError(format!("S3 upload failed: {}", e)))?;
+        if !(200..300).contains(&response.status_code()) {
+            return Err(DownloadError(format!("S3 upload failed with status {}", response.status_code())));
+        }
+        Ok(format!("s3://{}/{}", self.bucket.name(), key))
+    }
+
+    async fn remove_folder(&self, folder: &str) -> Result<u64> {
+        let listing = self.bucket
+            .list(format!("{}/", folder), None)
+            .await
+            .map_err(|e| DownloadError(format!("S3 listing failed: {}", e)))?;
+        for object in listing.into_iter().flat_map(|page| page.contents) {
+            self.bucket
+                .delete_object(&object.key)
+                .await
+                .map_err(|e| DownloadError(format!("S3 delete failed: {}", e)))?;
+        }
+        Ok(0)
+    }
+
+    async fn exists(&self, key: &str) -> bool {
+        matches!(self.bucket.head_object(key).await, Ok((_, 200)))
+    }
+}
+
+/// Build the backend selected by `STORAGE_BACKEND` (`local` by default, or `s3`).
+pub fn storage_from_config(config: &Config) -> Result<Arc<dyn Storage>> {
+    match config.storage_backend.as_str() {
+        "local" => Ok(Arc::new(LocalStorage::new(&config.storage_local_root))),
+        #[cfg(feature = "s3")]
+        "s3" => Ok(Arc::new(S3Storage::from_config(config)?)),
+        #[cfg(not(feature = "s3"))]
+        "s3" => Err(DownloadError("STORAGE_BACKEND=s3 requires building with the `s3` feature".to_string())),
+        other => Err(DownloadError(format!("Unknown STORAGE_BACKEND '{}'", other))),
+    }
+}
+
+/// Select the storage backend once at startup.
+pub fn init_storage(config: &Config) -> Result<()> {
+    let backend = storage_from_config(config)?;
+    let _ = STORAGE.set(backend);
+    Ok(())
+}
+
+/// The configured backend, defaulting to local storage in the working directory.
+pub fn storage() -> Arc<dyn Storage> {
+    STORAGE.get_or_init(|| Arc::new(LocalStorage::new("."))).clone()
+}
+
+// Storage keys mirror the local layout: "<job_folder>/<file>"
+fn storage_key(path: &Path) -> String {
+    path.to_string_lossy().trim_start_matches("./").replace('\\', "/")
+}
+
+/// Hand a locally downloaded file to the storage backend.
+/// A no-op for local storage rooted at the working directory, where the file already lives.
+pub async fn persist_file(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let backend = storage();
+    let key = storage_key(path);
+    if backend.exists(&key).await {
+        return Ok(());
+    }
+    let location = backend.put_file(&key, path).await?;
+    info!("Stored {} at {}", key, location);
+    Ok(())
+}
+
+/// Hand a small file already written at `path` (the manifest) to the storage backend from
+/// memory instead of reading it back.
+pub async fn persist_bytes(path: impl AsRef<Path>, bytes: Bytes) -> Result<()> {
+    let backend = storage();
+    let key = storage_key(path.as_ref());
+    if backend.exists(&key).await {
+        return Ok(());
+    }
+    let location = backend.put(&key, bytes).await?;
+    info!("Stored {} at {}", key, location);
+    Ok(())
+}
+
+/// Hand every file in a job folder to the storage backend (used after yt-dlp runs).
+pub async fn persist_folder(folder: impl AsRef<Path>) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(folder.as_ref())
+        .await
+        .map_err(|e| DownloadError(format!("Failed to read folder: {}", e)))?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if entry.path().is_file() {
+            persist_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Bytes that deleting `dir` gives back. Deduplicated downloads are hard links shared with
+/// other jobs (or the stored copy), so files with other links stay on disk and aren't counted.
+pub fn reclaimable_bytes(dir: &Path) -> std::io::Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let metadata = path.symlink_metadata()?;
+        if metadata.is_dir() {
+            total += reclaimable_bytes(&path)?;
+        } else if metadata.is_file() && link_count(&metadata) <= 1 {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &std::fs::Metadata) -> u64 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_storage_round_trips_bytes_and_files() {
+        let scratch = std::env::temp_dir().join(format!("insta_storage_test_{}", crate::utils::job::new_job_id()));
+        let storage = LocalStorage::new(scratch.join("root"));
+
+        assert!(!storage.exists("insta_job/a.jpg").await);
+        let location = storage.put("insta_job/a.jpg", Bytes::from_static(b"jpeg bytes")).await.unwrap();
+        assert_eq!(std::fs::read(&location).unwrap(), b"jpeg bytes");
+        assert!(storage.exists("insta_job/a.jpg").await);
+
+        let source = scratch.join("reel.mp4");
+        std::fs::write(&source, vec![7u8; 2048]).unwrap();
+        let location = storage.put_file("insta_job/reel.mp4", &source).await.unwrap();
+        assert_eq!(std::fs::read(&location).unwrap(), vec![7u8; 2048]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Stored by linking, not by writing a second copy
+            assert_eq!(std::fs::metadata(&location).unwrap().ino(), std::fs::metadata(&source).unwrap().ino());
+        }
+
+        // The working copy still holds the video, so only the image is freed
+        assert_eq!(storage.remove_folder("insta_job").await.unwrap(), 10);
+        assert!(!storage.exists("insta_job/reel.mp4").await);
+        assert_eq!(storage.remove_folder("insta_job").await.unwrap(), 0);
+        std::fs::remove_dir_all(&scratch).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use crate::services::http_client::client_builder;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, warn};
+use crate::services::downloader::{backoff_delay, JitterStrategy};
+use crate::utils::url_guard::CallbackTarget;
+
+const CALLBACK_ATTEMPTS: u32 = 3;
+const CALLBACK_TIMEOUT_SECS: u64 = 10;
+const CALLBACK_BACKOFF_MS: u64 = 1_000;
+const CALLBACK_MAX_BACKOFF_MS: u64 = 10_000;
+
+/// POST `payload` as JSON to a client's callback URL, retrying a couple of times on
+/// failure. Returns whether the callback was accepted (any 2xx).
+pub async fn post_callback<T: Serialize>(target: &CallbackTarget, payload: &T) -> bool {
+    let callback_url = &target.url;
+    // Redirects are not followed: the target was validated, where it redirects to was not
+    let mut builder = client_builder()
+        .timeout(Duration::from_secs(CALLBACK_TIMEOUT_SECS))
+        .redirect(reqwest::redirect::Policy::none())
+        // A proxy would resolve the host again on its own
+        .no_proxy();
+    // Connect to the addresses checked at validation instead of resolving the name again
+    if let Some(domain) = callback_url.domain() {
+        builder = builder.resolve_to_addrs(domain, &target.addrs);
+    }
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Failed to build callback client: {}", e);
+            return false;
+        }
+    };
+
+    for attempt in 1..=CALLBACK_ATTEMPTS {
+        match client.post(callback_url.clone()).json(payload).send().await {
+            Ok(response) if response.status().is_success() => {
+                info!("📨 Delivered callback to {}", callback_url);
+                return true;
+            }
+            Ok(response) => warn!("Callback to {} returned {} (attempt {})", callback_url, response.status(), attempt),
+            Err(e) => warn!("Callback to {} failed (attempt {}): {}", callback_url, attempt, e),
+        }
+        if attempt < CALLBACK_ATTEMPTS {
+            sleep(backoff_delay(attempt, CALLBACK_BACKOFF_MS, CALLBACK_MAX_BACKOFF_MS, JitterStrategy::None)).await;
+        }
+    }
+    warn!("Giving up on callback to {} after {} attempts", callback_url, CALLBACK_ATTEMPTS);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn callback_connects_to_the_validated_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let read = socket.read(&mut request).await.unwrap();
+            socket.write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+
+        // The name doesn't resolve at all, so only the pinned address can be reached
+        let target = CallbackTarget {
+            url: format!("http://callback.invalid:{}/hook", addr.port()).parse().unwrap(),
+            addrs: vec![addr],
+        };
+        assert!(post_callback(&target, &serde_json::json!({ "ok": true })).await);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook "));
+        assert!(request.to_ascii_lowercase().contains(&format!("host: callback.invalid:{}", addr.port())));
+    }
+}
@@ -0,0 +1,34 @@
+use axum::extract::FromRef;
+use headless_chrome::Browser;
+use std::sync::Arc;
+use crate::config::Config;
+use crate::services::downloader::DownloadLimiter;
+
+/// State shared by every route. Handlers extract just the part they need
+/// (`State<Option<Arc<Browser>>>`, `State<Arc<Config>>` or `State<DownloadLimiter>`).
+#[derive(Clone)]
+pub struct AppState {
+    /// `None` when Chrome couldn't be launched at startup; yt-dlp paths still work without it
+    pub browser: Option<Arc<Browser>>,
+    pub config: Arc<Config>,
+    /// Server-wide cap on media fetches in flight, sized from `config.max_global_downloads`
+    pub download_limiter: DownloadLimiter,
+}
+
+impl FromRef<AppState> for Option<Arc<Browser>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.browser.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for DownloadLimiter {
+    fn from_ref(state: &AppState) -> Self {
+        state.download_limiter.clone()
+    }
+}
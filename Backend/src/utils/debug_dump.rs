@@ -0,0 +1,50 @@
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+use crate::config::Config;
+
+// Write a debug JSON document to `<dir>/<timestamp>_<job_id>.json`
+pub fn write_debug_dump(dir: &Path, job_id: &str, value: &Value) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f");
+    let path = dir.join(format!("{}_{}.json", timestamp, job_id));
+    let json = serde_json::to_string_pretty(value)?;
+    std::fs::write(&path, json)?;
+    Ok(path)
+}
+
+// Record extraction debug output: to a file when DEBUG_DUMP_DIR is set, otherwise as a one-line summary
+pub fn dump_debug(job_id: &str, stage: &str, value: &Value) {
+    match Config::current().debug_dump_dir.as_deref() {
+        Some(dir) => {
+            let dump_id = format!("{}_{}", job_id, stage);
+            match write_debug_dump(Path::new(dir), &dump_id, value) {
+                Ok(path) => debug!("[{}] {} debug written to {}", job_id, stage, path.display()),
+                Err(e) => warn!("[{}] Failed to write {} debug dump: {}", job_id, stage, e),
+            }
+        }
+        None => {
+            let fields = value.get("elements").and_then(|e| e.as_object()).map(|o| o.len()).unwrap_or(0);
+            let errors = value.get("errors").and_then(|e| e.as_array()).map(|a| a.len()).unwrap_or(0);
+            debug!("[{}] {} debug: {} fields, {} errors", job_id, stage, fields, errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumps_are_valid_json_named_after_the_job() {
+        let dir = std::env::temp_dir().join(format!("insta_test_dump_{}", std::process::id()));
+        let value = serde_json::json!({"elements": {"video": true}, "errors": ["no carousel"]});
+        let path = write_debug_dump(&dir.join("nested"), "job42_post", &value).unwrap();
+
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.ends_with("_job42_post.json"), "{}", name);
+        let written: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written, value);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,24 +1,34 @@
 use std::fmt;
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
 use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AppError {
     BadRequest(String),
-    #[allow(dead_code)]
     NotFound(String),
+    PrivateAccount(String),
     InternalServerError(String),
     #[allow(dead_code)]
     ValidationError(String),
     #[allow(dead_code)]
     BrowserError(String),
-    #[allow(dead_code)]
     NetworkError(String),
+    /// The download queue is full; answered with 503 and a Retry-After of this many seconds
+    Overloaded(String, u64),
+    /// The disk filled up while saving a download; answered with 507
+    InsufficientStorage(String),
+    /// An upstream server answered with an error status, passed on as is along with its
+    /// Retry-After header, if any
+    Upstream(StatusCode, String, Option<String>),
+    /// The fetched media can't be processed (wrong type, too large, undecodable); answered
+    /// with the given 4xx status
+    UnprocessableMedia(StatusCode, String),
 }
 
 impl fmt::Display for AppError {
@@ -26,40 +36,59 @@ impl fmt::Display for AppError {
         match self {
             Self::BadRequest(msg) => write!(f, "Bad request: {}", msg),
             Self::NotFound(msg) => write!(f, "Not found: {}", msg),
+            Self::PrivateAccount(msg) => write!(f, "Private account: {}", msg),
             Self::InternalServerError(msg) => write!(f, "Internal server error: {}", msg),
             Self::ValidationError(msg) => write!(f, "Validation error: {}", msg),
             Self::BrowserError(msg) => write!(f, "Browser error: {}", msg),
             Self::NetworkError(msg) => write!(f, "Network error: {}", msg),
+            Self::Overloaded(msg, _) => write!(f, "Overloaded: {}", msg),
+            Self::InsufficientStorage(msg) => write!(f, "Insufficient storage: {}", msg),
+            Self::Upstream(status, msg, _) => write!(f, "Upstream error ({}): {}", status, msg),
+            Self::UnprocessableMedia(status, msg) => write!(f, "Unprocessable media ({}): {}", status, msg),
         }
     }
 }
 
-#[derive(Serialize)]
-#[allow(dead_code)]
-struct ErrorResponse {
-    error: String,
-    success: bool,
-    error_type: String,
+/// JSON body of every error response
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub success: bool,
+    /// e.g. `bad_request`, `not_found`, `private_account`
+    pub error_type: String,
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let retry_after = match &self {
+            Self::Overloaded(_, secs) => Some(secs.to_string()),
+            Self::Upstream(_, _, retry_after) => retry_after.clone(),
+            _ => None,
+        };
         let (status, error_message, error_type) = match self {
             Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg, "bad_request"),
             Self::NotFound(msg) => (StatusCode::NOT_FOUND, msg, "not_found"),
+            Self::PrivateAccount(msg) => (StatusCode::FORBIDDEN, msg, "private_account"),
             Self::InternalServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg, "internal_server_error"),
             Self::ValidationError(msg) => (StatusCode::BAD_REQUEST, msg, "validation_error"),
             Self::BrowserError(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg, "browser_error"),
             Self::NetworkError(msg) => (StatusCode::BAD_GATEWAY, msg, "network_error"),
+            Self::Overloaded(msg, _) => (StatusCode::SERVICE_UNAVAILABLE, msg, "overloaded"),
+            Self::InsufficientStorage(msg) => (StatusCode::INSUFFICIENT_STORAGE, msg, "insufficient_storage"),
+            Self::Upstream(status, msg, _) => (status, msg, "upstream_error"),
+            Self::UnprocessableMedia(status, msg) => (status, msg, "unprocessable_media"),
         };
 
-        let body = Json(json!({
-            "error": error_message,
-            "success": false,
-            "error_type": error_type
-        }));
+        let body = Json(ErrorResponse {
+            error: error_message,
+            success: false,
+            error_type: error_type.to_string(),
+        });
 
-        (status, body).into_response()
+        match retry_after {
+            Some(retry_after) => (status, [(RETRY_AFTER, retry_after)], body).into_response(),
+            None => (status, body).into_response(),
+        }
     }
 }
 
@@ -88,6 +117,19 @@ impl From<reqwest::Error> for AppError {
     }
 }
 
+// Turn a handler panic into the usual error envelope instead of a dropped connection.
+// The job id ties the client's 500 to the logged panic message.
+pub fn panic_response(panic: Box<dyn std::any::Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    let job_id = crate::utils::job::new_job_id();
+    tracing::error!("Handler panicked (job {}): {}", job_id, message);
+    AppError::InternalServerError(format!("Unexpected server error (job {})", job_id)).into_response()
+}
+
 // Helper for creating error responses
 #[allow(dead_code)]
 pub fn error_response(status: StatusCode, message: String) -> Response {
@@ -116,3 +158,32 @@ pub fn browser_error(message: &str) -> AppError {
 pub fn network_error(message: &str) -> AppError {
     AppError::NetworkError(message.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+
+    #[tokio::test]
+    async fn panicking_handlers_answer_500_and_the_router_keeps_serving() {
+        async fn boom() -> &'static str {
+            panic!("deliberate test panic")
+        }
+        let app = Router::new()
+            .route("/boom", get(boom))
+            .route("/ok", get(|| async { "ok" }))
+            .layer(CatchPanicLayer::custom(panic_response));
+
+        let response = app.clone().oneshot(Request::get("/boom").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = serde_json::from_slice(&crate::utils::test_server::body_bytes(response).await).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error_type"], "internal_server_error");
+        assert!(body["error"].as_str().unwrap().starts_with("Unexpected server error (job "));
+
+        let response = app.oneshot(Request::get("/ok").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::Utc;
+use rand::random;
+use std::io;
+
+// Short random identifier used to correlate logs, debug dumps and files of one request
+pub fn new_job_id() -> String {
+    format!("{:08x}", random::<u32>())
+}
+
+// Attempts before giving up on finding an unused folder name
+const JOB_FOLDER_ATTEMPTS: usize = 5;
+
+/// Folder name for a download job: `insta_{kind}_{timestamp}_{suffix}`.
+/// The random suffix keeps requests started in the same second apart.
+pub fn job_folder_name(kind: &str, timestamp: i64) -> String {
+    format!("insta_{}_{}_{}", kind, timestamp, new_job_id())
+}
+
+/// Create a new, empty job folder and return its name.
+/// An existing directory is never reused, so concurrent jobs can't write into each other's folder.
+pub fn create_job_folder(kind: &str) -> io::Result<String> {
+    for _ in 0..JOB_FOLDER_ATTEMPTS {
+        let name = job_folder_name(kind, Utc::now().timestamp());
+        match std::fs::create_dir(&name) {
+            Ok(()) => return Ok(name),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no unused job folder name found"))
+}
+
+// Longest base name accepted for a caller-chosen output filename
+const MAX_OUTPUT_NAME_LEN: usize = 100;
+// Media extensions dropped from a caller-chosen name, since the real one is appended
+const MEDIA_EXTENSIONS: &[&str] = &["mp4", "jpg", "jpeg", "png", "webp", "heic"];
+
+/// Validate a caller-chosen output filename and reduce it to a safe base name.
+/// Names containing path separators are rejected; other unusual characters become `_`,
+/// and a trailing media extension is dropped. `None` or blank keeps the default naming.
+pub fn parse_output_name(filename: Option<&str>) -> Result<Option<String>, String> {
+    let Some(raw) = filename.map(str::trim).filter(|f| !f.is_empty()) else {
+        return Ok(None);
+    };
+    if raw.contains('/') || raw.contains('\\') {
+        return Err(format!("filename '{}' must not contain path separators", raw));
+    }
+    let stem = match raw.rsplit_once('.') {
+        Some((stem, ext)) if MEDIA_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()) => stem,
+        _ => raw,
+    };
+    let name: String = stem
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') { c } else { '_' })
+        .take(MAX_OUTPUT_NAME_LEN)
+        .collect();
+    // Leading dots would make hidden files (or `..`), which /api/files refuses to serve
+    let name = name.trim_start_matches('.').trim();
+    if name.is_empty() {
+        return Err(format!("filename '{}' has no usable characters", raw));
+    }
+    Ok(Some(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_started_in_the_same_second_get_their_own_folder() {
+        let timestamp = Utc::now().timestamp();
+        assert_ne!(job_folder_name("reel", timestamp), job_folder_name("reel", timestamp));
+
+        let first = create_job_folder("test").unwrap();
+        let second = create_job_folder("test").unwrap();
+        assert_ne!(first, second);
+        assert!(first.starts_with("insta_test_"));
+        for folder in [first, second] {
+            assert!(std::fs::read_dir(&folder).unwrap().next().is_none());
+            std::fs::remove_dir(&folder).unwrap();
+        }
+    }
+}
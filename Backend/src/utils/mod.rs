@@ -1 +1,6 @@
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod job;
+pub mod debug_dump;
+pub mod url_guard;
+#[cfg(test)]
+pub mod test_server;
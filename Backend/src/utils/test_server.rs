@@ -0,0 +1,91 @@
+// Minimal HTTP/1.1 server for tests of code that talks to a CDN, Instagram or a callback.
+// Each connection gets one canned answer and is then closed.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// A local server answering every request with whatever `respond` returns for it
+pub struct TestServer {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    /// Listen on a free local port. `respond` gets the request line and headers and returns the
+    /// raw bytes written back; the connection is closed right after, so a body shorter than its
+    /// Content-Length reads as a dropped connection.
+    pub async fn start(respond: impl Fn(&str) -> Vec<u8> + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let respond = Arc::new(respond);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let seen = seen.clone();
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let request = read_head(&mut socket).await;
+                    seen.lock().unwrap().push(request.clone());
+                    let _ = socket.write_all(&respond(&request)).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        Self { addr, requests }
+    }
+
+    /// `http://127.0.0.1:<port><path>`
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Request lines and headers received so far, in arrival order
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// Read up to the blank line ending the headers; request bodies aren't needed by any test
+async fn read_head(socket: &mut tokio::net::TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    String::from_utf8_lossy(&head).to_string()
+}
+
+/// A complete response with the given status line (e.g. `200 OK`), extra headers and body
+pub fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    [head.into_bytes(), body.to_vec()].concat()
+}
+
+/// The value of header `name` in a raw request, matched case-insensitively
+pub fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Everything an axum response body yields, read to the end
+pub async fn body_bytes(response: axum::response::Response) -> Vec<u8> {
+    use axum::body::HttpBody;
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk.unwrap());
+    }
+    bytes
+}
@@ -0,0 +1,252 @@
+use std::net::{IpAddr, SocketAddr};
+use url::{Host, Url};
+
+// Hosts Instagram serves media from; anything else is refused so the proxy
+// endpoints can't be pointed at internal services (SSRF)
+const ALLOWED_MEDIA_HOST_SUFFIXES: &[&str] = &["cdninstagram.com", "fbcdn.net", "instagram.com"];
+
+// Hosts Instagram post, reel and story links live on
+const INSTAGRAM_PAGE_HOSTS: &[&str] = &["instagram.com", "www.instagram.com", "m.instagram.com"];
+
+// Parse `raw` as an absolute http(s) URL
+fn parse_http_url(raw: &str) -> Result<Url, String> {
+    let url = Url::parse(raw).map_err(|e| format!("Invalid URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Unsupported URL scheme '{}'", url.scheme()));
+    }
+    Ok(url)
+}
+
+/// Parse `raw` and make sure it points at an Instagram media host over http(s).
+/// Returns the reason as a human readable message when it doesn't.
+pub fn validate_media_url(raw: &str) -> Result<Url, String> {
+    let url = parse_http_url(raw)?;
+    // IP literals are never Instagram CDN hosts
+    let host = match url.host() {
+        Some(Host::Domain(host)) => host.to_ascii_lowercase(),
+        _ => return Err("Media URL must use an Instagram host name".to_string()),
+    };
+    let allowed = ALLOWED_MEDIA_HOST_SUFFIXES
+        .iter()
+        .any(|suffix| host == *suffix || host.ends_with(&format!(".{}", suffix)));
+    if !allowed {
+        return Err(format!("Host '{}' is not an Instagram media host", host));
+    }
+    Ok(url)
+}
+
+/// Trim and canonicalise a user-supplied Instagram link to `https://www.instagram.com/<path>`,
+/// dropping tracking query strings (`?igsh=...`) and fragments so equal links compare equal.
+/// Anything that isn't an Instagram page link is refused with a human readable reason.
+pub fn normalize_instagram_url(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Err("URL is empty".to_string());
+    }
+    let url = parse_http_url(raw)?;
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    if !INSTAGRAM_PAGE_HOSTS.contains(&host.as_str()) {
+        return Err(format!("'{}' is not an Instagram URL", raw));
+    }
+    if url.path() == "/" {
+        return Err(format!("'{}' doesn't point at a post, reel or story", raw));
+    }
+    Ok(format!("https://www.instagram.com{}", url.path()))
+}
+
+// Instagram usernames: letters, digits, '.' and '_', at most 30 characters
+const MAX_USERNAME_LEN: usize = 30;
+
+/// Expand a bare `username` or `@username` to its `https://www.instagram.com/stories/<username>/`
+/// URL so stories can be requested without the full link. Anything with a '/' or ':' is taken
+/// as a URL: Instagram links get the same canonical form (see `normalize_instagram_url`, plus
+/// a trailing slash), other input is returned trimmed for the caller's URL checks to refuse.
+pub fn expand_story_username(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    if raw.contains('/') || raw.contains(':') {
+        return Ok(match normalize_instagram_url(raw) {
+            Ok(url) if url.ends_with('/') => url,
+            Ok(url) => format!("{}/", url),
+            Err(_) => raw.to_string(),
+        });
+    }
+    let username = raw.strip_prefix('@').unwrap_or(raw);
+    let valid = !username.is_empty()
+        && username.len() <= MAX_USERNAME_LEN
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+        && !username.starts_with('.')
+        && !username.ends_with('.')
+        && !username.contains("..");
+    if !valid {
+        return Err(format!("'{}' is neither a URL nor a valid Instagram username", raw));
+    }
+    Ok(format!("https://www.instagram.com/stories/{}/", username.to_ascii_lowercase()))
+}
+
+/// Whether `ip` is a publicly routable address (not loopback, private, link-local, ...).
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                // Carrier-grade NAT (100.64.0.0/10)
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // Unique local (fc00::/7) and link-local (fe80::/10)
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// A validated callback URL together with the public addresses its host resolved to.
+/// Callbacks connect to these addresses only, so a DNS answer that changes between
+/// validation and delivery (DNS rebinding) can't point them at an internal service.
+#[derive(Debug, Clone)]
+pub struct CallbackTarget {
+    pub url: Url,
+    pub addrs: Vec<SocketAddr>,
+}
+
+/// Validate a client-supplied callback URL: http(s) only, and every address the host
+/// resolves to must be public so callbacks can't reach internal services.
+pub async fn validate_callback_url(raw: &str) -> Result<CallbackTarget, String> {
+    let url = parse_http_url(raw)?;
+    let host = url.host_str().ok_or("Callback URL has no host")?.to_string();
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<_> = tokio::net::lookup_host((host.trim_matches(|c| c == '[' || c == ']'), port))
+        .await
+        .map_err(|e| format!("Failed to resolve callback host '{}': {}", host, e))?
+        .collect();
+    if addrs.is_empty() || addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+        return Err(format!("Callback host '{}' is not a public address", host));
+    }
+    Ok(CallbackTarget { url, addrs })
+}
+
+/// Redirect policy for media fetches: follow redirects only while they stay on Instagram media hosts.
+pub fn media_redirect_policy() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= 10 {
+            attempt.error("too many redirects")
+        } else if validate_media_url(attempt.url().as_str()).is_ok() {
+            attempt.follow()
+        } else {
+            attempt.stop()
+        }
+    })
+}
+
+/// Regex patterns a deployment restricts accepted Instagram URLs to (`ALLOWED_URL_PATTERNS`).
+/// Empty allows every Instagram URL. Patterns are compiled once, when the config is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct UrlAllowlist {
+    patterns: Vec<regex::Regex>,
+}
+
+impl UrlAllowlist {
+    /// Compile a comma-separated list of regexes; blank entries are ignored.
+    /// Errors name the pattern that doesn't compile.
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        let patterns = value
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| regex::Regex::new(p).map_err(|e| format!("ALLOWED_URL_PATTERNS entry '{}' is not a valid regex: {}", p, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Whether `raw` may be processed. Instagram links are matched in their normalized
+    /// `https://www.instagram.com/<path>` form so tracking parameters can't affect the result.
+    pub fn allows(&self, raw: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        let url = normalize_instagram_url(raw).unwrap_or_else(|_| raw.trim().to_string());
+        self.patterns.iter().any(|pattern| pattern.is_match(&url))
+    }
+
+    /// `Err` with a human readable reason when `raw` isn't allowed.
+    pub fn check(&self, raw: &str) -> Result<(), String> {
+        if self.allows(raw) {
+            Ok(())
+        } else {
+            Err(format!("'{}' is not allowed on this server (ALLOWED_URL_PATTERNS)", raw.trim()))
+        }
+    }
+}
+
+impl serde::Serialize for UrlAllowlist {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.patterns.iter().map(|pattern| pattern.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_and_special_addresses_are_not_public() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} should not be public", ip);
+        }
+        for ip in ["8.8.8.8", "157.240.1.174", "2a03:2880:f12f:83:face:b00c:0:25de"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn callback_validation_returns_the_checked_addresses() {
+        let target = validate_callback_url("https://8.8.8.8/hook").await.unwrap();
+        assert_eq!(target.addrs, vec!["8.8.8.8:443".parse().unwrap()]);
+        assert!(validate_callback_url("http://localhost:8080/hook").await.is_err());
+        assert!(validate_callback_url("http://169.254.169.254/latest").await.is_err());
+        assert!(validate_callback_url("ftp://8.8.8.8/").await.is_err());
+    }
+
+    #[test]
+    fn allowlist_parses_patterns_and_matches_normalized_links() {
+        let allowlist = UrlAllowlist::parse(Some(r" ^https://www\.instagram\.com/reels?/ , ,")).unwrap();
+        assert!(allowlist.allows("https://instagram.com/reel/abc/?igsh=1"));
+        assert!(!allowlist.allows("https://www.instagram.com/p/abc/"));
+        assert!(UrlAllowlist::parse(None).unwrap().allows("https://www.instagram.com/p/abc/"));
+        let err = UrlAllowlist::parse(Some("ok,(unclosed")).unwrap_err();
+        assert!(err.contains("(unclosed"), "{}", err);
+    }
+
+    #[test]
+    fn story_usernames_expand_to_story_urls() {
+        assert_eq!(expand_story_username(" @nat.geo_ ").unwrap(), "https://www.instagram.com/stories/nat.geo_/");
+        assert_eq!(expand_story_username("natgeo").unwrap(), "https://www.instagram.com/stories/natgeo/");
+        assert_eq!(
+            expand_story_username(" https://www.instagram.com/stories/natgeo/123/ ").unwrap(),
+            "https://www.instagram.com/stories/natgeo/123/"
+        );
+        let canonical = expand_story_username("https://www.instagram.com/stories/natgeo/").unwrap();
+        for same in ["@natgeo", "natgeo", "https://instagram.com/stories/natgeo", "https://www.instagram.com/stories/natgeo/?igsh=1"] {
+            assert_eq!(expand_story_username(same).unwrap(), canonical, "{:?}", same);
+        }
+        assert_eq!(expand_story_username("https://example.com/stories/natgeo").unwrap(), "https://example.com/stories/natgeo");
+        for bad in ["", "@", ".natgeo", "nat..geo", "natgeo.", "nat geo", &"a".repeat(MAX_USERNAME_LEN + 1)] {
+            assert!(expand_story_username(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+}